@@ -0,0 +1,108 @@
+//! Benchmarks of the core operations of aspartam
+//!
+//! Run them via `cargo bench`, optionally passing a filter: `cargo bench -- latency`.
+//! Every benchmark is repeated a few times, and the best result is reported.
+
+use aspartam::prelude::*;
+use futures_util::stream;
+use std::time::{Duration, Instant};
+
+const ROUNDS: usize = 5;
+
+struct Count(u64);
+struct Total;
+
+struct Counter(u64);
+impl Actor for Counter {}
+
+#[async_trait]
+impl Handler<Count> for Counter {
+    type Response = ();
+    async fn handle(&mut self, msg: Count, _ctx: &mut ActorContext<Self>) {
+        self.0 += msg.0;
+    }
+}
+
+#[async_trait]
+impl Handler<Total> for Counter {
+    type Response = u64;
+    async fn handle(&mut self, _msg: Total, _ctx: &mut ActorContext<Self>) -> u64 {
+        self.0
+    }
+}
+
+/// Messages sent via do_send, waiting only for the last one
+async fn throughput(messages: u64) {
+    let counter = Counter(0).start();
+    for _ in 0..messages {
+        counter.do_send(Count(1));
+    }
+    assert_eq!(counter.send(Total).await.unwrap(), messages);
+}
+
+/// Same as [throughput], but enqueued at once
+async fn batched_throughput(messages: u64) {
+    let counter = Counter(0).start();
+    counter.do_send_many((0..messages).map(|_| Count(1)));
+    assert_eq!(counter.send(Total).await.unwrap(), messages);
+}
+
+/// Requests sent one after another, each waiting for its' response
+async fn latency(requests: u64) {
+    let counter = Counter(0).start();
+    for _ in 0..requests {
+        counter.send(Count(1)).await.unwrap();
+    }
+}
+
+/// Actors started and stopped right away
+async fn churn(actors: u64) {
+    for _ in 0..actors {
+        let counter = Counter(0).start();
+        counter.send(Count(1)).await.unwrap();
+        counter.stop(StopMode::Abandon);
+    }
+}
+
+/// Messages forwarded from a stream
+async fn stream_ingestion(messages: u64) {
+    let counter = Counter::create(|ctx| {
+        ctx.add_stream(stream::iter((0..messages).map(|_| Count(1))));
+        Counter(0)
+    });
+    while counter.send(Total).await.unwrap() < messages {
+        tokio::task::yield_now().await;
+    }
+}
+
+fn bench<F, Fut>(rt: &tokio::runtime::Runtime, filter: &Option<String>, name: &str, ops: u64, f: F)
+where
+    F: Fn(u64) -> Fut,
+    Fut: std::future::Future<Output = ()>,
+{
+    if filter.as_ref().is_some_and(|filter| !name.contains(filter.as_str())) {
+        return;
+    }
+    let best = (0..ROUNDS)
+        .map(|_| {
+            let started = Instant::now();
+            rt.block_on(f(ops));
+            started.elapsed()
+        })
+        .min()
+        .unwrap_or(Duration::ZERO);
+    let per_op = best / ops as u32;
+    let per_sec = ops as f64 / best.as_secs_f64();
+    println!("{:<20} {:>10?}/op {:>14.0} ops/s", name, per_op, per_sec);
+}
+
+fn main() {
+    // cargo passes --bench, which is not a filter
+    let filter = std::env::args().skip(1).find(|arg| !arg.starts_with("--"));
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    bench(&rt, &filter, "throughput", 1_000_000, throughput);
+    bench(&rt, &filter, "batched_throughput", 1_000_000, batched_throughput);
+    bench(&rt, &filter, "latency", 100_000, latency);
+    bench(&rt, &filter, "churn", 10_000, churn);
+    bench(&rt, &filter, "stream_ingestion", 100_000, stream_ingestion);
+}
@@ -0,0 +1,278 @@
+//! Batched acknowledgements for message sources
+//!
+//! Sources like Kafka, AMQP or Redis streams redeliver the messages which have not been acknowledged.
+//! Acknowledging every message on its' own is slow, while acknowledging rarely means that more messages
+//! get redelivered after a crash. The [AckBatcher] sits in between: the source [tracks](Addr::track)
+//! every message it delivers, getting an [AckToken] which travels along with the message,
+//! and the handler calls [AckToken::ack] once it's done with it.
+//! Messages which could not be handled get [nacked](AckToken::nack) instead, as do the ones whose
+//! token gets dropped, and handed to the closure of [AckBatcher::with_nack] for the source to redeliver them.
+//!
+//! The batcher commits a [Watermark] per partition, i.e. the offset up to which all the messages
+//! have been acknowledged, once enough messages got acknowledged or the interval has elapsed.
+//! Messages acknowledged out of order get committed once all the ones before them are.
+
+use crate::{
+    actor::{Actor, Handler},
+    addr::Addr,
+    context::ActorContext,
+};
+use async_trait::async_trait;
+use futures_util::future::BoxFuture;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    future::Future,
+    time::Duration,
+};
+
+/// Offset up to which (inclusive) all the messages of a partition have been acknowledged
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct Watermark {
+    pub partition: u32,
+    pub offset: u64,
+}
+
+/// Commits the watermarks to the source
+type Commit = Box<dyn FnMut(Vec<Watermark>) -> BoxFuture<'static, ()> + Send>;
+/// Hands a nacked message back to the source
+type Nack = Box<dyn FnMut(Watermark) -> BoxFuture<'static, ()> + Send>;
+
+/// Messages of a partition which have not been committed yet
+#[derive(Default)]
+struct Partition {
+    /// Whether each of the messages has been acknowledged
+    pending: BTreeMap<u64, bool>,
+}
+
+impl Partition {
+    /// Removes the acknowledged messages which are not preceded by pending ones,
+    /// returning the highest removed offset
+    fn advance(&mut self) -> Option<u64> {
+        let mut watermark = None;
+        while let Some(entry) = self.pending.first_entry() {
+            if !*entry.get() {
+                break;
+            }
+            watermark = Some(entry.remove_entry().0);
+        }
+        watermark
+    }
+}
+
+/// Actor batching the acknowledgements of a source, see [crate::ack]
+pub struct AckBatcher {
+    commit: Commit,
+    nack: Nack,
+    interval: Duration,
+    batch: usize,
+    partitions: HashMap<u32, Partition>,
+    /// Acknowledged since the last commit
+    acked: usize,
+}
+
+impl fmt::Debug for AckBatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AckBatcher")
+            .field("interval", &self.interval)
+            .field("batch", &self.batch)
+            .field("acked", &self.acked)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AckBatcher {
+    /// Creates a batcher committing via the given closure every second,
+    /// or once 1000 messages have been acknowledged
+    pub fn new<F, Fut>(mut commit: F) -> Self
+    where
+        F: 'static + FnMut(Vec<Watermark>) -> Fut + Send,
+        Fut: 'static + Future<Output = ()> + Send,
+    {
+        Self {
+            commit: Box::new(move |watermarks| Box::pin(commit(watermarks))),
+            nack: Box::new(|_| Box::pin(async {})),
+            interval: Duration::from_secs(1),
+            batch: 1000,
+            partitions: HashMap::new(),
+            acked: 0,
+        }
+    }
+    /// Sets how often the acknowledgements get committed
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+    /// Sets how many acknowledgements make the batcher commit right away
+    pub fn with_batch(mut self, batch: usize) -> Self {
+        self.batch = batch;
+        self
+    }
+    /// Sets the closure which the nacked messages get handed to, so that the source redelivers them.
+    ///
+    /// Nacked messages do not hold the watermark back, so without one they do not get redelivered.
+    pub fn with_nack<F, Fut>(mut self, mut nack: F) -> Self
+    where
+        F: 'static + FnMut(Watermark) -> Fut + Send,
+        Fut: 'static + Future<Output = ()> + Send,
+    {
+        self.nack = Box::new(move |watermark| Box::pin(nack(watermark)));
+        self
+    }
+    async fn flush(&mut self) {
+        self.acked = 0;
+        let watermarks: Vec<_> = self
+            .partitions
+            .iter_mut()
+            .filter_map(|(partition, pending)| {
+                let offset = pending.advance()?;
+                Some(Watermark {
+                    partition: *partition,
+                    offset,
+                })
+            })
+            .collect();
+        if !watermarks.is_empty() {
+            (self.commit)(watermarks).await;
+        }
+    }
+}
+
+#[async_trait]
+impl Actor for AckBatcher {
+    async fn started(&mut self, ctx: &mut ActorContext<Self>) {
+        let interval = self.interval;
+        // Unlike a stream, it does not keep the batcher alive
+        let batcher = ctx.weak_address();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                match batcher.upgrade() {
+                    Some(batcher) => batcher.do_send(Flush),
+                    None => break,
+                }
+            }
+        });
+    }
+    async fn stopped(&mut self, _ctx: &mut ActorContext<Self>) {
+        self.flush().await;
+    }
+}
+
+impl Addr<AckBatcher> {
+    /// Starts tracking the message with the given offset, returning its' token.
+    ///
+    /// Offsets of a partition need to be tracked in the order in which the source delivers them.
+    pub fn track(&self, partition: u32, offset: u64) -> AckToken {
+        self.do_send(Track(Watermark { partition, offset }));
+        AckToken {
+            batcher: self.clone(),
+            watermark: Watermark { partition, offset },
+            settled: false,
+        }
+    }
+}
+
+/// Acknowledgement of a single message, see [crate::ack]
+///
+/// Dropping it without calling [AckToken::ack] or [AckToken::nack] nacks the message,
+/// so that it does not hold the watermark of its' partition back forever.
+#[derive(Debug)]
+pub struct AckToken {
+    batcher: Addr<AckBatcher>,
+    watermark: Watermark,
+    /// Whether it has been acked or nacked
+    settled: bool,
+}
+
+impl AckToken {
+    /// Partition of the message
+    pub fn partition(&self) -> u32 {
+        self.watermark.partition
+    }
+    /// Offset of the message
+    pub fn offset(&self) -> u64 {
+        self.watermark.offset
+    }
+    /// Marks the message as handled
+    pub fn ack(mut self) {
+        self.settled = true;
+        self.batcher.do_send(Acked(self.watermark));
+    }
+    /// Marks the message as failed, handing it to the closure of [AckBatcher::with_nack]
+    pub fn nack(mut self) {
+        self.settled = true;
+        self.batcher.do_send(Nacked(self.watermark));
+    }
+}
+
+impl Drop for AckToken {
+    fn drop(&mut self) {
+        if !self.settled {
+            self.batcher.do_send(Nacked(self.watermark));
+        }
+    }
+}
+
+#[doc(hidden)]
+pub struct Track(Watermark);
+
+#[async_trait]
+impl Handler<Track> for AckBatcher {
+    type Response = ();
+    async fn handle(&mut self, msg: Track, _ctx: &mut ActorContext<Self>) {
+        let partition = self.partitions.entry(msg.0.partition).or_default();
+        partition.pending.insert(msg.0.offset, false);
+    }
+}
+
+#[doc(hidden)]
+pub struct Acked(Watermark);
+
+#[async_trait]
+impl Handler<Acked> for AckBatcher {
+    type Response = ();
+    async fn handle(&mut self, msg: Acked, _ctx: &mut ActorContext<Self>) {
+        let acked = self
+            .partitions
+            .get_mut(&msg.0.partition)
+            .and_then(|partition| partition.pending.get_mut(&msg.0.offset));
+        if let Some(acked) = acked {
+            *acked = true;
+            self.acked += 1;
+            if self.acked >= self.batch {
+                self.flush().await;
+            }
+        }
+    }
+}
+
+#[doc(hidden)]
+pub struct Nacked(Watermark);
+
+#[async_trait]
+impl Handler<Nacked> for AckBatcher {
+    type Response = ();
+    async fn handle(&mut self, msg: Nacked, _ctx: &mut ActorContext<Self>) {
+        let pending = self
+            .partitions
+            .get_mut(&msg.0.partition)
+            .and_then(|partition| partition.pending.get_mut(&msg.0.offset));
+        if let Some(settled) = pending {
+            // Committed past along with the rest, as it's the source's job to redeliver it
+            *settled = true;
+            (self.nack)(msg.0).await;
+        }
+    }
+}
+
+#[doc(hidden)]
+pub struct Flush;
+
+#[async_trait]
+impl Handler<Flush> for AckBatcher {
+    type Response = ();
+    async fn handle(&mut self, _msg: Flush, _ctx: &mut ActorContext<Self>) {
+        self.flush().await;
+    }
+}
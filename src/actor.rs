@@ -2,13 +2,60 @@
 
 use crate::{
     addr::*,
-    context::ActorContext,
-    message_queue::{MessageQueue, QueuePayload},
+    context::{ActorContext, ContextEvent},
+    logging::Sampling,
+    message_queue::{ActorShared, Mailbox, MessageQueue},
     runner::*,
 };
 use async_trait::async_trait;
-use std::sync::Arc;
-use tokio::sync::mpsc::UnboundedReceiver;
+use std::{
+    fmt,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// Process-wide unique identifier of an actor
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct ActorId(pub(crate) u64);
+
+impl ActorId {
+    /// Returns the numeric value of the identifier
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for ActorId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// Process-wide unique identifier of a message, used to correlate requests with their handling
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct MessageId(pub(crate) u64);
+
+impl MessageId {
+    /// Allocates a fresh identifier
+    pub(crate) fn next() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+    /// Returns the numeric value of the identifier
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for MessageId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "msg-{}", self.0)
+    }
+}
 
 /// Represents the current lifecycle state of the actor
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -30,6 +77,39 @@ pub enum Stopping {
     Stop,
 }
 
+/// What happens to the messages still waiting in the mailbox once the actor decides to stop
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Default)]
+pub enum StopMode {
+    /// The waiting messages get dropped, their senders get [crate::error::ActorError::ActorStopping]
+    #[default]
+    Abandon,
+    /// The waiting messages get handled before [Actor::stopped] is called,
+    /// for at most `deadline` (if given), after which the rest gets abandoned.
+    /// The handler running when the deadline passes gets to finish.
+    /// 
+    /// New messages are rejected while draining.
+    Drain { deadline: Option<Duration> },
+}
+
+/// When the actor becomes ready, and what happens to the messages sent to it before
+///
+/// Readiness is reached once: [crate::supervised::Supervised] actors stay ready when restarted.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Default)]
+pub enum Readiness {
+    /// The actor is ready as soon as [Actor::started] completes
+    #[default]
+    Started,
+    /// The actor is ready once it calls [ActorContext::set_ready] (e.g. after warming up its' caches),
+    /// the messages sent before get handled as usual
+    Allow,
+    /// Like [Readiness::Allow], but the messages sent before
+    /// wait aside until the actor becomes ready, then get enqueued in their' order
+    Buffer,
+    /// Like [Readiness::Allow], but the messages sent before get rejected with
+    /// [crate::error::ActorError::NotReady]
+    Reject,
+}
+
 /// Inner implementation of actor creation logic
 pub(crate) fn actor_create_impl<A: Actor, F: FnOnce(&mut ActorContext<A>) -> A + Send>(
     f: F,
@@ -37,29 +117,56 @@ pub(crate) fn actor_create_impl<A: Actor, F: FnOnce(&mut ActorContext<A>) -> A +
     A,
     Addr<A>,
     ActorContext<A>,
-    UnboundedReceiver<QueuePayload<A>>,
+    Mailbox<A>,
+) {
+    let (ret, mut ctx, msg_rx) = addr_create_impl();
+    let actor = f(&mut ctx);
+    (actor, ret, ctx, msg_rx)
+}
+
+/// Creates the address and context for a new actor
+pub(crate) fn addr_create_impl<A: Actor>() -> (
+    Addr<A>,
+    ActorContext<A>,
+    Mailbox<A>,
 ) {
-    let (msg_queue, msg_rx) = MessageQueue::new();
+    let shared = ActorShared::new::<A>();
+    let (msg_queue, msg_rx) = MessageQueue::new(shared.clone());
     let ret = Addr::<A> {
         msg_queue: Arc::from(msg_queue),
     };
-    let weakaddr = ret.downgrade();
-    let mut ctx = ActorContext::new(weakaddr);
-    let actor = f(&mut ctx);
-    (actor, ret, ctx, msg_rx)
+    let ctx = ActorContext::new(ret.downgrade(), shared);
+    (ret, ctx, msg_rx)
 }
 
 /// The actor trait
 #[async_trait]
 pub trait Actor: 'static + Sized + Send {
+    /// Maximum number of messages waiting in the actor's mailbox.
+    /// 
+    /// When the mailbox is full, [Addr::send] and [Addr::try_send] fail with [crate::error::ActorError::MailboxFull],
+    /// while messages sent via [Addr::do_send] get dropped.
+    /// `None` means that the mailbox is unbounded.
+    const MAILBOX_CAPACITY: Option<usize> = None;
+    /// Maximum approximate size (in bytes) of the messages waiting in the actor's mailbox,
+    /// behaving like [Actor::MAILBOX_CAPACITY] once exceeded.
+    /// 
+    /// Sizes get reported by [Handler::message_size], so that a few huge messages cannot exhaust the memory.
+    /// A message exceeding the budget on its' own is still accepted into an empty mailbox.
+    /// `None` means no limit.
+    const MAILBOX_BYTES: Option<usize> = None;
+    /// Maximum number of messages handled concurrently via [ReadHandler]
+    const MAX_CONCURRENT_READS: usize = 64;
+    /// When the actor becomes ready to handle messages, see [Addr::ready].
+    /// 
+    /// The messages the actor sends itself via [ActorContext] are not affected by it.
+    const READINESS: Readiness = Readiness::Started;
+    /// Which of the handled messages get traced, see [crate::logging#message-traces]
+    const TRACE_SAMPLING: Sampling = Sampling::ALL;
     /// Starts the actor, consuming the underlying structure and returning an address to it.
     fn start(self) -> Addr<Self> {
-        let (msg_queue, msg_rx) = MessageQueue::new();
-        let ret = Addr::<Self> {
-            msg_queue: Arc::from(msg_queue),
-        };
-        let weakaddr = ret.downgrade();
-        tokio::spawn(actor_runner_loop(self, ActorContext::new(weakaddr), msg_rx));
+        let (ret, ctx, msg_rx) = addr_create_impl();
+        tokio::spawn(actor_runner_loop(self, ctx, msg_rx));
         ret
     }
     /// Uses the given closure to build and start the actor, returning its' address.
@@ -70,6 +177,45 @@ pub trait Actor: 'static + Sized + Send {
         tokio::spawn(actor_runner_loop(actor, ctx, msg_rx));
         ret
     }
+    /// Uses the given asynchronous factory to build and start the actor, returning its' address right away.
+    /// 
+    /// The factory gets awaited inside the actor's task, so it can perform asynchronous initialization
+    /// (like connecting to a database). Messages sent in the meantime wait in the mailbox.
+    /// 
+    /// Unlike with [Actor::create], the factory receives a context it can keep for the duration of the future,
+    /// which shares the state and address with the one passed to handlers.
+    fn create_async<F, Fut>(f: F) -> Addr<Self>
+    where
+        F: 'static + FnOnce(ActorContext<Self>) -> Fut + Send,
+        Fut: Future<Output = Self> + Send,
+    {
+        let (ret, ctx, msg_rx) = addr_create_impl();
+        tokio::spawn(async move {
+            let actor = f(ctx.fork()).await;
+            actor_runner_loop(actor, ctx, msg_rx).await
+        });
+        ret
+    }
+    /// Starts the actor as a pool of `workers` clones, handling up to `workers` messages concurrently.
+    /// 
+    /// The clones share the returned address and do not share state, which suits stateless actors.
+    /// [Actor::started] is called before cloning, while [Actor::stopping] and [Actor::stopped]
+    /// are called on a single clone, once all the others are idle.
+    /// 
+    /// Like any other actor, the clones cannot [Addr::send] messages to the pool they belong to.
+    /// 
+    /// # Panics
+    /// 
+    /// Panics if `workers` is zero.
+    fn start_workers(self, workers: usize) -> Addr<Self>
+    where
+        Self: Clone,
+    {
+        assert!(workers > 0, "A worker pool needs at least one worker");
+        let (ret, ctx, msg_rx) = addr_create_impl();
+        tokio::spawn(worker_pool_runner_loop(self, ctx, msg_rx, workers));
+        ret
+    }
     /// Called when the actor is about to begin processing messages.
     async fn started(&mut self, _ctx: &mut ActorContext<Self>) {}
     /// Called when the actor is in stopping state.
@@ -80,6 +226,11 @@ pub trait Actor: 'static + Sized + Send {
     }
     /// Called when the actors stops.
     async fn stopped(&mut self, _ctx: &mut ActorContext<Self>) {}
+    /// Called with notifications about the plumbing of the actor set up via its' context,
+    /// like streams ending or timers firing, see [ContextEvent].
+    /// 
+    /// The events are delivered in the mailbox, like messages the actor sends to itself.
+    async fn context_event(&mut self, _ctx: &mut ActorContext<Self>, _event: ContextEvent) {}
 }
 
 /// Trait implemented on [Actor]s to enable them to process messages of a given type
@@ -88,5 +239,40 @@ pub trait Handler<T: Send>: Actor {
     /// Type used to respond to incoming messages
     type Response: Send + 'static;
     /// The method used to handle incoming messages
+    /// 
+    /// If it panics, the actor enters [ActorState::Stopping] state 
+    /// and the sender gets [crate::error::ActorError::HandlerPanicked].
     async fn handle(&mut self, msg: T, ctx: &mut ActorContext<Self>) -> Self::Response;
+    /// Approximate size of the message in bytes, counted against [Actor::MAILBOX_BYTES].
+    /// 
+    /// Defaults to the size of the message itself, it should be overriden
+    /// to account for heap allocated payloads, like the contents of a [Vec].
+    fn message_size(_msg: &T) -> usize {
+        std::mem::size_of::<T>()
+    }
+    /// Overrides [Actor::TRACE_SAMPLING] for the messages of this type
+    const MESSAGE_SAMPLING: Option<Sampling> = None;
+}
+
+/// Trait implemented on [Actor]s to enable them to process messages of a given type
+/// without modifying their state
+///
+/// Such messages are sent via [Addr::send_read].
+/// Read-only messages waiting next to each other in the mailbox get handled concurrently
+/// (up to [Actor::MAX_CONCURRENT_READS] of them), while all other messages keep exclusive access to the actor.
+#[async_trait]
+pub trait ReadHandler<T: Send>: Actor + Sync {
+    /// Type used to respond to incoming messages
+    type Response: Send + 'static;
+    /// The method used to handle incoming messages
+    ///
+    /// As many messages can be handled at once, the context does not carry
+    /// per-message information, like [ActorContext::current_message_id].
+    async fn handle_read(&self, msg: T, ctx: &ActorContext<Self>) -> Self::Response;
+    /// Approximate size of the message in bytes, see [Handler::message_size]
+    fn message_size(_msg: &T) -> usize {
+        std::mem::size_of::<T>()
+    }
+    /// Overrides [Actor::TRACE_SAMPLING] for the messages of this type
+    const MESSAGE_SAMPLING: Option<Sampling> = None;
 }
@@ -0,0 +1,281 @@
+//! Futures and streams continuing on the actor's task
+//!
+//! Handlers often need to await something (a query, a request to another actor) and then update
+//! the state of the actor with the result. Awaiting it within the handler keeps the actor busy
+//! in the meantime, while spawning a task requires sending the result back in a message
+//! of its' own.
+//!
+//! An [ActorFuture] gets awaited off the actor's task, like a spawned task, but its' continuations
+//! ([ActorFuture::map], [ActorFuture::then]) get access to the actor and its' context:
+//! they run on the actor's task, between the messages, once the future resolves.
+//! Futures and streams get wrapped via [WrapFuture::into_actor] and [WrapStream::into_actor],
+//! then handed to [ActorContext::spawn]. [WrapFuture::then_actor] does both the wrapping
+//! and the continuation at once, which covers the common case of storing the result in the actor.
+//!
+//! Spawned futures do not keep the actor alive: they get dropped once it's gone.
+//! Other messages get handled in between the continuations, unless the future gets
+//! [run atomically](ActorFuture::run_atomic) by the handler instead.
+
+use crate::{actor::Actor, addr::WeakAddr, context::ActorContext};
+use futures_util::{
+    future::{select, BoxFuture, Either, FutureExt},
+    stream::{Stream, StreamExt},
+};
+use std::{fmt, future::Future, marker::PhantomData, pin::pin};
+
+/// Continuation of an [ActorFuture], run on the actor's task
+pub(crate) type Step<A, T = ()> =
+    Box<dyn FnOnce(&mut A, &mut ActorContext<A>) -> ActorFuture<A, T> + Send>;
+
+type Continuation<A, T, U> =
+    Box<dyn FnOnce(T, &mut A, &mut ActorContext<A>) -> ActorFuture<A, U> + Send>;
+
+pub(crate) enum Inner<A: Actor, T> {
+    /// Resolved
+    Ready(T),
+    /// Awaited off the actor's task
+    Future(BoxFuture<'static, ActorFuture<A, T>>),
+    /// Waiting for its' turn on the actor's task
+    OnActor(Step<A, T>),
+}
+
+/// Future whose continuations get access to the actor, see [crate::actor_future]
+#[must_use = "ActorFutures do nothing unless spawned via ActorContext::spawn"]
+pub struct ActorFuture<A: Actor, T> {
+    pub(crate) inner: Inner<A, T>,
+}
+
+impl<A: Actor, T> fmt::Debug for ActorFuture<A, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self.inner {
+            Inner::Ready(_) => "Ready",
+            Inner::Future(_) => "Future",
+            Inner::OnActor(_) => "OnActor",
+        };
+        f.debug_struct("ActorFuture")
+            .field("state", &state)
+            .finish()
+    }
+}
+
+impl<A, T> ActorFuture<A, T>
+where
+    A: Actor,
+    T: 'static + Send,
+{
+    /// Creates an already resolved future
+    pub fn ready(value: T) -> Self {
+        Self {
+            inner: Inner::Ready(value),
+        }
+    }
+    /// Wraps a future, see [WrapFuture::into_actor]
+    pub fn new<F>(future: F) -> Self
+    where
+        F: 'static + Future<Output = T> + Send,
+    {
+        Self {
+            inner: Inner::Future(future.map(Self::ready).boxed()),
+        }
+    }
+    /// Runs the closure on the actor's task once the future resolves
+    pub fn map<F, U>(self, f: F) -> ActorFuture<A, U>
+    where
+        F: 'static + FnOnce(T, &mut A, &mut ActorContext<A>) -> U + Send,
+        U: 'static + Send,
+    {
+        self.chain(Box::new(move |value, act, ctx| {
+            ActorFuture::ready(f(value, act, ctx))
+        }))
+    }
+    /// Runs the closure on the actor's task once the future resolves,
+    /// then awaits the future it returns off the actor's task
+    pub fn then<F, Fut>(self, f: F) -> ActorFuture<A, Fut::Output>
+    where
+        F: 'static + FnOnce(T, &mut A, &mut ActorContext<A>) -> Fut + Send,
+        Fut: 'static + Future + Send,
+        Fut::Output: 'static + Send,
+    {
+        self.chain(Box::new(move |value, act, ctx| {
+            ActorFuture::new(f(value, act, ctx))
+        }))
+    }
+    /// Like [ActorFuture::then], continuing with another [ActorFuture]
+    pub fn and_then<F, U>(self, f: F) -> ActorFuture<A, U>
+    where
+        F: 'static + FnOnce(T, &mut A, &mut ActorContext<A>) -> ActorFuture<A, U> + Send,
+        U: 'static + Send,
+    {
+        self.chain(Box::new(f))
+    }
+    /// Runs the future along with its' continuations from within a handler, keeping exclusive access
+    /// to the actor until it resolves.
+    ///
+    /// Unlike with [ActorContext::spawn], no other messages get handled in the meantime,
+    /// so invariants spanning several awaits (e.g. reserving, then committing) cannot be observed
+    /// half-way by other messages.
+    pub async fn run_atomic(self, act: &mut A, ctx: &mut ActorContext<A>) -> T {
+        let mut next = self;
+        loop {
+            next = match next.inner {
+                Inner::Ready(value) => return value,
+                Inner::Future(future) => future.await,
+                Inner::OnActor(step) => step(act, ctx),
+            };
+        }
+    }
+    fn chain<U: 'static + Send>(self, f: Continuation<A, T, U>) -> ActorFuture<A, U> {
+        let inner = match self.inner {
+            Inner::Ready(value) => Inner::OnActor(Box::new(move |act, ctx| f(value, act, ctx))),
+            Inner::Future(future) => Inner::Future(future.map(|next| next.chain(f)).boxed()),
+            // Continuations which are ready get run right away, while the actor is at hand
+            Inner::OnActor(step) => {
+                Inner::OnActor(Box::new(move |act, ctx| match step(act, ctx).inner {
+                    Inner::Ready(value) => f(value, act, ctx),
+                    inner => ActorFuture { inner }.chain(f),
+                }))
+            }
+        };
+        ActorFuture { inner }
+    }
+}
+
+/// Awaits the future in a task of its' own, handing the continuations over to the actor
+pub(crate) fn drive<A: Actor>(future: ActorFuture<A, ()>, address: WeakAddr<A>) {
+    match future.inner {
+        Inner::Ready(()) => (),
+        Inner::Future(future) => {
+            let Some(shared) = address.upgrade().map(|addr| addr.msg_queue.shared().clone()) else {
+                return;
+            };
+            tokio::spawn(async move {
+                // Resolving does not require the actor, so it does not have to stay alive meanwhile,
+                // but the future gets dropped once it's gone
+                let terminated = shared.terminated();
+                let next = match select(pin!(future), pin!(terminated)).await {
+                    Either::Left((next, _)) => next,
+                    Either::Right(_) => return,
+                };
+                if address.upgrade().is_some() {
+                    drive(next, address);
+                }
+            });
+        }
+        Inner::OnActor(step) => {
+            if let Some(addr) = address.upgrade() {
+                addr.msg_queue.continuation(step);
+            }
+        }
+    }
+}
+
+/// Either the actor or its' context, telling [WrapFuture::into_actor] and [WrapStream::into_actor]
+/// which actor the continuations get access to
+pub trait ActorScope<A: Actor> {}
+
+impl<A: Actor> ActorScope<A> for A {}
+
+impl<A: Actor> ActorScope<A> for ActorContext<A> {}
+
+/// Extension trait wrapping futures into [ActorFuture]s
+pub trait WrapFuture: Future + Sized + Send + 'static
+where
+    Self::Output: Send + 'static,
+{
+    /// Wraps the future, so that its' continuations get access to the actor
+    /// (given either as itself or as its' context)
+    fn into_actor<A: Actor>(self, _scope: &impl ActorScope<A>) -> ActorFuture<A, Self::Output> {
+        ActorFuture::new(self)
+    }
+    /// Shorthand for `into_actor(scope).map(f)`: runs the closure on the actor's task
+    /// once the future resolves
+    fn then_actor<A, F, U>(self, scope: &impl ActorScope<A>, f: F) -> ActorFuture<A, U>
+    where
+        A: Actor,
+        F: 'static + FnOnce(Self::Output, &mut A, &mut ActorContext<A>) -> U + Send,
+        U: 'static + Send,
+    {
+        self.into_actor(scope).map(f)
+    }
+}
+
+impl<F> WrapFuture for F
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+}
+
+/// Stream whose items get handled with access to the actor, see [crate::actor_future]
+#[must_use = "ActorStreams do nothing unless turned into an ActorFuture and spawned"]
+pub struct ActorStream<A, S> {
+    stream: S,
+    _actor: PhantomData<fn(&mut A)>,
+}
+
+impl<A, S> fmt::Debug for ActorStream<A, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ActorStream").finish_non_exhaustive()
+    }
+}
+
+impl<A, S> ActorStream<A, S>
+where
+    A: Actor,
+    S: 'static + Stream + Unpin + Send,
+    S::Item: Send,
+{
+    /// Runs the closure on the actor's task for each of the items.
+    ///
+    /// The next item is taken from the stream once the previous one has been handled.
+    /// The returned future resolves once the stream ends.
+    pub fn for_each<F>(self, mut f: F) -> ActorFuture<A, ()>
+    where
+        F: 'static + FnMut(S::Item, &mut A, &mut ActorContext<A>) + Send,
+    {
+        self.fold((), move |(), item, act, ctx| f(item, act, ctx))
+    }
+    /// Folds the items into an accumulator on the actor's task, resolving to it once the stream ends
+    pub fn fold<B, F>(self, init: B, f: F) -> ActorFuture<A, B>
+    where
+        B: 'static + Send,
+        F: 'static + FnMut(B, S::Item, &mut A, &mut ActorContext<A>) -> B + Send,
+    {
+        fold(self.stream, init, f)
+    }
+}
+
+fn fold<A, S, B, F>(mut stream: S, acc: B, mut f: F) -> ActorFuture<A, B>
+where
+    A: Actor,
+    S: 'static + Stream + Unpin + Send,
+    S::Item: Send,
+    B: 'static + Send,
+    F: 'static + FnMut(B, S::Item, &mut A, &mut ActorContext<A>) -> B + Send,
+{
+    let next = async move {
+        let item = stream.next().await;
+        (item, stream)
+    };
+    ActorFuture::new(next).and_then(move |(item, stream), act, ctx| match item {
+        Some(item) => {
+            let acc = f(acc, item, act, ctx);
+            fold(stream, acc, f)
+        }
+        None => ActorFuture::ready(acc),
+    })
+}
+
+/// Extension trait wrapping streams into [ActorStream]s
+pub trait WrapStream: Stream + Sized {
+    /// Wraps the stream, so that its' items get handled with access to the actor
+    /// (given either as itself or as its' context)
+    fn into_actor<A: Actor>(self, _scope: &impl ActorScope<A>) -> ActorStream<A, Self> {
+        ActorStream {
+            stream: self,
+            _actor: PhantomData,
+        }
+    }
+}
+
+impl<S: Stream> WrapStream for S {}
@@ -1,11 +1,25 @@
 //! Actor addresses
 
 use crate::{
-    actor::{Actor, Handler},
+    actor::{Actor, ActorId, ActorState, Handler, MessageId, ReadHandler, StopMode},
+    demand::{demand_stream, Demand, DemandStream},
     error::*,
+    health::{Health, Ping},
+    histogram::Latency,
     message_queue::MessageQueue,
+    supervised::RestartReason,
 };
-use std::sync::{Arc, Weak};
+use futures_util::future::BoxFuture;
+use std::{
+    any::Any,
+    fmt,
+    future::Future,
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+/// Response to a message of type `M` sent to an actor of type `T`, or the reason it did not arrive
+type HandlerResult<T, M> = Result<<T as Handler<M>>::Response, ActorError>;
 
 /// Address of an actor
 /// 
@@ -31,15 +45,97 @@ impl<T: Actor> Addr<T> {
     /// 
     /// This function will fail if the actor is unable to process the message.
     /// 
-    /// This function should not be used by actor to send messages to themselves, as it will result in a deadlock.
-    /// [crate::context::ActorContext::notify] should be used for that purpose.
+    /// Dropping the returned future before it completes cancels the request:
+    /// the message won't be handled if the actor has not started handling it yet,
+    /// otherwise the handler gets notified via [crate::context::ActorContext::request_cancellation].
+    /// 
+    /// This function cannot be used by actors to send messages to themselves, as it would result in a deadlock.
+    /// Such calls fail with [ActorError::Reentrant], [crate::context::ActorContext::notify] should be used instead.
+    /// See [crate::deadlock] for detecting other deadlocks.
     pub async fn send<M>(&self, msg: M) -> Result<<T as Handler<M>>::Response, ActorError>
     where
         M: 'static + Send,
         T: Handler<M>,
     {
-        let resp = self.msg_queue.send(msg)?;
-        Ok(resp.await?)
+        self.request(msg)?.await
+    }
+    /// Enqueues the message right away, returning the future awaiting its' response, see [Addr::send]
+    pub(crate) fn request<M>(
+        &self,
+        msg: M,
+    ) -> Result<impl Future<Output = Result<<T as Handler<M>>::Response, ActorError>>, ActorError>
+    where
+        M: 'static + Send,
+        T: Handler<M>,
+    {
+        let (_, response) = self.send_identified(msg)?;
+        Ok(response)
+    }
+    /// Behaves like [Addr::send], except that the message gets enqueued right away, before the returned
+    /// future gets awaited, and that its' identifier gets returned along with the future.
+    ///
+    /// It's the identifier the handler gets from [crate::context::ActorContext::current_message_id]
+    /// and the [crate::dead_letters::DeadLetter]s of the message refer to,
+    /// so that the caller can correlate them with the request.
+    pub fn send_identified<M>(
+        &self,
+        msg: M,
+    ) -> Result<(MessageId, impl Future<Output = HandlerResult<T, M>>), ActorError>
+    where
+        M: 'static + Send,
+        T: Handler<M>,
+    {
+        self.check_reentrancy::<M>()?;
+        let (id, resp, token) = self.msg_queue.send(msg, true)?;
+        let msg_queue = self.msg_queue.clone();
+        let response = async move {
+            let _wait = crate::deadlock::begin_wait(msg_queue.shared().id());
+            let guard = token.drop_guard();
+            let resp = resp.await;
+            guard.disarm();
+            resp.unwrap_or_else(|_| Err(msg_queue.lost_error::<M>()))
+        };
+        Ok((id, response))
+    }
+    /// Sends a read-only message to the actor and asynchronously waits for its' response.
+    /// 
+    /// Unlike with [Addr::send], the message may be handled concurrently with other read-only messages.
+    /// See [ReadHandler].
+    pub async fn send_read<M>(&self, msg: M) -> Result<<T as ReadHandler<M>>::Response, ActorError>
+    where
+        M: 'static + Send,
+        T: ReadHandler<M>,
+    {
+        self.check_reentrancy::<M>()?;
+        let resp = self.msg_queue.send_read(msg)?;
+        let _wait = crate::deadlock::begin_wait(self.id());
+        resp.await
+            .unwrap_or_else(|_| Err(self.msg_queue.lost_error::<M>()))
+    }
+    /// Fails if called from within the actor's own task, where waiting for a response would deadlock
+    fn check_reentrancy<M>(&self) -> Result<(), ActorError> {
+        if crate::deadlock::current_actor() == Some(self.id()) {
+            return Err(ActorError::Reentrant(self.msg_queue.error_context::<M>()));
+        }
+        Ok(())
+    }
+    /// Behaves like [Addr::send], but fails with [ActorError::Timeout]
+    /// if the response does not arrive within the given time.
+    /// 
+    /// The request gets cancelled on timeout.
+    pub async fn send_timeout<M>(
+        &self,
+        msg: M,
+        timeout: Duration,
+    ) -> Result<<T as Handler<M>>::Response, ActorError>
+    where
+        M: 'static + Send,
+        T: Handler<M>,
+    {
+        match tokio::time::timeout(timeout, self.send(msg)).await {
+            Ok(resp) => resp,
+            Err(_) => Err(ActorError::Timeout(self.msg_queue.error_context::<M>())),
+        }
     }
     /// Sends a message to the actor without waiting for response, ignoring all errors.
     pub fn do_send<M>(&self, msg: M)
@@ -47,7 +143,20 @@ impl<T: Actor> Addr<T> {
         M: 'static + Send,
         T: Handler<M>,
     {
-        self.msg_queue.do_send(msg)
+        self.msg_queue.do_send(msg, true);
+    }
+    /// Sends a batch of messages to the actor without waiting for responses, ignoring all errors.
+    ///
+    /// It is considerably cheaper than calling [Addr::do_send] for every message,
+    /// which matters when seeding actors with large datasets.
+    /// The messages which do not fit in the mailbox get dropped.
+    pub fn do_send_many<M, I>(&self, msgs: I)
+    where
+        M: 'static + Send,
+        T: Handler<M>,
+        I: IntoIterator<Item = M>,
+    {
+        self.msg_queue.do_send_many(msgs)
     }
     /// Sends a message to the actor without waiting for response.
     /// Fails if the message cannot be enqueued.
@@ -58,12 +167,148 @@ impl<T: Actor> Addr<T> {
     {
         self.msg_queue.try_send(msg)
     }
+    /// Makes the actor enter [crate::actor::ActorState::Stopping] state once it's done with the current message,
+    /// as if it called [crate::context::ActorContext::stop_with] itself.
+    pub fn stop(&self, mode: StopMode) {
+        self.msg_queue.stop(mode)
+    }
+    /// Makes the actor stop for good: unlike with [Addr::stop],
+    /// a [crate::supervised::Supervised] actor does not get restarted, whatever its' [crate::supervised::Restart].
+    /// 
+    /// It's what actors managing others (like supervisors or a [crate::shutdown::ShutdownCoordinator]) use.
+    pub fn shutdown(&self, mode: StopMode) {
+        self.msg_queue.shutdown(mode)
+    }
+    /// Checks whether the actor is alive and responsive, see [crate::health].
+    /// 
+    /// Fails with [ActorError::Timeout] if the actor does not respond within the given time.
+    pub async fn ping(&self, timeout: Duration) -> Result<Health, ActorError> {
+        let resp = self.msg_queue.ping()?;
+        match tokio::time::timeout(timeout, resp).await {
+            Ok(Ok(health)) => Ok(health),
+            Ok(Err(_)) => Err(self.msg_queue.lost_error::<Ping>()),
+            Err(_) => Err(ActorError::Timeout(self.msg_queue.error_context::<Ping>())),
+        }
+    }
+    /// Returns the identifier of the actor
+    pub fn id(&self) -> ActorId {
+        self.msg_queue.shared().id()
+    }
+    /// Returns the current lifecycle state of the actor, or `None` if it's no longer [Addr::connected].
+    /// 
+    /// It's cheap, as the state is shared between the actor and its' addresses.
+    pub fn state(&self) -> Option<ActorState> {
+        self.connected().then(|| self.msg_queue.shared().state())
+    }
+    /// Returns `true` unless the actor has stopped for good
+    /// (a stopped [crate::supervised::Supervised] actor is about to restart).
+    /// 
+    /// Messages can still fail to be handled if the actor stops in the meantime.
+    pub fn connected(&self) -> bool {
+        !self.msg_queue.is_closed()
+    }
+    /// Returns `true` once the actor has become ready to handle messages, see [Actor::READINESS]
+    pub fn is_ready(&self) -> bool {
+        self.msg_queue.shared().is_ready()
+    }
+    /// Waits until the actor becomes ready to handle messages, see [Actor::READINESS].
+    /// 
+    /// Returns `false` if the actor terminates before.
+    pub async fn ready(&self) -> bool {
+        self.msg_queue.shared().ready().await
+    }
+    /// Waits until the actor stops for good, returning why it stopped.
+    /// 
+    /// [crate::supervised::Supervised] actors restart instead of stopping, as long as they have addresses
+    /// and their' [crate::supervised::Supervised::RESTART] says so.
+    pub async fn terminated(&self) -> RestartReason {
+        self.msg_queue.shared().terminated().await
+    }
+    /// Returns the capacity of the actor's mailbox, `None` meaning no limit
+    pub fn mailbox_capacity(&self) -> Option<usize> {
+        self.msg_queue.shared().capacity()
+    }
+    /// Returns how long ago the message the actor is handling got enqueued, zero while it's idle.
+    ///
+    /// Unlike the length of the queue, it tells how far behind the actor is: a growing lag means
+    /// the messages arrive faster than they get handled. As the messages waiting in the mailbox
+    /// arrived after the one being handled, it grows along with the age of the oldest of them,
+    /// even while the actor is stuck in a single handler. With messages handled concurrently,
+    /// it's the age of the latest one of them.
+    pub fn mailbox_lag(&self) -> Duration {
+        self.msg_queue.shared().mailbox_lag()
+    }
+    /// Returns the histograms of how long the actor's handlers took
+    /// and how long the messages waited in its' mailbox, see [crate::histogram]
+    pub fn latency(&self) -> Latency {
+        self.msg_queue.shared().latency()
+    }
+    /// Changes the capacity of the actor's mailbox at runtime, overriding [Actor::MAILBOX_CAPACITY]
+    /// until changed again (restarts of [crate::supervised::Supervised] actors included).
+    /// 
+    /// Tightening it does not drop the messages already waiting in the mailbox,
+    /// new messages just get rejected until it drains below the new capacity.
+    pub fn set_mailbox_capacity(&self, capacity: Option<usize>) {
+        self.msg_queue.shared().set_capacity(capacity)
+    }
+    /// Mutes messages of type `M` from the outside, see [crate::context::ActorContext::mute]
+    pub fn mute<M>(&self) -> bool
+    where
+        M: 'static + Send,
+        T: Handler<M>,
+    {
+        self.msg_queue.shared().mute::<M>()
+    }
+    /// See [crate::context::ActorContext::unmute]
+    pub fn unmute<M>(&self) -> bool
+    where
+        M: 'static + Send,
+        T: Handler<M>,
+    {
+        self.msg_queue.shared().unmute::<M>()
+    }
+    /// Returns the number of messages waiting in the actor's mailbox
+    pub(crate) fn queue_depth(&self) -> usize {
+        self.msg_queue.shared().depth()
+    }
+    /// Returns a [Recipient] for messages of type `M`, hiding the type of the actor.
+    pub fn recipient<M>(&self) -> Recipient<M, <T as Handler<M>>::Response>
+    where
+        M: 'static + Send,
+        T: Handler<M>,
+    {
+        Recipient {
+            inner: Arc::new(self.clone()),
+        }
+    }
+    /// Returns an [AnyAddr], hiding the type of the actor.
+    pub fn any(&self) -> AnyAddr {
+        AnyAddr {
+            inner: Arc::new(self.clone()),
+        }
+    }
+    /// Turns the actor into a [DemandStream] of items of type `M`, produced in response to [Demand] messages.
+    ///
+    /// The stream asks for `batch` items at a time, once it has received the ones asked for before.
+    /// See [crate::demand].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `batch` is zero.
+    pub fn into_stream<M>(self, batch: usize) -> DemandStream<M>
+    where
+        M: 'static + Send,
+        T: Handler<Demand<M>>,
+    {
+        demand_stream(self, batch)
+    }
     /// Returns a non-owning version of the address.
     /// 
     /// It can be used to prevent memory leaks resulting from circular references.
     pub fn downgrade(&self) -> WeakAddr<T> {
         WeakAddr::<T> {
             msg_queue: Arc::downgrade(&self.msg_queue),
+            id: self.id(),
         }
     }
 }
@@ -77,11 +322,14 @@ impl<T: Actor> Addr<T> {
 #[derive(Debug)]
 pub struct WeakAddr<T: Actor> {
     msg_queue: Weak<MessageQueue<T>>,
+    /// Kept for error reporting once the actor is gone
+    id: ActorId,
 }
 impl<T: Actor> Clone for WeakAddr<T> {
     fn clone(&self) -> Self {
         Self {
             msg_queue: Weak::clone(&self.msg_queue),
+            id: self.id,
         }
     }
 }
@@ -94,4 +342,341 @@ impl<T: Actor> WeakAddr<T> {
             msg_queue: self.msg_queue.upgrade()?,
         })
     }
+    /// Returns the current lifecycle state of the actor, see [Addr::state]
+    pub fn state(&self) -> Option<ActorState> {
+        self.upgrade()?.state()
+    }
+    /// Returns `true` if the actor still exists and has not stopped for good, see [Addr::connected]
+    pub fn connected(&self) -> bool {
+        self.upgrade().is_some_and(|addr| addr.connected())
+    }
+    /// Returns the identifier of the actor
+    pub fn id(&self) -> ActorId {
+        self.id
+    }
+    /// Upgrades the address, failing with [ActorError::CannotSend] if the actor is gone
+    fn upgrade_for<M>(&self) -> Result<Addr<T>, ActorError> {
+        self.upgrade()
+            .ok_or_else(|| ActorError::CannotSend(ErrorContext::new::<T, M>(self.id)))
+    }
+    /// Behaves like [Addr::send], failing with [ActorError::CannotSend] if the actor is gone.
+    /// 
+    /// The actor is kept alive until the response arrives.
+    pub async fn send<M>(&self, msg: M) -> Result<<T as Handler<M>>::Response, ActorError>
+    where
+        M: 'static + Send,
+        T: Handler<M>,
+    {
+        self.upgrade_for::<M>()?.send(msg).await
+    }
+    /// Behaves like [Addr::do_send], doing nothing if the actor is gone.
+    pub fn do_send<M>(&self, msg: M)
+    where
+        M: 'static + Send,
+        T: Handler<M>,
+    {
+        if let Some(addr) = self.upgrade() {
+            addr.do_send(msg)
+        }
+    }
+    /// Behaves like [Addr::try_send], failing with [ActorError::CannotSend] if the actor is gone.
+    pub fn try_send<M>(&self, msg: M) -> Result<(), ActorError>
+    where
+        M: 'static + Send,
+        T: Handler<M>,
+    {
+        self.upgrade_for::<M>()?.try_send(msg)
+    }
+}
+
+/// Type-erased operations of [Addr] for a single message type
+pub(crate) trait RecipientProxy<M, R>: Send + Sync {
+    fn send(&self, msg: M) -> BoxFuture<'static, Result<R, ActorError>>;
+    fn try_send(&self, msg: M) -> Result<(), ActorError>;
+    fn do_send(&self, msg: M);
+    fn id(&self) -> ActorId;
+    fn queue_depth(&self) -> usize;
+    fn error_context(&self) -> ErrorContext;
+}
+
+impl<T, M> RecipientProxy<M, <T as Handler<M>>::Response> for Addr<T>
+where
+    T: Handler<M>,
+    M: 'static + Send,
+{
+    fn send(&self, msg: M) -> BoxFuture<'static, Result<<T as Handler<M>>::Response, ActorError>> {
+        let addr = self.clone();
+        Box::pin(async move { addr.send(msg).await })
+    }
+    fn try_send(&self, msg: M) -> Result<(), ActorError> {
+        Addr::try_send(self, msg)
+    }
+    fn do_send(&self, msg: M) {
+        Addr::do_send(self, msg)
+    }
+    fn id(&self) -> ActorId {
+        Addr::id(self)
+    }
+    fn queue_depth(&self) -> usize {
+        Addr::queue_depth(self)
+    }
+    fn error_context(&self) -> ErrorContext {
+        self.msg_queue.error_context::<M>()
+    }
+}
+
+/// Address of an actor able to handle messages of type `M`, responding with `R`
+///
+/// Unlike [Addr], it does not depend on the type of the actor,
+/// which allows storing addresses of different kinds of actors together.
+///
+/// Created via [Addr::recipient].
+pub struct Recipient<M, R> {
+    inner: Arc<dyn RecipientProxy<M, R>>,
+}
+impl<M, R> Clone for Recipient<M, R> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+impl<M, R> fmt::Debug for Recipient<M, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Recipient")
+            .field("id", &self.inner.id())
+            .finish()
+    }
+}
+
+impl<M: 'static + Send, R: 'static + Send> Recipient<M, R> {
+    /// Sends a message to the actor and asynchronously waits for its' response.
+    ///
+    /// See [Addr::send].
+    pub async fn send(&self, msg: M) -> Result<R, ActorError> {
+        self.inner.send(msg).await
+    }
+    /// Sends a message to the actor without waiting for response, ignoring all errors.
+    pub fn do_send(&self, msg: M) {
+        self.inner.do_send(msg)
+    }
+    /// Sends a message to the actor without waiting for response.
+    /// Fails if the message cannot be enqueued.
+    pub fn try_send(&self, msg: M) -> Result<(), ActorError> {
+        self.inner.try_send(msg)
+    }
+    /// Returns the identifier of the actor
+    pub fn id(&self) -> ActorId {
+        self.inner.id()
+    }
+    /// Returns the number of messages waiting in the actor's mailbox
+    pub(crate) fn queue_depth(&self) -> usize {
+        self.inner.queue_depth()
+    }
+    /// Describes message of type `M` sent to this actor, for error reporting
+    pub(crate) fn error_context(&self) -> ErrorContext {
+        self.inner.error_context()
+    }
+}
+
+/// Type-erased lifecycle operations of [Addr]
+trait LifecycleProxy: Send + Sync {
+    fn stop(&self, mode: StopMode);
+    fn shutdown(&self, mode: StopMode);
+    fn ping(&self, timeout: Duration) -> BoxFuture<'static, Result<Health, ActorError>>;
+    fn terminated(&self) -> BoxFuture<'static, RestartReason>;
+    fn ready(&self) -> BoxFuture<'static, bool>;
+    fn abort(&self);
+    fn state(&self) -> Option<ActorState>;
+    fn connected(&self) -> bool;
+    fn mailbox_capacity(&self) -> Option<usize>;
+    fn latency(&self) -> Latency;
+    fn mailbox_lag(&self) -> Duration;
+    fn muted(&self) -> Vec<&'static str>;
+    fn unmute_all(&self) -> usize;
+    fn depend_on(&self, id: ActorId);
+    fn dependencies(&self) -> Vec<ActorId>;
+    fn set_mailbox_capacity(&self, capacity: Option<usize>);
+    fn id(&self) -> ActorId;
+    fn actor_type(&self) -> &'static str;
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<T: Actor> LifecycleProxy for Addr<T> {
+    fn stop(&self, mode: StopMode) {
+        Addr::stop(self, mode)
+    }
+    fn shutdown(&self, mode: StopMode) {
+        Addr::shutdown(self, mode)
+    }
+    fn ping(&self, timeout: Duration) -> BoxFuture<'static, Result<Health, ActorError>> {
+        let addr = self.clone();
+        Box::pin(async move { addr.ping(timeout).await })
+    }
+    fn terminated(&self) -> BoxFuture<'static, RestartReason> {
+        let shared = self.msg_queue.shared().clone();
+        Box::pin(async move { shared.terminated().await })
+    }
+    fn ready(&self) -> BoxFuture<'static, bool> {
+        let shared = self.msg_queue.shared().clone();
+        Box::pin(async move { shared.ready().await })
+    }
+    fn abort(&self) {
+        self.msg_queue.shared().abort_handlers()
+    }
+    fn state(&self) -> Option<ActorState> {
+        Addr::state(self)
+    }
+    fn connected(&self) -> bool {
+        Addr::connected(self)
+    }
+    fn latency(&self) -> Latency {
+        Addr::latency(self)
+    }
+    fn mailbox_lag(&self) -> Duration {
+        Addr::mailbox_lag(self)
+    }
+    fn mailbox_capacity(&self) -> Option<usize> {
+        Addr::mailbox_capacity(self)
+    }
+    fn set_mailbox_capacity(&self, capacity: Option<usize>) {
+        Addr::set_mailbox_capacity(self, capacity)
+    }
+    fn muted(&self) -> Vec<&'static str> {
+        self.msg_queue.shared().muted()
+    }
+    fn unmute_all(&self) -> usize {
+        self.msg_queue.shared().unmute_all()
+    }
+    fn depend_on(&self, id: ActorId) {
+        self.msg_queue.shared().depend_on(id)
+    }
+    fn dependencies(&self) -> Vec<ActorId> {
+        self.msg_queue.shared().dependencies()
+    }
+    fn id(&self) -> ActorId {
+        Addr::id(self)
+    }
+    fn actor_type(&self) -> &'static str {
+        std::any::type_name::<T>()
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Address of an actor of any type, exposing only the lifecycle operations
+///
+/// It allows managing different kinds of actors uniformly, e.g. by keeping them in a single [Vec].
+///
+/// Created via [Addr::any] or [From].
+#[derive(Clone)]
+pub struct AnyAddr {
+    inner: Arc<dyn LifecycleProxy>,
+}
+
+impl fmt::Debug for AnyAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AnyAddr")
+            .field("id", &self.inner.id())
+            .field("actor_type", &self.inner.actor_type())
+            .finish()
+    }
+}
+
+impl<T: Actor> From<Addr<T>> for AnyAddr {
+    fn from(addr: Addr<T>) -> Self {
+        Self {
+            inner: Arc::new(addr),
+        }
+    }
+}
+
+impl AnyAddr {
+    /// See [Addr::stop]
+    pub fn stop(&self, mode: StopMode) {
+        self.inner.stop(mode)
+    }
+    /// See [Addr::shutdown]
+    pub fn shutdown(&self, mode: StopMode) {
+        self.inner.shutdown(mode)
+    }
+    /// See [Addr::ping]
+    pub async fn ping(&self, timeout: Duration) -> Result<Health, ActorError> {
+        self.inner.ping(timeout).await
+    }
+    /// See [Addr::terminated]
+    pub async fn terminated(&self) -> RestartReason {
+        self.inner.terminated().await
+    }
+    /// Like [AnyAddr::terminated], without keeping the actor alive meanwhile
+    pub(crate) fn terminated_future(&self) -> BoxFuture<'static, RestartReason> {
+        self.inner.terminated()
+    }
+    /// See [Addr::ready]
+    pub async fn ready(&self) -> bool {
+        self.inner.ready().await
+    }
+    /// Aborts the running handlers at their' next await point, see [crate::watchdog].
+    ///
+    /// Does nothing while the actor is idle.
+    pub(crate) fn abort(&self) {
+        self.inner.abort()
+    }
+    /// See [Addr::state]
+    pub fn state(&self) -> Option<ActorState> {
+        self.inner.state()
+    }
+    /// See [Addr::connected]
+    pub fn connected(&self) -> bool {
+        self.inner.connected()
+    }
+    /// See [Addr::mailbox_capacity]
+    pub fn mailbox_capacity(&self) -> Option<usize> {
+        self.inner.mailbox_capacity()
+    }
+    /// See [Addr::latency]
+    pub fn latency(&self) -> Latency {
+        self.inner.latency()
+    }
+    /// See [Addr::mailbox_lag]
+    pub fn mailbox_lag(&self) -> Duration {
+        self.inner.mailbox_lag()
+    }
+    /// See [Addr::set_mailbox_capacity]
+    pub fn set_mailbox_capacity(&self, capacity: Option<usize>) {
+        self.inner.set_mailbox_capacity(capacity)
+    }
+    /// Returns the type names of the messages muted via [Addr::mute], sorted
+    pub fn muted(&self) -> Vec<&'static str> {
+        self.inner.muted()
+    }
+    /// Lets all the muted messages through again, returning how many types were muted
+    pub fn unmute_all(&self) -> usize {
+        self.inner.unmute_all()
+    }
+    /// Records that the actor relies on the given one (e.g. consumes what it produces),
+    /// so that a [crate::shutdown::ShutdownCoordinator] stops it first
+    pub fn depend_on(&self, id: ActorId) {
+        self.inner.depend_on(id)
+    }
+    /// Returns the actors which the actor relies on, see [AnyAddr::depend_on].
+    ///
+    /// Besides the explicit ones, they include the actors it [watches](crate::context::ActorContext::watch),
+    /// like the children of a [crate::supervisor::Supervisor].
+    pub fn dependencies(&self) -> Vec<ActorId> {
+        self.inner.dependencies()
+    }
+    /// Returns the identifier of the actor
+    pub fn id(&self) -> ActorId {
+        self.inner.id()
+    }
+    /// Returns the type name of the actor
+    pub fn actor_type(&self) -> &'static str {
+        self.inner.actor_type()
+    }
+    /// Returns the typed address, if the actor is of type `T`
+    pub fn downcast<T: Actor>(&self) -> Option<Addr<T>> {
+        self.inner.as_any().downcast_ref::<Addr<T>>().cloned()
+    }
 }
@@ -0,0 +1,179 @@
+//! Batching of concurrent lookups into multi-gets
+//!
+//! Actors backed by a database or a remote service can usually look many keys up at once
+//! about as fast as a single one. [MultiGet] collects the keys asked for at about the same time
+//! and sends them to the actor in a single [GetMany] message, once enough of them have been
+//! collected or the linger time has elapsed, then hands each caller the value of its' key.
+
+use crate::{addr::Recipient, error::ActorError};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    hash::Hash,
+    mem,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::oneshot;
+
+/// Message asking the actor for the values of many keys at once
+///
+/// The actor responds with the values of the keys it knows, the missing ones are reported
+/// to the callers as `None`. The keys are unique.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GetMany<K> {
+    pub keys: Vec<K>,
+}
+
+type Waiter<V> = oneshot::Sender<Result<Option<V>, ActorError>>;
+
+/// Keys collected since the last batch was sent
+struct Pending<K, V> {
+    /// Identifies the batch, so that the linger timer of a batch which has been sent
+    /// because it got full does not send the next one early
+    batch: u64,
+    waiters: Vec<(K, Waiter<V>)>,
+}
+
+/// Batches concurrent lookups, see [crate::batching]
+///
+/// Clones share the batches.
+pub struct MultiGet<K, V> {
+    recipient: Recipient<GetMany<K>, HashMap<K, V>>,
+    linger: Duration,
+    max_batch: usize,
+    pending: Arc<Mutex<Pending<K, V>>>,
+}
+
+impl<K, V> Clone for MultiGet<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            recipient: self.recipient.clone(),
+            linger: self.linger,
+            max_batch: self.max_batch,
+            pending: self.pending.clone(),
+        }
+    }
+}
+
+impl<K, V> fmt::Debug for MultiGet<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MultiGet")
+            .field("recipient", &self.recipient)
+            .field("linger", &self.linger)
+            .field("max_batch", &self.max_batch)
+            .field("pending", &self.pending.lock().unwrap().waiters.len())
+            .finish()
+    }
+}
+
+impl<K, V> MultiGet<K, V>
+where
+    K: 'static + Hash + Eq + Clone + Send,
+    V: 'static + Clone + Send,
+{
+    /// Wraps the recipient of the batched lookups.
+    ///
+    /// By default keys linger for a millisecond, and at most 64 of them are sent at once.
+    pub fn new(recipient: Recipient<GetMany<K>, HashMap<K, V>>) -> Self {
+        Self {
+            recipient,
+            linger: Duration::from_millis(1),
+            max_batch: 64,
+            pending: Arc::new(Mutex::new(Pending {
+                batch: 0,
+                waiters: Vec::new(),
+            })),
+        }
+    }
+    /// Sets how long the first key of a batch waits for others to join it
+    pub fn with_linger(mut self, linger: Duration) -> Self {
+        self.linger = linger;
+        self
+    }
+    /// Sets how many lookups make the batch get sent right away
+    pub fn with_max_batch(mut self, max_batch: usize) -> Self {
+        assert!(max_batch > 0, "Batches need to hold at least one lookup");
+        self.max_batch = max_batch;
+        self
+    }
+    /// Returns the underlying recipient
+    pub fn inner(&self) -> &Recipient<GetMany<K>, HashMap<K, V>> {
+        &self.recipient
+    }
+    /// Looks the key up as part of the next batch.
+    ///
+    /// Fails with the error of the whole batch if it could not be handled.
+    pub async fn get(&self, key: K) -> Result<Option<V>, ActorError> {
+        let (tx, rx) = oneshot::channel();
+        let full = {
+            let mut pending = self.pending.lock().unwrap();
+            pending.waiters.push((key, tx));
+            if pending.waiters.len() >= self.max_batch {
+                Some(take(&mut pending))
+            } else {
+                if pending.waiters.len() == 1 {
+                    self.linger(pending.batch);
+                }
+                None
+            }
+        };
+        if let Some(waiters) = full {
+            // Sent from a task of its' own, so that it does not depend on this caller being polled
+            tokio::spawn(dispatch(self.recipient.clone(), waiters));
+        }
+        // The batch got dropped without answering, e.g. because the runtime is shutting down
+        rx.await
+            .unwrap_or_else(|_| Err(ActorError::MessageLost(self.recipient.error_context())))
+    }
+    /// Sends the batch once the linger time elapses, unless it gets full earlier
+    fn linger(&self, batch: u64) {
+        let (recipient, pending, linger) =
+            (self.recipient.clone(), self.pending.clone(), self.linger);
+        tokio::spawn(async move {
+            tokio::time::sleep(linger).await;
+            let waiters = {
+                let mut pending = pending.lock().unwrap();
+                if pending.batch != batch {
+                    return;
+                }
+                take(&mut pending)
+            };
+            dispatch(recipient, waiters).await;
+        });
+    }
+}
+
+fn take<K, V>(pending: &mut Pending<K, V>) -> Vec<(K, Waiter<V>)> {
+    pending.batch += 1;
+    mem::take(&mut pending.waiters)
+}
+
+async fn dispatch<K, V>(
+    recipient: Recipient<GetMany<K>, HashMap<K, V>>,
+    waiters: Vec<(K, Waiter<V>)>,
+) where
+    K: 'static + Hash + Eq + Clone + Send,
+    V: 'static + Clone + Send,
+{
+    let keys = {
+        let mut unique = HashSet::with_capacity(waiters.len());
+        waiters
+            .iter()
+            .filter(|(key, _)| unique.insert(key))
+            .map(|(key, _)| key.clone())
+            .collect()
+    };
+    match recipient.send(GetMany { keys }).await {
+        Ok(values) => {
+            for (key, tx) in waiters {
+                let _ = tx.send(Ok(values.get(&key).cloned()));
+            }
+        }
+        Err(e) => {
+            for (_, tx) in waiters {
+                let _ = tx.send(Err(e.clone()));
+            }
+        }
+    }
+}
@@ -0,0 +1,291 @@
+//! Starting systems of actors which depend on each other
+//!
+//! [Bootstrap] starts named actors in the order given by their' dependencies:
+//! an actor starts only once all its' dependencies are [ready](crate::addr::Addr::ready)
+//! (their' [Actor::started] has completed, or they have warmed up, as per [Actor::READINESS]),
+//! while independent actors start concurrently.
+
+use crate::{
+    actor::{Actor, StopMode},
+    addr::{Addr, AnyAddr},
+    set::ActorSet,
+};
+use futures_util::{
+    future::{BoxFuture, FutureExt},
+    stream::{FuturesUnordered, StreamExt},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    future::Future,
+    time::Duration,
+};
+use thiserror::Error;
+
+/// Builds the actor, given the already started dependencies
+type Factory = Box<dyn FnOnce(&Actors) -> BoxFuture<'static, Result<AnyAddr, String>> + Send>;
+
+/// Error returned when a [Bootstrap] fails.
+///
+/// The actors started so far get stopped.
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum BootstrapError {
+    #[error("Actor `{actor}` depends on `{dependency}`, which is not a part of the system.")]
+    /// An actor depends on an actor which is not a part of the system.
+    UnknownDependency { actor: String, dependency: String },
+    #[error("Actors {0:?} depend on each other.")]
+    /// Some actors depend on each other, so none of them can start.
+    Cycle(Vec<String>),
+    #[error("Actor `{name}` appears more than once.")]
+    /// Two actors share a name.
+    Duplicate { name: String },
+    #[error("Actor `{actor}` failed to start: {reason}")]
+    /// An actor failed to start.
+    Failed { actor: String, reason: String },
+    #[error("Actor `{actor}` did not become ready in time.")]
+    /// An actor did not become ready in time.
+    Timeout { actor: String },
+}
+
+/// Named actors started by a [Bootstrap]
+#[derive(Clone, Debug, Default)]
+pub struct Actors {
+    actors: HashMap<String, AnyAddr>,
+    /// Names in the order in which the actors became ready
+    order: Vec<String>,
+}
+
+impl Actors {
+    /// Returns the address of the actor with the given name, if it's of type `A`
+    pub fn get<A: Actor>(&self, name: &str) -> Option<Addr<A>> {
+        self.actors.get(name)?.downcast()
+    }
+    /// Returns the address of the actor with the given name
+    pub fn any(&self, name: &str) -> Option<&AnyAddr> {
+        self.actors.get(name)
+    }
+    /// Returns the names of the actors in the order in which they became ready
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.order.iter().map(String::as_str)
+    }
+    /// Number of actors
+    pub fn len(&self) -> usize {
+        self.actors.len()
+    }
+    /// Returns `true` if there are no actors
+    pub fn is_empty(&self) -> bool {
+        self.actors.is_empty()
+    }
+    /// Moves the actors into an [ActorSet]
+    pub fn into_set(self) -> ActorSet {
+        let mut set = ActorSet::new();
+        for name in &self.order {
+            set.insert(self.actors[name].clone());
+        }
+        set
+    }
+    fn insert(&mut self, name: String, addr: AnyAddr) {
+        self.actors.insert(name.clone(), addr);
+        self.order.push(name);
+    }
+    fn stop_all(&self) {
+        for addr in self.actors.values() {
+            addr.shutdown(StopMode::Abandon);
+        }
+    }
+}
+
+struct Node {
+    name: String,
+    dependencies: Vec<String>,
+    factory: Factory,
+}
+
+/// Description of a system of actors, see [crate::bootstrap]
+pub struct Bootstrap {
+    nodes: Vec<Node>,
+    timeout: Duration,
+}
+
+impl Default for Bootstrap {
+    fn default() -> Self {
+        Self {
+            nodes: Vec::new(),
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl fmt::Debug for Bootstrap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let nodes: Vec<_> = self
+            .nodes
+            .iter()
+            .map(|node| (&node.name, &node.dependencies))
+            .collect();
+        f.debug_struct("Bootstrap")
+            .field("nodes", &nodes)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl Bootstrap {
+    /// Creates an empty system, giving each actor 30 seconds to become ready
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Sets how long each actor has to become ready, including the time it takes to build it
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+    /// Adds an actor to the system.
+    ///
+    /// The factory gets called once all the dependencies are ready, receiving their' addresses.
+    pub fn actor<A, F, Fut>(
+        self,
+        name: impl Into<String>,
+        dependencies: &[&str],
+        factory: F,
+    ) -> Self
+    where
+        A: Actor,
+        F: 'static + FnOnce(&Actors) -> Fut + Send,
+        Fut: 'static + Future<Output = Addr<A>> + Send,
+    {
+        self.try_actor(name, dependencies, |actors| {
+            factory(actors).map(Ok::<_, String>)
+        })
+    }
+    /// Behaves like [Bootstrap::actor], with a factory which can fail, e.g. via [crate::startup::TryStart]
+    pub fn try_actor<A, E, F, Fut>(
+        mut self,
+        name: impl Into<String>,
+        dependencies: &[&str],
+        factory: F,
+    ) -> Self
+    where
+        A: Actor,
+        E: fmt::Display,
+        F: 'static + FnOnce(&Actors) -> Fut + Send,
+        Fut: 'static + Future<Output = Result<Addr<A>, E>> + Send,
+    {
+        let factory: Factory = Box::new(move |actors| {
+            let started = factory(actors);
+            Box::pin(async move {
+                match started.await {
+                    Ok(addr) => Ok(addr.into()),
+                    Err(e) => Err(e.to_string()),
+                }
+            })
+        });
+        self.nodes.push(Node {
+            name: name.into(),
+            dependencies: dependencies.iter().map(|d| d.to_string()).collect(),
+            factory,
+        });
+        self
+    }
+    /// Checks that all dependencies exist and do not form cycles
+    fn validate(&self) -> Result<(), BootstrapError> {
+        let mut names = HashSet::new();
+        for node in &self.nodes {
+            if !names.insert(node.name.as_str()) {
+                return Err(BootstrapError::Duplicate {
+                    name: node.name.clone(),
+                });
+            }
+        }
+        for node in &self.nodes {
+            for dependency in &node.dependencies {
+                if !names.contains(dependency.as_str()) {
+                    return Err(BootstrapError::UnknownDependency {
+                        actor: node.name.clone(),
+                        dependency: dependency.clone(),
+                    });
+                }
+            }
+        }
+        // Peel off the actors which could start, whatever remains is stuck in a cycle
+        let mut remaining: Vec<&Node> = self.nodes.iter().collect();
+        let mut startable = HashSet::new();
+        loop {
+            let before = remaining.len();
+            remaining.retain(|node| {
+                let ready = node
+                    .dependencies
+                    .iter()
+                    .all(|d| startable.contains(d.as_str()));
+                if ready {
+                    startable.insert(node.name.as_str());
+                }
+                !ready
+            });
+            if remaining.is_empty() {
+                return Ok(());
+            }
+            if remaining.len() == before {
+                let names = remaining.iter().map(|node| node.name.clone()).collect();
+                return Err(BootstrapError::Cycle(names));
+            }
+        }
+    }
+    /// Starts all the actors, resolving once all of them are ready
+    pub async fn start(self) -> Result<Actors, BootstrapError> {
+        self.validate()?;
+        let timeout = self.timeout;
+        let mut waiting = self.nodes;
+        let mut actors = Actors::default();
+        let mut starting = FuturesUnordered::new();
+        loop {
+            // Start everything which is no longer waiting for anything
+            let (ready, blocked): (Vec<_>, Vec<_>) = waiting.into_iter().partition(|node| {
+                node.dependencies
+                    .iter()
+                    .all(|d| actors.actors.contains_key(d))
+            });
+            waiting = blocked;
+            for node in ready {
+                let started = (node.factory)(&actors);
+                starting.push(async move {
+                    let actor = node.name.clone();
+                    let ret =
+                        match tokio::time::timeout(timeout, ready_check(started)).await {
+                            Ok(Ok(addr)) => Ok(addr),
+                            Ok(Err(reason)) => Err(BootstrapError::Failed { actor, reason }),
+                            Err(_) => Err(BootstrapError::Timeout { actor }),
+                        };
+                    (node.name, ret)
+                });
+            }
+            let Some((name, ret)) = starting.next().await else {
+                return Ok(actors);
+            };
+            match ret {
+                Ok(addr) => actors.insert(name, addr),
+                Err(e) => {
+                    // Wait for the ones being started, so that they can be stopped as well
+                    while let Some((name, ret)) = starting.next().await {
+                        if let Ok(addr) = ret {
+                            actors.insert(name, addr);
+                        }
+                    }
+                    actors.stop_all();
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+/// Builds the actor and waits until it's ready
+async fn ready_check(
+    started: BoxFuture<'static, Result<AnyAddr, String>>,
+) -> Result<AnyAddr, String> {
+    let addr = started.await?;
+    match addr.ready().await {
+        true => Ok(addr),
+        false => Err("The actor stopped before becoming ready".to_string()),
+    }
+}
@@ -0,0 +1,368 @@
+//! Response caching in front of read-mostly actors
+//!
+//! [CachedRecipient] answers repeated queries from its' cache instead of sending them to the actor.
+//! Queries are identified by their' [CacheKey]. Responses expire after a while, and the actor,
+//! which knows when its' state changes, can drop them earlier through [Invalidations].
+//!
+//! [CoalescingRecipient] does not keep responses, but lets identical queries sent at the same time
+//! share a single request, which protects the actor from the bursts following cache misses.
+
+use crate::{addr::Recipient, error::ActorError};
+use futures_util::future::{BoxFuture, FutureExt, Shared};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    hash::Hash,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::broadcast;
+
+/// How many invalidations are buffered for each cache before it has to be cleared whole
+const INVALIDATIONS_BUFFER: usize = 1024;
+
+/// Trait implemented on queries whose responses can be cached
+///
+/// Two queries with equal keys are considered to be answered with the same response.
+pub trait CacheKey {
+    /// Type of the key
+    type Key: Hash + Eq + Clone + Send + 'static;
+    /// Returns the key of the query
+    fn cache_key(&self) -> Self::Key;
+}
+
+#[derive(Clone, Debug)]
+enum Invalidation<K> {
+    Key(K),
+    All,
+}
+
+/// Channel through which an actor invalidates the responses cached by [CachedRecipient]s
+///
+/// The actor keeps it and calls [Invalidations::invalidate] whenever its' state changes.
+pub struct Invalidations<K> {
+    tx: broadcast::Sender<Invalidation<K>>,
+}
+
+impl<K> Clone for Invalidations<K> {
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+        }
+    }
+}
+
+impl<K> fmt::Debug for Invalidations<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Invalidations")
+            .field("caches", &self.tx.receiver_count())
+            .finish()
+    }
+}
+
+impl<K: Clone> Default for Invalidations<K> {
+    fn default() -> Self {
+        Self {
+            tx: broadcast::channel(INVALIDATIONS_BUFFER).0,
+        }
+    }
+}
+
+impl<K: Clone> Invalidations<K> {
+    /// Creates a channel with no caches attached
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Drops the response cached for the key from all the attached caches
+    pub fn invalidate(&self, key: K) {
+        let _ = self.tx.send(Invalidation::Key(key));
+    }
+    /// Drops all the responses from all the attached caches
+    pub fn invalidate_all(&self) {
+        let _ = self.tx.send(Invalidation::All);
+    }
+}
+
+struct Entry<R> {
+    cached_at: Instant,
+    stamp: u64,
+    response: R,
+}
+
+/// Bounded, least-recently-used cache of responses
+struct Cache<K, R> {
+    capacity: usize,
+    ttl: Duration,
+    next_stamp: u64,
+    entries: HashMap<K, Entry<R>>,
+    order: BTreeMap<u64, K>,
+    /// Bumped on every invalidation, so that responses to queries sent before it do not get cached
+    generation: u64,
+    invalidations: Option<broadcast::Receiver<Invalidation<K>>>,
+}
+
+impl<K: Hash + Eq + Clone, R: Clone> Cache<K, R> {
+    fn bump(&mut self) -> u64 {
+        self.next_stamp += 1;
+        self.next_stamp
+    }
+    fn remove(&mut self, key: &K) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.order.remove(&entry.stamp);
+        }
+    }
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+    /// Applies the invalidations sent since the last call
+    fn invalidate(&mut self) {
+        let Some(rx) = self.invalidations.as_mut() else {
+            return;
+        };
+        let mut keys = Vec::new();
+        let mut all = false;
+        loop {
+            match rx.try_recv() {
+                Ok(Invalidation::Key(key)) => keys.push(key),
+                Ok(Invalidation::All) | Err(broadcast::error::TryRecvError::Lagged(_)) => {
+                    all = true
+                }
+                Err(_) => break,
+            }
+        }
+        if all || !keys.is_empty() {
+            self.generation += 1;
+        }
+        if all {
+            self.clear();
+        }
+        for key in keys {
+            self.remove(&key);
+        }
+    }
+    fn get(&mut self, key: &K) -> Option<R> {
+        self.invalidate();
+        let stamp = self.bump();
+        let entry = self.entries.get_mut(key)?;
+        if entry.cached_at.elapsed() > self.ttl {
+            self.remove(key);
+            return None;
+        }
+        self.order.remove(&entry.stamp);
+        entry.stamp = stamp;
+        self.order.insert(stamp, key.clone());
+        Some(entry.response.clone())
+    }
+    fn insert(&mut self, key: K, response: R, generation: u64) {
+        self.invalidate();
+        if generation != self.generation {
+            return;
+        }
+        self.remove(&key);
+        let stamp = self.bump();
+        let entry = Entry {
+            cached_at: Instant::now(),
+            stamp,
+            response,
+        };
+        self.entries.insert(key.clone(), entry);
+        self.order.insert(stamp, key);
+        while self.entries.len() > self.capacity {
+            match self.order.pop_first() {
+                Some((_, oldest)) => {
+                    self.entries.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+/// [Recipient] wrapper caching the responses to queries of type `M`
+///
+/// Responses are kept for the duration of the time to live, up to the configured capacity
+/// (the least recently used ones get evicted first). Errors are not cached.
+///
+/// Clones share the cache.
+pub struct CachedRecipient<M: CacheKey, R> {
+    recipient: Recipient<M, R>,
+    cache: Arc<Mutex<Cache<M::Key, R>>>,
+}
+
+impl<M: CacheKey, R> Clone for CachedRecipient<M, R> {
+    fn clone(&self) -> Self {
+        Self {
+            recipient: self.recipient.clone(),
+            cache: self.cache.clone(),
+        }
+    }
+}
+
+impl<M: CacheKey, R> fmt::Debug for CachedRecipient<M, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cache = self.cache.lock().unwrap();
+        f.debug_struct("CachedRecipient")
+            .field("recipient", &self.recipient)
+            .field("capacity", &cache.capacity)
+            .field("ttl", &cache.ttl)
+            .field("cached", &cache.entries.len())
+            .finish()
+    }
+}
+
+impl<M, R> CachedRecipient<M, R>
+where
+    M: CacheKey + Send + 'static,
+    R: Clone + Send + 'static,
+{
+    /// Wraps the recipient, caching up to `capacity` responses for the duration of `ttl`
+    pub fn new(recipient: Recipient<M, R>, capacity: usize, ttl: Duration) -> Self {
+        let cache = Cache {
+            capacity,
+            ttl,
+            next_stamp: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            generation: 0,
+            invalidations: None,
+        };
+        Self {
+            recipient,
+            cache: Arc::new(Mutex::new(cache)),
+        }
+    }
+    /// Makes the cache drop the responses invalidated through the given channel
+    pub fn with_invalidations(self, invalidations: &Invalidations<M::Key>) -> Self {
+        self.cache.lock().unwrap().invalidations = Some(invalidations.tx.subscribe());
+        self
+    }
+    /// Returns the underlying recipient
+    pub fn inner(&self) -> &Recipient<M, R> {
+        &self.recipient
+    }
+    /// Answers the query from the cache, or sends it to the actor and caches the response
+    pub async fn send(&self, msg: M) -> Result<R, ActorError> {
+        let key = msg.cache_key();
+        let generation = {
+            let mut cache = self.cache.lock().unwrap();
+            if let Some(response) = cache.get(&key) {
+                return Ok(response);
+            }
+            cache.generation
+        };
+        let response = self.recipient.send(msg).await?;
+        let mut cache = self.cache.lock().unwrap();
+        cache.insert(key, response.clone(), generation);
+        Ok(response)
+    }
+}
+
+type Request<R> = Shared<BoxFuture<'static, Result<R, ActorError>>>;
+
+struct Pending<R> {
+    request: Request<R>,
+    /// Callers waiting for the response
+    waiters: usize,
+}
+
+type InFlight<K, R> = HashMap<K, Pending<R>>;
+
+/// Forgets the request once it completes, or once all of its' callers have given up on it
+struct Waiter<'a, K: Hash + Eq, R> {
+    in_flight: &'a Mutex<InFlight<K, R>>,
+    key: K,
+    request: Request<R>,
+    done: bool,
+}
+
+impl<K: Hash + Eq, R> Drop for Waiter<'_, K, R> {
+    fn drop(&mut self) {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        // Unless it's already been replaced by a newer request
+        let Some(pending) = in_flight
+            .get_mut(&self.key)
+            .filter(|pending| pending.request.ptr_eq(&self.request))
+        else {
+            return;
+        };
+        pending.waiters -= 1;
+        if self.done || pending.waiters == 0 {
+            in_flight.remove(&self.key);
+        }
+    }
+}
+
+/// [Recipient] wrapper letting identical queries of type `M` in flight share a single request
+///
+/// Queries are identical if their' [CacheKey]s are equal. The ones sent while an identical query
+/// awaits its' response do not reach the actor, but get the same response (or error) once it arrives.
+///
+/// Clones share the requests in flight.
+pub struct CoalescingRecipient<M: CacheKey, R> {
+    recipient: Recipient<M, R>,
+    in_flight: Arc<Mutex<InFlight<M::Key, R>>>,
+}
+
+impl<M: CacheKey, R> Clone for CoalescingRecipient<M, R> {
+    fn clone(&self) -> Self {
+        Self {
+            recipient: self.recipient.clone(),
+            in_flight: self.in_flight.clone(),
+        }
+    }
+}
+
+impl<M: CacheKey, R> fmt::Debug for CoalescingRecipient<M, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CoalescingRecipient")
+            .field("recipient", &self.recipient)
+            .field("in_flight", &self.in_flight.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl<M, R> CoalescingRecipient<M, R>
+where
+    M: CacheKey + Send + 'static,
+    R: Clone + Send + Sync + 'static,
+{
+    /// Wraps the recipient
+    pub fn new(recipient: Recipient<M, R>) -> Self {
+        Self {
+            recipient,
+            in_flight: Arc::default(),
+        }
+    }
+    /// Returns the underlying recipient
+    pub fn inner(&self) -> &Recipient<M, R> {
+        &self.recipient
+    }
+    /// Sends the query to the actor, unless an identical one is already waiting for its' response.
+    ///
+    /// The shared request keeps going as long as any of the callers waits for it.
+    pub async fn send(&self, msg: M) -> Result<R, ActorError> {
+        let key = msg.cache_key();
+        let request = {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            let pending = in_flight.entry(key.clone()).or_insert_with(|| {
+                let recipient = self.recipient.clone();
+                Pending {
+                    request: async move { recipient.send(msg).await }.boxed().shared(),
+                    waiters: 0,
+                }
+            });
+            pending.waiters += 1;
+            pending.request.clone()
+        };
+        let mut waiter = Waiter {
+            in_flight: &self.in_flight,
+            key,
+            request: request.clone(),
+            done: false,
+        };
+        let ret = request.await;
+        waiter.done = true;
+        ret
+    }
+}
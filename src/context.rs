@@ -1,39 +1,242 @@
 //! Execution context for actors
 
 use crate::{
-    actor::{Actor, ActorState, Handler},
-    addr::{Addr, WeakAddr},
+    actor::{Actor, ActorId, ActorState, Handler, MessageId, StopMode},
+    actor_future::{self, ActorFuture},
+    addr::{Addr, AnyAddr, WeakAddr},
+    cancellation::CancellationToken,
+    error::ActorError,
+    histogram::Latency,
+    logging::ActorLogger,
+    message_queue::{ActorShared, CounterGuard, QueuePayload},
+    supervised::{panic_message, RestartReason},
 };
-use futures_util::stream::{Stream, StreamExt};
+use futures_util::{
+    future::{select, Either, FutureExt},
+    stream::{FuturesUnordered, Stream, StreamExt},
+};
+use std::{
+    any::TypeId,
+    collections::VecDeque,
+    fmt,
+    future::Future,
+    panic::AssertUnwindSafe,
+    pin::pin,
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::watch;
 /// Actor execution context 
 /// 
 /// It allows an actor to manage its' lifecycle, 
 /// retrieve its' address and enqueue messages for later processing.
-#[derive(Debug)]
 pub struct ActorContext<T: Actor> {
     address: WeakAddr<T>,
-    state: ActorState,
+    shared: Arc<ActorShared>,
+    request_cancellation: Option<CancellationToken>,
+    current_message_id: Option<MessageId>,
+    strict_responses: bool,
+    /// Locked only to keep the context `Sync`, as it's only accessed through `&mut self`
+    stash: Mutex<Stash<T>>,
 }
 unsafe impl<T: Actor> Send for ActorContext<T> {}
 
+/// Type of a message, as seen by the mailbox filter, see [ActorContext::set_mailbox_filter]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct MessageType {
+    type_id: TypeId,
+    name: &'static str,
+}
+
+impl MessageType {
+    pub fn of<M: 'static>() -> Self {
+        Self {
+            type_id: TypeId::of::<M>(),
+            name: std::any::type_name::<M>(),
+        }
+    }
+    /// Returns `true` if it's the type `M`
+    pub fn is<M: 'static>(&self) -> bool {
+        self.type_id == TypeId::of::<M>()
+    }
+    /// Returns the full name of the type, e.g. to match a family of messages by prefix
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+type MailboxFilter = Box<dyn Fn(MessageType) -> bool + Send>;
+
+/// Messages deferred by the mailbox filter
+struct Stash<T: Actor> {
+    filter: Option<MailboxFilter>,
+    stashed: VecDeque<QueuePayload<T>>,
+    /// Released via [ActorContext::unstash_all], handled before the messages of the mailbox
+    unstashed: VecDeque<QueuePayload<T>>,
+}
+
+impl<T: Actor> Default for Stash<T> {
+    fn default() -> Self {
+        Self {
+            filter: None,
+            stashed: VecDeque::new(),
+            unstashed: VecDeque::new(),
+        }
+    }
+}
+
+impl<T: Actor> fmt::Debug for ActorContext<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ActorContext")
+            .field("diagnostics", &self.diagnostics())
+            .finish()
+    }
+}
+
+/// Process-wide unique identifier of a stream added via [ActorContext::add_stream]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct StreamId(u64);
+
+/// Process-wide unique identifier of a timer set via [ActorContext::set_timer]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct TimerId(u64);
+
+/// Allocates the identifiers of streams and timers
+fn next_id() -> u64 {
+    static NEXT_ID: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Handle of a stream added via [ActorContext::add_stream], allowing to pause it
+#[derive(Clone)]
+pub struct StreamHandle {
+    id: StreamId,
+    paused: Arc<watch::Sender<bool>>,
+}
+
+impl fmt::Debug for StreamHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StreamHandle")
+            .field("id", &self.id)
+            .field("paused", &self.is_paused())
+            .finish()
+    }
+}
+
+impl StreamHandle {
+    /// Returns the identifier of the stream, as found in [ContextEvent]s
+    pub fn id(&self) -> StreamId {
+        self.id
+    }
+    /// Returns `true` if the stream has been paused via [ActorContext::pause_stream]
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+}
+
+/// Details of a stream which panicked, see [ContextEvent::StreamFailed]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StreamFailed {
+    /// The stream which panicked
+    pub stream: StreamId,
+    /// Type name of the messages produced by the stream
+    pub message_type: &'static str,
+    /// The panic message, if it was a string
+    pub message: Option<String>,
+}
+
+/// Notification about the plumbing of the actor, see [Actor::context_event]
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum ContextEvent {
+    /// A stream added via [ActorContext::add_stream] has ended,
+    /// after the messages taken from it have been handled
+    StreamFinished(StreamId),
+    /// A stream added via [ActorContext::add_stream] panicked,
+    /// after the messages taken from it have been handled. The stream gets dropped.
+    StreamFailed(StreamFailed),
+    /// A stream added via [ActorContext::add_stream] stopped being forwarded, because the actor
+    /// can no longer take messages. The stream gets dropped.
+    StreamInterrupted { stream: StreamId, error: ActorError },
+    /// A timer set via [ActorContext::set_timer] has fired
+    TimerFired(TimerId),
+    /// An actor watched via [ActorContext::watch], like a child, has terminated
+    Terminated { actor_id: ActorId, reason: RestartReason },
+}
+
+/// Snapshot of the actor's runtime information, meant for logging
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Diagnostics {
+    pub actor_id: ActorId,
+    /// Type name of the actor
+    pub actor_type: &'static str,
+    pub state: ActorState,
+    /// Identifier of the message currently being handled
+    pub current_message_id: Option<MessageId>,
+    /// Number of messages waiting in the mailbox
+    pub queue_depth: usize,
+    /// Capacity of the mailbox, `None` meaning no limit
+    pub mailbox_capacity: Option<usize>,
+    /// Approximate size of the messages waiting in the mailbox, see [Actor::MAILBOX_BYTES]
+    pub queue_bytes: usize,
+    /// Number of streams registered via [ActorContext::add_stream] which are still active
+    pub streams: usize,
+    /// Number of streams which have panicked, see [ContextEvent::StreamFailed]
+    pub failed_streams: usize,
+    /// Number of closures spawned via [ActorContext::spawn_blocking] which are still running
+    pub blocking_tasks: usize,
+    /// Number of messages deferred by the mailbox filter, see [ActorContext::set_mailbox_filter]
+    pub stashed: usize,
+    /// How long ago the message being handled got enqueued, see [Addr::mailbox_lag]
+    pub mailbox_lag: Duration,
+}
+
 impl<T: Actor> ActorContext<T> {
     #[inline]
     /// Retrieves the current state of the actor
     pub fn state(&self) -> ActorState {
-        self.state
+        self.shared.state()
+    }
+    #[inline]
+    /// Returns the identifier of the actor
+    pub fn id(&self) -> ActorId {
+        self.shared.id()
     }
     /// Causes an actor to enter [ActorState::Stopping] state
     /// and then, as a result, potentially to stop.
     /// 
     /// It's adequate to call this function when the actor needs to stop, 
     /// for example, due to an error condition.
+    /// 
+    /// Messages still waiting in the mailbox get abandoned, see [ActorContext::stop_with].
     pub fn stop(&mut self) {
-        self.state = ActorState::Stopping
+        self.stop_with(StopMode::Abandon)
+    }
+    /// Behaves like [ActorContext::stop], letting you choose what happens
+    /// to the messages still waiting in the mailbox if the actor stops.
+    /// 
+    /// Supervised actors keep their mailbox across restarts, so the mode applies to them
+    /// only once they stop for good (see [crate::supervised::Supervised::RESTART]).
+    pub fn stop_with(&mut self, mode: StopMode) {
+        self.shared.stop(mode)
+    }
+    /// Stops the actor due to a failure noticed outside of its' handlers, like a stream panicking
+    /// (see [ContextEvent::StreamFailed]).
+    ///
+    /// Unlike [ActorContext::stop], the reason gets passed on to [crate::supervised::Supervised::restarting]
+    /// and [Addr::terminated], so that the restart can tell the failure apart from an explicit stop.
+    pub fn fail(&mut self, reason: RestartReason) {
+        self.shared.failed(reason)
     }
     #[inline]
     /// Returns the actors' address.
     /// 
     /// It is meant to be used to pass the address to other actors.
+    /// 
+    /// # Panics
+    /// 
+    /// Panics when called from [Actor::stopped] after all addresses of the actor got dropped.
+    /// [ActorContext::weak_address] can be used when this is a concern.
     pub fn address(&self) -> Addr<T> {
         self.address.upgrade().unwrap()
     }
@@ -48,45 +251,474 @@ impl<T: Actor> ActorContext<T> {
         M: 'static + Send,
         T: Handler<M>,
     {
-        self.address().do_send(msg)
+        if let Some(addr) = self.address.upgrade() {
+            // Messages sent by the actor to itself are not subject to the capacity limit
+            addr.msg_queue.do_send(msg, false);
+        }
+    }
+    #[inline]
+    /// Returns a token which gets cancelled when the sender of the message currently being handled
+    /// stops waiting for the response (i.e. drops the future returned by [Addr::send]).
+    /// 
+    /// Long-running handlers can use it to skip or abort work nobody is waiting for.
+    /// 
+    /// Returns `None` for messages which do not expect a response, 
+    /// like the ones sent via [Addr::do_send] or [ActorContext::notify].
+    pub fn request_cancellation(&self) -> Option<CancellationToken> {
+        self.request_cancellation.clone()
+    }
+    /// Returns a snapshot of the actor's runtime information
+    pub fn diagnostics(&self) -> Diagnostics {
+        Diagnostics {
+            actor_id: self.id(),
+            actor_type: std::any::type_name::<T>(),
+            state: self.state(),
+            current_message_id: self.current_message_id,
+            queue_depth: self.shared.depth(),
+            mailbox_capacity: self.shared.capacity(),
+            queue_bytes: self.shared.bytes(),
+            streams: self.shared.streams.load(Ordering::Relaxed),
+            failed_streams: self.shared.failed_streams.load(Ordering::Relaxed),
+            blocking_tasks: self.shared.blocking_tasks.load(Ordering::Relaxed),
+            mailbox_lag: self.shared.mailbox_lag(),
+            stashed: self.stash.lock().unwrap().stashed.len(),
+        }
+    }
+    #[inline]
+    /// Returns a token which gets cancelled as soon as the actor enters [ActorState::Stopping] state.
+    /// 
+    /// Futures spawned by handlers and long-running handlers can use it to abort during shutdown.
+    /// If the actor goes back to normal operation (or gets restarted), a fresh token is handed out from then on.
+    pub fn stop_token(&self) -> CancellationToken {
+        self.shared.stop_token()
+    }
+    #[inline]
+    /// Returns the identifier of the message currently being handled.
+    /// 
+    /// It can be used to correlate log entries and [crate::dead_letters::DeadLetter]s with requests.
+    /// Returns `None` outside of message handlers.
+    pub fn current_message_id(&self) -> Option<MessageId> {
+        self.current_message_id
+    }
+    /// Installs a filter on the mailbox: from now on, only the messages whose types it accepts get handled,
+    /// while the other ones get stashed, in the order in which they arrived, until [ActorContext::unstash_all].
+    /// 
+    /// It's meant for states in which the actor cannot handle most of its' messages yet,
+    /// e.g. letting through only the messages of a handshake. Filters do not apply to the messages
+    /// of the framework itself (like [Addr::ping]), nor to the actors started as worker pools.
+    /// Stashed messages do not take up space in the mailbox, and get dropped if the actor stops or restarts,
+    /// which also removes the filter.
+    pub fn set_mailbox_filter<F>(&mut self, filter: F)
+    where
+        F: 'static + Fn(MessageType) -> bool + Send,
+    {
+        self.stash.get_mut().unwrap().filter = Some(Box::new(filter));
+    }
+    /// Removes the mailbox filter, so that all the messages get handled again.
+    /// 
+    /// Messages which have already been stashed stay stashed until [ActorContext::unstash_all].
+    pub fn clear_mailbox_filter(&mut self) {
+        self.stash.get_mut().unwrap().filter = None;
+    }
+    /// Releases the stashed messages, which get handled before the ones waiting in the mailbox,
+    /// in the order in which they arrived. Returns how many messages have been released.
+    /// 
+    /// Messages still rejected by the mailbox filter get stashed again.
+    pub fn unstash_all(&mut self) -> usize {
+        let stash = self.stash.get_mut().unwrap();
+        let released = stash.stashed.len();
+        // Messages released earlier but not handled yet keep their' place in the line
+        stash.unstashed.extend(stash.stashed.drain(..));
+        released
+    }
+    /// Takes the next message released via [ActorContext::unstash_all]
+    pub(crate) fn take_unstashed(&mut self) -> Option<QueuePayload<T>> {
+        self.stash.get_mut().unwrap().unstashed.pop_front()
+    }
+    /// Puts a message taken out of the mailbox back in front of the line, as the next one to handle
+    pub(crate) fn put_back(&mut self, msg: QueuePayload<T>) {
+        self.stash.get_mut().unwrap().unstashed.push_front(msg);
+    }
+    /// Returns `true` if messages released via [ActorContext::unstash_all] are waiting to be handled
+    pub(crate) fn has_unstashed(&mut self) -> bool {
+        !self.stash.get_mut().unwrap().unstashed.is_empty()
+    }
+    /// Removes the mailbox filter and drops the stashed messages, before the actor restarts.
+    /// 
+    /// The messages already released keep waiting in front of the mailbox, like the ones in it.
+    pub(crate) fn reset_stash(&mut self) {
+        let stash = self.stash.get_mut().unwrap();
+        stash.filter = None;
+        stash.stashed.clear();
+    }
+    /// Stashes the message if the mailbox filter rejects it, returning it otherwise
+    pub(crate) fn stash_unless_accepted(
+        &mut self,
+        msg: QueuePayload<T>,
+    ) -> Option<QueuePayload<T>> {
+        let stash = self.stash.get_mut().unwrap();
+        match (&stash.filter, msg.message_type()) {
+            (Some(filter), Some(message_type)) if !filter(message_type) => {
+                stash.stashed.push_back(msg);
+                None
+            }
+            _ => Some(msg),
+        }
+    }
+    /// Returns the latencies of the messages handled so far, see [Addr::latency]
+    pub fn latency(&self) -> Latency {
+        self.shared.latency()
+    }
+    /// Returns a logger tagging the records with the actor and the message currently being handled,
+    /// see [crate::logging]
+    pub fn log(&self) -> ActorLogger {
+        ActorLogger::new(self)
+    }
+    /// Names the actor, e.g. to tell apart the actors of the same type in the log records
+    pub fn set_name(&self, name: impl Into<Arc<str>>) {
+        self.shared.set_name(name.into())
+    }
+    /// Returns the name of the actor, see [ActorContext::set_name]
+    pub fn name(&self) -> Option<Arc<str>> {
+        self.shared.name()
+    }
+    #[inline]
+    /// Returns `true` if strict response mode is enabled
+    pub fn strict_responses(&self) -> bool {
+        self.strict_responses
+    }
+    /// Enables or disables strict response mode.
+    /// 
+    /// By default, responses which cannot be delivered because their sender stopped waiting for them
+    /// are dropped and recorded as [crate::dead_letters::DeadLetter]s.
+    /// In strict mode, the actor panics instead.
+    pub fn set_strict_responses(&mut self, strict: bool) {
+        self.strict_responses = strict;
+    }
+    /// Marks the actor as ready, see [Actor::READINESS].
+    /// 
+    /// Messages which have been waiting for it get enqueued, in the order in which they were sent.
+    pub fn set_ready(&self) {
+        match self.address.upgrade() {
+            Some(addr) => addr.msg_queue.set_ready(),
+            // Nobody can be waiting for it
+            None => self.shared.set_ready(),
+        }
+    }
+    /// Returns `true` once the actor has become ready, see [Actor::READINESS]
+    pub fn is_ready(&self) -> bool {
+        self.shared.is_ready()
+    }
+    /// Makes sending messages of type `M` fail with [crate::error::ActorError::Muted],
+    /// e.g. to shed an expensive kind of requests during overload, until [unmuted](ActorContext::unmute).
+    /// 
+    /// Messages sent without awaiting the response, like the ones of [ActorContext::notify],
+    /// get recorded as [crate::dead_letters::DeadLetter]s, while the ones of streams get dropped.
+    /// Messages which were already waiting in the mailbox still get handled.
+    /// 
+    /// Returns `false` if messages of type `M` were already muted.
+    pub fn mute<M>(&self) -> bool
+    where
+        M: 'static + Send,
+        T: Handler<M>,
+    {
+        self.shared.mute::<M>()
+    }
+    /// Lets messages of type `M` through again, returning `false` if they were not muted
+    pub fn unmute<M>(&self) -> bool
+    where
+        M: 'static + Send,
+        T: Handler<M>,
+    {
+        self.shared.unmute::<M>()
+    }
+    /// Changes the capacity of the actor's mailbox, see [Addr::set_mailbox_capacity]
+    pub fn set_mailbox_capacity(&self, capacity: Option<usize>) {
+        self.shared.set_capacity(capacity)
     }
     #[inline]
     /// Returns [WeakAddr] of the actor.
     pub fn weak_address(&self) -> WeakAddr<T> {
         self.address.clone()
     }
+    /// Runs a synchronous, blocking closure on tokio's blocking thread pool and waits for its' result.
+    /// 
+    /// It prevents the closure from stalling the runtime, while the actor stays busy with the current message.
+    /// Panics of the closure get propagated to the handler.
+    /// If the runtime shuts down before the closure runs, it never completes.
+    pub async fn run_blocking<F, R>(&self, f: F) -> R
+    where
+        F: 'static + FnOnce() -> R + Send,
+        R: 'static + Send,
+    {
+        match tokio::task::spawn_blocking(f).await {
+            Ok(ret) => ret,
+            // The runtime is shutting down, dropping the handler along with it
+            Err(e) if e.is_cancelled() => std::future::pending().await,
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+    /// Runs a synchronous, blocking closure on tokio's blocking thread pool
+    /// without waiting for it, so that the actor can keep processing other messages.
+    /// 
+    /// The value returned by the closure gets sent to the actor as a message, like with [ActorContext::notify].
+    pub fn spawn_blocking<F, M>(&self, f: F)
+    where
+        F: 'static + FnOnce() -> M + Send,
+        M: 'static + Send,
+        T: Handler<M>,
+    {
+        let address = self.address.clone();
+        let guard = CounterGuard::new(self.shared.clone(), |shared| &shared.blocking_tasks);
+        tokio::task::spawn_blocking(move || {
+            let msg = f();
+            drop(guard);
+            if let Some(addr) = address.upgrade() {
+                addr.msg_queue.do_send(msg, false);
+            }
+        });
+    }
     /// Forwards messages from the given [Stream] to the actor's message queue
     /// 
     /// The actor will not be dropped as long as the stream produces values
-    pub fn add_stream<S, M>(&self, mut s: S)
+    /// 
+    /// The stream gets dropped immediately if the actor is gone.
+    /// 
+    /// The next message is taken from the stream once the previous one has been handled,
+    /// so a stream which produces messages faster than the others cannot starve them:
+    /// each stream has at most one message waiting in the mailbox, and the streams take turns.
+    /// See [ActorContext::add_stream_with_share] for streams which deserve more turns.
+    /// 
+    /// The returned handle allows [pausing](ActorContext::pause_stream) the stream.
+    pub fn add_stream<S, M>(&self, s: S) -> StreamHandle
+    where
+        S: 'static + Stream<Item = M> + Unpin + Send,
+        M: 'static + Send,
+        T: Handler<M>,
+    {
+        self.add_stream_with_share(s, 1)
+    }
+    /// Behaves like [ActorContext::add_stream], letting up to `share` messages of the stream
+    /// wait in the mailbox at once.
+    /// 
+    /// While all the streams are busy, the stream gets `share` turns for each turn of
+    /// a stream added via [ActorContext::add_stream].
+    pub fn add_stream_with_share<S, M>(&self, mut s: S, share: usize) -> StreamHandle
     where
         S: 'static + Stream<Item = M> + Unpin + Send,
         M: 'static + Send,
         T: Handler<M>,
     {
-        let addr = self.address.upgrade().unwrap();
+        assert!(share > 0, "The stream needs at least one message in flight");
+        let (paused_tx, mut paused) = watch::channel(false);
+        let id = StreamId(next_id());
+        let handle = StreamHandle {
+            id,
+            paused: Arc::new(paused_tx),
+        };
+        let mut addr = match self.address.upgrade() {
+            Some(addr) => addr,
+            None => return handle,
+        };
+        let weak = addr.downgrade();
+        let guard = CounterGuard::new(self.shared.clone(), |shared| &shared.streams);
+        tokio::spawn(async move {
+            let _guard = guard;
+            let mut in_flight = FuturesUnordered::new();
+            let mut ended = ContextEvent::StreamFinished(id);
+            'forwarding: loop {
+                while in_flight.len() >= share {
+                    let error = match in_flight.next().await {
+                        Some(Ok(Ok(_))) | None => continue,
+                        // A failed handler does not end the stream, the actor may get restarted
+                        Some(Ok(Err(ActorError::HandlerPanicked(_) | ActorError::Aborted(_)))) => {
+                            continue
+                        }
+                        Some(Ok(Err(error))) => error,
+                        Some(Err(_)) => addr.msg_queue.lost_error::<M>(),
+                    };
+                    // Only an actor gone for good cannot take the following messages
+                    if addr.msg_queue.shared().has_terminated() {
+                        ended = ContextEvent::StreamInterrupted { stream: id, error };
+                        break 'forwarding;
+                    }
+                }
+                if *paused.borrow_and_update() {
+                    // A paused stream does not keep the actor alive
+                    drop(addr);
+                    while *paused.borrow_and_update() {
+                        // All the handles are gone, so it would never get resumed
+                        if paused.changed().await.is_err() {
+                            break 'forwarding;
+                        }
+                    }
+                    addr = match weak.upgrade() {
+                        Some(addr) => addr,
+                        None => break,
+                    };
+                }
+                // Waiting for the next message gets interrupted if the stream gets paused
+                let pausing = async { paused.wait_for(|paused| *paused).await.is_ok() };
+                let next = AssertUnwindSafe(s.next()).catch_unwind();
+                let next = match select(pin!(next), pin!(pausing)).await {
+                    Either::Left((next, _)) => next,
+                    Either::Right((true, _)) => continue,
+                    // Cannot get paused anymore
+                    Either::Right((false, next)) => next.await,
+                };
+                let msg = match next {
+                    Ok(Some(msg)) => msg,
+                    Ok(None) => break,
+                    // The stream cannot be polled anymore
+                    Err(payload) => {
+                        addr.msg_queue.shared().failed_streams.fetch_add(1, Ordering::Relaxed);
+                        ended = ContextEvent::StreamFailed(StreamFailed {
+                            stream: id,
+                            message_type: std::any::type_name::<M>(),
+                            message: panic_message(&*payload),
+                        });
+                        break;
+                    }
+                };
+                // Deterministic runs interleave the message with the ones sent directly by the seed
+                for _ in 0..crate::random::seeded_below(4) {
+                    tokio::task::yield_now().await;
+                }
+                // Waiting for the response provides backpressure, so the capacity limit does not apply
+                match addr.msg_queue.send(msg, false) {
+                    Ok((_, resp, _token)) => in_flight.push(resp),
+                    // Muted messages get dropped, while the stream keeps going
+                    Err(ActorError::Muted(_)) => continue,
+                    // The actor no longer accepts messages
+                    Err(error) => {
+                        ended = ContextEvent::StreamInterrupted { stream: id, error };
+                        break;
+                    }
+                }
+            }
+            // Responses nobody waits for would end up as dead letters
+            while in_flight.next().await.is_some() {}
+            if let Some(addr) = weak.upgrade() {
+                addr.msg_queue.context_event(ended);
+            }
+        });
+        handle
+    }
+    /// Stops taking messages from the stream until it gets [resumed](ActorContext::resume_stream).
+    /// 
+    /// Messages already taken from the stream still get handled. A paused stream does not keep
+    /// the actor alive, and it ends if all of its' handles get dropped (e.g. along with the actor).
+    pub fn pause_stream(&self, stream: &StreamHandle) {
+        stream.paused.send_replace(true);
+    }
+    /// Resumes taking messages from a paused stream
+    pub fn resume_stream(&self, stream: &StreamHandle) {
+        stream.paused.send_replace(false);
+    }
+    /// Awaits the future off the actor's task, running its' continuations on the actor's task
+    /// as they become ready, see [crate::actor_future].
+    /// 
+    /// The future gets dropped once the actor is gone.
+    pub fn spawn(&self, future: ActorFuture<T, ()>) {
+        actor_future::drive(future, self.address.clone());
+    }
+    /// Delivers a [ContextEvent::TimerFired] event to the actor once the delay elapses.
+    /// 
+    /// Pending timers do not keep the actor alive.
+    pub fn set_timer(&self, delay: Duration) -> TimerId {
+        let id = TimerId(next_id());
+        let address = self.address.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            if let Some(addr) = address.upgrade() {
+                addr.msg_queue.context_event(ContextEvent::TimerFired(id));
+            }
+        });
+        id
+    }
+    /// Delivers a [ContextEvent::Terminated] event to the actor once the given actor terminates.
+    /// 
+    /// Watching does not keep either of the actors alive. The watching actor is considered to rely
+    /// on the watched one until it terminates, see [ActorContext::depend_on].
+    pub fn watch<A: Actor>(&self, actor: &Addr<A>) {
+        let watched = actor.msg_queue.shared().clone();
+        self.watch_until(watched.id(), async move { watched.terminated().await });
+    }
+    /// Like [ActorContext::watch], for actors of any type
+    pub(crate) fn watch_any(&self, actor: &AnyAddr) {
+        self.watch_until(actor.id(), actor.terminated_future());
+    }
+    fn watch_until<F>(&self, actor_id: ActorId, terminated: F)
+    where
+        F: 'static + Future<Output = RestartReason> + Send,
+    {
+        self.shared.depend_on(actor_id);
+        let (shared, address) = (self.shared.clone(), self.address.clone());
         tokio::spawn(async move {
-            while let Some(msg) = s.next().await {
-                let _ = addr.send(msg).await;
+            let reason = terminated.await;
+            shared.forget_dependency(actor_id);
+            if let Some(addr) = address.upgrade() {
+                addr.msg_queue.context_event(ContextEvent::Terminated { actor_id, reason });
             }
         });
     }
-    /// Creates new [ActorContext] from the given [WeakAddr].
+    /// Records that the actor relies on the given one, so that a coordinated shutdown stops it first,
+    /// see [crate::shutdown]
+    pub fn depend_on(&self, id: ActorId) {
+        self.shared.depend_on(id)
+    }
+    /// Creates new [ActorContext] from the given [WeakAddr] and the state it shares with the addresses.
     /// 
     /// The initial state is [ActorState::Starting]
     /// 
     /// The context will not be valid if the [WeakAddr] refers to a dropped actor
-    pub(crate) fn new(weakaddr: WeakAddr<T>) -> Self {
+    pub(crate) fn new(weakaddr: WeakAddr<T>, shared: Arc<ActorShared>) -> Self {
+        shared.set_state(ActorState::Starting);
         Self {
             address: weakaddr,
-            state: ActorState::Starting,
+            shared,
+            request_cancellation: None,
+            current_message_id: None,
+            strict_responses: false,
+            stash: Mutex::default(),
+        }
+    }
+    /// Creates another context for the same actor, used by the clones of a worker pool
+    /// 
+    /// For internal use.
+    pub(crate) fn fork(&self) -> Self {
+        Self {
+            address: self.address.clone(),
+            shared: self.shared.clone(),
+            request_cancellation: None,
+            current_message_id: None,
+            strict_responses: self.strict_responses,
+            stash: Mutex::default(),
         }
     }
     /// Sets the state of the actor to the given value
     /// 
     /// For internal use.
     pub(crate) fn set_state(&mut self, state: ActorState) {
-        self.state = state;
+        self.shared.set_state(state);
+    }
+    /// Returns the state shared with the addresses of the actor
+    /// 
+    /// For internal use.
+    pub(crate) fn shared(&self) -> &Arc<ActorShared> {
+        &self.shared
+    }
+    /// Sets the cancellation token of the message currently being handled
+    /// 
+    /// For internal use.
+    pub(crate) fn set_request_cancellation(&mut self, token: Option<CancellationToken>) {
+        self.request_cancellation = token;
+    }
+    /// Sets the identifier of the message currently being handled
+    /// 
+    /// For internal use.
+    pub(crate) fn set_current_message_id(&mut self, id: Option<MessageId>) {
+        self.current_message_id = id;
     }
     /// Replaces the internal [WeakAddr]
     /// 
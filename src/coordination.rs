@@ -0,0 +1,346 @@
+//! Coordination primitives implemented as actors
+//!
+//! [Semaphore], [TokenBucket] and [Barrier] are ordinary actors, so their' addresses can be handed
+//! to any number of actors and tasks, and they can be supervised or pooled like any other actor.
+//! Waiting never blocks them: the callers get answered once their' turn comes.
+//!
+//! All of them are fair: callers get served in the order in which their' requests arrived,
+//! and a caller asking for a lot cannot be overtaken by the ones asking for less.
+
+use crate::{
+    actor::{Actor, Handler},
+    addr::Addr,
+    context::ActorContext,
+    error::ActorError,
+};
+use async_trait::async_trait;
+use std::{
+    collections::VecDeque,
+    fmt,
+    time::{Duration, Instant},
+};
+use tokio::sync::oneshot;
+
+/// Number of permits or tokens along with the caller waiting for them
+type Waiter<T> = (usize, oneshot::Sender<T>);
+
+/// Actor limiting how many permits can be held at once
+#[derive(Debug)]
+pub struct Semaphore {
+    available: usize,
+    waiters: VecDeque<Waiter<Permit>>,
+}
+
+impl Semaphore {
+    /// Creates a semaphore with the given number of permits
+    pub fn new(permits: usize) -> Self {
+        Self {
+            available: permits,
+            waiters: VecDeque::new(),
+        }
+    }
+    /// Hands out the permits to the waiters, in the order in which they asked
+    fn grant(&mut self, ctx: &ActorContext<Self>) {
+        while let Some((permits, _)) = self.waiters.front() {
+            let permits = *permits;
+            if permits > self.available {
+                break;
+            }
+            let (_, tx) = self.waiters.pop_front().unwrap();
+            self.available -= permits;
+            let permit = Permit {
+                semaphore: Some(ctx.address()),
+                permits,
+            };
+            // The caller has given up
+            if let Err(mut permit) = tx.send(permit) {
+                permit.semaphore = None;
+                self.available += permits;
+            }
+        }
+    }
+}
+
+impl Actor for Semaphore {}
+
+impl Addr<Semaphore> {
+    /// Waits until the given number of permits is available, then takes them
+    pub async fn acquire(&self, permits: usize) -> Result<Permit, ActorError> {
+        let granted = self.send(Acquire(permits)).await?;
+        granted
+            .await
+            .map_err(|_| self.msg_queue.lost_error::<Acquire>())
+    }
+    /// Takes the given number of permits if they're available right away,
+    /// and there's nobody waiting for permits already
+    pub async fn try_acquire(&self, permits: usize) -> Result<Option<Permit>, ActorError> {
+        self.send(TryAcquire(permits)).await
+    }
+}
+
+/// Permits taken from a [Semaphore], given back when dropped
+pub struct Permit {
+    semaphore: Option<Addr<Semaphore>>,
+    permits: usize,
+}
+
+impl fmt::Debug for Permit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Permit")
+            .field("permits", &self.permits)
+            .finish()
+    }
+}
+
+impl Permit {
+    /// Number of permits held
+    pub fn permits(&self) -> usize {
+        self.permits
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some(semaphore) = self.semaphore.take() {
+            // Releases must not get lost because the mailbox is full
+            semaphore.msg_queue.do_send(Release(self.permits), false);
+        }
+    }
+}
+
+#[doc(hidden)]
+pub struct Acquire(usize);
+
+#[async_trait]
+impl Handler<Acquire> for Semaphore {
+    type Response = oneshot::Receiver<Permit>;
+    async fn handle(&mut self, msg: Acquire, ctx: &mut ActorContext<Self>) -> Self::Response {
+        let (tx, rx) = oneshot::channel();
+        self.waiters.push_back((msg.0, tx));
+        self.grant(ctx);
+        rx
+    }
+}
+
+#[doc(hidden)]
+pub struct TryAcquire(usize);
+
+#[async_trait]
+impl Handler<TryAcquire> for Semaphore {
+    type Response = Option<Permit>;
+    async fn handle(&mut self, msg: TryAcquire, ctx: &mut ActorContext<Self>) -> Option<Permit> {
+        if !self.waiters.is_empty() || msg.0 > self.available {
+            return None;
+        }
+        self.available -= msg.0;
+        Some(Permit {
+            semaphore: Some(ctx.address()),
+            permits: msg.0,
+        })
+    }
+}
+
+#[doc(hidden)]
+pub struct Release(usize);
+
+#[async_trait]
+impl Handler<Release> for Semaphore {
+    type Response = ();
+    async fn handle(&mut self, msg: Release, ctx: &mut ActorContext<Self>) {
+        self.available += msg.0;
+        self.grant(ctx);
+    }
+}
+
+/// Actor handing out tokens at a steady rate, for rate limiting
+///
+/// The bucket holds up to `capacity` tokens, which allows bursts, and gets a token
+/// every `interval`. Requests for more tokens than the capacity get granted once the bucket is full,
+/// emptying it.
+#[derive(Debug)]
+pub struct TokenBucket {
+    capacity: usize,
+    interval: Duration,
+    tokens: usize,
+    /// When the latest token was added
+    refilled_at: Instant,
+    waiters: VecDeque<Waiter<()>>,
+    /// Whether a [Refill] is on its' way
+    refill_pending: bool,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket
+    pub fn new(capacity: usize, interval: Duration) -> Self {
+        assert!(
+            !interval.is_zero(),
+            "Tokens need to be added at a finite rate"
+        );
+        Self {
+            capacity,
+            interval,
+            tokens: capacity,
+            refilled_at: Instant::now(),
+            waiters: VecDeque::new(),
+            refill_pending: false,
+        }
+    }
+    /// Adds the tokens accumulated since the last refill
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let added = (now.duration_since(self.refilled_at).as_nanos() / self.interval.as_nanos())
+            .min(usize::MAX as u128) as usize;
+        if self.tokens.saturating_add(added) >= self.capacity {
+            self.tokens = self.capacity;
+            self.refilled_at = now;
+        } else {
+            self.tokens += added;
+            self.refilled_at += self.interval * added as u32;
+        }
+    }
+    /// Hands out the tokens to the waiters, in the order in which they asked
+    fn grant(&mut self, ctx: &ActorContext<Self>) {
+        self.refill();
+        while let Some((tokens, _)) = self.waiters.front() {
+            let tokens = (*tokens).min(self.capacity);
+            if tokens > self.tokens {
+                break;
+            }
+            let (_, tx) = self.waiters.pop_front().unwrap();
+            // Tokens of callers which have given up stay in the bucket
+            if tx.send(()).is_ok() {
+                self.tokens -= tokens;
+            }
+        }
+        if !self.waiters.is_empty() && !self.refill_pending {
+            self.refill_pending = true;
+            let delay =
+                (self.refilled_at + self.interval).saturating_duration_since(Instant::now());
+            // The weak address does not keep the bucket alive while waiting
+            let bucket = ctx.weak_address();
+            tokio::spawn(async move {
+                tokio::time::sleep(delay).await;
+                if let Some(bucket) = bucket.upgrade() {
+                    bucket.msg_queue.do_send(Refill, false);
+                }
+            });
+        }
+    }
+}
+
+impl Actor for TokenBucket {}
+
+impl Addr<TokenBucket> {
+    /// Waits until the given number of tokens is available, then takes them
+    pub async fn take(&self, tokens: usize) -> Result<(), ActorError> {
+        let granted = self.send(Take(tokens)).await?;
+        granted
+            .await
+            .map_err(|_| self.msg_queue.lost_error::<Take>())
+    }
+    /// Takes the given number of tokens if they're available right away,
+    /// and there's nobody waiting for tokens already
+    pub async fn try_take(&self, tokens: usize) -> Result<bool, ActorError> {
+        self.send(TryTake(tokens)).await
+    }
+}
+
+#[doc(hidden)]
+pub struct Take(usize);
+
+#[async_trait]
+impl Handler<Take> for TokenBucket {
+    type Response = oneshot::Receiver<()>;
+    async fn handle(&mut self, msg: Take, ctx: &mut ActorContext<Self>) -> Self::Response {
+        let (tx, rx) = oneshot::channel();
+        self.waiters.push_back((msg.0, tx));
+        self.grant(ctx);
+        rx
+    }
+}
+
+#[doc(hidden)]
+pub struct TryTake(usize);
+
+#[async_trait]
+impl Handler<TryTake> for TokenBucket {
+    type Response = bool;
+    async fn handle(&mut self, msg: TryTake, _ctx: &mut ActorContext<Self>) -> bool {
+        self.refill();
+        let tokens = msg.0.min(self.capacity);
+        if !self.waiters.is_empty() || tokens > self.tokens {
+            return false;
+        }
+        self.tokens -= tokens;
+        true
+    }
+}
+
+#[doc(hidden)]
+pub struct Refill;
+
+#[async_trait]
+impl Handler<Refill> for TokenBucket {
+    type Response = ();
+    async fn handle(&mut self, _msg: Refill, ctx: &mut ActorContext<Self>) {
+        self.refill_pending = false;
+        self.grant(ctx);
+    }
+}
+
+/// Actor letting callers wait until a given number of them has arrived
+///
+/// The barrier can be reused: once it releases the callers, the next ones start a new generation.
+#[derive(Debug)]
+pub struct Barrier {
+    parties: usize,
+    waiters: Vec<oneshot::Sender<bool>>,
+}
+
+impl Barrier {
+    /// Creates a barrier releasing the callers in groups of `parties`
+    pub fn new(parties: usize) -> Self {
+        assert!(
+            parties > 0,
+            "The barrier needs to wait for at least one caller"
+        );
+        Self {
+            parties,
+            waiters: Vec::with_capacity(parties),
+        }
+    }
+}
+
+impl Actor for Barrier {}
+
+impl Addr<Barrier> {
+    /// Waits until all the parties have arrived.
+    ///
+    /// Returns `true` for exactly one of the callers of each generation, the one which arrived last.
+    pub async fn wait(&self) -> Result<bool, ActorError> {
+        let released = self.send(Wait).await?;
+        released
+            .await
+            .map_err(|_| self.msg_queue.lost_error::<Wait>())
+    }
+}
+
+#[doc(hidden)]
+pub struct Wait;
+
+#[async_trait]
+impl Handler<Wait> for Barrier {
+    type Response = oneshot::Receiver<bool>;
+    async fn handle(&mut self, _msg: Wait, _ctx: &mut ActorContext<Self>) -> Self::Response {
+        let (tx, rx) = oneshot::channel();
+        self.waiters.push(tx);
+        if self.waiters.len() == self.parties {
+            let leader = self.waiters.pop().unwrap();
+            for waiter in self.waiters.drain(..) {
+                let _ = waiter.send(false);
+            }
+            let _ = leader.send(true);
+        }
+        rx
+    }
+}
@@ -0,0 +1,218 @@
+//! Records of messages and responses which could not be delivered
+//!
+//! Dead letters are published process-wide. Anyone interested can [DeadLetters::subscribe] to them,
+//! while [DeadLetters::count] serves as a cheap metric.
+//!
+//! ## Replay
+//!
+//! Records tell what got lost, but not the messages themselves. When losing them is unacceptable,
+//! e.g. during a transient outage of an actor, [DeadLetters::capture] makes the messages sent
+//! without awaiting the response which got rejected (because the mailbox was full, the actor was
+//! not ready or had stopped, or their' type was muted) or were left in the mailbox of a stopped actor
+//! get kept along with their' records. Once the actor has recovered, [DeadLetters::replay_into] sends
+//! it the ones addressed to it again, while [DeadLetters::replay_type_into] hands a fresh actor
+//! the ones addressed to any actor of its' type.
+//! Messages which have been sent via [Addr::send] are not captured, as their' senders have already
+//! been told about the failure.
+
+use crate::{
+    actor::{Actor, ActorId, MessageId},
+    addr::Addr,
+    message_queue::QueuePayload,
+};
+use std::{
+    any::{Any, TypeId},
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex, OnceLock,
+    },
+};
+use tokio::sync::broadcast;
+
+/// How many records are buffered for each subscriber before the oldest ones get dropped
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// Why a dead letter came into existence
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DeadLetterReason {
+    /// The message got handled, but its' sender stopped waiting for the response
+    ResponseUndeliverable,
+    /// The sender stopped waiting for the response before the message got handled,
+    /// so the message has been skipped
+    Cancelled,
+    /// The message has been sent without awaiting the response, but the mailbox was full
+    MailboxFull,
+    /// The message has been sent without awaiting the response, but its' type was muted,
+    /// see [crate::context::ActorContext::mute]
+    Muted,
+    /// The message has been sent without awaiting the response before the actor became ready,
+    /// see [crate::actor::Readiness::Reject]
+    NotReady,
+    /// The message has been sent without awaiting the response to an actor which had stopped
+    Stopped,
+    /// The message has been sent without awaiting the response, but the actor stopped before handling it,
+    /// see [crate::actor::StopMode]
+    Abandoned,
+}
+
+/// Record of a dead letter
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DeadLetter {
+    /// Identifier of the message
+    pub message_id: MessageId,
+    /// Type name of the receiving actor
+    pub actor_type: &'static str,
+    /// Type name of the message
+    pub message_type: &'static str,
+    pub reason: DeadLetterReason,
+}
+
+impl DeadLetter {
+    pub(crate) fn new<A, M>(message_id: MessageId, reason: DeadLetterReason) -> Self {
+        Self {
+            message_id,
+            actor_type: std::any::type_name::<A>(),
+            message_type: std::any::type_name::<M>(),
+            reason,
+        }
+    }
+}
+
+/// Process-wide sink for dead letters
+#[derive(Debug)]
+pub struct DeadLetters;
+
+static COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Rejected message kept for [DeadLetters::replay_into]
+struct Captured {
+    letter: DeadLetter,
+    actor_id: ActorId,
+    actor_type: TypeId,
+    message_type: TypeId,
+    /// The [QueuePayload] of the actor
+    envelope: Box<dyn Any + Send>,
+}
+
+/// Maximum number of captured messages, `0` meaning that capturing is disabled.
+///
+/// Checked before locking [CAPTURED], so that dead letters cost no lock while nothing gets captured.
+static CAPACITY: AtomicUsize = AtomicUsize::new(0);
+static CAPTURED: Mutex<VecDeque<Captured>> = Mutex::new(VecDeque::new());
+
+fn channel() -> &'static broadcast::Sender<DeadLetter> {
+    static CHANNEL: OnceLock<broadcast::Sender<DeadLetter>> = OnceLock::new();
+    CHANNEL.get_or_init(|| broadcast::channel(CHANNEL_CAPACITY).0)
+}
+
+impl DeadLetters {
+    /// Returns a receiver of all dead letters recorded from now on
+    pub fn subscribe() -> broadcast::Receiver<DeadLetter> {
+        channel().subscribe()
+    }
+    /// Returns the number of dead letters recorded since the start of the process
+    pub fn count() -> u64 {
+        COUNT.load(Ordering::Relaxed)
+    }
+    /// Records a dead letter
+    pub(crate) fn record(letter: DeadLetter) {
+        COUNT.fetch_add(1, Ordering::Relaxed);
+        // Nobody might be listening
+        let _ = channel().send(letter);
+    }
+    /// Records a dead letter of a message which has not been handled,
+    /// keeping the message if capturing is enabled
+    pub(crate) fn record_replayable<A: Actor, M: 'static>(
+        actor_id: ActorId,
+        letter: DeadLetter,
+        envelope: QueuePayload<A>,
+    ) {
+        Self::record(letter.clone());
+        if CAPACITY.load(Ordering::Acquire) == 0 {
+            return;
+        }
+        let mut captured = CAPTURED.lock().unwrap();
+        // Might have changed before locking
+        let capacity = CAPACITY.load(Ordering::Acquire);
+        if capacity == 0 {
+            return;
+        }
+        if captured.len() >= capacity {
+            captured.pop_front();
+        }
+        captured.push_back(Captured {
+            letter,
+            actor_id,
+            actor_type: TypeId::of::<A>(),
+            message_type: TypeId::of::<M>(),
+            envelope: Box::new(envelope),
+        });
+    }
+    /// Starts keeping the rejected messages for [DeadLetters::replay_into], see [crate::dead_letters#replay].
+    ///
+    /// At most `capacity` messages are kept, the oldest ones getting dropped to make room for new ones.
+    /// A capacity of `0` stops capturing, dropping the captured messages.
+    pub fn capture(capacity: usize) {
+        let mut captured = CAPTURED.lock().unwrap();
+        CAPACITY.store(capacity, Ordering::Release);
+        let excess = captured.len().saturating_sub(capacity);
+        captured.drain(..excess);
+    }
+    /// Returns the records of the captured messages, oldest first
+    pub fn captured() -> Vec<DeadLetter> {
+        let captured = CAPTURED.lock().unwrap();
+        captured.iter().map(|c| c.letter.clone()).collect()
+    }
+    /// Sends the captured messages addressed to the given actor to it again,
+    /// in the order in which they got rejected, returning how many of them got sent.
+    ///
+    /// Like with [Addr::do_send], their' responses get ignored. Messages which get rejected again
+    /// stay captured, without being recorded as dead letters again.
+    pub fn replay_into<A: Actor>(addr: &Addr<A>) -> usize {
+        let actor_id = addr.id();
+        Self::replay(addr, |c| c.actor_id == actor_id)
+    }
+    /// Like [DeadLetters::replay_into], but sends the captured messages addressed to any actor of type `A`,
+    /// e.g. to a fresh actor taking over from a stopped one.
+    pub fn replay_type_into<A: Actor>(addr: &Addr<A>) -> usize {
+        Self::replay(addr, |_| true)
+    }
+    fn replay<A: Actor>(addr: &Addr<A>, filter: impl Fn(&Captured) -> bool) -> usize {
+        let actor_type = TypeId::of::<A>();
+        let replayed: VecDeque<Captured> = {
+            let mut captured = CAPTURED.lock().unwrap();
+            let (replayed, kept) = std::mem::take(&mut *captured)
+                .into_iter()
+                .partition(|c| c.actor_type == actor_type && filter(c));
+            *captured = kept;
+            replayed
+        };
+        let mut sent = 0;
+        let mut rejected = Vec::new();
+        for captured in replayed {
+            if addr.msg_queue.shared().is_type_muted(captured.message_type) {
+                rejected.push(captured);
+                continue;
+            }
+            let envelope = *captured.envelope.downcast::<QueuePayload<A>>().unwrap();
+            match addr.msg_queue.enqueue_payload(envelope, true) {
+                Ok(()) => sent += 1,
+                Err((_, envelope)) => rejected.push(Captured {
+                    envelope: Box::new(envelope),
+                    ..captured
+                }),
+            }
+        }
+        let mut captured = CAPTURED.lock().unwrap();
+        for rejected in rejected.into_iter().rev() {
+            captured.push_front(rejected);
+        }
+        // Messages might have been captured in the meantime
+        let excess = captured
+            .len()
+            .saturating_sub(CAPACITY.load(Ordering::Acquire));
+        captured.drain(..excess);
+        sent
+    }
+}
@@ -0,0 +1,115 @@
+//! Opt-in detection of deadlocks caused by cyclic [crate::addr::Addr::send] calls
+//!
+//! When an actor awaits the response from an actor which (transitively) awaits a response from the former,
+//! none of them can make progress. Once [set_detection] is enabled, such `send` calls made
+//! from within message handlers panic, describing the cycle, instead of hanging forever.
+//!
+//! The detection is meant for debugging, as it serializes all `send` calls made by actors through a global lock.
+
+use crate::actor::ActorId;
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, OnceLock,
+    },
+};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+tokio::task_local! {
+    /// The actor whose runner loop is being polled
+    static CURRENT_ACTOR: ActorId;
+}
+
+/// Enables or disables the deadlock detection process-wide
+pub fn set_detection(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns `true` if the deadlock detection is enabled
+pub fn detection_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Returns the actor whose task is currently running, if any
+pub(crate) fn current_actor() -> Option<ActorId> {
+    CURRENT_ACTOR.try_with(|id| *id).ok()
+}
+
+/// Marks the future as the runner loop of the given actor
+pub(crate) async fn scope<F: Future>(id: ActorId, f: F) -> F::Output {
+    CURRENT_ACTOR.scope(id, f).await
+}
+
+/// For each actor, the actors it awaits responses from
+fn waits() -> &'static Mutex<HashMap<ActorId, Vec<ActorId>>> {
+    static WAITS: OnceLock<Mutex<HashMap<ActorId, Vec<ActorId>>>> = OnceLock::new();
+    WAITS.get_or_init(Mutex::default)
+}
+
+/// Finds a path of awaited responses leading from `from` to `to`
+fn find_path(
+    waits: &HashMap<ActorId, Vec<ActorId>>,
+    from: ActorId,
+    to: ActorId,
+    path: &mut Vec<ActorId>,
+) -> bool {
+    path.push(from);
+    if from == to {
+        return true;
+    }
+    for &next in waits.get(&from).into_iter().flatten() {
+        if !path.contains(&next) && find_path(waits, next, to, path) {
+            return true;
+        }
+    }
+    path.pop();
+    false
+}
+
+/// Removes the edge of the wait graph when the response arrives (or the request gets cancelled)
+#[derive(Debug)]
+pub(crate) struct WaitGuard {
+    from: ActorId,
+    to: ActorId,
+}
+
+impl Drop for WaitGuard {
+    fn drop(&mut self) {
+        let mut waits = waits().lock().unwrap();
+        if let Some(awaited) = waits.get_mut(&self.from) {
+            if let Some(pos) = awaited.iter().position(|id| *id == self.to) {
+                awaited.swap_remove(pos);
+            }
+            if awaited.is_empty() {
+                waits.remove(&self.from);
+            }
+        }
+    }
+}
+
+/// Records that the current actor is about to await a response from the given one.
+///
+/// # Panics
+///
+/// Panics if the detection is enabled and awaiting the response would complete a cycle.
+pub(crate) fn begin_wait(to: ActorId) -> Option<WaitGuard> {
+    if !detection_enabled() {
+        return None;
+    }
+    let from = current_actor()?;
+    let mut waits = waits().lock().unwrap();
+    let mut path = Vec::new();
+    if find_path(&waits, to, from, &mut path) {
+        drop(waits);
+        let cycle: Vec<String> = std::iter::once(from)
+            .chain(path)
+            .map(|id| id.to_string())
+            .collect();
+        panic!("Deadlock detected, actors await each other: {}", cycle.join(" -> "));
+    }
+    waits.entry(from).or_default().push(to);
+    Some(WaitGuard { from, to })
+}
@@ -0,0 +1,175 @@
+//! Pull-based streams of items produced by actors
+//!
+//! [Addr::into_stream] turns an actor into a [Stream], which tells the actor how many items
+//! it's ready to receive via [Demand] messages, like in reactive streams.
+//! The actor never produces more than the consumer asked for, so a slow consumer
+//! slows the actor down instead of piling the items up in memory.
+
+use crate::{
+    actor::{Actor, Handler},
+    addr::Addr,
+    supervised::Backoff,
+};
+use futures_util::stream::{self, BoxStream, Stream, StreamExt};
+use std::{
+    fmt,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::sync::mpsc;
+
+/// Delays asking again after demands got given back without any items
+const RETRY: Backoff = Backoff {
+    base: Duration::from_millis(1),
+    max: Duration::from_secs(1),
+    jitter: 0.2,
+    reset_after: Duration::ZERO,
+};
+
+enum Signal<M> {
+    Item(M),
+    /// Demand given back without producing items
+    Returned(usize),
+    Complete,
+}
+
+/// Message telling the actor that the consumer of a [DemandStream] is ready for more items
+///
+/// The actor can produce the items right away or keep the demand and fulfil it later,
+/// e.g. once new data arrives, which is what actors without items at hand should do.
+/// Dropping it gives the rest of the demand back, so that the stream asks for it again;
+/// if no items arrived in the meantime, only after a delay growing up to a second.
+pub struct Demand<M> {
+    remaining: usize,
+    tx: mpsc::UnboundedSender<Signal<M>>,
+}
+
+impl<M> fmt::Debug for Demand<M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Demand")
+            .field("remaining", &self.remaining)
+            .finish()
+    }
+}
+
+impl<M> Demand<M> {
+    /// Number of items the consumer is still waiting for
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+    /// Returns `true` if the consumer has been dropped
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+    /// Delivers the item to the consumer.
+    ///
+    /// Gives the item back if the demand is exhausted or the consumer has been dropped.
+    pub fn emit(&mut self, item: M) -> Result<(), M> {
+        if self.remaining == 0 {
+            return Err(item);
+        }
+        self.tx.send(Signal::Item(item)).map_err(|e| match e.0 {
+            Signal::Item(item) => item,
+            _ => unreachable!(),
+        })?;
+        self.remaining -= 1;
+        Ok(())
+    }
+    /// Ends the stream, once the consumer receives the items produced so far
+    pub fn complete(mut self) {
+        self.remaining = 0;
+        let _ = self.tx.send(Signal::Complete);
+    }
+}
+
+impl<M> Drop for Demand<M> {
+    fn drop(&mut self) {
+        if self.remaining > 0 {
+            let _ = self.tx.send(Signal::Returned(self.remaining));
+        }
+    }
+}
+
+/// Stream of items produced by an actor on demand, see [Addr::into_stream]
+///
+/// It ends once the actor completes it or stops.
+pub struct DemandStream<M> {
+    inner: BoxStream<'static, M>,
+}
+
+impl<M> Stream for DemandStream<M> {
+    type Item = M;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<M>> {
+        self.inner.poll_next_unpin(cx)
+    }
+}
+
+impl<M> fmt::Debug for DemandStream<M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DemandStream").finish_non_exhaustive()
+    }
+}
+
+struct State<A: Actor, M> {
+    addr: Addr<A>,
+    tx: mpsc::UnboundedSender<Signal<M>>,
+    rx: mpsc::UnboundedReceiver<Signal<M>>,
+    /// Items asked for, but not received yet
+    outstanding: usize,
+    /// Demands sent since the last item arrived
+    idle: u32,
+}
+
+impl<A, M> State<A, M>
+where
+    A: Handler<Demand<M>>,
+    M: 'static + Send,
+{
+    async fn next(mut self, batch: usize) -> Option<(M, Self)> {
+        loop {
+            if self.outstanding == 0 {
+                if self.idle > 0 {
+                    tokio::time::sleep(RETRY.delay(self.idle - 1)).await;
+                }
+                let demand = Demand {
+                    remaining: batch,
+                    tx: self.tx.clone(),
+                };
+                self.addr.send(demand).await.ok()?;
+                self.outstanding = batch;
+                self.idle += 1;
+            }
+            // The stream keeps a sender, so the channel never closes
+            match self.rx.recv().await? {
+                Signal::Item(item) => {
+                    self.outstanding -= 1;
+                    self.idle = 0;
+                    return Some((item, self));
+                }
+                Signal::Returned(n) => self.outstanding -= n,
+                Signal::Complete => return None,
+            }
+        }
+    }
+}
+
+/// Creates the stream, see [Addr::into_stream]
+pub(crate) fn demand_stream<A, M>(addr: Addr<A>, batch: usize) -> DemandStream<M>
+where
+    A: Handler<Demand<M>>,
+    M: 'static + Send,
+{
+    assert!(batch > 0, "The stream needs to ask for at least one item at a time");
+    let (tx, rx) = mpsc::unbounded_channel();
+    let state = State {
+        addr,
+        tx,
+        rx,
+        outstanding: 0,
+        idle: 0,
+    };
+    DemandStream {
+        inner: stream::unfold(state, move |state| state.next(batch)).boxed(),
+    }
+}
@@ -0,0 +1,241 @@
+//! Leader election among local actors
+//!
+//! Some work, like periodic cleanups, should be performed by only one of many identical actors.
+//! The candidates [join](Addr::join) an [Election], which elects one of them at a time,
+//! telling it via an [Elected] message, and elects the next one in the order of joining
+//! once the leader leaves or terminates.
+//!
+//! The leader holds a lease, renewed by answering the [pings](Addr::ping) of the election.
+//! A leader which does not answer in time (e.g. because it's stuck in a handler) loses the lease,
+//! gets [Demoted] and moves to the end of the line.
+
+use crate::{
+    actor::{Actor, ActorId, Handler},
+    addr::{Addr, AnyAddr, Recipient},
+    context::{ActorContext, ContextEvent},
+    error::ActorError,
+};
+use async_trait::async_trait;
+use std::{collections::VecDeque, fmt, time::Duration};
+
+/// Message telling a candidate that it has become the leader
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Elected;
+
+/// Message telling the leader that it's no longer the leader
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Demoted;
+
+struct Candidate {
+    addr: AnyAddr,
+    elected: Recipient<Elected, ()>,
+    demoted: Recipient<Demoted, ()>,
+}
+
+/// Actor electing the leader among its' candidates, see [crate::election]
+pub struct Election {
+    lease: Duration,
+    /// The leader comes first
+    candidates: VecDeque<Candidate>,
+    leader: Option<ActorId>,
+}
+
+impl fmt::Debug for Election {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Election")
+            .field("lease", &self.lease)
+            .field("candidates", &self.candidates.len())
+            .field("leader", &self.leader)
+            .finish()
+    }
+}
+
+impl Default for Election {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Election {
+    /// Creates an election without candidates, with leases of 5 seconds
+    pub fn new() -> Self {
+        Self {
+            lease: Duration::from_secs(5),
+            candidates: VecDeque::new(),
+            leader: None,
+        }
+    }
+    /// Sets for how long the leader may not answer before losing the lease
+    pub fn with_lease(mut self, lease: Duration) -> Self {
+        self.lease = lease;
+        self
+    }
+    /// Elects the first candidate, unless it's already the leader
+    fn elect(&mut self) {
+        let Some(first) = self.candidates.front() else {
+            self.leader = None;
+            return;
+        };
+        if self.leader != Some(first.addr.id()) {
+            self.leader = Some(first.addr.id());
+            first.elected.do_send(Elected);
+        }
+    }
+    /// Removes the candidate, returning it
+    fn remove(&mut self, id: ActorId) -> Option<Candidate> {
+        let index = self.candidates.iter().position(|c| c.addr.id() == id)?;
+        if self.leader == Some(id) {
+            self.leader = None;
+        }
+        self.candidates.remove(index)
+    }
+}
+
+#[async_trait]
+impl Actor for Election {
+    async fn started(&mut self, ctx: &mut ActorContext<Self>) {
+        let lease = self.lease;
+        let election = ctx.weak_address();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(lease).await;
+                match election.upgrade() {
+                    Some(election) => election.do_send(CheckLease),
+                    None => break,
+                }
+            }
+        });
+    }
+    async fn stopped(&mut self, _ctx: &mut ActorContext<Self>) {
+        if let Some(leader) = self.leader.take() {
+            if let Some(candidate) = self.candidates.iter().find(|c| c.addr.id() == leader) {
+                candidate.demoted.do_send(Demoted);
+            }
+        }
+    }
+    async fn context_event(&mut self, _ctx: &mut ActorContext<Self>, event: ContextEvent) {
+        if let ContextEvent::Terminated { actor_id, .. } = event {
+            if self.remove(actor_id).is_some() {
+                self.elect();
+            }
+        }
+    }
+}
+
+impl Addr<Election> {
+    /// Adds the actor to the candidates.
+    ///
+    /// It gets elected right away if there's no leader.
+    pub async fn join<A>(&self, candidate: &Addr<A>) -> Result<(), ActorError>
+    where
+        A: Handler<Elected, Response = ()> + Handler<Demoted, Response = ()>,
+    {
+        let candidate = Candidate {
+            addr: candidate.clone().into(),
+            elected: candidate.recipient(),
+            demoted: candidate.recipient(),
+        };
+        self.send(Join(candidate)).await
+    }
+    /// Removes the actor from the candidates, demoting it if it's the leader.
+    ///
+    /// Returns `false` if it was not a candidate.
+    pub async fn leave(&self, candidate: ActorId) -> Result<bool, ActorError> {
+        self.send(Leave(candidate)).await
+    }
+    /// Returns the identifier of the leader
+    pub async fn leader(&self) -> Result<Option<ActorId>, ActorError> {
+        self.send(GetLeader).await
+    }
+}
+
+#[doc(hidden)]
+pub struct Join(Candidate);
+
+#[async_trait]
+impl Handler<Join> for Election {
+    type Response = ();
+    async fn handle(&mut self, msg: Join, ctx: &mut ActorContext<Self>) {
+        let id = msg.0.addr.id();
+        if self.candidates.iter().any(|c| c.addr.id() == id) {
+            return;
+        }
+        ctx.watch_any(&msg.0.addr);
+        self.candidates.push_back(msg.0);
+        self.elect();
+    }
+}
+
+#[doc(hidden)]
+pub struct Leave(ActorId);
+
+#[async_trait]
+impl Handler<Leave> for Election {
+    type Response = bool;
+    async fn handle(&mut self, msg: Leave, _ctx: &mut ActorContext<Self>) -> bool {
+        let was_leader = self.leader == Some(msg.0);
+        let Some(candidate) = self.remove(msg.0) else {
+            return false;
+        };
+        if was_leader {
+            candidate.demoted.do_send(Demoted);
+        }
+        self.elect();
+        true
+    }
+}
+
+#[doc(hidden)]
+pub struct GetLeader;
+
+#[async_trait]
+impl Handler<GetLeader> for Election {
+    type Response = Option<ActorId>;
+    async fn handle(&mut self, _msg: GetLeader, _ctx: &mut ActorContext<Self>) -> Option<ActorId> {
+        self.leader
+    }
+}
+
+#[doc(hidden)]
+pub struct CheckLease;
+
+#[async_trait]
+impl Handler<CheckLease> for Election {
+    type Response = ();
+    async fn handle(&mut self, _msg: CheckLease, ctx: &mut ActorContext<Self>) {
+        let Some(leader) = self.candidates.front() else {
+            return;
+        };
+        let (leader, lease) = (leader.addr.clone(), self.lease);
+        let election = ctx.weak_address();
+        // Pinged from a task of its' own, so that the election keeps answering in the meantime
+        tokio::spawn(async move {
+            if leader.ping(lease).await.is_err() {
+                if let Some(election) = election.upgrade() {
+                    election.do_send(LeaseExpired(leader.id()));
+                }
+            }
+        });
+    }
+}
+
+#[doc(hidden)]
+pub struct LeaseExpired(ActorId);
+
+#[async_trait]
+impl Handler<LeaseExpired> for Election {
+    type Response = ();
+    async fn handle(&mut self, msg: LeaseExpired, _ctx: &mut ActorContext<Self>) {
+        if self.leader != Some(msg.0) {
+            return;
+        }
+        let leader = self.candidates.pop_front().unwrap();
+        leader.demoted.do_send(Demoted);
+        self.leader = None;
+        // Still a candidate, unless it has terminated
+        if leader.addr.connected() {
+            self.candidates.push_back(leader);
+        }
+        self.elect();
+    }
+}
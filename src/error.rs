@@ -1,32 +1,100 @@
 //! Stores aspartam's error type
 
-use crate::{actor::Actor, message_queue::QueuePayload};
+use crate::actor::ActorId;
+use std::fmt;
 use thiserror::Error;
-use tokio::sync::mpsc::error::SendError as TokioSendError;
-use tokio::sync::oneshot::error::RecvError as TokioRecvError;
 
-#[derive(Error, Debug, PartialEq, Eq)]
+/// Describes the interaction which failed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ErrorContext {
+    /// Identifier of the actor the message was sent to
+    pub actor_id: ActorId,
+    /// Type name of the actor the message was sent to
+    pub actor_type: &'static str,
+    /// Type name of the message
+    pub message_type: &'static str,
+}
+
+impl ErrorContext {
+    pub(crate) fn new<A, M>(actor_id: ActorId) -> Self {
+        Self {
+            actor_id,
+            actor_type: std::any::type_name::<A>(),
+            message_type: std::any::type_name::<M>(),
+        }
+    }
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "message `{}` sent to actor `{}` {}",
+            self.message_type, self.actor_type, self.actor_id
+        )
+    }
+}
+
+#[derive(Error, Clone, Debug, PartialEq, Eq)]
 /// The error type used by actor interactions
+///
+/// Each variant carries the [ErrorContext] of the failed interaction.
 pub enum ActorError {
-    #[error("Failed to enqueue new message for actor. Actor has most likely stopped.")]
+    #[error("Failed to enqueue {0}. Actor has most likely stopped.")]
     /// Failed to enqueue new message for actor. Actor has most likely stopped.
-    CannotSend,
-    #[error("The actor has most likely stopped before the message could be handled.")]
+    CannotSend(ErrorContext),
+    #[error("The actor has most likely stopped before {0} could be handled.")]
     /// The actor has most likely stopped before the message could be handled.
-    MessageLost,
-}
-
-impl<T> From<TokioSendError<QueuePayload<T>>> for ActorError
-where
-    T: Actor,
-{
-    fn from(_: TokioSendError<QueuePayload<T>>) -> Self {
-        Self::CannotSend
-    }
+    MessageLost(ErrorContext),
+    #[error("The mailbox is full, failed to enqueue {0}.")]
+    /// The actor's mailbox has reached its' capacity.
+    MailboxFull(ErrorContext),
+    #[error("No response to {0} arrived in time.")]
+    /// No response arrived in time.
+    Timeout(ErrorContext),
+    #[error("The handler panicked while processing {0}.")]
+    /// The handler panicked while processing the message.
+    HandlerPanicked(ErrorContext),
+    #[error("The actor stopped before {0} could be handled.")]
+    /// The actor stopped before the message could be handled.
+    ActorStopping(ErrorContext),
+    #[error("The actor is overloaded, {0} has been rejected.")]
+    /// The message has been rejected by a [crate::shedding::LoadShedder], as the actor is overloaded.
+    Overloaded(ErrorContext),
+    #[error("{0} is a duplicate of one that is still being processed.")]
+    /// The message is a duplicate of one that is still being processed.
+    Duplicate(ErrorContext),
+    #[error("The handler has been aborted while processing {0}.")]
+    /// The handler got stuck and has been aborted by a [crate::watchdog::Watchdog].
+    Aborted(ErrorContext),
+    #[error("The actor sent {0} to itself, awaiting the response would deadlock.")]
+    /// The actor sent the message to itself from within a handler, which would deadlock.
+    /// [crate::context::ActorContext::notify] should be used instead.
+    Reentrant(ErrorContext),
+    #[error("The actor does not accept {0} at the moment.")]
+    /// Messages of this type have been muted via [crate::context::ActorContext::mute].
+    Muted(ErrorContext),
+    #[error("The actor is not ready to handle {0} yet.")]
+    /// The actor has not become ready yet, see [crate::actor::Readiness::Reject].
+    NotReady(ErrorContext),
 }
 
-impl From<TokioRecvError> for ActorError {
-    fn from(_: TokioRecvError) -> Self {
-        Self::MessageLost
+impl ActorError {
+    /// Returns the context of the failed interaction
+    pub fn context(&self) -> &ErrorContext {
+        match self {
+            Self::CannotSend(context)
+            | Self::MessageLost(context)
+            | Self::MailboxFull(context)
+            | Self::Timeout(context)
+            | Self::HandlerPanicked(context)
+            | Self::ActorStopping(context)
+            | Self::Overloaded(context)
+            | Self::Duplicate(context)
+            | Self::Aborted(context)
+            | Self::Reentrant(context)
+            | Self::Muted(context)
+            | Self::NotReady(context) => context,
+        }
     }
 }
@@ -0,0 +1,101 @@
+//! Estimating memory usage of actors
+//!
+//! Actors implementing [MemoryFootprint] report their' approximate heap usage on request.
+//! Once [tracked](track), they are included in the process-wide [report],
+//! which helps with capacity debugging without attaching a heap profiler.
+
+use crate::{
+    actor::{Actor, ActorId, Handler},
+    addr::{Addr, WeakAddr},
+    context::ActorContext,
+};
+use async_trait::async_trait;
+use futures_util::future::{join_all, BoxFuture};
+use std::{sync::Mutex, time::Duration};
+
+/// Actors able to estimate how much memory they use
+pub trait MemoryFootprint: Actor {
+    /// Approximate number of bytes allocated on the heap by the actor,
+    /// not including the size of the actor itself
+    fn heap_size(&self) -> usize;
+}
+
+/// Memory usage reported by an actor
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Footprint {
+    pub actor_id: ActorId,
+    /// Type name of the actor
+    pub actor_type: &'static str,
+    /// Size of the actor along with its' [MemoryFootprint::heap_size]
+    pub bytes: usize,
+}
+
+/// Message making the actor report its' [Footprint]
+#[doc(hidden)]
+pub struct Measure;
+
+#[async_trait]
+impl<A: MemoryFootprint> Handler<Measure> for A {
+    type Response = usize;
+    async fn handle(&mut self, _msg: Measure, _ctx: &mut ActorContext<Self>) -> usize {
+        std::mem::size_of::<A>() + self.heap_size()
+    }
+}
+
+/// Type-erased tracked actor
+trait Probe: Send {
+    /// Resolves to `None` if the actor is gone or does not respond in time
+    fn measure(&self, timeout: Duration) -> BoxFuture<'static, Option<Footprint>>;
+    fn alive(&self) -> bool;
+}
+
+struct Target<A: Actor> {
+    addr: WeakAddr<A>,
+}
+
+impl<A: MemoryFootprint> Probe for Target<A> {
+    fn measure(&self, timeout: Duration) -> BoxFuture<'static, Option<Footprint>> {
+        let addr = self.addr.upgrade();
+        Box::pin(async move {
+            let addr = addr?;
+            let bytes = addr.send_timeout(Measure, timeout).await.ok()?;
+            Some(Footprint {
+                actor_id: addr.id(),
+                actor_type: std::any::type_name::<A>(),
+                bytes,
+            })
+        })
+    }
+    fn alive(&self) -> bool {
+        self.addr.connected()
+    }
+}
+
+static TRACKED: Mutex<Vec<Box<dyn Probe>>> = Mutex::new(Vec::new());
+
+/// Includes the actor in the [report], for as long as it's alive.
+///
+/// The actor is not kept alive by being tracked.
+pub fn track<A: MemoryFootprint>(addr: &Addr<A>) {
+    let target = Target {
+        addr: addr.downgrade(),
+    };
+    TRACKED.lock().unwrap().push(Box::new(target));
+}
+
+/// Asks all the tracked actors for their' [Footprint], waiting at most `timeout` for each of them.
+///
+/// Actors which do not respond in time are left out.
+pub async fn report(timeout: Duration) -> Vec<Footprint> {
+    let measurements: Vec<_> = {
+        let mut tracked = TRACKED.lock().unwrap();
+        tracked.retain(|probe| probe.alive());
+        tracked.iter().map(|probe| probe.measure(timeout)).collect()
+    };
+    join_all(measurements).await.into_iter().flatten().collect()
+}
+
+/// Sums up the [Footprint]s of all the tracked actors, see [report]
+pub async fn total(timeout: Duration) -> usize {
+    report(timeout).await.iter().map(|footprint| footprint.bytes).sum()
+}
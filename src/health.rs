@@ -0,0 +1,21 @@
+//! Health checks answered by every actor
+//!
+//! [crate::addr::Addr::ping] goes through the mailbox like any other message, but gets answered by the framework itself,
+//! which makes it a uniform liveness probe: actors stuck in a handler do not respond.
+
+use crate::actor::ActorState;
+use std::time::Instant;
+
+/// Message type used for reporting errors of [crate::addr::Addr::ping]
+#[derive(Clone, Copy, Debug)]
+pub struct Ping;
+
+/// Health report of an actor
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Health {
+    pub state: ActorState,
+    /// Number of messages waiting in the mailbox
+    pub queue_depth: usize,
+    /// When the actor took the latest message out of its' mailbox
+    pub last_activity: Instant,
+}
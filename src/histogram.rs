@@ -0,0 +1,179 @@
+//! Latency histograms
+//!
+//! Averages hide exactly the tail behavior actor systems are used to control, so latencies are
+//! recorded in [Histogram]s instead, from which any quantile (p50, p95, p999) can be read.
+//! Like the ones of HdrHistogram, the buckets get wider as the values grow, keeping the relative error
+//! below 1/16 all the way from nanoseconds to minutes, with a fixed amount of memory and without locking.
+//!
+//! Every actor records two of them, which [Addr::latency] returns:
+//! how long its' handlers took, and how long the messages waited in its' mailbox before getting handled.
+
+#[cfg(doc)]
+use crate::addr::Addr;
+use std::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// Number of buckets per power of two
+const SUB_BUCKETS: u64 = 16;
+const SUB_BITS: u32 = SUB_BUCKETS.trailing_zeros();
+/// Values get clamped below 2^40 nanoseconds, about 18 minutes
+const MAX_BITS: u32 = 40;
+const BUCKETS: usize = ((MAX_BITS - SUB_BITS + 1) as u64 * SUB_BUCKETS) as usize;
+
+fn bucket(nanos: u64) -> usize {
+    let nanos = nanos.min((1 << MAX_BITS) - 1);
+    if nanos < SUB_BUCKETS {
+        return nanos as usize;
+    }
+    let exponent = 63 - nanos.leading_zeros();
+    let sub = (nanos >> (exponent - SUB_BITS)) - SUB_BUCKETS;
+    (SUB_BUCKETS + (exponent - SUB_BITS) as u64 * SUB_BUCKETS + sub) as usize
+}
+
+/// Returns the highest value falling into the bucket
+fn highest(bucket: usize) -> u64 {
+    let bucket = bucket as u64;
+    if bucket < SUB_BUCKETS {
+        return bucket;
+    }
+    let shift = ((bucket - SUB_BUCKETS) / SUB_BUCKETS) as u32;
+    let sub = (bucket - SUB_BUCKETS) % SUB_BUCKETS;
+    ((SUB_BUCKETS + sub) << shift) + (1 << shift) - 1
+}
+
+/// Histogram of durations, recorded concurrently, see [crate::histogram]
+pub struct Histogram {
+    buckets: Box<[AtomicU64]>,
+    max: AtomicU64,
+}
+
+impl fmt::Debug for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Histogram").field(&self.snapshot()).finish()
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Histogram {
+    /// Creates an empty histogram
+    pub fn new() -> Self {
+        Self {
+            buckets: (0..BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+            max: AtomicU64::new(0),
+        }
+    }
+    /// Records the duration
+    pub fn record(&self, duration: Duration) {
+        let nanos = duration.as_nanos().min(u64::MAX as u128) as u64;
+        self.buckets[bucket(nanos)].fetch_add(1, Ordering::Relaxed);
+        self.max.fetch_max(nanos, Ordering::Relaxed);
+    }
+    /// Returns a copy of the recorded values, which the quantiles get read from
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        let counts: Vec<u64> = self
+            .buckets
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .collect();
+        HistogramSnapshot {
+            count: counts.iter().sum(),
+            max: Duration::from_nanos(self.max.load(Ordering::Relaxed)),
+            counts,
+        }
+    }
+}
+
+/// Copy of the values recorded by a [Histogram]
+#[derive(Clone, Default, Eq, PartialEq)]
+pub struct HistogramSnapshot {
+    count: u64,
+    max: Duration,
+    counts: Vec<u64>,
+}
+
+impl fmt::Debug for HistogramSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HistogramSnapshot")
+            .field("count", &self.count)
+            .field("p50", &self.p50())
+            .field("p95", &self.p95())
+            .field("p999", &self.p999())
+            .field("max", &self.max)
+            .finish()
+    }
+}
+
+impl HistogramSnapshot {
+    /// Number of recorded values
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+    /// The highest recorded value, exactly
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+    /// Returns the value below or at which the given fraction (between 0 and 1) of the values fall,
+    /// `Duration::ZERO` if nothing has been recorded.
+    ///
+    /// The value is rounded up to the end of its' bucket, so it overestimates by at most 1/16.
+    pub fn quantile(&self, quantile: f64) -> Duration {
+        let rank = ((quantile.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Duration::from_nanos(highest(bucket)).min(self.max);
+            }
+        }
+        Duration::ZERO
+    }
+    /// The median
+    pub fn p50(&self) -> Duration {
+        self.quantile(0.5)
+    }
+    pub fn p95(&self) -> Duration {
+        self.quantile(0.95)
+    }
+    pub fn p999(&self) -> Duration {
+        self.quantile(0.999)
+    }
+}
+
+/// Latencies of the messages handled by an actor, see [Addr::latency]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Latency {
+    /// How long the handlers took
+    pub handling: HistogramSnapshot,
+    /// How long the messages waited in the mailbox before getting handled
+    pub dwell: HistogramSnapshot,
+}
+
+/// Histograms recorded by every actor
+#[derive(Debug, Default)]
+pub(crate) struct ActorHistograms {
+    handling: Histogram,
+    dwell: Histogram,
+}
+
+impl ActorHistograms {
+    pub fn record_dwell(&self, dwell: Duration) {
+        self.dwell.record(dwell);
+    }
+    pub fn record_handling(&self, handling: Duration) {
+        self.handling.record(handling);
+    }
+    pub fn latency(&self) -> Latency {
+        Latency {
+            handling: self.handling.snapshot(),
+            dwell: self.dwell.snapshot(),
+        }
+    }
+}
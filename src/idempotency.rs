@@ -0,0 +1,222 @@
+//! Duplicate suppression for messages delivered more than once
+//!
+//! Transports with at-least-once delivery semantics can hand the same message over several times.
+//! [IdempotentAddr] remembers the keys of recently sent messages (see [IdempotencyKey])
+//! and suppresses duplicates, answering them from the cached response where possible.
+
+use crate::{
+    actor::{Actor, Handler},
+    addr::Addr,
+    error::{ActorError, ErrorContext},
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Trait implemented on messages which carry a unique identifier
+///
+/// Two messages with equal keys are considered to be duplicates of each other.
+pub trait IdempotencyKey {
+    /// Type of the identifier
+    type Key: Hash + Eq + Clone + Send + 'static;
+    /// Returns the identifier of the message
+    fn idempotency_key(&self) -> Self::Key;
+}
+
+struct Entry<R> {
+    seen_at: Instant,
+    stamp: u64,
+    /// `None` while the original message is still being processed
+    response: Option<R>,
+}
+
+/// Bounded, least-recently-used registry of seen message keys
+struct SeenKeys<K, R> {
+    capacity: usize,
+    window: Duration,
+    next_stamp: u64,
+    entries: HashMap<K, Entry<R>>,
+    order: BTreeMap<u64, K>,
+}
+
+impl<K: Hash + Eq + Clone, R: Clone> SeenKeys<K, R> {
+    fn new(capacity: usize, window: Duration) -> Self {
+        Self {
+            capacity,
+            window,
+            next_stamp: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+    fn bump(&mut self) -> u64 {
+        self.next_stamp += 1;
+        self.next_stamp
+    }
+    /// Looks the key up, forgetting it if it is older than the window.
+    ///
+    /// Returns `None` for unseen keys and `Some(cached_response)` for duplicates.
+    fn lookup(&mut self, key: &K) -> Option<Option<R>> {
+        let stamp = self.bump();
+        let entry = self.entries.get_mut(key)?;
+        if entry.seen_at.elapsed() > self.window {
+            self.order.remove(&entry.stamp);
+            self.entries.remove(key);
+            return None;
+        }
+        self.order.remove(&entry.stamp);
+        entry.stamp = stamp;
+        self.order.insert(stamp, key.clone());
+        Some(entry.response.clone())
+    }
+    /// Marks the key as seen
+    fn insert(&mut self, key: K) {
+        let stamp = self.bump();
+        if let Some(old) = self.entries.insert(
+            key.clone(),
+            Entry {
+                seen_at: Instant::now(),
+                stamp,
+                response: None,
+            },
+        ) {
+            self.order.remove(&old.stamp);
+        }
+        self.order.insert(stamp, key);
+        while self.entries.len() > self.capacity {
+            match self.order.pop_first() {
+                Some((_, oldest)) => {
+                    self.entries.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+    }
+    fn set_response(&mut self, key: &K, response: R) {
+        if let Some(entry) = self.entries.get_mut(key) {
+            entry.response = Some(response);
+        }
+    }
+    fn remove(&mut self, key: &K) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.order.remove(&entry.stamp);
+        }
+    }
+}
+
+/// Forgets the key of a message being sent unless its' response arrives,
+/// so that a failed or cancelled message can be retried
+struct PendingKey<'a, K: Hash + Eq + Clone, R: Clone> {
+    seen: &'a Mutex<SeenKeys<K, R>>,
+    key: Option<K>,
+}
+
+impl<K: Hash + Eq + Clone, R: Clone> PendingKey<'_, K, R> {
+    fn resolve(mut self, response: R) {
+        let key = self.key.take().unwrap();
+        self.seen.lock().unwrap().set_response(&key, response);
+    }
+}
+
+impl<K: Hash + Eq + Clone, R: Clone> Drop for PendingKey<'_, K, R> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.seen.lock().unwrap().remove(&key);
+        }
+    }
+}
+
+type SharedKeys<A, M> =
+    Arc<Mutex<SeenKeys<<M as IdempotencyKey>::Key, <A as Handler<M>>::Response>>>;
+
+/// Address wrapper suppressing duplicate messages of type `M`
+///
+/// Keys are remembered for the duration of the window,
+/// up to the configured capacity (the least recently used keys get evicted first).
+///
+/// Clones share the registry of seen keys.
+pub struct IdempotentAddr<A, M>
+where
+    A: Handler<M>,
+    M: IdempotencyKey + Send,
+{
+    addr: Addr<A>,
+    seen: SharedKeys<A, M>,
+}
+
+impl<A, M> Clone for IdempotentAddr<A, M>
+where
+    A: Handler<M>,
+    M: IdempotencyKey + Send,
+{
+    fn clone(&self) -> Self {
+        Self {
+            addr: self.addr.clone(),
+            seen: self.seen.clone(),
+        }
+    }
+}
+
+impl<A, M> IdempotentAddr<A, M>
+where
+    A: Actor + Handler<M>,
+    M: IdempotencyKey + Send + 'static,
+    <A as Handler<M>>::Response: Clone,
+{
+    /// Wraps the given address, remembering up to `capacity` keys for the duration of `window`
+    pub fn new(addr: Addr<A>, capacity: usize, window: Duration) -> Self {
+        Self {
+            addr,
+            seen: Arc::new(Mutex::new(SeenKeys::new(capacity, window))),
+        }
+    }
+    /// Returns the underlying address
+    pub fn inner(&self) -> &Addr<A> {
+        &self.addr
+    }
+    /// Sends a message to the actor and asynchronously waits for its' response.
+    ///
+    /// Duplicates are answered with the response cached for the original message.
+    /// If the original message is still being processed, the duplicate fails with [ActorError::Duplicate].
+    pub async fn send(&self, msg: M) -> Result<<A as Handler<M>>::Response, ActorError> {
+        let key = msg.idempotency_key();
+        {
+            let mut seen = self.seen.lock().unwrap();
+            match seen.lookup(&key) {
+                Some(Some(response)) => return Ok(response),
+                Some(None) => {
+                    let context = ErrorContext::new::<A, M>(self.addr.id());
+                    return Err(ActorError::Duplicate(context));
+                }
+                None => seen.insert(key.clone()),
+            }
+        }
+        let pending = PendingKey {
+            seen: &self.seen,
+            key: Some(key),
+        };
+        let result = self.addr.send(msg).await;
+        if let Ok(response) = &result {
+            pending.resolve(response.clone());
+        }
+        result
+    }
+    /// Sends a message to the actor without waiting for response, ignoring all errors.
+    ///
+    /// Duplicates are silently dropped.
+    /// No response gets cached, so later duplicates passed to [IdempotentAddr::send] fail with [ActorError::Duplicate].
+    pub fn do_send(&self, msg: M) {
+        let key = msg.idempotency_key();
+        {
+            let mut seen = self.seen.lock().unwrap();
+            if seen.lookup(&key).is_some() {
+                return;
+            }
+            seen.insert(key);
+        }
+        self.addr.do_send(msg)
+    }
+}
@@ -0,0 +1,379 @@
+//! Sharded in-memory key-value store built out of actors
+//!
+//! Available with the `kv` feature. Besides being usable on its' own, it shows how the parts
+//! of the crate fit together: the entries are split among [Supervised] shard actors,
+//! and a [Kv] handle in front of them sends each request to the shard which owns the key,
+//! picked via a consistent [HashRing].
+//!
+//! Shards can write the entries through to a [KvBackend], which they load the entries from
+//! upon the first request, so that the store survives the process.
+//! Each shard handles its' requests one at a time, so requests for the same key
+//! are applied in the order in which they were sent.
+
+use crate::{
+    actor::{Actor, Handler},
+    addr::Addr,
+    context::ActorContext,
+    error::ActorError,
+    supervised::Supervised,
+};
+use async_trait::async_trait;
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::Infallible,
+    fmt,
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+use thiserror::Error;
+
+/// Number of points each shard gets on the ring, which evens out the distribution of keys
+const VIRTUAL_NODES: u32 = 64;
+
+/// Persistent storage behind the shards of a [Kv]
+#[async_trait]
+pub trait KvBackend<K, V>: Send + Sync + 'static {
+    type Error: std::error::Error + Send + 'static;
+    /// Returns all the entries stored by the shard
+    async fn load(&self, shard: usize) -> Result<Vec<(K, V)>, Self::Error>;
+    /// Stores the entry, replacing the previous value
+    async fn put(&self, shard: usize, key: &K, value: &V) -> Result<(), Self::Error>;
+    /// Removes the entry
+    async fn delete(&self, shard: usize, key: &K) -> Result<(), Self::Error>;
+}
+
+/// [KvBackend] which does not store anything, keeping the entries in memory only
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoPersistence;
+
+#[async_trait]
+impl<K: Sync + 'static, V: Sync + 'static> KvBackend<K, V> for NoPersistence {
+    type Error = Infallible;
+    async fn load(&self, _shard: usize) -> Result<Vec<(K, V)>, Infallible> {
+        Ok(Vec::new())
+    }
+    async fn put(&self, _shard: usize, _key: &K, _value: &V) -> Result<(), Infallible> {
+        Ok(())
+    }
+    async fn delete(&self, _shard: usize, _key: &K) -> Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+/// Error returned by the requests to a [Kv]
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum KvError<E> {
+    #[error(transparent)]
+    /// The request could not be delivered to the shard
+    Shard(#[from] ActorError),
+    #[error("The backend of the store failed: {0}")]
+    /// The backend failed, so the entries of the shard have not been changed
+    Backend(E),
+}
+
+/// Consistent hashing of keys onto shards
+///
+/// The hashes do not change between runs, so the keys stay in the same shards
+/// as long as the number of shards does not change. Changing it moves only some of the keys.
+#[derive(Clone, Debug)]
+pub struct HashRing {
+    shards: usize,
+    points: BTreeMap<u64, usize>,
+}
+
+/// 64-bit FNV-1a, whose output is fixed, unlike the one of [std::collections::hash_map::DefaultHasher].
+///
+/// Integers get hashed as little-endian 64-bit values, so the hashes do not depend on the platform either.
+/// FNV-1a barely changes the high bits for small inputs, which would bunch the points of the ring up,
+/// so the result gets scrambled by the finalizer of MurmurHash3.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+}
+
+impl Hasher for Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(Self::PRIME);
+        }
+    }
+    fn write_u16(&mut self, i: u16) {
+        self.write_u64(i.into())
+    }
+    fn write_u32(&mut self, i: u32) {
+        self.write_u64(i.into())
+    }
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes())
+    }
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes())
+    }
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64)
+    }
+    fn finish(&self) -> u64 {
+        let mut hash = self.0;
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+        hash ^ (hash >> 33)
+    }
+}
+
+fn hash_of(value: &impl Hash) -> u64 {
+    let mut hasher = Fnv1a(Fnv1a::OFFSET_BASIS);
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl HashRing {
+    /// Creates the ring of the given number of shards
+    pub fn new(shards: usize) -> Self {
+        assert!(shards > 0, "The ring needs at least one shard");
+        let points = (0..shards)
+            .flat_map(|shard| (0..VIRTUAL_NODES).map(move |node| (hash_of(&(shard, node)), shard)))
+            .collect();
+        Self { shards, points }
+    }
+    /// Number of shards
+    pub fn shards(&self) -> usize {
+        self.shards
+    }
+    /// Returns the shard owning the key
+    pub fn shard<K: Hash + ?Sized>(&self, key: &K) -> usize {
+        let hash = hash_of(&key);
+        let mut owners = self.points.range(hash..).chain(self.points.iter());
+        *owners.next().expect("The ring is never empty").1
+    }
+}
+
+/// Actor holding the entries of a single shard, see [crate::kv]
+pub struct Shard<K, V, B> {
+    index: usize,
+    backend: Arc<B>,
+    /// `None` until loaded from the backend
+    entries: Option<HashMap<K, V>>,
+}
+
+impl<K, V, B> fmt::Debug for Shard<K, V, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Shard")
+            .field("index", &self.index)
+            .field("entries", &self.entries.as_ref().map(HashMap::len))
+            .finish()
+    }
+}
+
+impl<K, V, B> Shard<K, V, B>
+where
+    K: 'static + Hash + Eq + Send,
+    V: 'static + Send,
+    B: KvBackend<K, V>,
+{
+    async fn entries(&mut self) -> Result<&mut HashMap<K, V>, B::Error> {
+        if self.entries.is_none() {
+            let loaded = self.backend.load(self.index).await?;
+            self.entries = Some(loaded.into_iter().collect());
+        }
+        Ok(self.entries.get_or_insert_with(HashMap::new))
+    }
+}
+
+impl<K, V, B> Actor for Shard<K, V, B>
+where
+    K: 'static + Send,
+    V: 'static + Send,
+    B: 'static + Send + Sync,
+{
+}
+
+/// Shards keep their' entries when restarted after a panic
+impl<K, V, B> Supervised for Shard<K, V, B>
+where
+    K: 'static + Send,
+    V: 'static + Send,
+    B: 'static + Send + Sync,
+{
+}
+
+#[doc(hidden)]
+pub struct Get<K>(K);
+
+#[async_trait]
+impl<K, V, B> Handler<Get<K>> for Shard<K, V, B>
+where
+    K: 'static + Hash + Eq + Send,
+    V: 'static + Clone + Send,
+    B: KvBackend<K, V>,
+{
+    type Response = Result<Option<V>, B::Error>;
+    async fn handle(&mut self, msg: Get<K>, _ctx: &mut ActorContext<Self>) -> Self::Response {
+        Ok(self.entries().await?.get(&msg.0).cloned())
+    }
+}
+
+#[doc(hidden)]
+pub struct Put<K, V>(K, V);
+
+#[async_trait]
+impl<K, V, B> Handler<Put<K, V>> for Shard<K, V, B>
+where
+    K: 'static + Hash + Eq + Send + Sync,
+    V: 'static + Send + Sync,
+    B: KvBackend<K, V>,
+{
+    type Response = Result<Option<V>, B::Error>;
+    async fn handle(&mut self, msg: Put<K, V>, _ctx: &mut ActorContext<Self>) -> Self::Response {
+        self.entries().await?;
+        self.backend.put(self.index, &msg.0, &msg.1).await?;
+        Ok(self.entries().await?.insert(msg.0, msg.1))
+    }
+}
+
+#[doc(hidden)]
+pub struct Delete<K>(K);
+
+#[async_trait]
+impl<K, V, B> Handler<Delete<K>> for Shard<K, V, B>
+where
+    K: 'static + Hash + Eq + Send + Sync,
+    V: 'static + Send,
+    B: KvBackend<K, V>,
+{
+    type Response = Result<Option<V>, B::Error>;
+    async fn handle(&mut self, msg: Delete<K>, _ctx: &mut ActorContext<Self>) -> Self::Response {
+        self.entries().await?;
+        self.backend.delete(self.index, &msg.0).await?;
+        Ok(self.entries().await?.remove(&msg.0))
+    }
+}
+
+#[doc(hidden)]
+pub struct Len;
+
+#[async_trait]
+impl<K, V, B> Handler<Len> for Shard<K, V, B>
+where
+    K: 'static + Hash + Eq + Send,
+    V: 'static + Send,
+    B: KvBackend<K, V>,
+{
+    type Response = Result<usize, B::Error>;
+    async fn handle(&mut self, _msg: Len, _ctx: &mut ActorContext<Self>) -> Self::Response {
+        Ok(self.entries().await?.len())
+    }
+}
+
+/// Handle of a sharded key-value store, see [crate::kv]
+///
+/// Clones share the shards, which stop once the last clone gets dropped.
+pub struct Kv<K, V, B = NoPersistence>
+where
+    K: 'static + Send,
+    V: 'static + Send,
+    B: 'static + Send + Sync,
+{
+    ring: Arc<HashRing>,
+    shards: Arc<Vec<Addr<Shard<K, V, B>>>>,
+}
+
+impl<K, V, B> Clone for Kv<K, V, B>
+where
+    K: 'static + Send,
+    V: 'static + Send,
+    B: 'static + Send + Sync,
+{
+    fn clone(&self) -> Self {
+        Self {
+            ring: self.ring.clone(),
+            shards: self.shards.clone(),
+        }
+    }
+}
+
+impl<K, V, B> fmt::Debug for Kv<K, V, B>
+where
+    K: 'static + Send,
+    V: 'static + Send,
+    B: 'static + Send + Sync,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Kv").field("shards", &self.shards).finish()
+    }
+}
+
+impl<K, V> Kv<K, V>
+where
+    K: 'static + Hash + Eq + Clone + Send + Sync,
+    V: 'static + Clone + Send + Sync,
+{
+    /// Starts a store of the given number of shards, which keeps the entries in memory only
+    pub fn start(shards: usize) -> Self {
+        Self::start_with(shards, NoPersistence)
+    }
+}
+
+impl<K, V, B> Kv<K, V, B>
+where
+    K: 'static + Hash + Eq + Clone + Send + Sync,
+    V: 'static + Clone + Send + Sync,
+    B: KvBackend<K, V>,
+{
+    /// Starts a store of the given number of shards, which write the entries through to the backend.
+    ///
+    /// The number of shards should stay the same between runs, as the backend stores the entries
+    /// per shard.
+    pub fn start_with(shards: usize, backend: B) -> Self {
+        let ring = HashRing::new(shards);
+        let backend = Arc::new(backend);
+        let shards = (0..shards)
+            .map(|index| {
+                let backend = backend.clone();
+                Shard::create_supervised(move |_ctx| Shard {
+                    index,
+                    backend,
+                    entries: None,
+                })
+            })
+            .collect();
+        Self {
+            ring: Arc::new(ring),
+            shards: Arc::new(shards),
+        }
+    }
+    /// Returns the shard actor owning the key, e.g. to send it messages of your own
+    pub fn shard(&self, key: &K) -> &Addr<Shard<K, V, B>> {
+        &self.shards[self.ring.shard(key)]
+    }
+    /// Returns the value of the key
+    pub async fn get(&self, key: K) -> Result<Option<V>, KvError<B::Error>> {
+        let shard = self.shard(&key);
+        shard.send(Get(key)).await?.map_err(KvError::Backend)
+    }
+    /// Sets the value of the key, returning the previous one
+    pub async fn put(&self, key: K, value: V) -> Result<Option<V>, KvError<B::Error>> {
+        let shard = self.shard(&key);
+        shard.send(Put(key, value)).await?.map_err(KvError::Backend)
+    }
+    /// Removes the key, returning its' value
+    pub async fn delete(&self, key: K) -> Result<Option<V>, KvError<B::Error>> {
+        let shard = self.shard(&key);
+        shard.send(Delete(key)).await?.map_err(KvError::Backend)
+    }
+    /// Returns the number of entries in all the shards
+    pub async fn len(&self) -> Result<usize, KvError<B::Error>> {
+        let mut len = 0;
+        for shard in self.shards.iter() {
+            len += shard.send(Len).await?.map_err(KvError::Backend)?;
+        }
+        Ok(len)
+    }
+    /// Returns `true` if none of the shards holds any entries
+    pub async fn is_empty(&self) -> Result<bool, KvError<B::Error>> {
+        Ok(self.len().await? == 0)
+    }
+}
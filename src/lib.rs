@@ -10,24 +10,72 @@
 //! * Support for typed messages via dynamic dispatch
 //! * Support for asynchronous message handlers, via async-trait
 //! * Actor supervision
+//! * Duplicate suppression for at-least-once transports
+//! * Routing messages among pools of actors
+//! * Sharded key-value store, with the `kv` feature
 
+pub mod ack;
 pub mod actor;
+pub mod actor_future;
 pub mod addr;
+pub mod batching;
+pub mod bootstrap;
+pub mod cache;
+pub mod cancellation;
 pub mod context;
+pub mod coordination;
+pub mod dead_letters;
+pub mod deadlock;
+pub mod demand;
+pub mod election;
 pub mod error;
+pub mod footprint;
+pub mod health;
+pub mod histogram;
+pub mod idempotency;
+#[cfg(feature = "kv")]
+pub mod kv;
+pub mod logging;
 #[doc(hidden)]
 pub mod message_queue;
+pub mod mirror;
+pub mod outbox;
+pub mod race;
+mod random;
+pub mod record;
+pub mod response;
+pub mod router;
 mod runner;
+pub mod saga;
+pub mod set;
+pub mod shedding;
+pub mod shutdown;
+pub mod startup;
 pub mod supervised;
+pub mod supervisor;
+pub mod swap;
+pub mod testing;
+pub mod two_phase;
+pub mod watchdog;
+pub mod workflow;
 
 pub mod prelude {
     //! Everything you need, re-exported
     pub use crate::{
-        actor::{Actor, ActorState, Handler},
-        addr::{Addr, WeakAddr},
+        actor::{
+            Actor, ActorId, ActorState, Handler, MessageId, ReadHandler, Readiness, StopMode,
+        },
+        actor_future::{ActorFuture, WrapFuture, WrapStream},
+        addr::{Addr, AnyAddr, Recipient, WeakAddr},
         context::ActorContext,
         error::ActorError,
-        supervised::Supervised,
+        idempotency::{IdempotencyKey, IdempotentAddr},
+        response::StreamingResponse,
+        router::Router,
+        saga::{Saga, Step},
+        set::ActorSet,
+        startup::TryStart,
+        supervised::{Backoff, Recoverable, Restart, RestartReason, Supervised},
     };
     pub use async_trait::async_trait;
     pub use futures_util::stream::{Stream, StreamExt};
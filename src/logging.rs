@@ -0,0 +1,202 @@
+//! Log records attributed to the actors emitting them
+//!
+//! Handlers log via the [ActorLogger] returned by [ActorContext::log], which tags each [LogRecord]
+//! with the type, identifier and [name](ActorContext::set_name) of the actor, along with the message
+//! being handled. The records get handed to the process-wide logger [installed](set_logger)
+//! by the application, which can forward them to the logging library of its' choice.
+//! Without a logger, or below its' level, logging costs an atomic load and does not format anything.
+//!
+//! ## Message traces
+//!
+//! With a logger of [Level::Trace], each handled message gets traced: a record tells which message got
+//! handled and for how long. For actors handling millions of messages per second that is way too much,
+//! so the traces get sampled according to [Actor::TRACE_SAMPLING], which can be overriden per message
+//! type via [Handler::MESSAGE_SAMPLING]. Messages whose handling fails (panics or gets aborted) are always
+//! traced, as are the ones handled slower than the threshold of [Sampling::with_slow].
+
+#[cfg(doc)]
+use crate::actor::Handler;
+use crate::{
+    actor::{Actor, ActorId, MessageId},
+    context::ActorContext,
+    error::ActorError,
+    message_queue::ActorShared,
+};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc, RwLock,
+    },
+    time::Duration,
+};
+
+/// Severity of a [LogRecord], from the most to the least severe
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+/// Record logged via an [ActorLogger]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LogRecord {
+    pub level: Level,
+    /// Type name of the actor
+    pub actor_type: &'static str,
+    pub actor_id: ActorId,
+    /// See [ActorContext::set_name]
+    pub actor_name: Option<Arc<str>>,
+    /// Identifier of the message being handled, if any
+    pub message_id: Option<MessageId>,
+    pub message: String,
+}
+
+type Logger = dyn Fn(&LogRecord) + Send + Sync;
+
+static LOGGER: RwLock<Option<Arc<Logger>>> = RwLock::new(None);
+/// The least severe [Level] being logged, `0` meaning nothing is
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(0);
+
+/// Installs the process-wide logger, receiving the records of the given level and the more severe ones.
+///
+/// Replaces the previous logger, if any.
+pub fn set_logger<F>(max_level: Level, logger: F)
+where
+    F: 'static + Fn(&LogRecord) + Send + Sync,
+{
+    *LOGGER.write().unwrap() = Some(Arc::new(logger));
+    MAX_LEVEL.store(max_level as u8, Ordering::Release);
+}
+
+/// Removes the process-wide logger, so that the records get dropped
+pub fn remove_logger() {
+    MAX_LEVEL.store(0, Ordering::Release);
+    *LOGGER.write().unwrap() = None;
+}
+
+/// Which handled messages get traced, see [crate::logging#message-traces]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Sampling {
+    one_in: u64,
+    slow: Option<Duration>,
+}
+
+impl Sampling {
+    /// Traces every message
+    pub const ALL: Self = Self::one_in(1);
+    /// Traces only the messages which failed or were slow
+    pub const NONE: Self = Self::one_in(0);
+    /// Traces one in `n` messages, `0` meaning none of them
+    pub const fn one_in(n: u64) -> Self {
+        Self {
+            one_in: n,
+            slow: None,
+        }
+    }
+    /// Traces the messages whose handling took at least the given time, even if they would not be sampled
+    pub const fn with_slow(mut self, threshold: Duration) -> Self {
+        self.slow = Some(threshold);
+        self
+    }
+    fn samples(&self, id: MessageId, elapsed: Duration) -> bool {
+        // Identifiers are shared by all the actors, so they get scrambled
+        // in order not to sample every message of an actor receiving every n-th one
+        let scrambled = id.as_u64().wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        let sampled = self.one_in != 0 && scrambled.is_multiple_of(self.one_in);
+        sampled || self.slow.is_some_and(|slow| elapsed >= slow)
+    }
+}
+
+/// Returns `true` if handled messages get traced at all
+pub(crate) fn tracing() -> bool {
+    Level::Trace as u8 <= MAX_LEVEL.load(Ordering::Acquire)
+}
+
+/// Traces the handling of a message of type `M`, if it gets sampled
+pub(crate) fn trace<A: Actor, M, R>(
+    shared: &ActorShared,
+    id: MessageId,
+    sampling: Sampling,
+    elapsed: Duration,
+    ret: &Result<R, ActorError>,
+) {
+    if ret.is_ok() && !sampling.samples(id, elapsed) {
+        return;
+    }
+    let log = ActorLogger {
+        actor_type: std::any::type_name::<A>(),
+        actor_id: shared.id(),
+        actor_name: shared.name(),
+        message_id: Some(id),
+    };
+    let message_type = std::any::type_name::<M>();
+    match ret {
+        Ok(_) => log.trace(format_args!("handled {message_type} in {elapsed:?}")),
+        Err(e) => log.trace(format_args!(
+            "failed handling {message_type} after {elapsed:?}: {e}"
+        )),
+    }
+}
+
+/// Logger tagging the records with the actor it belongs to, see [crate::logging]
+#[derive(Clone, Debug)]
+pub struct ActorLogger {
+    actor_type: &'static str,
+    actor_id: ActorId,
+    actor_name: Option<Arc<str>>,
+    message_id: Option<MessageId>,
+}
+
+impl ActorLogger {
+    pub(crate) fn new<T: Actor>(ctx: &ActorContext<T>) -> Self {
+        // Without a logger, the records get dropped anyway, so the name is not worth locking for
+        let logging = MAX_LEVEL.load(Ordering::Acquire) != 0;
+        Self {
+            actor_type: std::any::type_name::<T>(),
+            actor_id: ctx.id(),
+            actor_name: if logging { ctx.name() } else { None },
+            message_id: ctx.current_message_id(),
+        }
+    }
+    /// Returns `true` if records of the given level reach the logger
+    pub fn enabled(&self, level: Level) -> bool {
+        level as u8 <= MAX_LEVEL.load(Ordering::Acquire)
+    }
+    /// Hands the record over to the process-wide logger, unless its' level is filtered out
+    pub fn log(&self, level: Level, message: impl fmt::Display) {
+        if !self.enabled(level) {
+            return;
+        }
+        // Cloned, so that the logger may log on its' own without deadlocking
+        let Some(logger) = LOGGER.read().unwrap().clone() else {
+            return;
+        };
+        logger(&LogRecord {
+            level,
+            actor_type: self.actor_type,
+            actor_id: self.actor_id,
+            actor_name: self.actor_name.clone(),
+            message_id: self.message_id,
+            message: message.to_string(),
+        });
+    }
+    pub fn error(&self, message: impl fmt::Display) {
+        self.log(Level::Error, message)
+    }
+    pub fn warn(&self, message: impl fmt::Display) {
+        self.log(Level::Warn, message)
+    }
+    pub fn info(&self, message: impl fmt::Display) {
+        self.log(Level::Info, message)
+    }
+    pub fn debug(&self, message: impl fmt::Display) {
+        self.log(Level::Debug, message)
+    }
+    pub fn trace(&self, message: impl fmt::Display) {
+        self.log(Level::Trace, message)
+    }
+}
@@ -1,6 +1,29 @@
 //! Internal message queue implementation
 
-use crate::{actor::*, error::*};
+use crate::{
+    actor::*,
+    actor_future::Step,
+    cancellation::CancellationToken,
+    context::ContextEvent,
+    dead_letters::{DeadLetter, DeadLetterReason, DeadLetters},
+    error::*,
+    health::{Health, Ping},
+    histogram::{ActorHistograms, Latency},
+    supervised::RestartReason,
+    testing::Inspect,
+};
+use futures_util::future::{select, Either};
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt,
+    pin::pin,
+};
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering},
+    Arc, Mutex, OnceLock,
+};
+use std::time::{Duration, Instant};
 use tokio::sync::{mpsc, oneshot};
 
 mod envelope;
@@ -9,37 +32,727 @@ use envelope::*;
 /// The type used for wrapping enqueued messages
 pub(crate) type QueuePayload<T> = Box<dyn EnvelopeProxy<T> + Send>;
 
+/// Why [MessageQueue::enqueue_payload] rejected a message
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Rejection {
+    NotReady,
+    MailboxFull,
+    /// The actor is gone
+    Closed,
+}
+
+/// Receiver of the response to a message sent via [MessageQueue::send]
+pub(crate) type ResponseReceiver<T, M> =
+    oneshot::Receiver<Result<<T as Handler<M>>::Response, ActorError>>;
+
+/// Receiver of the response to a message sent via [MessageQueue::send_read]
+pub(crate) type ReadResponseReceiver<T, M> =
+    oneshot::Receiver<Result<<T as ReadHandler<M>>::Response, ActorError>>;
+
+/// State of an actor shared between its' addresses and its' context
+#[derive(Debug)]
+pub(crate) struct ActorShared {
+    id: ActorId,
+    state: AtomicU8,
+    /// Number of messages waiting in the queue
+    depth: AtomicUsize,
+    /// Maximum number of queued messages, [usize::MAX] meaning no limit
+    capacity: AtomicUsize,
+    /// Approximate size of the queued messages in bytes
+    bytes: AtomicUsize,
+    /// Maximum approximate size of the queued messages, [usize::MAX] meaning no limit
+    byte_budget: usize,
+    /// Cancelled as soon as the actor begins stopping, replaced once it runs again
+    stop_token: Mutex<CancellationToken>,
+    /// Mode of the latest stop request
+    stop_mode: Mutex<StopMode>,
+    /// Set once the actor has been asked to stop for good, so that it does not get restarted.
+    /// Only changed while holding `stop_mode`, see [ActorShared::restart]
+    shut_down: AtomicBool,
+    /// Set when the actor stops due to a failure rather than an explicit request
+    failure: Mutex<Option<RestartReason>>,
+    /// Cancelled in order to abort the handlers which are running
+    abort: Mutex<CancellationToken>,
+    /// Cancelled once the runner loop exits for good
+    terminated: CancellationToken,
+    /// Why the runner loop exited, set right before `terminated` gets cancelled
+    exit_reason: Mutex<Option<RestartReason>>,
+    /// See [Actor::READINESS]
+    readiness: Readiness,
+    /// Cancelled once the actor becomes ready
+    ready: CancellationToken,
+    /// Types of the messages rejected upon sending, along with their' names
+    muted: Mutex<HashMap<TypeId, &'static str>>,
+    /// Whether `muted` is not empty, so that sending does not have to lock it otherwise
+    any_muted: AtomicBool,
+    /// Actors which this one relies on, so that it stops before them during a coordinated shutdown
+    dependencies: Mutex<Vec<ActorId>>,
+    /// Set via ActorContext::set_name
+    name: Mutex<Option<Arc<str>>>,
+    /// Allocated once the first message gets handled, as not all actors get to handle any
+    histograms: OnceLock<Box<ActorHistograms>>,
+    /// Nanoseconds since `created` at which the latest message being handled got enqueued
+    handled_queued_at: AtomicU64,
+    /// Number of messages being handled
+    handling: AtomicUsize,
+    /// Number of streams forwarding messages to the actor
+    pub streams: AtomicUsize,
+    /// Number of streams which have panicked
+    pub failed_streams: AtomicUsize,
+    /// Number of running closures spawned via ActorContext::spawn_blocking
+    pub blocking_tasks: AtomicUsize,
+    created: Instant,
+    /// Microseconds since `created` at which the latest message got taken out of the queue
+    last_activity: AtomicU64,
+}
+
+impl ActorShared {
+    pub fn new<T: Actor>() -> Arc<Self> {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Arc::new(Self {
+            id: ActorId(NEXT_ID.fetch_add(1, Ordering::Relaxed)),
+            state: AtomicU8::new(ActorState::Starting as u8),
+            depth: AtomicUsize::new(0),
+            capacity: AtomicUsize::new(T::MAILBOX_CAPACITY.unwrap_or(usize::MAX)),
+            bytes: AtomicUsize::new(0),
+            byte_budget: T::MAILBOX_BYTES.unwrap_or(usize::MAX),
+            stop_token: Mutex::default(),
+            stop_mode: Mutex::default(),
+            shut_down: AtomicBool::new(false),
+            failure: Mutex::default(),
+            abort: Mutex::default(),
+            terminated: CancellationToken::new(),
+            exit_reason: Mutex::default(),
+            readiness: T::READINESS,
+            ready: CancellationToken::new(),
+            muted: Mutex::default(),
+            any_muted: AtomicBool::new(false),
+            dependencies: Mutex::default(),
+            name: Mutex::default(),
+            histograms: OnceLock::new(),
+            handled_queued_at: AtomicU64::new(0),
+            handling: AtomicUsize::new(0),
+            streams: AtomicUsize::new(0),
+            failed_streams: AtomicUsize::new(0),
+            blocking_tasks: AtomicUsize::new(0),
+            created: Instant::now(),
+            last_activity: AtomicU64::new(0),
+        })
+    }
+    pub fn id(&self) -> ActorId {
+        self.id
+    }
+    pub fn state(&self) -> ActorState {
+        match self.state.load(Ordering::Acquire) {
+            0 => ActorState::Starting,
+            1 => ActorState::Running,
+            2 => ActorState::Stopping,
+            _ => ActorState::Stopped,
+        }
+    }
+    pub fn set_state(&self, state: ActorState) {
+        self.state.store(state as u8, Ordering::Release);
+        if state == ActorState::Running && self.readiness == Readiness::Started {
+            self.ready.cancel();
+        }
+        let mut stop_token = self.stop_token.lock().unwrap();
+        match state {
+            ActorState::Stopping | ActorState::Stopped => stop_token.cancel(),
+            // The actor has recovered or restarted
+            _ if stop_token.is_cancelled() => *stop_token = CancellationToken::new(),
+            _ => {}
+        }
+    }
+    /// Makes the actor enter [ActorState::Stopping] state, remembering the mode
+    pub fn stop(&self, mode: StopMode) {
+        *self.stop_mode.lock().unwrap() = mode;
+        *self.failure.lock().unwrap() = None;
+        self.set_state(ActorState::Stopping);
+    }
+    /// Like [ActorShared::stop], also preventing the actor from getting restarted
+    pub fn shutdown(&self, mode: StopMode) {
+        {
+            let _stop_mode = self.stop_mode.lock().unwrap();
+            self.shut_down.store(true, Ordering::Release);
+        }
+        self.stop(mode);
+    }
+    /// Returns `true` if the actor has been asked to stop for good
+    pub fn is_shut_down(&self) -> bool {
+        self.shut_down.load(Ordering::Acquire)
+    }
+    /// Makes the stopped actor start again, unless it has been shut down in the meantime
+    pub fn restart(&self) -> bool {
+        let _stop_mode = self.stop_mode.lock().unwrap();
+        if self.is_shut_down() {
+            return false;
+        }
+        self.set_state(ActorState::Starting);
+        true
+    }
+    /// Makes the actor enter [ActorState::Stopping] state due to a panic of a handler of `M`
+    pub fn panicked<M>(&self, payload: &(dyn Any + Send)) {
+        self.failed(RestartReason::from_panic::<M>(payload));
+    }
+    /// Makes the actor enter [ActorState::Stopping] state due to an aborted handler of `M`
+    pub fn aborted<M>(&self) {
+        self.failed(RestartReason::Aborted {
+            message_type: std::any::type_name::<M>(),
+        });
+    }
+    /// Makes the actor enter [ActorState::Stopping] state due to the given failure
+    pub fn failed(&self, reason: RestartReason) {
+        self.stop(StopMode::Abandon);
+        *self.failure.lock().unwrap() = Some(reason);
+    }
+    /// Returns the token which aborts the running handlers once cancelled
+    pub fn abort_token(&self) -> CancellationToken {
+        let mut abort = self.abort.lock().unwrap();
+        if abort.is_cancelled() {
+            *abort = CancellationToken::new();
+        }
+        abort.clone()
+    }
+    /// Aborts the running handlers at their' next await point.
+    ///
+    /// Does nothing if no handler is running: the abort does not carry over to the next handler,
+    /// which has nothing to do with the one that got stuck.
+    pub fn abort_handlers(&self) {
+        self.abort.lock().unwrap().cancel();
+    }
+    /// Returns the reason of the latest stop
+    pub fn restart_reason(&self) -> RestartReason {
+        self.failure
+            .lock()
+            .unwrap()
+            .clone()
+            .unwrap_or(RestartReason::Stopped)
+    }
+    /// Returns the reason of the latest stop, clearing it
+    pub fn take_restart_reason(&self) -> RestartReason {
+        self.failure
+            .lock()
+            .unwrap()
+            .take()
+            .unwrap_or(RestartReason::Stopped)
+    }
+    /// Marks the actor as gone for good, waking up everyone awaiting [ActorShared::terminated]
+    pub fn terminate(&self) {
+        let reason = self.failure.lock().unwrap().clone();
+        self.exit_reason
+            .lock()
+            .unwrap()
+            .get_or_insert(reason.unwrap_or(RestartReason::Stopped));
+        self.set_state(ActorState::Stopped);
+        self.terminated.cancel();
+    }
+    /// Returns whether the actor is gone for good
+    pub fn has_terminated(&self) -> bool {
+        self.terminated.is_cancelled()
+    }
+    /// Waits until the actor is gone for good, returning why it stopped
+    pub async fn terminated(&self) -> RestartReason {
+        self.terminated.cancelled().await;
+        self.exit_reason.lock().unwrap().clone().unwrap()
+    }
+    /// Returns the mode of the latest stop request
+    pub fn stop_mode(&self) -> StopMode {
+        *self.stop_mode.lock().unwrap()
+    }
+    /// Returns the token cancelled when the actor begins stopping
+    pub fn stop_token(&self) -> CancellationToken {
+        self.stop_token.lock().unwrap().clone()
+    }
+    /// Returns the number of messages waiting in the queue
+    pub fn depth(&self) -> usize {
+        self.depth.load(Ordering::Acquire)
+    }
+    /// Returns the capacity of the mailbox, `None` meaning no limit
+    pub fn capacity(&self) -> Option<usize> {
+        match self.capacity.load(Ordering::Acquire) {
+            usize::MAX => None,
+            capacity => Some(capacity),
+        }
+    }
+    /// Changes the capacity of the mailbox, `None` meaning no limit
+    pub fn set_capacity(&self, capacity: Option<usize>) {
+        self.capacity
+            .store(capacity.unwrap_or(usize::MAX), Ordering::Release);
+    }
+    /// Marks the actor as ready, without enqueueing the messages waiting for it
+    pub fn set_ready(&self) {
+        self.ready.cancel();
+    }
+    /// Returns `true` once the actor has become ready, see [Actor::READINESS]
+    pub fn is_ready(&self) -> bool {
+        self.ready.is_cancelled()
+    }
+    /// Waits until the actor becomes ready, returning `false` if it terminates before
+    pub async fn ready(&self) -> bool {
+        let _ = select(pin!(self.ready.cancelled()), pin!(self.terminated.cancelled())).await;
+        self.is_ready()
+    }
+    /// Returns `true` if the messages sent from the outside cannot be enqueued as usual,
+    /// as the actor is not ready yet
+    fn gated(&self) -> bool {
+        matches!(self.readiness, Readiness::Buffer | Readiness::Reject) && !self.is_ready()
+    }
+    /// Makes messages of type `M` get rejected upon sending, returning `false` if they already were
+    pub fn mute<M: 'static>(&self) -> bool {
+        let mut muted = self.muted.lock().unwrap();
+        let newly = muted
+            .insert(TypeId::of::<M>(), std::any::type_name::<M>())
+            .is_none();
+        self.any_muted.store(true, Ordering::Release);
+        newly
+    }
+    /// Lets messages of type `M` through again, returning `false` if they were not muted
+    pub fn unmute<M: 'static>(&self) -> bool {
+        self.unmute_where(|type_id| type_id == TypeId::of::<M>()) > 0
+    }
+    /// Lets messages of all types through again, returning how many types were muted
+    pub fn unmute_all(&self) -> usize {
+        self.unmute_where(|_| true)
+    }
+    fn unmute_where(&self, f: impl Fn(TypeId) -> bool) -> usize {
+        let mut muted = self.muted.lock().unwrap();
+        let before = muted.len();
+        muted.retain(|type_id, _| !f(*type_id));
+        self.any_muted.store(!muted.is_empty(), Ordering::Release);
+        before - muted.len()
+    }
+    /// Returns `true` if messages of type `M` get rejected upon sending
+    pub fn is_muted<M: 'static>(&self) -> bool {
+        self.is_type_muted(TypeId::of::<M>())
+    }
+    pub fn is_type_muted(&self, message_type: TypeId) -> bool {
+        self.any_muted.load(Ordering::Acquire)
+            && self.muted.lock().unwrap().contains_key(&message_type)
+    }
+    /// Returns the type names of the muted messages
+    pub fn muted(&self) -> Vec<&'static str> {
+        let mut muted: Vec<_> = self.muted.lock().unwrap().values().copied().collect();
+        muted.sort_unstable();
+        muted
+    }
+    pub fn set_name(&self, name: Arc<str>) {
+        *self.name.lock().unwrap() = Some(name);
+    }
+    pub fn name(&self) -> Option<Arc<str>> {
+        self.name.lock().unwrap().clone()
+    }
+    /// Records how long the message about to be handled waited in the queue,
+    /// counting it as being handled until the returned guard gets dropped
+    pub fn start_handling(&self, queued_at: Instant, started: Instant) -> HandlingGuard<'_> {
+        let since_created = queued_at.saturating_duration_since(self.created).as_nanos();
+        let nanos = since_created.min(u64::MAX as u128) as u64;
+        self.handled_queued_at.fetch_max(nanos, Ordering::Relaxed);
+        self.handling.fetch_add(1, Ordering::AcqRel);
+        self.histograms().record_dwell(started.saturating_duration_since(queued_at));
+        HandlingGuard(self)
+    }
+    /// Records how long handling a message took
+    pub fn record_handling(&self, handling: Duration) {
+        self.histograms().record_handling(handling)
+    }
+    fn histograms(&self) -> &ActorHistograms {
+        self.histograms.get_or_init(Box::default)
+    }
+    /// Returns how long ago the latest message being handled got enqueued, zero if none is
+    pub fn mailbox_lag(&self) -> Duration {
+        if self.handling.load(Ordering::Acquire) == 0 {
+            return Duration::ZERO;
+        }
+        let nanos = self.handled_queued_at.load(Ordering::Relaxed);
+        (self.created + Duration::from_nanos(nanos)).elapsed()
+    }
+    pub fn latency(&self) -> Latency {
+        self.histograms
+            .get()
+            .map(|histograms| histograms.latency())
+            .unwrap_or_default()
+    }
+    /// Records that the actor relies on the given one
+    pub fn depend_on(&self, id: ActorId) {
+        let mut dependencies = self.dependencies.lock().unwrap();
+        if id != self.id && !dependencies.contains(&id) {
+            dependencies.push(id);
+        }
+    }
+    pub fn forget_dependency(&self, id: ActorId) {
+        self.dependencies.lock().unwrap().retain(|dependency| *dependency != id);
+    }
+    pub fn dependencies(&self) -> Vec<ActorId> {
+        self.dependencies.lock().unwrap().clone()
+    }
+    /// Returns the approximate size of the messages waiting in the queue
+    pub fn bytes(&self) -> usize {
+        self.bytes.load(Ordering::Acquire)
+    }
+    /// Takes up space for a new message of the given size, failing if the mailbox is full
+    fn reserve_slot(&self, respect_capacity: bool, size: usize) -> bool {
+        let depth = self.depth.fetch_add(1, Ordering::AcqRel);
+        if respect_capacity && depth >= self.capacity.load(Ordering::Acquire) {
+            self.depth.fetch_sub(1, Ordering::AcqRel);
+            return false;
+        }
+        if !self.reserve_bytes(respect_capacity, size) {
+            self.depth.fetch_sub(1, Ordering::AcqRel);
+            return false;
+        }
+        true
+    }
+    /// Takes up the given number of bytes of the budget, failing if it would get exceeded
+    fn reserve_bytes(&self, respect_budget: bool, size: usize) -> bool {
+        let bytes = self.bytes.fetch_add(size, Ordering::AcqRel);
+        // Oversized messages still get through one at a time
+        if respect_budget && bytes > 0 && bytes.saturating_add(size) > self.byte_budget {
+            self.bytes.fetch_sub(size, Ordering::AcqRel);
+            return false;
+        }
+        true
+    }
+    /// Takes up space for at most `count` new messages, returning the number of slots taken
+    fn reserve_slots(&self, count: usize) -> usize {
+        let capacity = self.capacity.load(Ordering::Acquire);
+        let mut depth = self.depth.load(Ordering::Acquire);
+        loop {
+            let granted = count.min(capacity.saturating_sub(depth));
+            match self.depth.compare_exchange_weak(
+                depth,
+                depth + granted,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return granted,
+                Err(current) => depth = current,
+            }
+        }
+    }
+    /// Frees up space taken up by a message of the given size which got removed from the queue
+    pub fn release_slot(&self, size: usize) {
+        self.depth.fetch_sub(1, Ordering::AcqRel);
+        self.bytes.fetch_sub(size, Ordering::AcqRel);
+        // Taking a message out of the queue counts as activity
+        let micros = self.created.elapsed().as_micros() as u64;
+        self.last_activity.store(micros, Ordering::Relaxed);
+    }
+    /// Returns the time at which the latest message got taken out of the queue,
+    /// or the creation time if there was none
+    pub fn last_activity(&self) -> Instant {
+        self.created + Duration::from_micros(self.last_activity.load(Ordering::Relaxed))
+    }
+}
+
+/// Calls [ActorShared::terminate] when dropped, even if the runner loop panics
+pub(crate) struct TerminationGuard(pub Arc<ActorShared>);
+
+impl Drop for TerminationGuard {
+    fn drop(&mut self) {
+        self.0.terminate();
+    }
+}
+
+/// Keeps one of the counters of [ActorShared] incremented while alive
+pub(crate) struct CounterGuard {
+    shared: Arc<ActorShared>,
+    counter: fn(&ActorShared) -> &AtomicUsize,
+}
+
+impl CounterGuard {
+    pub fn new(shared: Arc<ActorShared>, counter: fn(&ActorShared) -> &AtomicUsize) -> Self {
+        counter(&shared).fetch_add(1, Ordering::Relaxed);
+        Self { shared, counter }
+    }
+}
+
+impl Drop for CounterGuard {
+    fn drop(&mut self) {
+        (self.counter)(&self.shared).fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Finishes the handling of a message, see [ActorShared::start_handling]
+pub(crate) struct HandlingGuard<'a>(&'a ActorShared);
+
+impl Drop for HandlingGuard<'_> {
+    fn drop(&mut self) {
+        self.0.handling.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Receiving end of a [MessageQueue]
+///
+/// Framework-internal control messages (like stop requests and health checks) have a separate channel,
+/// which takes precedence over the user messages, so that they do not wait behind a long queue.
+pub(crate) struct Mailbox<T: Actor> {
+    user: mpsc::UnboundedReceiver<QueuePayload<T>>,
+    control: mpsc::UnboundedReceiver<QueuePayload<T>>,
+    shared: Arc<ActorShared>,
+    /// Dropped along with the receivers, as nobody would enqueue them anymore
+    held: Arc<Held<T>>,
+}
+
+impl<T: Actor> Drop for Mailbox<T> {
+    fn drop(&mut self) {
+        self.held.discard(&self.shared);
+        // The actor stopped without handling them
+        self.control.close();
+        self.user.close();
+        while let Some(envelope) = self.try_recv() {
+            self.shared.release_slot(envelope.size());
+            envelope.abandon(&self.shared);
+        }
+    }
+}
+
+impl<T: Actor> Mailbox<T> {
+    /// Receives the next message, preferring control messages.
+    ///
+    /// Returns `None` once all the senders are gone and both channels are empty.
+    pub async fn recv(&mut self) -> Option<QueuePayload<T>> {
+        // select() polls the left future first
+        match select(pin!(self.control.recv()), pin!(self.user.recv())).await {
+            Either::Left((Some(msg), _)) => Some(msg),
+            Either::Right((Some(msg), _)) => Some(msg),
+            // Both channels get closed at once, so the other one only has leftovers
+            Either::Left((None, user)) => user.await,
+            Either::Right((None, control)) => control.await,
+        }
+    }
+    /// Receives the next message if there is one waiting, preferring control messages
+    pub fn try_recv(&mut self) -> Option<QueuePayload<T>> {
+        self.control
+            .try_recv()
+            .or_else(|_| self.user.try_recv())
+            .ok()
+    }
+    /// Prevents the senders from enqueueing new messages, keeping the ones already enqueued.
+    ///
+    /// The messages held until the actor becomes ready get dropped, failing their' senders.
+    pub fn close(&mut self) {
+        self.control.close();
+        self.user.close();
+        self.held.discard(&self.shared);
+    }
+}
+
+/// Messages waiting aside until the actor becomes ready, see [Readiness::Buffer]
+///
+/// `None` once they have been enqueued.
+struct Held<T: Actor>(Mutex<Option<Vec<QueuePayload<T>>>>);
+
+impl<T: Actor> Held<T> {
+    /// Drops the messages, so that the following ones get sent to the (closed) queue
+    fn discard(&self, shared: &ActorShared) {
+        let held = self.0.lock().unwrap().take();
+        for envelope in held.into_iter().flatten() {
+            shared.release_slot(envelope.size());
+            envelope.abandon(shared);
+        }
+    }
+}
+
+impl<T: Actor> fmt::Debug for Held<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let held = self.0.lock().unwrap().as_ref().map(Vec::len);
+        f.debug_tuple("Held").field(&held).finish()
+    }
+}
+
 /// Message queue wraps a sender for [QueuePayload]
 #[derive(Debug)]
 pub(crate) struct MessageQueue<T: Actor> {
     tx: mpsc::UnboundedSender<QueuePayload<T>>,
+    /// Sender for framework-internal messages, see [Mailbox]
+    control_tx: mpsc::UnboundedSender<QueuePayload<T>>,
+    shared: Arc<ActorShared>,
+    held: Arc<Held<T>>,
 }
 
 impl<T: Actor> Clone for MessageQueue<T> {
     fn clone(&self) -> Self {
         Self {
             tx: self.tx.clone(),
+            control_tx: self.control_tx.clone(),
+            shared: self.shared.clone(),
+            held: self.held.clone(),
         }
     }
 }
 
 impl<T: Actor> MessageQueue<T> {
     /// New message queue with its' corresponding receiver
-    pub fn new() -> (Self, mpsc::UnboundedReceiver<QueuePayload<T>>) {
-        let (tx, rx) = mpsc::unbounded_channel();
-        (Self { tx }, rx)
+    pub fn new(shared: Arc<ActorShared>) -> (Self, Mailbox<T>) {
+        let (tx, user) = mpsc::unbounded_channel();
+        let (control_tx, control) = mpsc::unbounded_channel();
+        let held = shared.gated().then(Vec::new);
+        let held = Arc::new(Held(Mutex::new(held)));
+        let mailbox = Mailbox {
+            user,
+            control,
+            shared: shared.clone(),
+            held: held.clone(),
+        };
+        (Self { tx, control_tx, shared, held }, mailbox)
+    }
+    /// Makes the actor ready, enqueueing the messages which have been waiting for it
+    pub fn set_ready(&self) {
+        let mut held = self.held.0.lock().unwrap();
+        self.shared.set_ready();
+        for envelope in held.take().into_iter().flatten() {
+            let size = envelope.size();
+            if self.tx.send(envelope).is_err() {
+                self.shared.release_slot(size);
+            }
+        }
     }
+    pub fn shared(&self) -> &Arc<ActorShared> {
+        &self.shared
+    }
+    /// Returns `true` if the receiver has been closed or dropped
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+    /// Describes message of type `M` sent to this actor, for error reporting
+    pub fn error_context<M>(&self) -> ErrorContext {
+        ErrorContext::new::<T, M>(self.shared.id())
+    }
+    /// The error to report when the response to a message won't ever arrive
+    pub fn lost_error<M>(&self) -> ActorError {
+        match self.shared.state() {
+            ActorState::Stopping | ActorState::Stopped => {
+                ActorError::ActorStopping(self.error_context::<M>())
+            }
+            _ => ActorError::MessageLost(self.error_context::<M>()),
+        }
+    }
+    fn enqueue<M>(
+        &self,
+        envelope: QueuePayload<T>,
+        respect_capacity: bool,
+    ) -> Result<(), ActorError> {
+        self.enqueue_payload(envelope, respect_capacity)
+            .map_err(|(rejection, _)| match rejection {
+                Rejection::NotReady => ActorError::NotReady(self.error_context::<M>()),
+                Rejection::MailboxFull => ActorError::MailboxFull(self.error_context::<M>()),
+                Rejection::Closed => ActorError::CannotSend(self.error_context::<M>()),
+            })
+    }
+    /// Enqueues the envelope, handing it back if it gets rejected
+    pub(crate) fn enqueue_payload(
+        &self,
+        envelope: QueuePayload<T>,
+        respect_capacity: bool,
+    ) -> Result<(), (Rejection, QueuePayload<T>)> {
+        let size = envelope.size();
+        // Only the messages sent from the outside are subject to readiness, like to the capacity limit
+        let gated = respect_capacity && self.shared.gated();
+        // An actor which stopped before becoming ready stays gated
+        if gated && self.tx.is_closed() {
+            return Err((Rejection::Closed, envelope));
+        }
+        if gated && self.shared.readiness == Readiness::Reject {
+            return Err((Rejection::NotReady, envelope));
+        }
+        if !self.shared.reserve_slot(respect_capacity, size) {
+            return Err((Rejection::MailboxFull, envelope));
+        }
+        if gated {
+            if let Some(held) = self.held.0.lock().unwrap().as_mut() {
+                held.push(envelope);
+                return Ok(());
+            }
+        }
+        self.tx.send(envelope).map_err(|mpsc::error::SendError(envelope)| {
+            self.shared.release_slot(size);
+            (Rejection::Closed, envelope)
+        })
+    }
+    /// Enqueues a control message, bypassing the user messages and the capacity limit
+    fn enqueue_control<M>(&self, envelope: QueuePayload<T>) -> Result<(), ActorError> {
+        self.shared.reserve_slot(false, 0);
+        self.control_tx.send(envelope).map_err(|_| {
+            self.shared.release_slot(0);
+            ActorError::CannotSend(self.error_context::<M>())
+        })
+    }
+    /// Fails with [ActorError::Muted] if messages of type `M` are muted
+    fn check_muted<M: 'static>(&self) -> Result<(), ActorError> {
+        match self.shared.is_muted::<M>() {
+            true => Err(ActorError::Muted(self.error_context::<M>())),
+            false => Ok(()),
+        }
+    }
+    /// Enqueues a message expecting a response.
+    ///
+    /// Internal messages, like the ones forwarded from streams, are not subject to the capacity limit.
     pub fn send<M>(
         &self,
         msg: M,
-    ) -> Result<oneshot::Receiver<<T as Handler<M>>::Response>, ActorError>
+        respect_capacity: bool,
+    ) -> Result<(MessageId, ResponseReceiver<T, M>, CancellationToken), ActorError>
     where
         T: Handler<M>,
         M: 'static + Send,
     {
+        self.check_muted::<M>()?;
         let (tx, rx) = oneshot::channel();
-        let envelope = Envelope::new(msg, tx).pack();
-        self.tx.send(envelope)?;
+        let token = CancellationToken::new();
+        let size = T::message_size(&msg);
+        let envelope = Envelope::new(msg, tx, token.clone()).sized(size).pack();
+        let id = envelope.id();
+        self.enqueue::<M>(envelope, respect_capacity)?;
+        Ok((id, rx, token))
+    }
+    /// Makes the actor stop, waking it up if it's idle
+    pub fn stop(&self, mode: StopMode) {
+        self.shared.stop(mode);
+        self.wake_up();
+    }
+    /// Makes the actor stop for good, waking it up if it's idle
+    pub fn shutdown(&self, mode: StopMode) {
+        self.shared.shutdown(mode);
+        self.wake_up();
+    }
+    fn wake_up(&self) {
+        // Failure means that the actor is already gone
+        let _ = self.enqueue_control::<StopMode>(Box::new(Wakeup::new()));
+    }
+    /// Delivers the event to [Actor::context_event], after the messages already enqueued
+    pub fn context_event(&self, event: ContextEvent) {
+        let envelope = Box::new(ContextEventEnvelope::new(event));
+        // Failure means that the actor is already gone
+        let _ = self.enqueue::<ContextEvent>(envelope, false);
+    }
+    /// Enqueues the continuation of an [ActorFuture](crate::actor_future::ActorFuture)
+    pub(crate) fn continuation(&self, step: Step<T>) {
+        let envelope = Box::new(ContinuationEnvelope::new(step));
+        // Failure means that the actor is already gone
+        let _ = self.enqueue::<Step<T>>(envelope, false);
+    }
+    /// Enqueues a closure run on the actor in between its' messages, see [crate::testing::inspect]
+    pub(crate) fn inspect(&self, f: Inspection<T>) -> Result<(), ActorError> {
+        self.enqueue::<Inspect>(Box::new(InspectEnvelope::new(f)), true)
+    }
+    /// Enqueues a health check, answered by the framework itself
+    pub fn ping(&self) -> Result<oneshot::Receiver<Health>, ActorError> {
+        let (tx, rx) = oneshot::channel();
+        // Probes do not wait behind the user messages
+        self.enqueue_control::<Ping>(Box::new(PingEnvelope::new(tx)))?;
+        Ok(rx)
+    }
+    /// Enqueues a read-only message
+    pub fn send_read<M>(&self, msg: M) -> Result<ReadResponseReceiver<T, M>, ActorError>
+    where
+        T: ReadHandler<M>,
+        M: 'static + Send,
+    {
+        self.check_muted::<M>()?;
+        let (tx, rx) = oneshot::channel();
+        let size = T::message_size(&msg);
+        let envelope = ReadEnvelope::new(msg, tx).sized(size).pack();
+        self.enqueue::<M>(envelope, true)?;
         Ok(rx)
     }
     pub fn try_send<M>(&self, msg: M) -> Result<(), ActorError>
@@ -47,16 +760,92 @@ impl<T: Actor> MessageQueue<T> {
         T: Handler<M>,
         M: 'static + Send,
     {
-        let envelope = Envelope::new_no_sender(msg).pack();
-        Ok(self.tx.send(envelope)?)
+        self.check_muted::<M>()?;
+        let size = T::message_size(&msg);
+        let envelope = Envelope::new_no_sender(msg).sized(size).pack();
+        self.enqueue::<M>(envelope, true)
     }
-    pub fn do_send<M>(&self, msg: M)
+    /// Enqueues the message without waiting for its' response.
+    ///
+    /// Returns `true` if the message got enqueued, rejected ones get recorded as dead letters.
+    pub fn do_send<M>(&self, msg: M, respect_capacity: bool) -> bool
     where
         T: Handler<M>,
         M: 'static + Send,
     {
-        let envelope = Envelope::new_no_sender(msg).pack();
+        let size = T::message_size(&msg);
+        let envelope = Envelope::new_no_sender(msg).sized(size).pack();
+        if self.shared.is_muted::<M>() {
+            self.dead_letter::<M>(envelope, DeadLetterReason::Muted);
+            return false;
+        }
         // do send just ignores errors
-        let _ = self.tx.send(envelope);
+        let (reason, envelope) = match self.enqueue_payload(envelope, respect_capacity) {
+            Ok(()) => return true,
+            Err((Rejection::MailboxFull, envelope)) => (DeadLetterReason::MailboxFull, envelope),
+            Err((Rejection::NotReady, envelope)) => (DeadLetterReason::NotReady, envelope),
+            Err((Rejection::Closed, envelope)) => (DeadLetterReason::Stopped, envelope),
+        };
+        self.dead_letter::<M>(envelope, reason);
+        false
+    }
+    /// Records the rejected message as a dead letter, see [crate::dead_letters#replay]
+    fn dead_letter<M: 'static>(&self, envelope: QueuePayload<T>, reason: DeadLetterReason) {
+        let letter = DeadLetter::new::<T, M>(envelope.id(), reason);
+        DeadLetters::record_replayable::<T, M>(self.shared.id(), letter, envelope);
+    }
+    /// Enqueues a batch of messages, taking up space in the mailbox for all of them at once.
+    ///
+    /// Messages which do not fit in the mailbox get recorded as dead letters, like with [MessageQueue::do_send].
+    pub fn do_send_many<M, I>(&self, msgs: I)
+    where
+        T: Handler<M>,
+        M: 'static + Send,
+        I: IntoIterator<Item = M>,
+    {
+        if self.shared.gated() {
+            for msg in msgs {
+                self.do_send(msg, true);
+            }
+            return;
+        }
+        let envelopes: Vec<QueuePayload<T>> = msgs
+            .into_iter()
+            .map(|msg| {
+                let size = T::message_size(&msg);
+                Envelope::new_no_sender(msg).sized(size).pack()
+            })
+            .collect();
+        if self.shared.is_muted::<M>() {
+            for envelope in envelopes {
+                self.dead_letter::<M>(envelope, DeadLetterReason::Muted);
+            }
+            return;
+        }
+        let mut envelopes = envelopes.into_iter();
+        let mut granted = self.shared.reserve_slots(envelopes.len());
+        while granted > 0 {
+            let envelope = envelopes.next().unwrap();
+            granted -= 1;
+            let size = envelope.size();
+            if !self.shared.reserve_bytes(true, size) {
+                // Out of the byte budget, give the remaining slots back
+                self.shared.depth.fetch_sub(granted + 1, Ordering::AcqRel);
+                self.dead_letter::<M>(envelope, DeadLetterReason::MailboxFull);
+                break;
+            }
+            if let Err(mpsc::error::SendError(envelope)) = self.tx.send(envelope) {
+                // The actor is gone, so are the remaining messages
+                self.shared.depth.fetch_sub(granted + 1, Ordering::AcqRel);
+                self.shared.bytes.fetch_sub(size, Ordering::AcqRel);
+                for envelope in std::iter::once(envelope).chain(envelopes) {
+                    self.dead_letter::<M>(envelope, DeadLetterReason::Stopped);
+                }
+                return;
+            }
+        }
+        for envelope in envelopes {
+            self.dead_letter::<M>(envelope, DeadLetterReason::MailboxFull);
+        }
     }
 }
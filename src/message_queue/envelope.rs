@@ -1,21 +1,74 @@
 //! Helpers for hiding generics via dynamic dispatch
 
-use super::QueuePayload;
-use crate::{actor::*, context::ActorContext};
+use super::{ActorShared, QueuePayload};
+use crate::{
+    actor::*,
+    actor_future::{drive, Step},
+    cancellation::CancellationToken,
+    context::{ActorContext, ContextEvent, MessageType},
+    dead_letters::{DeadLetter, DeadLetterReason, DeadLetters},
+    error::{ActorError, ErrorContext},
+    health::Health,
+    logging::{self, Sampling},
+};
 use async_trait::async_trait;
+use futures_util::{
+    future::{join, join_all, select, BoxFuture, Either},
+    FutureExt,
+};
+use std::{future::Future, panic::AssertUnwindSafe, pin::pin, time::Instant};
 use tokio::sync::oneshot;
 
 /// A helper trait to hide generic message type behind a layer of dynamic dispatch
 #[async_trait]
 pub(crate) trait EnvelopeProxy<A: Actor> {
+    /// Identifier of the wrapped message
+    fn id(&self) -> MessageId;
+    /// Approximate size of the wrapped message in bytes, see [Handler::message_size]
+    fn size(&self) -> usize {
+        0
+    }
     /// Type-agnostic message handler for the envelope container, responsible for calling type-specific message handler
     async fn handle(&mut self, act: &mut A, ctx: &mut ActorContext<A>);
+    /// Type of the wrapped message, `None` for the ones of the framework itself,
+    /// which are not subject to mailbox filters
+    fn message_type(&self) -> Option<MessageType> {
+        None
+    }
+    /// Returns `true` for messages which can be handled concurrently via [EnvelopeProxy::handle_read]
+    fn is_read_only(&self) -> bool {
+        false
+    }
+    /// Handles this read-only message concurrently with the other ones in the batch
+    async fn handle_batch(
+        &mut self,
+        batch: &mut [QueuePayload<A>],
+        act: &mut A,
+        ctx: &mut ActorContext<A>,
+    ) {
+        self.handle(act, ctx).await;
+        for msg in batch {
+            msg.handle(act, ctx).await;
+        }
+    }
+    /// Handler for read-only messages, not requiring exclusive access to the actor
+    fn handle_read<'a>(&'a mut self, _act: &'a A, _ctx: &'a ActorContext<A>) -> BoxFuture<'a, ()> {
+        Box::pin(async { unreachable!("The message is not read-only") })
+    }
+    /// Drops the message which the stopped actor did not handle,
+    /// recording it as a dead letter unless a sender waits for the response
+    fn abandon(self: Box<Self>, _shared: &ActorShared) {}
 }
 
 /// The generic envelope structure, used for wrapping queueed messages and their response-senders
 pub(crate) struct Envelope<M: Send, R: Send> {
+    id: MessageId,
+    size: usize,
+    queued_at: Instant,
     item: Option<M>,
-    tx: Option<oneshot::Sender<R>>,
+    tx: Option<oneshot::Sender<Result<R, ActorError>>>,
+    /// Cancelled when the future created by Addr::send() gets dropped
+    cancellation: Option<CancellationToken>,
 }
 
 #[async_trait]
@@ -23,40 +76,240 @@ impl<A, M> EnvelopeProxy<A> for Envelope<M, <A as Handler<M>>::Response>
 where
     A: Actor,
     A: Handler<M>,
-    M: Send,
+    M: 'static + Send,
 {
+    fn id(&self) -> MessageId {
+        self.id
+    }
+    fn size(&self) -> usize {
+        self.size
+    }
+    fn message_type(&self) -> Option<MessageType> {
+        Some(MessageType::of::<M>())
+    }
+    fn abandon(self: Box<Self>, shared: &ActorShared) {
+        // Senders waiting for the response learn about it from the dropped sender
+        if self.tx.is_some() || self.item.is_none() {
+            return;
+        }
+        let letter = DeadLetter::new::<A, M>(self.id, DeadLetterReason::Abandoned);
+        DeadLetters::record_replayable::<A, M>(shared.id(), letter, self);
+    }
     async fn handle(&mut self, act: &mut A, ctx: &mut ActorContext<A>) {
         let item = self.item.take().unwrap();
+        let (id, queued_at) = (self.id, self.queued_at);
         if let Some(tx) = self.tx.take() {
             // If the sender got closed, the future created by Addr::send() got dropped.
             // No need to process the message.
-            if ! tx.is_closed() {
-                let ret = act.handle(item, ctx).await;
-                // We shouldn't panic when this fails:
-                let _ = tx.send(ret);
-                // This might happen when the future created by Addr::send() gets dropped right after the message got handled
+            if !tx.is_closed() {
+                ctx.set_request_cancellation(self.cancellation.take());
+                let ret = handle_catching_panics(act, id, queued_at, item, ctx).await;
+                ctx.set_request_cancellation(None);
+                // This might fail when the future created by Addr::send() gets dropped right after the message got handled
+                if tx.send(ret).is_err() {
+                    if ctx.strict_responses() {
+                        panic!("oneshot::Receiver must be dead");
+                    }
+                    let reason = DeadLetterReason::ResponseUndeliverable;
+                    DeadLetters::record(DeadLetter::new::<A, M>(id, reason));
+                }
+            } else {
+                DeadLetters::record(DeadLetter::new::<A, M>(id, DeadLetterReason::Cancelled));
             }
         } else {
             // handles Addr::do_send() messages
-            let _ = act.handle(item, ctx).await;
+            let _ = handle_catching_panics(act, id, queued_at, item, ctx).await;
+        }
+    }
+}
+
+/// Calls the message handler, exposing the identifier of the message via the context.
+async fn handle_catching_panics<A, M>(
+    act: &mut A,
+    id: MessageId,
+    queued_at: Instant,
+    item: M,
+    ctx: &mut ActorContext<A>,
+) -> Result<<A as Handler<M>>::Response, ActorError>
+where
+    A: Handler<M>,
+    M: Send,
+{
+    let shared = ctx.shared().clone();
+    ctx.set_current_message_id(Some(id));
+    let sampling = <A as Handler<M>>::MESSAGE_SAMPLING.unwrap_or(A::TRACE_SAMPLING);
+    let handling = act.handle(item, ctx);
+    let ret = run_guarded::<A, M, _>(handling, &shared, id, queued_at, sampling).await;
+    ctx.set_current_message_id(None);
+    ret
+}
+
+/// Runs the handler, catching its' panics and aborting it when requested by a [crate::watchdog::Watchdog].
+///
+/// In both cases, the actor gets stopped, as its' state might no longer be consistent.
+/// The latencies get recorded (see [crate::histogram]), and the handling gets traced
+/// according to the sampling, see [crate::logging#message-traces].
+async fn run_guarded<A: Actor, M, F: Future>(
+    handling: F,
+    shared: &ActorShared,
+    id: MessageId,
+    queued_at: Instant,
+    sampling: Sampling,
+) -> Result<F::Output, ActorError> {
+    let started = Instant::now();
+    let handling_guard = shared.start_handling(queued_at, started);
+    let ret = run_catching::<A, M, _>(handling, shared).await;
+    drop(handling_guard);
+    let elapsed = started.elapsed();
+    shared.record_handling(elapsed);
+    if logging::tracing() {
+        logging::trace::<A, M, _>(shared, id, sampling, elapsed, &ret);
+    }
+    ret
+}
+
+async fn run_catching<A: Actor, M, F: Future>(
+    handling: F,
+    shared: &ActorShared,
+) -> Result<F::Output, ActorError> {
+    let abort = shared.abort_token();
+    let handling = AssertUnwindSafe(handling).catch_unwind();
+    let cancelled = abort.cancelled();
+    let ret = match select(pin!(handling), pin!(cancelled)).await {
+        Either::Left((ret, _)) => Some(ret),
+        Either::Right(_) => None,
+    };
+    match ret {
+        Some(Ok(ret)) => Ok(ret),
+        Some(Err(panic)) => {
+            shared.panicked::<M>(&*panic);
+            Err(ActorError::HandlerPanicked(ErrorContext::new::<A, M>(
+                shared.id(),
+            )))
+        }
+        None => {
+            shared.aborted::<M>();
+            Err(ActorError::Aborted(ErrorContext::new::<A, M>(shared.id())))
         }
     }
 }
 
 impl<M: 'static + Send, R: 'static + Send> Envelope<M, R> {
-    pub fn new(item: M, tx: oneshot::Sender<R>) -> Self {
+    pub fn new(
+        item: M,
+        tx: oneshot::Sender<Result<R, ActorError>>,
+        cancellation: CancellationToken,
+    ) -> Self {
         Self {
+            id: MessageId::next(),
+            size: 0,
+            queued_at: Instant::now(),
             item: Some(item),
             tx: Some(tx),
+            cancellation: Some(cancellation),
         }
     }
     /// Used when the message response won't be handled
     pub fn new_no_sender(item: M) -> Self {
         Self {
+            id: MessageId::next(),
+            size: 0,
+            queued_at: Instant::now(),
             item: Some(item),
             tx: None,
+            cancellation: None,
+        }
+    }
+    /// Sets the approximate size of the message
+    pub fn sized(mut self, size: usize) -> Self {
+        self.size = size;
+        self
+    }
+    /// Wraps the message in a trait-object, abstracting away its' type
+    pub fn pack<A>(self) -> QueuePayload<A>
+    where
+        A: Actor,
+        Self: EnvelopeProxy<A>,
+    {
+        Box::from(self)
+    }
+}
+
+/// Envelope for messages handled via [ReadHandler]
+pub(crate) struct ReadEnvelope<M: Send, R: Send> {
+    id: MessageId,
+    size: usize,
+    queued_at: Instant,
+    item: Option<M>,
+    tx: Option<oneshot::Sender<Result<R, ActorError>>>,
+}
+
+#[async_trait]
+impl<A, M> EnvelopeProxy<A> for ReadEnvelope<M, <A as ReadHandler<M>>::Response>
+where
+    A: ReadHandler<M>,
+    M: 'static + Send,
+{
+    fn id(&self) -> MessageId {
+        self.id
+    }
+    fn size(&self) -> usize {
+        self.size
+    }
+    fn message_type(&self) -> Option<MessageType> {
+        Some(MessageType::of::<M>())
+    }
+    async fn handle(&mut self, act: &mut A, ctx: &mut ActorContext<A>) {
+        self.handle_read(act, ctx).await
+    }
+    fn is_read_only(&self) -> bool {
+        true
+    }
+    async fn handle_batch(
+        &mut self,
+        batch: &mut [QueuePayload<A>],
+        act: &mut A,
+        ctx: &mut ActorContext<A>,
+    ) {
+        let (act, ctx) = (&*act, &*ctx);
+        let others = join_all(batch.iter_mut().map(|msg| msg.handle_read(act, ctx)));
+        join(self.handle_read(act, ctx), others).await;
+    }
+    fn handle_read<'a>(&'a mut self, act: &'a A, ctx: &'a ActorContext<A>) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let item = self.item.take().unwrap();
+            let tx = self.tx.take().unwrap();
+            if tx.is_closed() {
+                DeadLetters::record(DeadLetter::new::<A, M>(self.id, DeadLetterReason::Cancelled));
+                return;
+            }
+            let sampling = <A as ReadHandler<M>>::MESSAGE_SAMPLING.unwrap_or(A::TRACE_SAMPLING);
+            let handling = act.handle_read(item, ctx);
+            let (shared, id) = (ctx.shared(), self.id);
+            let ret = run_guarded::<A, M, _>(handling, shared, id, self.queued_at, sampling).await;
+            if tx.send(ret).is_err() {
+                let reason = DeadLetterReason::ResponseUndeliverable;
+                DeadLetters::record(DeadLetter::new::<A, M>(self.id, reason));
+            }
+        })
+    }
+}
+
+impl<M: 'static + Send, R: 'static + Send> ReadEnvelope<M, R> {
+    pub fn new(item: M, tx: oneshot::Sender<Result<R, ActorError>>) -> Self {
+        Self {
+            id: MessageId::next(),
+            size: 0,
+            queued_at: Instant::now(),
+            item: Some(item),
+            tx: Some(tx),
         }
     }
+    /// Sets the approximate size of the message
+    pub fn sized(mut self, size: usize) -> Self {
+        self.size = size;
+        self
+    }
     /// Wraps the message in a trait-object, abstracting away its' type
     pub fn pack<A>(self) -> QueuePayload<A>
     where
@@ -66,3 +319,135 @@ impl<M: 'static + Send, R: 'static + Send> Envelope<M, R> {
         Box::from(self)
     }
 }
+
+/// Empty message, making the runner loop notice changes of the actor's state
+pub(crate) struct Wakeup {
+    id: MessageId,
+}
+
+impl Wakeup {
+    pub fn new() -> Self {
+        Self {
+            id: MessageId::next(),
+        }
+    }
+}
+
+#[async_trait]
+impl<A: Actor> EnvelopeProxy<A> for Wakeup {
+    fn id(&self) -> MessageId {
+        self.id
+    }
+    async fn handle(&mut self, _act: &mut A, _ctx: &mut ActorContext<A>) {}
+}
+
+/// Notification about the plumbing of the actor, see [Actor::context_event]
+pub(crate) struct ContextEventEnvelope {
+    id: MessageId,
+    event: Option<ContextEvent>,
+}
+
+impl ContextEventEnvelope {
+    pub fn new(event: ContextEvent) -> Self {
+        Self {
+            id: MessageId::next(),
+            event: Some(event),
+        }
+    }
+}
+
+#[async_trait]
+impl<A: Actor> EnvelopeProxy<A> for ContextEventEnvelope {
+    fn id(&self) -> MessageId {
+        self.id
+    }
+    async fn handle(&mut self, act: &mut A, ctx: &mut ActorContext<A>) {
+        act.context_event(ctx, self.event.take().unwrap()).await
+    }
+}
+
+/// Continuation of an [ActorFuture](crate::actor_future::ActorFuture), run on the actor's task
+pub(crate) struct ContinuationEnvelope<A: Actor> {
+    id: MessageId,
+    step: Option<Step<A>>,
+}
+
+impl<A: Actor> ContinuationEnvelope<A> {
+    pub fn new(step: Step<A>) -> Self {
+        Self {
+            id: MessageId::next(),
+            step: Some(step),
+        }
+    }
+}
+
+#[async_trait]
+impl<A: Actor> EnvelopeProxy<A> for ContinuationEnvelope<A> {
+    fn id(&self) -> MessageId {
+        self.id
+    }
+    async fn handle(&mut self, act: &mut A, ctx: &mut ActorContext<A>) {
+        let next = (self.step.take().unwrap())(act, ctx);
+        drive(next, ctx.weak_address());
+    }
+}
+
+/// Closure run on the actor by [crate::testing::inspect]
+pub(crate) type Inspection<A> = Box<dyn FnOnce(&A) + Send>;
+
+/// Runs an [Inspection] on the actor, without a handler of its' own
+pub(crate) struct InspectEnvelope<A> {
+    id: MessageId,
+    f: Option<Inspection<A>>,
+}
+
+impl<A> InspectEnvelope<A> {
+    pub fn new(f: Inspection<A>) -> Self {
+        Self {
+            id: MessageId::next(),
+            f: Some(f),
+        }
+    }
+}
+
+#[async_trait]
+impl<A: Actor> EnvelopeProxy<A> for InspectEnvelope<A> {
+    fn id(&self) -> MessageId {
+        self.id
+    }
+    async fn handle(&mut self, act: &mut A, _ctx: &mut ActorContext<A>) {
+        (self.f.take().unwrap())(act)
+    }
+}
+
+/// Health check, answered without involving the actor
+pub(crate) struct PingEnvelope {
+    id: MessageId,
+    tx: Option<oneshot::Sender<Health>>,
+}
+
+impl PingEnvelope {
+    pub fn new(tx: oneshot::Sender<Health>) -> Self {
+        Self {
+            id: MessageId::next(),
+            tx: Some(tx),
+        }
+    }
+}
+
+#[async_trait]
+impl<A: Actor> EnvelopeProxy<A> for PingEnvelope {
+    fn id(&self) -> MessageId {
+        self.id
+    }
+    async fn handle(&mut self, _act: &mut A, ctx: &mut ActorContext<A>) {
+        let shared = ctx.shared();
+        let health = Health {
+            state: shared.state(),
+            queue_depth: shared.depth(),
+            last_activity: shared.last_activity(),
+        };
+        // The prober might have given up
+        let _ = self.tx.take().unwrap().send(health);
+    }
+}
@@ -0,0 +1,111 @@
+//! Mirroring of traffic to a shadow actor
+//!
+//! Before cutting over to a rewritten implementation of an actor, it can be fed a copy
+//! of the production traffic. [MirroredRecipient] sends each message to the primary actor,
+//! whose responses reach the callers, and a clone of it to the shadow actor, whose responses
+//! get ignored. The shadow can never slow the callers down: the copies are sent without waiting,
+//! and get dropped (and counted in [MirrorStats]) if its' mailbox is full or it's gone.
+
+use crate::{addr::Recipient, error::ActorError};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+/// Counts of the messages sent via a [MirroredRecipient]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct MirrorStats {
+    /// Copies delivered to the shadow's mailbox
+    pub mirrored: u64,
+    /// Copies dropped, because the shadow's mailbox was full or the shadow was gone
+    pub dropped: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    mirrored: AtomicU64,
+    dropped: AtomicU64,
+}
+
+type Shadow<M> = dyn Fn(M) -> Result<(), ActorError> + Send + Sync;
+
+/// [Recipient] wrapper mirroring messages of type `M` to a shadow actor, see [crate::mirror]
+///
+/// Clones share the [MirrorStats].
+pub struct MirroredRecipient<M, R> {
+    primary: Recipient<M, R>,
+    shadow: Arc<Shadow<M>>,
+    counters: Arc<Counters>,
+}
+
+impl<M, R> Clone for MirroredRecipient<M, R> {
+    fn clone(&self) -> Self {
+        Self {
+            primary: self.primary.clone(),
+            shadow: self.shadow.clone(),
+            counters: self.counters.clone(),
+        }
+    }
+}
+
+impl<M, R> fmt::Debug for MirroredRecipient<M, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MirroredRecipient")
+            .field("primary", &self.primary)
+            .field("counters", &self.counters)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<M, R> MirroredRecipient<M, R>
+where
+    M: 'static + Clone + Send,
+    R: 'static + Send,
+{
+    /// Wraps the primary recipient, mirroring the messages to the shadow one.
+    ///
+    /// The shadow may respond with a different type, as its' responses get ignored anyway.
+    pub fn new<S: 'static + Send>(primary: Recipient<M, R>, shadow: Recipient<M, S>) -> Self {
+        Self {
+            primary,
+            shadow: Arc::new(move |msg| shadow.try_send(msg)),
+            counters: Arc::default(),
+        }
+    }
+    /// Returns the primary recipient
+    pub fn inner(&self) -> &Recipient<M, R> {
+        &self.primary
+    }
+    /// Returns the counts of the copies sent so far
+    pub fn stats(&self) -> MirrorStats {
+        MirrorStats {
+            mirrored: self.counters.mirrored.load(Ordering::Relaxed),
+            dropped: self.counters.dropped.load(Ordering::Relaxed),
+        }
+    }
+    fn mirror(&self, msg: &M) {
+        let counter = match (self.shadow)(msg.clone()) {
+            Ok(()) => &self.counters.mirrored,
+            Err(_) => &self.counters.dropped,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+    /// Sends the message to the primary actor, and its' copy to the shadow one
+    pub async fn send(&self, msg: M) -> Result<R, ActorError> {
+        self.mirror(&msg);
+        self.primary.send(msg).await
+    }
+    /// See [Recipient::do_send]
+    pub fn do_send(&self, msg: M) {
+        self.mirror(&msg);
+        self.primary.do_send(msg)
+    }
+    /// See [Recipient::try_send]
+    pub fn try_send(&self, msg: M) -> Result<(), ActorError> {
+        self.mirror(&msg);
+        self.primary.try_send(msg)
+    }
+}
@@ -0,0 +1,282 @@
+//! The outbox pattern for actors with external side effects
+//!
+//! Calling external systems straight from a handler loses the effect if the actor crashes
+//! right after changing its' state, or duplicates the change if the call gets retried.
+//! Instead, the handler [Outbox::push]es the effects into an [OutboxStore] as part of handling
+//! the message (ideally within the same transaction as the state change, if the store is backed by
+//! the same database), and a [Forwarder] actor delivers them in the background, retrying failures.
+//!
+//! Effects get removed from the store only once delivered, so they survive restarts
+//! and get delivered at least once, in the order in which they were pushed.
+
+use crate::{
+    actor::{Actor, Handler},
+    context::ActorContext,
+    supervised::Backoff,
+};
+use async_trait::async_trait;
+use futures_util::future::{select, BoxFuture};
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    fmt,
+    future::Future,
+    pin::pin,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::Notify;
+
+/// Identifier of an effect within an [OutboxStore]
+pub type EffectId = u64;
+
+/// Storage of the effects waiting for delivery
+#[async_trait]
+pub trait OutboxStore: Send + Sync + 'static {
+    /// The effects, e.g. requests to external services
+    type Effect: Send + 'static;
+    type Error: std::error::Error + Send + 'static;
+    /// Stores the effects, either all or none of them
+    async fn append(&self, effects: Vec<Self::Effect>) -> Result<(), Self::Error>;
+    /// Returns at most `limit` of the oldest stored effects, oldest first
+    async fn pending(&self, limit: usize) -> Result<Vec<(EffectId, Self::Effect)>, Self::Error>;
+    /// Removes a delivered effect
+    async fn remove(&self, id: EffectId) -> Result<(), Self::Error>;
+}
+
+/// [OutboxStore] keeping the effects in memory
+///
+/// The effects do not survive the process, but they do survive restarts of the actors.
+pub struct MemoryStore<E> {
+    inner: Mutex<(EffectId, BTreeMap<EffectId, E>)>,
+}
+
+impl<E> Default for MemoryStore<E> {
+    fn default() -> Self {
+        Self {
+            inner: Mutex::new((0, BTreeMap::new())),
+        }
+    }
+}
+
+impl<E> fmt::Debug for MemoryStore<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryStore")
+            .field("effects", &self.inner.lock().unwrap().1.len())
+            .finish()
+    }
+}
+
+impl<E> MemoryStore<E> {
+    /// Creates an empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Number of effects waiting for delivery
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().1.len()
+    }
+    /// Returns `true` if all the effects have been delivered
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[async_trait]
+impl<E: 'static + Clone + Send> OutboxStore for MemoryStore<E> {
+    type Effect = E;
+    type Error = Infallible;
+    async fn append(&self, effects: Vec<E>) -> Result<(), Infallible> {
+        let mut inner = self.inner.lock().unwrap();
+        let (next_id, stored) = &mut *inner;
+        for effect in effects {
+            stored.insert(*next_id, effect);
+            *next_id += 1;
+        }
+        Ok(())
+    }
+    async fn pending(&self, limit: usize) -> Result<Vec<(EffectId, E)>, Infallible> {
+        let inner = self.inner.lock().unwrap();
+        let pending = inner.1.iter().take(limit);
+        Ok(pending.map(|(id, effect)| (*id, effect.clone())).collect())
+    }
+    async fn remove(&self, id: EffectId) -> Result<(), Infallible> {
+        self.inner.lock().unwrap().1.remove(&id);
+        Ok(())
+    }
+}
+
+/// Handle through which handlers push effects, see [crate::outbox]
+pub struct Outbox<S> {
+    store: Arc<S>,
+    /// Wakes the forwarder up
+    notify: Arc<Notify>,
+}
+
+impl<S> Clone for Outbox<S> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+            notify: self.notify.clone(),
+        }
+    }
+}
+
+impl<S: fmt::Debug> fmt::Debug for Outbox<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Outbox")
+            .field("store", &self.store)
+            .finish()
+    }
+}
+
+impl<S: OutboxStore> Outbox<S> {
+    /// Creates an outbox backed by the given store
+    pub fn new(store: S) -> Self {
+        Self {
+            store: Arc::new(store),
+            notify: Arc::new(Notify::new()),
+        }
+    }
+    /// Returns the underlying store
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+    /// Stores the effects and wakes the [Forwarder] up
+    pub async fn push(&self, effects: Vec<S::Effect>) -> Result<(), S::Error> {
+        self.store.append(effects).await?;
+        self.notify.notify_one();
+        Ok(())
+    }
+    /// Creates the actor delivering the effects via the given closure.
+    ///
+    /// Only one forwarder should be running per store, so that the effects get delivered in order.
+    pub fn forwarder<F, Fut, E>(&self, mut deliver: F) -> Forwarder<S>
+    where
+        F: 'static + FnMut(S::Effect) -> Fut + Send,
+        Fut: 'static + Future<Output = Result<(), E>> + Send,
+    {
+        Forwarder {
+            store: self.store.clone(),
+            notify: self.notify.clone(),
+            deliver: Box::new(move |effect| {
+                let delivered = deliver(effect);
+                Box::pin(async move { delivered.await.is_ok() })
+            }),
+            backoff: Backoff::DEFAULT,
+            poll_interval: Duration::from_secs(5),
+            batch: 64,
+            attempt: 0,
+            retry_at: None,
+        }
+    }
+}
+
+/// Delivers a single effect, returning `true` on success
+type Deliver<E> = Box<dyn FnMut(E) -> BoxFuture<'static, bool> + Send>;
+
+/// Actor delivering the effects of an [Outbox], see [crate::outbox]
+pub struct Forwarder<S: OutboxStore> {
+    store: Arc<S>,
+    notify: Arc<Notify>,
+    deliver: Deliver<S::Effect>,
+    backoff: Backoff,
+    poll_interval: Duration,
+    batch: usize,
+    /// Consecutive failures
+    attempt: u32,
+    /// Deliveries are suspended until then
+    retry_at: Option<Instant>,
+}
+
+impl<S: OutboxStore> fmt::Debug for Forwarder<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Forwarder")
+            .field("backoff", &self.backoff)
+            .field("poll_interval", &self.poll_interval)
+            .field("batch", &self.batch)
+            .field("attempt", &self.attempt)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S: OutboxStore> Forwarder<S> {
+    /// Sets the delays between retries of failed deliveries, [Backoff::DEFAULT] by default
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+    /// Sets how often the store is checked for effects pushed by someone else,
+    /// like another process sharing the store. 5 seconds by default.
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+    /// Sets how many effects are read from the store at once, 64 by default
+    pub fn with_batch(mut self, batch: usize) -> Self {
+        self.batch = batch;
+        self
+    }
+    /// Delivers the stored effects, returning `false` on failure
+    async fn forward(&mut self) -> bool {
+        loop {
+            let pending = match self.store.pending(self.batch).await {
+                Ok(pending) if pending.is_empty() => return true,
+                Ok(pending) => pending,
+                Err(_) => return false,
+            };
+            for (id, effect) in pending {
+                if !(self.deliver)(effect).await || self.store.remove(id).await.is_err() {
+                    return false;
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<S: OutboxStore> Actor for Forwarder<S> {
+    async fn started(&mut self, ctx: &mut ActorContext<Self>) {
+        let notify = self.notify.clone();
+        let poll_interval = self.poll_interval;
+        let forwarder = ctx.weak_address();
+        tokio::spawn(async move {
+            loop {
+                let notified = pin!(notify.notified());
+                let _ = select(notified, pin!(tokio::time::sleep(poll_interval))).await;
+                match forwarder.upgrade() {
+                    Some(forwarder) => forwarder.do_send(Forward),
+                    None => break,
+                }
+            }
+        });
+        // Effects left over from before a restart
+        ctx.notify(Forward);
+    }
+}
+
+#[doc(hidden)]
+pub struct Forward;
+
+#[async_trait]
+impl<S: OutboxStore> Handler<Forward> for Forwarder<S> {
+    type Response = ();
+    async fn handle(&mut self, _msg: Forward, _ctx: &mut ActorContext<Self>) {
+        if self.retry_at.is_some_and(|retry_at| Instant::now() < retry_at) {
+            return;
+        }
+        self.retry_at = None;
+        if self.forward().await {
+            self.attempt = 0;
+            return;
+        }
+        let delay = self.backoff.delay(self.attempt);
+        self.attempt = self.attempt.saturating_add(1);
+        self.retry_at = Some(Instant::now() + delay);
+        let notify = self.notify.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            notify.notify_one();
+        });
+    }
+}
@@ -0,0 +1,166 @@
+//! Requests raced across replicas of an actor
+//!
+//! Sending the same query to several equivalent actors and taking the first response
+//! trims the tail latency caused by a replica which happens to be busy or slow.
+//! [race] does so right away, while a [Hedge] asks the next replica only once the previous one
+//! has not responded for a while, which costs far fewer extra requests.
+
+use crate::{actor::Handler, addr::Addr, error::ActorError};
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// Sends the message to all the replicas, resolving with the first successful response.
+///
+/// The other requests get dropped, so their' handlers can notice via
+/// [crate::context::ActorContext::request_cancellation]. If all the requests fail,
+/// the error of the last one is returned.
+///
+/// # Panics
+///
+/// Panics if there are no replicas.
+pub async fn race<A, M>(
+    replicas: &[Addr<A>],
+    msg: M,
+) -> Result<<A as Handler<M>>::Response, ActorError>
+where
+    A: Handler<M>,
+    M: 'static + Send + Clone,
+{
+    assert!(!replicas.is_empty(), "A race needs at least one replica");
+    let mut pending: FuturesUnordered<_> = replicas
+        .iter()
+        .map(|replica| replica.send(msg.clone()))
+        .collect();
+    let mut error = None;
+    while let Some(ret) = pending.next().await {
+        match ret {
+            Ok(response) => return Ok(response),
+            Err(e) => error = Some(e),
+        }
+    }
+    Err(error.unwrap())
+}
+
+/// Counts of the requests sent via a [Hedge]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct HedgeStats {
+    pub requests: u64,
+    /// Requests which got sent to more than one replica
+    pub hedged: u64,
+    /// Requests answered by a replica other than the primary one
+    pub hedge_wins: u64,
+}
+
+impl HedgeStats {
+    /// Fraction of the requests which got sent to more than one replica
+    pub fn hedge_rate(&self) -> f64 {
+        if self.requests == 0 {
+            return 0.0;
+        }
+        self.hedged as f64 / self.requests as f64
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    requests: AtomicU64,
+    hedged: AtomicU64,
+    hedge_wins: AtomicU64,
+}
+
+/// Sends requests to a primary replica, hedging them to the next ones after a delay
+///
+/// The delay is usually set to a high percentile (like the 95th) of the latency of the replicas,
+/// so that only the slowest requests get hedged. Clones share the [HedgeStats].
+#[derive(Clone, Debug)]
+pub struct Hedge {
+    delay: Duration,
+    counters: Arc<Counters>,
+}
+
+impl Hedge {
+    /// Creates a hedge sending the request to the next replica once the given delay elapses
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            counters: Arc::default(),
+        }
+    }
+    /// Returns the counts of the requests sent so far
+    pub fn stats(&self) -> HedgeStats {
+        HedgeStats {
+            requests: self.counters.requests.load(Ordering::Relaxed),
+            hedged: self.counters.hedged.load(Ordering::Relaxed),
+            hedge_wins: self.counters.hedge_wins.load(Ordering::Relaxed),
+        }
+    }
+    /// Sends the message to the first replica, and to each next one once the delay elapses
+    /// without a response (or right away once all the requests sent so far have failed).
+    ///
+    /// Resolves with the first successful response, like [race].
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are no replicas.
+    pub async fn send<A, M>(
+        &self,
+        replicas: &[Addr<A>],
+        msg: M,
+    ) -> Result<<A as Handler<M>>::Response, ActorError>
+    where
+        A: Handler<M>,
+        M: 'static + Send + Clone,
+    {
+        assert!(!replicas.is_empty(), "A hedge needs at least one replica");
+        self.counters.requests.fetch_add(1, Ordering::Relaxed);
+        let launch = |index: usize| {
+            let msg = msg.clone();
+            async move { (index, replicas[index].send(msg).await) }
+        };
+        let mut pending = FuturesUnordered::new();
+        pending.push(launch(0));
+        let mut launched = 1;
+        let hedge = |pending: &mut FuturesUnordered<_>, launched: &mut usize| {
+            if *launched == 1 {
+                self.counters.hedged.fetch_add(1, Ordering::Relaxed);
+            }
+            pending.push(launch(*launched));
+            *launched += 1;
+        };
+        let mut error = None;
+        loop {
+            let next = if launched < replicas.len() {
+                match tokio::time::timeout(self.delay, pending.next()).await {
+                    Ok(next) => next,
+                    Err(_) => {
+                        hedge(&mut pending, &mut launched);
+                        continue;
+                    }
+                }
+            } else {
+                pending.next().await
+            };
+            match next {
+                Some((index, Ok(response))) => {
+                    if index > 0 {
+                        self.counters.hedge_wins.fetch_add(1, Ordering::Relaxed);
+                    }
+                    return Ok(response);
+                }
+                Some((_, Err(e))) => {
+                    error = Some(e);
+                    if pending.is_empty() && launched < replicas.len() {
+                        hedge(&mut pending, &mut launched);
+                    }
+                }
+                None => return Err(error.unwrap()),
+            }
+        }
+    }
+}
@@ -0,0 +1,55 @@
+//! Internal source of randomness, seeded by [crate::testing::run_deterministic]
+
+use std::{
+    cell::RefCell,
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+};
+
+thread_local! {
+    /// State of the generator while running deterministically
+    static SEEDED: RefCell<Option<u64>> = const { RefCell::new(None) };
+}
+
+/// Advances the state of splitmix64, returning the next random number
+pub(crate) fn splitmix64(state: &mut u64) -> u64 {
+    // The state walks through all the values, so no seed gets it stuck
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Seeds the randomness of the current thread for as long as it's alive,
+/// putting back the seed of the enclosing run afterwards, even when unwinding
+pub(crate) struct Seeded(Option<u64>);
+
+impl Seeded {
+    pub fn new(seed: u64) -> Self {
+        Self(SEEDED.with(|seeded| seeded.replace(Some(seed))))
+    }
+}
+
+impl Drop for Seeded {
+    fn drop(&mut self) {
+        SEEDED.with(|seeded| *seeded.borrow_mut() = self.0.take());
+    }
+}
+
+/// Returns the next seeded random number, if the current thread is seeded
+fn seeded() -> Option<u64> {
+    SEEDED.with(|seeded| seeded.borrow_mut().as_mut().map(splitmix64))
+}
+
+/// Returns a random number in [0, 1)
+pub(crate) fn fraction() -> f64 {
+    // Without pulling in a dependency
+    let random = seeded().unwrap_or_else(|| RandomState::new().build_hasher().finish());
+    random as f64 / (u64::MAX as f64 + 1.0)
+}
+
+/// Returns a random number lower than `bound` if the current thread is seeded, zero otherwise
+pub(crate) fn seeded_below(bound: u64) -> u64 {
+    seeded().map_or(0, |random| random % bound)
+}
@@ -0,0 +1,261 @@
+//! Recording and replaying the messages sent to an actor
+//!
+//! [RecordingSender] logs every message sent through it to a file, along with the time it was sent at.
+//! It records on the sending side: messages reaching the actor some other way, like via other
+//! clones of its' [Addr], [crate::context::ActorContext::notify] or streams, are not recorded.
+//! For a complete recording, the wrapper has to be the only way of sending the messages.
+//! The recording can then be fed back into a fresh instance of the actor via [replay],
+//! which helps with reproducing bugs of actors implementing complex state machines.
+//!
+//! A recording holds messages of a single type, typically an enum of all the inputs of the state machine.
+//! Each message takes up one line of the file, see [Recordable].
+//!
+//! Messages get recorded once they have been enqueued, in the order of the mailbox.
+//! The file gets written by a thread of its' own, so that the runtime does not block on the disk;
+//! [RecordingSender::flush] waits for the messages sent so far to be written.
+
+use crate::{
+    actor::{Actor, Handler},
+    addr::Addr,
+    error::ActorError,
+};
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    marker::PhantomData,
+    path::Path,
+    sync::{mpsc, Arc, Mutex},
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+use tokio::sync::oneshot;
+
+/// Trait implemented on messages which can be recorded
+pub trait Recordable: Sized {
+    /// Converts the message into text
+    fn encode(&self) -> String;
+    /// Converts the text produced by [Recordable::encode] back into the message
+    fn decode(text: &str) -> Option<Self>;
+}
+
+/// Error returned by a [RecordingSender]
+#[derive(Error, Debug)]
+pub enum RecordError {
+    #[error(transparent)]
+    /// The message could not be sent, so it has not been recorded
+    Send(#[from] ActorError),
+    #[error("Failed to write the recording: {0}")]
+    /// Writing the recording failed, so no more messages get sent
+    Io(Arc<io::Error>),
+}
+
+enum Command {
+    Line(String),
+    /// Answered once the lines before it have been written
+    Flush(oneshot::Sender<()>),
+}
+
+struct Recorder {
+    /// Locked while enqueueing, so that the lines keep the order of the mailbox
+    commands: Mutex<mpsc::Sender<Command>>,
+    /// The error the writer stopped on
+    failure: Arc<Mutex<Option<Arc<io::Error>>>>,
+    started: Instant,
+}
+
+impl Recorder {
+    fn new(file: File) -> Self {
+        let (commands, received) = mpsc::channel();
+        let failure = Arc::new(Mutex::new(None));
+        let writer_failure = failure.clone();
+        std::thread::spawn(move || write(BufWriter::new(file), received, &writer_failure));
+        Self {
+            commands: Mutex::new(commands),
+            failure,
+            started: Instant::now(),
+        }
+    }
+    fn failure(&self) -> Option<Arc<io::Error>> {
+        self.failure.lock().unwrap().clone()
+    }
+    /// Enqueues the message via `enqueue` and records its' text if that succeeds
+    fn record<R>(
+        &self,
+        text: &str,
+        enqueue: impl FnOnce() -> Result<R, ActorError>,
+    ) -> Result<R, RecordError> {
+        let commands = self.commands.lock().unwrap();
+        if let Some(e) = self.failure() {
+            return Err(RecordError::Io(e));
+        }
+        let enqueued = enqueue()?;
+        let micros = self.started.elapsed().as_micros();
+        // The writer only stops on errors, which the next message runs into
+        let _ = commands.send(Command::Line(format!("{}\t{}", micros, escape(text))));
+        Ok(enqueued)
+    }
+}
+
+/// Appends the lines to the recording, flushing each right away so that it survives a crash
+fn write(
+    mut file: BufWriter<File>,
+    commands: mpsc::Receiver<Command>,
+    failure: &Mutex<Option<Arc<io::Error>>>,
+) {
+    for command in commands {
+        match command {
+            Command::Line(line) => {
+                if let Err(e) = writeln!(file, "{}", line).and_then(|()| file.flush()) {
+                    *failure.lock().unwrap() = Some(Arc::new(e));
+                    return;
+                }
+            }
+            Command::Flush(done) => {
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
+/// Wrapper of an address, recording the messages of type `M` sent through it, see [crate::record]
+pub struct RecordingSender<A: Actor, M> {
+    addr: Addr<A>,
+    recorder: Arc<Recorder>,
+    _msg: PhantomData<fn(M)>,
+}
+
+impl<A: Actor, M> Clone for RecordingSender<A, M> {
+    fn clone(&self) -> Self {
+        Self {
+            addr: self.addr.clone(),
+            recorder: self.recorder.clone(),
+            _msg: PhantomData,
+        }
+    }
+}
+
+impl<A, M> RecordingSender<A, M>
+where
+    A: Handler<M>,
+    M: 'static + Send + Recordable,
+{
+    /// Wraps the address, recording to a file at the given path, which gets truncated if it exists
+    pub fn create(addr: Addr<A>, path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self {
+            addr,
+            recorder: Arc::new(Recorder::new(File::create(path)?)),
+            _msg: PhantomData,
+        })
+    }
+    /// Returns the wrapped address. Messages sent through it directly do not get recorded.
+    pub fn addr(&self) -> &Addr<A> {
+        &self.addr
+    }
+    /// Behaves like [Addr::send], recording the message once it has been enqueued.
+    ///
+    /// Fails with [RecordError::Io] without sending anything once writing the recording has failed.
+    pub async fn send(&self, msg: M) -> Result<<A as Handler<M>>::Response, RecordError> {
+        let text = msg.encode();
+        let response = self.recorder.record(&text, || self.addr.request(msg))?;
+        Ok(response.await?)
+    }
+    /// Behaves like [Addr::do_send], recording the message if it has been enqueued, ignoring all errors
+    pub fn do_send(&self, msg: M) {
+        let text = msg.encode();
+        let _ = self.recorder.record(&text, || {
+            let context = self.addr.msg_queue.error_context::<M>();
+            if self.addr.msg_queue.do_send(msg, true) {
+                Ok(())
+            } else {
+                // Already recorded as a dead letter, the error only skips the recording
+                Err(ActorError::CannotSend(context))
+            }
+        });
+    }
+    /// Waits until the messages recorded so far have been written to the file
+    pub async fn flush(&self) -> Result<(), RecordError> {
+        let (done, written) = oneshot::channel();
+        let _ = self.recorder.commands.lock().unwrap().send(Command::Flush(done));
+        // Dropped without an answer if the writer has failed
+        let _ = written.await;
+        match self.recorder.failure() {
+            Some(e) => Err(RecordError::Io(e)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Reads the recording at the given path, returning the messages along with the times they were sent at
+/// (counting from the creation of the [RecordingSender])
+pub fn load<M: Recordable>(path: impl AsRef<Path>) -> io::Result<Vec<(Duration, M)>> {
+    let invalid = |line: usize| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Malformed recording entry at line {}", line + 1),
+        )
+    };
+    let mut messages = Vec::new();
+    for (number, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        let (micros, text) = line.split_once('\t').ok_or_else(|| invalid(number))?;
+        let micros = micros.parse().map_err(|_| invalid(number))?;
+        let msg = unescape(text)
+            .as_deref()
+            .and_then(M::decode)
+            .ok_or_else(|| invalid(number))?;
+        messages.push((Duration::from_micros(micros), msg));
+    }
+    Ok(messages)
+}
+
+/// Sends the messages from the recording at the given path to the actor, one after another,
+/// returning the responses in order.
+///
+/// The messages are sent as fast as the actor handles them, regardless of the recorded times.
+pub async fn replay<A, M>(
+    path: impl AsRef<Path>,
+    addr: &Addr<A>,
+) -> io::Result<Vec<Result<<A as Handler<M>>::Response, ActorError>>>
+where
+    A: Handler<M>,
+    M: 'static + Send + Recordable,
+{
+    let mut responses = Vec::new();
+    for (_, msg) in load::<M>(path)? {
+        responses.push(addr.send(msg).await);
+    }
+    Ok(responses)
+}
+
+/// Makes the text fit in a single line
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Reverses [escape], returning `None` for invalid escape sequences
+fn unescape(text: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next()? {
+            '\\' => unescaped.push('\\'),
+            'n' => unescaped.push('\n'),
+            'r' => unescaped.push('\r'),
+            _ => return None,
+        }
+    }
+    Some(unescaped)
+}
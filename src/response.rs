@@ -0,0 +1,128 @@
+//! Special response types for message handlers
+
+use crate::error::ActorError;
+use futures_util::{
+    future::{AndThen, MapOk, TryFutureExt},
+    stream::{BoxStream, Stream, StreamExt},
+};
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::sync::mpsc;
+
+enum StreamingInner<T> {
+    Channel(mpsc::Receiver<T>),
+    Boxed(BoxStream<'static, T>),
+}
+
+/// Response consisting of a stream of items
+///
+/// It lets a [crate::actor::Handler] answer with many results produced over time
+/// (e.g. paged reads, tailing logs) instead of collecting them into one big collection.
+///
+/// The handler returns the [StreamingResponse] right away, while the items get
+/// delivered later on, either from a [StreamSender] or from an arbitrary [Stream].
+/// The sender receives the [StreamingResponse] from [crate::addr::Addr::send] and consumes it as a [Stream].
+pub struct StreamingResponse<T> {
+    inner: StreamingInner<T>,
+}
+
+impl<T: Send + 'static> StreamingResponse<T> {
+    /// Creates a response fed through the returned [StreamSender].
+    ///
+    /// At most `buffer` items are kept before sending starts waiting for the consumer.
+    /// The stream ends once all senders get dropped.
+    pub fn channel(buffer: usize) -> (StreamSender<T>, Self) {
+        let (tx, rx) = mpsc::channel(buffer);
+        (
+            StreamSender { tx },
+            Self {
+                inner: StreamingInner::Channel(rx),
+            },
+        )
+    }
+    /// Creates a response yielding items from the given stream
+    pub fn from_stream<S>(stream: S) -> Self
+    where
+        S: Stream<Item = T> + Send + 'static,
+    {
+        Self {
+            inner: StreamingInner::Boxed(stream.boxed()),
+        }
+    }
+}
+
+impl<T> Stream for StreamingResponse<T> {
+    type Item = T;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        match &mut self.inner {
+            StreamingInner::Channel(rx) => rx.poll_recv(cx),
+            StreamingInner::Boxed(stream) => stream.poll_next_unpin(cx),
+        }
+    }
+}
+
+impl<T> fmt::Debug for StreamingResponse<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StreamingResponse").finish_non_exhaustive()
+    }
+}
+
+/// Producing end of [StreamingResponse::channel]
+#[derive(Debug)]
+pub struct StreamSender<T> {
+    tx: mpsc::Sender<T>,
+}
+
+impl<T> Clone for StreamSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+        }
+    }
+}
+
+impl<T> StreamSender<T> {
+    /// Sends the next item, waiting if the buffer is full.
+    ///
+    /// Gives the item back if the receiving end has been dropped.
+    pub async fn send(&self, item: T) -> Result<(), T> {
+        self.tx.send(item).await.map_err(|e| e.0)
+    }
+    /// Returns `true` if the receiving end has been dropped
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+}
+
+/// Combinators for the futures returned by [crate::addr::Addr::send] and alike
+///
+/// They let chains of requests spanning several actors read top to bottom,
+/// while all the steps share [ActorError] as the error type:
+/// `addr.send(Get).map_response(|v| v + 1).and_then_response(|v| other.send(Put(v)))`.
+///
+/// Not part of the prelude, as it would shadow the combinators of [futures_util::FutureExt].
+pub trait RequestExt<R>: Future<Output = Result<R, ActorError>> + Sized {
+    /// Transforms the response, once it arrives
+    fn map_response<T, F>(self, f: F) -> MapOk<Self, F>
+    where
+        F: FnOnce(R) -> T,
+    {
+        TryFutureExt::map_ok(self, f)
+    }
+    /// Makes another request using the response, once it arrives.
+    ///
+    /// The chain fails with the first error, skipping the requests after it.
+    fn and_then_response<T, F, Fut>(self, f: F) -> AndThen<Self, Fut, F>
+    where
+        F: FnOnce(R) -> Fut,
+        Fut: Future<Output = Result<T, ActorError>>,
+    {
+        TryFutureExt::and_then(self, f)
+    }
+}
+
+impl<R, Fut: Future<Output = Result<R, ActorError>>> RequestExt<R> for Fut {}
@@ -0,0 +1,434 @@
+//! Distributing messages among a pool of actors of the same type
+//!
+//! A [Router] holds the addresses of its' members, sending each message to one of them.
+//! The members stay ordinary actors, so they can be reached directly via [Router::members],
+//! e.g. for custom routing or targeted maintenance messages.
+//!
+//! With [Dispatch::Shared], members pull messages from a shared queue
+//! instead of getting them assigned in turns.
+//!
+//! The pool can be [resized](Router::resize) at runtime, also [automatically](Router::autoscale).
+
+use crate::{
+    actor::{Actor, ActorId, Handler, StopMode},
+    addr::Addr,
+    error::ActorError,
+    health::Health,
+};
+use futures_util::future::join_all;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
+    pin::pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::Notify;
+
+/// Starts new members
+type Factory<A> = Box<dyn FnMut() -> Addr<A> + Send>;
+
+/// How long a key stays pinned to a member without being used, unless configured otherwise
+const DEFAULT_SESSION_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Load of the pool, as seen by a [ScalingPolicy]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PoolLoad {
+    /// Number of members
+    pub members: usize,
+    /// Number of messages waiting in the mailboxes of all members
+    pub total_queue_depth: usize,
+    /// Number of messages waiting in the fullest mailbox
+    pub max_queue_depth: usize,
+}
+
+/// Decides the size of a pool, see [Router::autoscale]
+pub trait ScalingPolicy: Send + Sync {
+    /// Returns the number of members the pool should have
+    fn desired_size(&self, load: &PoolLoad) -> usize;
+}
+
+/// Adds a member while the average mailbox holds more than `grow_above` messages
+/// and removes one while it holds fewer than `shrink_below`, staying within `min..=max` members
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct QueueDepthScaling {
+    pub min: usize,
+    pub max: usize,
+    pub grow_above: usize,
+    pub shrink_below: usize,
+}
+
+impl ScalingPolicy for QueueDepthScaling {
+    fn desired_size(&self, load: &PoolLoad) -> usize {
+        let average = load.total_queue_depth / load.members.max(1);
+        let desired = if average > self.grow_above {
+            load.members + 1
+        } else if average < self.shrink_below {
+            load.members.saturating_sub(1)
+        } else {
+            load.members
+        };
+        desired.clamp(self.min, self.max)
+    }
+}
+
+/// How a [Router] assigns messages to its' members
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Default)]
+pub enum Dispatch {
+    /// Each message goes straight to the mailbox of the next member
+    #[default]
+    RoundRobin,
+    /// Members pull messages from a queue shared by the router, one at a time,
+    /// so that a slow message does not hold up the ones which would be stuck behind it.
+    ///
+    /// Messages sent via [Router::do_send] and [Router::try_send] occupy the member
+    /// until they're handled as well.
+    Shared,
+}
+
+struct Inner<A: Actor> {
+    members: RwLock<Vec<Addr<A>>>,
+    next: AtomicUsize,
+    factory: Mutex<Option<Factory<A>>>,
+    dispatch: Mutex<Dispatch>,
+    /// Members currently handling a message dispatched in [Dispatch::Shared] mode
+    busy: Mutex<HashSet<ActorId>>,
+    /// Notified when a member stops being busy
+    idle: Notify,
+    sessions: Mutex<Sessions<A>>,
+}
+
+/// Members pinned to keys by [Router::send_sticky]
+struct Sessions<A: Actor> {
+    timeout: Duration,
+    /// Keyed by the hash of the key, along with the time of the latest use
+    pins: HashMap<u64, (Addr<A>, Instant)>,
+}
+
+/// Marks the member as busy for as long as it's alive
+struct Lease<A: Actor> {
+    inner: Arc<Inner<A>>,
+    member: Addr<A>,
+}
+
+impl<A: Actor> Drop for Lease<A> {
+    fn drop(&mut self) {
+        self.inner.busy.lock().unwrap().remove(&self.member.id());
+        self.inner.idle.notify_one();
+    }
+}
+
+/// Pool of actors of the same type, fronted by a single handle
+///
+/// Messages are distributed round-robin, skipping members which are no longer connected.
+/// Clones of the router share the pool.
+pub struct Router<A: Actor> {
+    inner: Arc<Inner<A>>,
+}
+
+impl<A: Actor> Clone for Router<A> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<A: Actor> Router<A> {
+    /// Creates a router over the given actors
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are no members.
+    pub fn new(members: impl IntoIterator<Item = Addr<A>>) -> Self {
+        Self::new_impl(members.into_iter().collect(), None)
+    }
+    fn new_impl(members: Vec<Addr<A>>, factory: Option<Factory<A>>) -> Self {
+        assert!(!members.is_empty(), "A router needs at least one member");
+        Self {
+            inner: Arc::new(Inner {
+                members: RwLock::new(members),
+                next: AtomicUsize::new(0),
+                factory: Mutex::new(factory),
+                dispatch: Mutex::default(),
+                busy: Mutex::default(),
+                idle: Notify::new(),
+                sessions: Mutex::new(Sessions {
+                    timeout: DEFAULT_SESSION_TIMEOUT,
+                    pins: HashMap::new(),
+                }),
+            }),
+        }
+    }
+    /// Starts `size` actors built by the factory, routing messages among them
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is zero.
+    pub fn spawn(size: usize, mut factory: impl 'static + FnMut() -> A + Send) -> Self {
+        Self::spawn_with(size, move || factory().start())
+    }
+    /// Behaves like [Router::spawn], but the factory starts the members itself,
+    /// e.g. via [crate::supervised::Supervised::create_supervised]
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is zero.
+    pub fn spawn_with(
+        size: usize,
+        mut factory: impl 'static + FnMut() -> Addr<A> + Send,
+    ) -> Self {
+        let members = (0..size).map(|_| factory()).collect();
+        Self::new_impl(members, Some(Box::new(factory)))
+    }
+    /// Sets the way messages get assigned to members
+    pub fn with_dispatch(self, dispatch: Dispatch) -> Self {
+        *self.inner.dispatch.lock().unwrap() = dispatch;
+        self
+    }
+    /// Sets how long a key stays pinned to a member without being used, see [Router::send_sticky]
+    pub fn with_session_timeout(self, timeout: Duration) -> Self {
+        self.inner.sessions.lock().unwrap().timeout = timeout;
+        self
+    }
+    fn dispatch(&self) -> Dispatch {
+        *self.inner.dispatch.lock().unwrap()
+    }
+    /// Grows or shrinks the pool to the given size (at least one), returning the new size.
+    ///
+    /// New members are started via the factory given to [Router::spawn],
+    /// so routers created via [Router::new] cannot grow.
+    /// Removed members are stopped once they have handled the messages waiting in their' mailboxes.
+    pub fn resize(&self, size: usize) -> usize {
+        let size = size.max(1);
+        let mut members = self.inner.members.write().unwrap();
+        if size > members.len() {
+            if let Some(factory) = self.inner.factory.lock().unwrap().as_mut() {
+                let missing = size - members.len();
+                members.extend((0..missing).map(|_| factory()));
+            }
+        } else {
+            for removed in members.drain(size..) {
+                removed.shutdown(StopMode::Drain { deadline: None });
+            }
+        }
+        members.len()
+    }
+    /// Periodically resizes the pool as decided by the policy, for as long as the router exists
+    pub fn autoscale(&self, policy: impl ScalingPolicy + 'static, interval: Duration) {
+        let inner = Arc::downgrade(&self.inner);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                let Some(inner) = inner.upgrade() else {
+                    break;
+                };
+                let router = Router { inner };
+                let load = router.load();
+                let desired = policy.desired_size(&load);
+                if desired != load.members {
+                    router.resize(desired);
+                }
+            }
+        });
+    }
+    /// Returns the current load of the pool
+    pub fn load(&self) -> PoolLoad {
+        let members = self.inner.members.read().unwrap();
+        let depths = members.iter().map(|member| member.queue_depth());
+        PoolLoad {
+            members: members.len(),
+            total_queue_depth: depths.clone().sum(),
+            max_queue_depth: depths.max().unwrap_or(0),
+        }
+    }
+    /// Returns the number of members
+    pub fn len(&self) -> usize {
+        self.inner.members.read().unwrap().len()
+    }
+    /// Returns `true` if the pool has no members
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Returns the addresses of the members
+    pub fn members(&self) -> impl Iterator<Item = Addr<A>> {
+        self.inner.members.read().unwrap().clone().into_iter()
+    }
+    /// [Pings](Addr::ping) all the members concurrently
+    pub async fn health(&self, timeout: Duration) -> Vec<(Addr<A>, Result<Health, ActorError>)> {
+        join_all(self.members().map(|member| async move {
+            let health = member.ping(timeout).await;
+            (member, health)
+        }))
+        .await
+    }
+    /// Picks the member which should receive the next message
+    fn pick(&self) -> Addr<A> {
+        let members = self.inner.members.read().unwrap();
+        let start = self.inner.next.fetch_add(1, Ordering::Relaxed);
+        (0..members.len())
+            .map(|offset| &members[(start + offset) % members.len()])
+            .find(|member| member.connected())
+            // Let sending fail in the usual way
+            .unwrap_or(&members[start % members.len()])
+            .clone()
+    }
+    /// Marks the next connected member which is not busy as busy
+    fn try_lease(&self) -> Option<Lease<A>> {
+        let members = self.inner.members.read().unwrap();
+        let mut busy = self.inner.busy.lock().unwrap();
+        let start = self.inner.next.fetch_add(1, Ordering::Relaxed);
+        let member = (0..members.len())
+            .map(|offset| &members[(start + offset) % members.len()])
+            .find(|member| member.connected() && !busy.contains(&member.id()))?;
+        busy.insert(member.id());
+        Some(Lease {
+            inner: self.inner.clone(),
+            member: member.clone(),
+        })
+    }
+    /// Waits until some member is not busy, in the order of calls.
+    /// Returns `None` once no member is connected, as none would become idle anymore.
+    async fn lease(&self) -> Option<Lease<A>> {
+        loop {
+            let mut idle = pin!(self.inner.idle.notified());
+            idle.as_mut().enable();
+            if let Some(lease) = self.try_lease() {
+                return Some(lease);
+            }
+            if !self.members().any(|member| member.connected()) {
+                // Pass the wake-up on to the next caller waiting
+                self.inner.idle.notify_one();
+                return None;
+            }
+            idle.await;
+        }
+    }
+    /// Sends a message to one of the members and asynchronously waits for its' response, see [Addr::send]
+    pub async fn send<M>(&self, msg: M) -> Result<<A as Handler<M>>::Response, ActorError>
+    where
+        M: 'static + Send,
+        A: Handler<M>,
+    {
+        match self.dispatch() {
+            Dispatch::RoundRobin => self.pick().send(msg).await,
+            Dispatch::Shared => match self.lease().await {
+                Some(lease) => lease.member.send(msg).await,
+                // Let sending fail in the usual way
+                None => self.pick().send(msg).await,
+            },
+        }
+    }
+    /// Sends a message to one of the members without waiting for response, ignoring all errors.
+    ///
+    /// In [Dispatch::Shared] mode, each message waits for an idle member in a task of its' own,
+    /// so the messages may get handled in a different order than sent, and nothing limits
+    /// how many of them wait. Use [Router::try_send] to not send more than the members can take.
+    pub fn do_send<M>(&self, msg: M)
+    where
+        M: 'static + Send,
+        A: Handler<M>,
+    {
+        match self.dispatch() {
+            Dispatch::RoundRobin => self.pick().do_send(msg),
+            Dispatch::Shared => {
+                let router = self.clone();
+                tokio::spawn(async move {
+                    let _ = router.send(msg).await;
+                });
+            }
+        }
+    }
+    /// Sends a message to one of the members without waiting for response.
+    /// Fails if the message cannot be enqueued.
+    /// 
+    /// In [Dispatch::Shared] mode, fails with [ActorError::MailboxFull] if all the members are busy.
+    pub fn try_send<M>(&self, msg: M) -> Result<(), ActorError>
+    where
+        M: 'static + Send,
+        A: Handler<M>,
+    {
+        match self.dispatch() {
+            Dispatch::RoundRobin => self.pick().try_send(msg),
+            Dispatch::Shared => {
+                let Some(lease) = self.try_lease() else {
+                    let member = self.pick();
+                    if !member.connected() {
+                        // Let sending fail in the usual way
+                        return member.try_send(msg);
+                    }
+                    return Err(ActorError::MailboxFull(member.msg_queue.error_context::<M>()));
+                };
+                tokio::spawn(async move {
+                    let _ = lease.member.send(msg).await;
+                });
+                Ok(())
+            }
+        }
+    }
+    /// Returns the member pinned to the key, pinning the next one if needed
+    fn pick_sticky<K: Hash>(&self, key: &K) -> Addr<A> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let hash = hasher.finish();
+        let now = Instant::now();
+        let mut sessions = self.inner.sessions.lock().unwrap();
+        let timeout = sessions.timeout;
+        if let Some((member, last_used)) = sessions.pins.get_mut(&hash) {
+            let members = self.inner.members.read().unwrap();
+            let is_member = members.iter().any(|m| m.id() == member.id());
+            if now.duration_since(*last_used) < timeout && member.connected() && is_member {
+                *last_used = now;
+                return member.clone();
+            }
+        }
+        // The pin is missing, expired, or the member has failed or got removed
+        sessions
+            .pins
+            .retain(|_, (_, last_used)| now.duration_since(*last_used) < timeout);
+        let member = self.pick();
+        sessions.pins.insert(hash, (member.clone(), now));
+        member
+    }
+    /// Sends a message to the member pinned to the key and asynchronously waits for its' response.
+    ///
+    /// The first message with a given key pins the key to the next member.
+    /// The pin expires once the key is not used for the session timeout (5 minutes by default),
+    /// or when the member stops or gets removed from the pool, in which case the key gets pinned again.
+    pub async fn send_sticky<K, M>(
+        &self,
+        key: &K,
+        msg: M,
+    ) -> Result<<A as Handler<M>>::Response, ActorError>
+    where
+        K: Hash,
+        M: 'static + Send,
+        A: Handler<M>,
+    {
+        let member = self.pick_sticky(key);
+        member.send(msg).await
+    }
+    /// Sends a message to the member pinned to the key without waiting for response, ignoring all errors.
+    /// See [Router::send_sticky].
+    pub fn do_send_sticky<K, M>(&self, key: &K, msg: M)
+    where
+        K: Hash,
+        M: 'static + Send,
+        A: Handler<M>,
+    {
+        self.pick_sticky(key).do_send(msg)
+    }
+    /// Sends a copy of the message to every member, ignoring all errors
+    pub fn broadcast<M>(&self, msg: M)
+    where
+        M: 'static + Send + Clone,
+        A: Handler<M>,
+    {
+        for member in self.members() {
+            member.do_send(msg.clone());
+        }
+    }
+}
@@ -1,39 +1,116 @@
 //! Internal runtime, including runner loops
 
 use crate::{
-    actor::{Actor, ActorState, Stopping},
+    actor::{Actor, ActorState, StopMode, Stopping},
     addr::Addr,
     context::ActorContext,
-    message_queue::{MessageQueue, QueuePayload},
-    supervised::Supervised,
+    deadlock,
+    message_queue::{Mailbox, MessageQueue, QueuePayload, TerminationGuard},
+    supervised::{Recoverable, Restart, Supervised},
+};
+use futures_util::{
+    future::{select, Either},
+    stream::{FuturesUnordered, StreamExt},
+};
+use std::{
+    pin::pin,
+    sync::Arc,
+    time::{Duration, Instant},
 };
-use std::sync::Arc;
-use tokio::sync::mpsc::UnboundedReceiver;
+
+/// Handles the given read-only message along with the read-only messages waiting right behind it, concurrently.
+///
+/// Returns the first message which is not read-only, if it has been taken out of the queue.
+async fn handle_reads<A: Actor>(
+    mut first: QueuePayload<A>,
+    act: &mut A,
+    ctx: &mut ActorContext<A>,
+    msg_rx: &mut Mailbox<A>,
+) -> Option<QueuePayload<A>> {
+    let mut batch = Vec::new();
+    let mut next = None;
+    // Messages released from the stash go first, so the ones of the mailbox cannot join the batch
+    while batch.len() + 1 < A::MAX_CONCURRENT_READS && !ctx.has_unstashed() {
+        match msg_rx.try_recv() {
+            Some(msg) => {
+                ctx.shared().release_slot(msg.size());
+                let Some(msg) = ctx.stash_unless_accepted(msg) else {
+                    continue;
+                };
+                if msg.is_read_only() {
+                    batch.push(msg);
+                } else {
+                    next = Some(msg);
+                    break;
+                }
+            }
+            None => break,
+        }
+    }
+    first.handle_batch(&mut batch, act, ctx).await;
+    next
+}
 
 async fn stopping_check<A: Actor>(act: &mut A, ctx: &mut ActorContext<A>) {
     if ctx.state() == ActorState::Stopping {
         let new_state = match act.stopping(ctx).await {
-            Stopping::Continue => ActorState::Running,
+            Stopping::Continue => {
+                // Forget about the failure the actor has recovered from
+                ctx.shared().take_restart_reason();
+                ActorState::Running
+            }
             Stopping::Stop => ActorState::Stopped,
         };
         ctx.set_state(new_state);
     }
 }
 
+/// Handles the messages left in the closed queue, starting new ones for at most `deadline`
+///
+/// The ones released from the stash go first, as they would have otherwise.
+async fn drain<A: Actor>(
+    act: &mut A,
+    ctx: &mut ActorContext<A>,
+    msg_rx: &mut Mailbox<A>,
+    deadline: Option<Duration>,
+) {
+    let deadline = deadline.map(|deadline| Instant::now() + deadline);
+    // Checked between the messages, as cancelling a running handler would leave the actor half-way
+    while deadline.is_none_or(|deadline| Instant::now() < deadline) {
+        let msg = match ctx.take_unstashed() {
+            Some(msg) => msg,
+            None => match msg_rx.recv().await {
+                Some(msg) => {
+                    ctx.shared().release_slot(msg.size());
+                    msg
+                }
+                None => break,
+            },
+        };
+        if let Some(mut msg) = ctx.stash_unless_accepted(msg) {
+            msg.handle(act, ctx).await;
+        }
+    }
+    // The handlers cannot bring the actor back
+    ctx.set_state(ActorState::Stopped);
+}
+
 struct FinishedActor<A: Actor> {
     actor: A,
     ctx: ActorContext<A>,
-    died_from_dropping_last_reference: bool,
-    msg_rx: UnboundedReceiver<QueuePayload<A>>,
+    /// The actor is about to begin its' lifecycle again
+    restarting: bool,
+    msg_rx: Mailbox<A>,
 }
 
 async fn actor_runner_loop_impl<A: Actor>(
     mut act: A,
     mut ctx: ActorContext<A>,
-    mut msg_rx: UnboundedReceiver<QueuePayload<A>>,
+    mut msg_rx: Mailbox<A>,
+    restart: Restart,
 ) -> FinishedActor<A> {
-    // starting phase
-    assert_eq!(ctx.state(), ActorState::Starting);
+    // starting phase, the actor might have been asked to stop already
+    assert!(matches!(ctx.state(), ActorState::Starting | ActorState::Stopping));
     act.started(&mut ctx).await;
     if ctx.state() == ActorState::Starting {
         ctx.set_state(ActorState::Running);
@@ -43,7 +120,14 @@ async fn actor_runner_loop_impl<A: Actor>(
     if ctx.state() != ActorState::Stopped {
         loop {
             let mut _fresh_addr_opt = None;
-            match msg_rx.recv().await {
+            let unstashed = ctx.take_unstashed();
+            // Messages released from the stash have freed up their' space in the mailbox already
+            let from_stash = unstashed.is_some();
+            let received = match unstashed {
+                Some(msg) => Some(msg),
+                None => msg_rx.recv().await,
+            };
+            match received {
                 None => {
                     // We need to manually set the state to Stopping
                     ctx.set_state(ActorState::Stopping);
@@ -53,7 +137,7 @@ async fn actor_runner_loop_impl<A: Actor>(
                     //
                     // Thus we need to reset the context, in case if the actor
                     // wants to generate a new Addr in Actor::stopping()
-                    let (new_msg_queue, new_rx) = MessageQueue::new();
+                    let (new_msg_queue, new_rx) = MessageQueue::new(ctx.shared().clone());
                     _fresh_addr_opt = Some(Addr::<A> {
                         msg_queue: Arc::from(new_msg_queue),
                     });
@@ -61,8 +145,24 @@ async fn actor_runner_loop_impl<A: Actor>(
                     msg_rx = new_rx;
                     died_from_dropping_last_reference = true;
                 }
-                Some(mut msg) => {
-                    msg.handle(&mut act, &mut ctx).await;
+                Some(msg) => {
+                    if !from_stash {
+                        ctx.shared().release_slot(msg.size());
+                    }
+                    match ctx.stash_unless_accepted(msg) {
+                        Some(msg) if msg.is_read_only() => {
+                            match handle_reads(msg, &mut act, &mut ctx, &mut msg_rx).await {
+                                // Reads might have made the actor stop due to a panic,
+                                // so the message waits for the outcome, like the ones behind it
+                                Some(next) if ctx.state() == ActorState::Stopping => ctx.put_back(next),
+                                Some(mut next) => next.handle(&mut act, &mut ctx).await,
+                                None => {}
+                            }
+                        }
+                        Some(mut msg) => msg.handle(&mut act, &mut ctx).await,
+                        // Stashed
+                        None => {}
+                    }
                 }
             }
             // Need to check if the state is Stopping
@@ -82,33 +182,122 @@ async fn actor_runner_loop_impl<A: Actor>(
     }
     // final phase
     assert_eq!(ctx.state(), ActorState::Stopped);
+    let restarting = !died_from_dropping_last_reference
+        && !ctx.shared().is_shut_down()
+        && restart.applies_to(&ctx.shared().restart_reason());
+    if !restarting {
+        // Reject new messages right away instead of accepting them until the receiver gets dropped
+        msg_rx.close();
+        if let StopMode::Drain { deadline } = ctx.shared().stop_mode() {
+            drain(&mut act, &mut ctx, &mut msg_rx, deadline).await;
+        }
+    }
     act.stopped(&mut ctx).await;
     FinishedActor {
         actor: act,
         ctx,
-        died_from_dropping_last_reference,
+        restarting,
         msg_rx,
     }
 }
 
+/// Counts consecutive restarts of a supervised actor
+struct RestartTracker {
+    attempt: u32,
+    last_restart: Instant,
+}
+
+impl RestartTracker {
+    fn new() -> Self {
+        Self {
+            attempt: 0,
+            last_restart: Instant::now(),
+        }
+    }
+    /// Notifies the stopped actor and sleeps before its' restart
+    async fn back_off<A: Supervised>(&mut self, act: &mut A, ctx: &mut ActorContext<A>) {
+        let backoff = A::RESTART_BACKOFF;
+        if self.last_restart.elapsed() >= backoff.reset_after {
+            self.attempt = 0;
+        }
+        let delay = backoff.delay(self.attempt);
+        self.attempt = self.attempt.saturating_add(1);
+        if !delay.is_zero() {
+            act.backing_off(ctx, delay).await;
+            tokio::time::sleep(delay).await;
+        }
+        self.last_restart = Instant::now();
+    }
+}
+
 /// Should be very similar to [actor_runner_loop] except that the actor gets restarted when it's Stopped.
 ///
-/// The actor might actually die when all references to it are dropped.
+/// The actor might actually die when all references to it are dropped, or when [Supervised::RESTART] says so.
 pub(crate) async fn supervised_actor_runner_loop<A: Supervised>(
     mut act: A,
     mut ctx: ActorContext<A>,
-    mut msg_rx: UnboundedReceiver<QueuePayload<A>>,
+    mut msg_rx: Mailbox<A>,
 ) {
+    let id = ctx.id();
+    let _terminated = TerminationGuard(ctx.shared().clone());
+    let mut restarts = RestartTracker::new();
     loop {
-        let finished_actor = actor_runner_loop_impl(act, ctx, msg_rx).await;
-        if finished_actor.died_from_dropping_last_reference {
+        let finished_actor =
+            deadlock::scope(id, actor_runner_loop_impl(act, ctx, msg_rx, A::RESTART)).await;
+        if !finished_actor.restarting {
             break;
         } else {
             act = finished_actor.actor;
             ctx = finished_actor.ctx;
             msg_rx = finished_actor.msg_rx;
-            act.restarting(&mut ctx).await;
-            ctx.set_state(ActorState::Starting);
+            restarts.back_off(&mut act, &mut ctx).await;
+            ctx.reset_stash();
+            let reason = ctx.shared().take_restart_reason();
+            act.restarting(&mut ctx, reason).await;
+            // Shut down while restarting
+            if !ctx.shared().restart() {
+                break;
+            }
+        }
+    }
+}
+
+/// Should be very similar to [supervised_actor_runner_loop] except that the actor gets rebuilt on restart.
+pub(crate) async fn recoverable_actor_runner_loop<A, F>(
+    mut act: A,
+    mut ctx: ActorContext<A>,
+    mut msg_rx: Mailbox<A>,
+    mut factory: F,
+) where
+    A: Recoverable,
+    F: FnMut(&mut ActorContext<A>) -> A + Send,
+{
+    let id = ctx.id();
+    let _terminated = TerminationGuard(ctx.shared().clone());
+    let mut restarts = RestartTracker::new();
+    loop {
+        let finished_actor =
+            deadlock::scope(id, actor_runner_loop_impl(act, ctx, msg_rx, A::RESTART)).await;
+        if !finished_actor.restarting {
+            break;
+        } else {
+            let mut old_act = finished_actor.actor;
+            ctx = finished_actor.ctx;
+            msg_rx = finished_actor.msg_rx;
+            restarts.back_off(&mut old_act, &mut ctx).await;
+            ctx.reset_stash();
+            let snapshot = old_act.snapshot();
+            drop(old_act);
+            act = factory(&mut ctx);
+            let reason = ctx.shared().take_restart_reason();
+            act.restarting(&mut ctx, reason).await;
+            if let Some(snapshot) = snapshot {
+                act.recover(&mut ctx, snapshot).await;
+            }
+            // Shut down while restarting
+            if !ctx.shared().restart() {
+                break;
+            }
         }
     }
 }
@@ -116,7 +305,114 @@ pub(crate) async fn supervised_actor_runner_loop<A: Supervised>(
 pub(crate) async fn actor_runner_loop<A: Actor>(
     act: A,
     ctx: ActorContext<A>,
-    msg_rx: UnboundedReceiver<QueuePayload<A>>,
+    msg_rx: Mailbox<A>,
 ) {
-    let _ = actor_runner_loop_impl(act, ctx, msg_rx).await;
+    let id = ctx.id();
+    let _terminated = TerminationGuard(ctx.shared().clone());
+    let _ = deadlock::scope(id, actor_runner_loop_impl(act, ctx, msg_rx, Restart::Temporary)).await;
+}
+
+/// A clone of the actor taking part in a worker pool
+struct Worker<A: Actor> {
+    act: A,
+    ctx: ActorContext<A>,
+}
+
+async fn worker_handle<A: Actor>(mut worker: Worker<A>, mut msg: QueuePayload<A>) -> Worker<A> {
+    msg.handle(&mut worker.act, &mut worker.ctx).await;
+    worker
+}
+
+enum PoolEvent<A: Actor> {
+    Received(Option<QueuePayload<A>>),
+    Finished(Worker<A>),
+}
+
+/// Should be very similar to [actor_runner_loop] except that messages get handled concurrently by clones of the actor.
+pub(crate) async fn worker_pool_runner_loop<A: Actor + Clone>(
+    act: A,
+    ctx: ActorContext<A>,
+    msg_rx: Mailbox<A>,
+    workers: usize,
+) {
+    let id = ctx.id();
+    let _terminated = TerminationGuard(ctx.shared().clone());
+    deadlock::scope(id, worker_pool_runner_loop_impl(act, ctx, msg_rx, workers)).await
+}
+
+async fn worker_pool_runner_loop_impl<A: Actor + Clone>(
+    mut act: A,
+    mut ctx: ActorContext<A>,
+    mut msg_rx: Mailbox<A>,
+    workers: usize,
+) {
+    // starting phase, the actor might have been asked to stop already
+    assert!(matches!(ctx.state(), ActorState::Starting | ActorState::Stopping));
+    act.started(&mut ctx).await;
+    if ctx.state() == ActorState::Starting {
+        ctx.set_state(ActorState::Running);
+    }
+    stopping_check(&mut act, &mut ctx).await;
+    let shared = ctx.shared().clone();
+    let mut idle: Vec<Worker<A>> = (1..workers)
+        .map(|_| Worker {
+            act: act.clone(),
+            ctx: ctx.fork(),
+        })
+        .collect();
+    idle.push(Worker { act, ctx });
+    let mut busy = FuturesUnordered::new();
+    // Keeps the actor alive if it decides to continue after all its' addresses got dropped
+    let mut _fresh_addr_opt = None;
+    while shared.state() != ActorState::Stopped {
+        let event = if busy.is_empty() {
+            PoolEvent::Received(msg_rx.recv().await)
+        } else if idle.is_empty() {
+            PoolEvent::Finished(busy.next().await.unwrap())
+        } else {
+            match select(pin!(msg_rx.recv()), busy.next()).await {
+                Either::Left((msg, _)) => PoolEvent::Received(msg),
+                Either::Right((worker, _)) => PoolEvent::Finished(worker.unwrap()),
+            }
+        };
+        match event {
+            PoolEvent::Received(Some(msg)) => {
+                let worker = idle.pop().unwrap();
+                worker.ctx.shared().release_slot(msg.size());
+                busy.push(worker_handle(worker, msg));
+            }
+            PoolEvent::Received(None) => {
+                while let Some(worker) = busy.next().await {
+                    idle.push(worker);
+                }
+                // Same as in actor_runner_loop_impl(), but for all the workers
+                shared.set_state(ActorState::Stopping);
+                let (new_msg_queue, new_rx) = MessageQueue::new(shared.clone());
+                let fresh_addr = Addr::<A> {
+                    msg_queue: Arc::from(new_msg_queue),
+                };
+                for worker in idle.iter_mut() {
+                    worker.ctx.reset_from(fresh_addr.downgrade());
+                }
+                _fresh_addr_opt = Some(fresh_addr);
+                msg_rx = new_rx;
+            }
+            PoolEvent::Finished(worker) => idle.push(worker),
+        }
+        if shared.state() == ActorState::Stopping {
+            // Let the others finish first, so that Actor::stopping() has the last word
+            while let Some(worker) = busy.next().await {
+                idle.push(worker);
+            }
+            let worker = &mut idle[0];
+            stopping_check(&mut worker.act, &mut worker.ctx).await;
+        }
+    }
+    // final phase
+    msg_rx.close();
+    let Worker { mut act, mut ctx } = idle.swap_remove(0);
+    if let StopMode::Drain { deadline } = shared.stop_mode() {
+        drain(&mut act, &mut ctx, &mut msg_rx, deadline).await;
+    }
+    act.stopped(&mut ctx).await;
 }
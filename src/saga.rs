@@ -0,0 +1,199 @@
+//! Coordination of multi-step workflows spanning several actors
+//!
+//! A [Saga] runs its' [Step]s one after another.
+//! When a step fails or times out, the compensations of all previously completed steps
+//! are run in reverse order, undoing their effects.
+
+use futures_util::future::BoxFuture;
+use std::{convert::Infallible, fmt, future::Future, time::Duration};
+use thiserror::Error;
+
+type Action<E> = Box<dyn FnMut() -> BoxFuture<'static, Result<(), E>> + Send>;
+type Compensation = Box<dyn FnMut() -> BoxFuture<'static, ()> + Send>;
+
+/// Single step of a [Saga]
+pub struct Step<E> {
+    name: String,
+    action: Action<E>,
+    compensation: Option<Compensation>,
+    timeout: Option<Duration>,
+}
+
+impl<E> Step<E> {
+    /// Creates a new step performing the given action.
+    ///
+    /// The closure gets called each time the step is executed
+    /// and usually sends messages to the actors taking part in the saga.
+    pub fn new<F, Fut>(name: impl Into<String>, mut action: F) -> Self
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+    {
+        Self {
+            name: name.into(),
+            action: Box::new(move || Box::pin(action())),
+            compensation: None,
+            timeout: None,
+        }
+    }
+    /// Sets the action undoing the effects of this step.
+    ///
+    /// It is called if any of the subsequent steps fails.
+    pub fn compensate<F, Fut>(mut self, mut compensation: F) -> Self
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.compensation = Some(Box::new(move || Box::pin(compensation())));
+        self
+    }
+    /// Fails the step if it does not complete within the given time
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+    /// Returns the name of the step
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<E> fmt::Debug for Step<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Step")
+            .field("name", &self.name)
+            .field("compensated", &self.compensation.is_some())
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+/// The reason for which a saga step failed
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum StepFailure<E> {
+    #[error("The step returned an error.")]
+    /// The step returned an error.
+    Failed(E),
+    #[error("The step did not complete in time.")]
+    /// The step did not complete in time.
+    TimedOut,
+}
+
+/// Error returned when a [Saga] gets aborted
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("Saga step `{name}` (#{step}) failed. Completed steps have been compensated.")]
+pub struct SagaError<E> {
+    /// Index of the failed step
+    pub step: usize,
+    /// Name of the failed step
+    pub name: String,
+    /// Why the step failed
+    pub failure: StepFailure<E>,
+}
+
+/// Workflow made out of a sequence of compensable steps
+#[derive(Debug)]
+pub struct Saga<E> {
+    steps: Vec<Step<E>>,
+}
+
+impl<E> Default for Saga<E> {
+    fn default() -> Self {
+        Self { steps: Vec::new() }
+    }
+}
+
+impl<E: Send + 'static> Saga<E> {
+    /// Creates an empty saga
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Appends a step to the saga
+    pub fn step(mut self, step: Step<E>) -> Self {
+        self.steps.push(step);
+        self
+    }
+    /// Returns the number of steps
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+    /// Returns `true` if the saga has no steps
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+    /// Executes all steps in order.
+    ///
+    /// On failure, completed steps get compensated in reverse order.
+    pub async fn run(&mut self) -> Result<(), SagaError<E>> {
+        self.resume(0).await
+    }
+    /// Executes the steps starting from the one with the given index,
+    /// treating all preceding steps as completed.
+    ///
+    /// Useful for continuing a saga which got interrupted, e.g. by the process being restarted,
+    /// provided that the index of the next step has been stored somewhere.
+    pub async fn resume(&mut self, first_step: usize) -> Result<(), SagaError<E>> {
+        let completed = |_| async { Ok::<_, Infallible>(()) };
+        let Ok(result) = self.resume_with(first_step, completed).await;
+        if let Err(e) = &result {
+            let compensated = |_| async { Ok::<_, Infallible>(()) };
+            let Ok(()) = self.compensate_with(e.step, compensated).await;
+        }
+        result
+    }
+    /// Behaves like [Saga::resume], calling `completed` with the index of every completed step,
+    /// but leaves compensating a failed step up to the caller, see [Saga::compensate_with].
+    ///
+    /// The saga stops if `completed` fails, returning its' error.
+    pub(crate) async fn resume_with<F, Fut, C>(
+        &mut self,
+        first_step: usize,
+        mut completed: F,
+    ) -> Result<Result<(), SagaError<E>>, C>
+    where
+        F: FnMut(usize) -> Fut,
+        Fut: Future<Output = Result<(), C>>,
+    {
+        for index in first_step..self.steps.len() {
+            let step = &mut self.steps[index];
+            let action = (step.action)();
+            let result = match step.timeout {
+                Some(timeout) => match tokio::time::timeout(timeout, action).await {
+                    Ok(result) => result.map_err(StepFailure::Failed),
+                    Err(_) => Err(StepFailure::TimedOut),
+                },
+                None => action.await.map_err(StepFailure::Failed),
+            };
+            if let Err(failure) = result {
+                return Ok(Err(SagaError {
+                    step: index,
+                    name: step.name.clone(),
+                    failure,
+                }));
+            }
+            completed(index).await?;
+        }
+        Ok(Ok(()))
+    }
+    /// Runs compensations of the steps preceding the given index, in reverse order,
+    /// calling `compensated` with the index of every compensated step.
+    ///
+    /// The compensations stop if `compensated` fails, returning its' error.
+    pub(crate) async fn compensate_with<F, Fut, C>(
+        &mut self,
+        remaining: usize,
+        mut compensated: F,
+    ) -> Result<(), C>
+    where
+        F: FnMut(usize) -> Fut,
+        Fut: Future<Output = Result<(), C>>,
+    {
+        for index in (0..remaining).rev() {
+            if let Some(compensation) = self.steps[index].compensation.as_mut() {
+                compensation().await;
+                compensated(index).await?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,104 @@
+//! Managing groups of actors together
+
+use crate::{
+    actor::{Actor, ActorId, StopMode},
+    addr::{Addr, AnyAddr},
+    supervised::RestartReason,
+};
+use futures_util::{
+    future::BoxFuture,
+    stream::{FuturesUnordered, StreamExt},
+};
+
+/// Why an actor of an [ActorSet] has stopped
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Termination {
+    pub actor_id: ActorId,
+    /// Type name of the actor
+    pub actor_type: &'static str,
+    pub reason: RestartReason,
+}
+
+/// Collection of actors, possibly of different types, which can be stopped and awaited together
+///
+/// It's the actor-aware counterpart of [tokio::task::JoinSet], meant for fan-out/fan-in orchestration.
+/// The set keeps its' actors alive until they terminate.
+#[derive(Default)]
+pub struct ActorSet {
+    actors: Vec<AnyAddr>,
+    pending: FuturesUnordered<BoxFuture<'static, Termination>>,
+}
+
+impl ActorSet {
+    /// Creates an empty set
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Starts the actor, adding it to the set
+    pub fn start<A: Actor>(&mut self, actor: A) -> Addr<A> {
+        let addr = actor.start();
+        self.insert(addr.clone());
+        addr
+    }
+    /// Adds a running actor to the set
+    pub fn insert(&mut self, addr: impl Into<AnyAddr>) {
+        let addr = addr.into();
+        let watched = addr.clone();
+        self.pending.push(Box::pin(async move {
+            Termination {
+                actor_id: watched.id(),
+                actor_type: watched.actor_type(),
+                reason: watched.terminated().await,
+            }
+        }));
+        self.actors.push(addr);
+    }
+    /// Number of actors which have not terminated yet, or whose termination has not been awaited
+    pub fn len(&self) -> usize {
+        self.actors.len()
+    }
+    /// Returns `true` if the set holds no actors
+    pub fn is_empty(&self) -> bool {
+        self.actors.is_empty()
+    }
+    /// Iterates over the actors of the set
+    pub fn iter(&self) -> impl Iterator<Item = &AnyAddr> {
+        self.actors.iter()
+    }
+    /// Makes all the actors of the set stop, see [Addr::stop]
+    pub fn stop_all(&self, mode: StopMode) {
+        for addr in &self.actors {
+            addr.stop(mode);
+        }
+    }
+    /// Waits until any of the actors terminates, removing it from the set.
+    ///
+    /// Returns `None` if the set is empty.
+    pub async fn join_next(&mut self) -> Option<Termination> {
+        let termination = self.pending.next().await?;
+        self.actors.retain(|addr| addr.id() != termination.actor_id);
+        Some(termination)
+    }
+    /// Waits until all the actors terminate, emptying the set.
+    ///
+    /// The terminations are returned in the order in which they happened.
+    /// Supervised actors which restart when stopped only terminate once [shut down](Addr::shutdown).
+    pub async fn join_all(&mut self) -> Vec<Termination> {
+        let mut terminations = Vec::with_capacity(self.len());
+        while let Some(termination) = self.join_next().await {
+            terminations.push(termination);
+        }
+        terminations
+    }
+    /// Makes all the actors of the set stop for good, see [Addr::shutdown]
+    pub fn shutdown_all(&self, mode: StopMode) {
+        for addr in &self.actors {
+            addr.shutdown(mode);
+        }
+    }
+    /// Stops all the actors for good and waits until they terminate, see [ActorSet::join_all]
+    pub async fn shutdown(&mut self, mode: StopMode) -> Vec<Termination> {
+        self.shutdown_all(mode);
+        self.join_all().await
+    }
+}
@@ -0,0 +1,187 @@
+//! Send-side load shedding
+//!
+//! [LoadShedder] wraps a [Recipient] and rejects messages with [ActorError::Overloaded]
+//! as soon as its' [SheddingPolicy] considers the actor to be overloaded,
+//! instead of letting them pile up in the mailbox.
+
+use crate::{addr::Recipient, error::ActorError};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Importance of a message sent through a [LoadShedder]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Default)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/// Signals describing the current load of the actor
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Load {
+    /// Number of messages waiting in the actor's mailbox
+    pub queue_depth: usize,
+}
+
+/// Decides which messages get rejected
+pub trait SheddingPolicy: Send + Sync {
+    /// Returns `true` if the message should be sent to the actor
+    fn admit(&self, load: &Load, priority: Priority) -> bool;
+    /// Called with the time it took for the actor to respond to an admitted message
+    fn record_latency(&self, _latency: Duration) {}
+}
+
+/// Rejects messages less important than `min_priority` once the mailbox holds `max_depth` messages
+#[derive(Clone, Copy, Debug)]
+pub struct StaticThreshold {
+    pub max_depth: usize,
+    pub min_priority: Priority,
+}
+
+impl StaticThreshold {
+    /// Rejects [Priority::Low] messages once the mailbox holds `max_depth` messages
+    pub fn new(max_depth: usize) -> Self {
+        Self {
+            max_depth,
+            min_priority: Priority::Normal,
+        }
+    }
+}
+
+impl SheddingPolicy for StaticThreshold {
+    fn admit(&self, load: &Load, priority: Priority) -> bool {
+        priority >= self.min_priority || load.queue_depth < self.max_depth
+    }
+}
+
+#[derive(Debug, Default)]
+struct CodelState {
+    above_target_since: Option<Instant>,
+    dropping: bool,
+    /// When the last latency got recorded, or the last probe got admitted
+    last_sample: Option<Instant>,
+}
+
+/// CoDel-style policy based on response latency
+///
+/// Once the latency stays above `target` for a whole `interval`,
+/// messages less important than `min_priority` get rejected
+/// until a response arrives within the target again.
+/// If no response arrives for a whole `interval` (e.g. because all the messages get rejected),
+/// one of them is let through as a probe.
+#[derive(Debug)]
+pub struct Codel {
+    target: Duration,
+    interval: Duration,
+    min_priority: Priority,
+    state: Mutex<CodelState>,
+}
+
+impl Codel {
+    /// Creates the policy, rejecting [Priority::Low] messages while overloaded
+    pub fn new(target: Duration, interval: Duration) -> Self {
+        Self {
+            target,
+            interval,
+            min_priority: Priority::Normal,
+            state: Mutex::default(),
+        }
+    }
+    /// Sets the priority below which messages get rejected while overloaded
+    pub fn min_priority(mut self, min_priority: Priority) -> Self {
+        self.min_priority = min_priority;
+        self
+    }
+}
+
+impl SheddingPolicy for Codel {
+    fn admit(&self, _load: &Load, priority: Priority) -> bool {
+        if priority >= self.min_priority {
+            return true;
+        }
+        let mut state = self.state.lock().unwrap();
+        if !state.dropping {
+            return true;
+        }
+        let probe = state.last_sample.is_none_or(|at| at.elapsed() >= self.interval);
+        if probe {
+            state.last_sample = Some(Instant::now());
+        }
+        probe
+    }
+    fn record_latency(&self, latency: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.last_sample = Some(Instant::now());
+        if latency <= self.target {
+            state.above_target_since = None;
+            state.dropping = false;
+            return;
+        }
+        match state.above_target_since {
+            None => state.above_target_since = Some(Instant::now()),
+            Some(since) if since.elapsed() >= self.interval => state.dropping = true,
+            Some(_) => {}
+        }
+    }
+}
+
+/// [Recipient] wrapper which rejects messages when the actor is overloaded
+pub struct LoadShedder<M, R> {
+    recipient: Recipient<M, R>,
+    policy: Arc<dyn SheddingPolicy>,
+}
+
+impl<M, R> Clone for LoadShedder<M, R> {
+    fn clone(&self) -> Self {
+        Self {
+            recipient: self.recipient.clone(),
+            policy: self.policy.clone(),
+        }
+    }
+}
+
+impl<M: 'static + Send, R: 'static + Send> LoadShedder<M, R> {
+    /// Wraps the recipient, applying the given policy
+    pub fn new(recipient: Recipient<M, R>, policy: impl SheddingPolicy + 'static) -> Self {
+        Self {
+            recipient,
+            policy: Arc::new(policy),
+        }
+    }
+    fn admit(&self, priority: Priority) -> Result<(), ActorError> {
+        let load = Load {
+            queue_depth: self.recipient.queue_depth(),
+        };
+        if self.policy.admit(&load, priority) {
+            Ok(())
+        } else {
+            Err(ActorError::Overloaded(self.recipient.error_context()))
+        }
+    }
+    /// Sends a message of [Priority::Normal]. See [LoadShedder::send_with_priority].
+    pub async fn send(&self, msg: M) -> Result<R, ActorError> {
+        self.send_with_priority(msg, Priority::Normal).await
+    }
+    /// Sends a message to the actor and asynchronously waits for its' response,
+    /// unless the policy rejects it, in which case [ActorError::Overloaded] is returned.
+    pub async fn send_with_priority(&self, msg: M, priority: Priority) -> Result<R, ActorError> {
+        self.admit(priority)?;
+        let sent_at = Instant::now();
+        let resp = self.recipient.send(msg).await;
+        self.policy.record_latency(sent_at.elapsed());
+        resp
+    }
+    /// Sends a message to the actor without waiting for response,
+    /// failing if the policy rejects it or if the message cannot be enqueued.
+    pub fn try_send_with_priority(&self, msg: M, priority: Priority) -> Result<(), ActorError> {
+        self.admit(priority)?;
+        self.recipient.try_send(msg)
+    }
+    /// Returns the wrapped recipient
+    pub fn inner(&self) -> &Recipient<M, R> {
+        &self.recipient
+    }
+}
@@ -0,0 +1,247 @@
+//! Coordinated shutdown of systems of actors
+//!
+//! The [ShutdownCoordinator] stops actors in ordered phases, like the ones of a typical service:
+//! [STOP_INGESTION] → [DRAIN_WORKERS] → [FLUSH_SINKS] → [STOP_INFRASTRUCTURE].
+//! Actors and hooks register for a phase, and the next phase begins only once all the actors
+//! of the previous one terminated, or its' timeout has elapsed. In the latter case,
+//! the remaining actors get their' handlers aborted, like with a [crate::watchdog::Watchdog].
+//!
+//! Within a phase, the actors stop in the reverse order of their' [dependencies](AnyAddr::dependencies):
+//! an actor gets stopped only once all the actors of the phase relying on it have terminated,
+//! so that consumers stop before the producers they depend on, and in-flight work is not cut off
+//! mid-pipeline. The dependencies include the actors being [watched](crate::context::ActorContext::watch),
+//! like the children of supervisors: a supervisor gets stopped first, stopping its' children on its' own.
+//! Actors relying on each other in a cycle get stopped at once.
+
+use crate::{
+    actor::{ActorId, StopMode},
+    addr::AnyAddr,
+    cancellation::CancellationToken,
+    set::{ActorSet, Termination},
+};
+use futures_util::future::{join_all, BoxFuture};
+use std::{
+    collections::HashSet,
+    fmt,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Stop accepting new work, e.g. close listeners and stop consuming queues
+pub const STOP_INGESTION: &str = "stop-ingestion";
+/// Let the workers finish the work already accepted
+pub const DRAIN_WORKERS: &str = "drain-workers";
+/// Write buffered data out, e.g. to files or databases
+pub const FLUSH_SINKS: &str = "flush-sinks";
+/// Stop whatever the other actors relied on, like connection pools
+pub const STOP_INFRASTRUCTURE: &str = "stop-infrastructure";
+
+/// Work done at the beginning of a phase
+type Hook = Box<dyn FnOnce() -> BoxFuture<'static, ()> + Send>;
+
+struct Phase {
+    name: String,
+    timeout: Duration,
+    actors: Vec<(AnyAddr, StopMode)>,
+    hooks: Vec<Hook>,
+}
+
+/// What happened during a shutdown phase
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PhaseReport {
+    pub name: String,
+    pub elapsed: Duration,
+    /// Actors which terminated in time
+    pub terminated: Vec<Termination>,
+    /// Actors which did not terminate in time and have been aborted
+    pub aborted: Vec<ActorId>,
+}
+
+struct Inner {
+    phases: Vec<Phase>,
+    triggered: CancellationToken,
+}
+
+/// Stops actors in ordered phases, see [crate::shutdown]
+///
+/// Clones share the phases, so that actors can be registered from anywhere.
+#[derive(Clone)]
+pub struct ShutdownCoordinator {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl fmt::Debug for ShutdownCoordinator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.inner.lock().unwrap();
+        let phases: Vec<_> = inner
+            .phases
+            .iter()
+            .map(|phase| (&phase.name, phase.timeout, phase.actors.len()))
+            .collect();
+        f.debug_struct("ShutdownCoordinator")
+            .field("phases", &phases)
+            .finish()
+    }
+}
+
+impl Default for ShutdownCoordinator {
+    fn default() -> Self {
+        let timeout = Duration::from_secs(10);
+        Self::with_phases(&[
+            (STOP_INGESTION, timeout),
+            (DRAIN_WORKERS, timeout),
+            (FLUSH_SINKS, timeout),
+            (STOP_INFRASTRUCTURE, timeout),
+        ])
+    }
+}
+
+impl ShutdownCoordinator {
+    /// Creates a coordinator with the standard phases, giving each of them 10 seconds
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Creates a coordinator with custom phases, in the order in which they run
+    pub fn with_phases(phases: &[(&str, Duration)]) -> Self {
+        let phases = phases
+            .iter()
+            .map(|(name, timeout)| Phase {
+                name: name.to_string(),
+                timeout: *timeout,
+                actors: Vec::new(),
+                hooks: Vec::new(),
+            })
+            .collect();
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                phases,
+                triggered: CancellationToken::new(),
+            })),
+        }
+    }
+    fn with_phase<R>(&self, name: &str, f: impl FnOnce(&mut Phase) -> R) -> R {
+        let mut inner = self.inner.lock().unwrap();
+        let phase = inner
+            .phases
+            .iter_mut()
+            .find(|phase| phase.name == name)
+            .unwrap_or_else(|| panic!("Unknown shutdown phase `{}`", name));
+        f(phase)
+    }
+    /// Makes the actor stop for good in the given phase, using the given mode, see [AnyAddr::shutdown].
+    ///
+    /// # Panics
+    ///
+    /// Panics if there's no such phase.
+    pub fn register(&self, phase: &str, addr: impl Into<AnyAddr>, mode: StopMode) {
+        let addr = addr.into();
+        self.with_phase(phase, |phase| phase.actors.push((addr, mode)))
+    }
+    /// Makes the hook run at the beginning of the given phase, along with its' other hooks.
+    ///
+    /// The phase waits for the hook, within its' timeout.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there's no such phase.
+    pub fn on_phase<F, Fut>(&self, phase: &str, hook: F)
+    where
+        F: 'static + FnOnce() -> Fut + Send,
+        Fut: 'static + Future<Output = ()> + Send,
+    {
+        let hook: Hook = Box::new(move || Box::pin(hook()));
+        self.with_phase(phase, |phase| phase.hooks.push(hook))
+    }
+    /// Returns a token which gets cancelled once the shutdown begins
+    pub fn triggered(&self) -> CancellationToken {
+        self.inner.lock().unwrap().triggered.clone()
+    }
+    /// Runs all the phases in order, returning what happened in each of them.
+    ///
+    /// Running it again only shuts down the actors registered in the meantime.
+    pub async fn run(&self) -> Vec<PhaseReport> {
+        let phases = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.triggered.cancel();
+            let phases: Vec<_> = inner
+                .phases
+                .iter_mut()
+                .map(|phase| Phase {
+                    name: phase.name.clone(),
+                    timeout: phase.timeout,
+                    actors: std::mem::take(&mut phase.actors),
+                    hooks: std::mem::take(&mut phase.hooks),
+                })
+                .collect();
+            phases
+        };
+        let mut reports = Vec::with_capacity(phases.len());
+        for phase in phases {
+            reports.push(run_phase(phase).await);
+        }
+        reports
+    }
+}
+
+async fn run_phase(phase: Phase) -> PhaseReport {
+    let started = Instant::now();
+    let mut set = ActorSet::new();
+    let mut running = phase.actors;
+    let hooks = join_all(phase.hooks.into_iter().map(|hook| hook()));
+    let mut terminated = Vec::with_capacity(running.len());
+    let finish = async {
+        let stopping = async {
+            loop {
+                stop_next_wave(&mut running, &mut set);
+                match set.join_next().await {
+                    Some(termination) => terminated.push(termination),
+                    None => break,
+                }
+            }
+        };
+        futures_util::join!(hooks, stopping);
+    };
+    let _ = tokio::time::timeout(phase.timeout, finish).await;
+    // Actors which were still waiting for their' turn get aborted as well
+    for (addr, _) in running {
+        set.insert(addr);
+    }
+    let aborted = set
+        .iter()
+        .map(|addr| {
+            addr.abort();
+            addr.shutdown(StopMode::Abandon);
+            addr.id()
+        })
+        .collect();
+    PhaseReport {
+        name: phase.name,
+        elapsed: started.elapsed(),
+        terminated,
+        aborted,
+    }
+}
+
+/// Stops the actors which no other actor of the phase relies on anymore
+fn stop_next_wave(running: &mut Vec<(AnyAddr, StopMode)>, set: &mut ActorSet) {
+    let relied_on: HashSet<ActorId> = running
+        .iter()
+        .map(|(addr, _)| addr)
+        .chain(set.iter())
+        .flat_map(AnyAddr::dependencies)
+        .collect();
+    let (mut wave, rest): (Vec<_>, Vec<_>) = std::mem::take(running)
+        .into_iter()
+        .partition(|(addr, _)| !relied_on.contains(&addr.id()));
+    // The remaining actors rely on each other in a cycle
+    if wave.is_empty() && set.is_empty() {
+        wave = rest;
+    } else {
+        *running = rest;
+    }
+    for (addr, mode) in wave {
+        addr.shutdown(mode);
+        set.insert(addr);
+    }
+}
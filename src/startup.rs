@@ -0,0 +1,70 @@
+//! Utilities for starting actors whose initialization can fail
+
+use crate::{
+    actor::{actor_create_impl, addr_create_impl, Actor, ActorState},
+    addr::Addr,
+    context::ActorContext,
+    message_queue::Mailbox,
+    runner::*,
+};
+use async_trait::async_trait;
+use tokio::sync::oneshot;
+
+#[async_trait]
+/// Trait allowing actors to report initialization failures (bad configuration, failed connection)
+/// to whoever starts them.
+///
+/// The address is only handed out after the initialization succeeds.
+pub trait TryStart: Actor {
+    /// Type describing the initialization failure
+    type Error: Send + 'static;
+    /// Called before [Actor::started]. Returning an error prevents the actor from starting.
+    ///
+    /// [Actor::stopping] and [Actor::stopped] are not called for actors which failed to start.
+    async fn try_started(&mut self, ctx: &mut ActorContext<Self>) -> Result<(), Self::Error>;
+
+    /// Starts the actor, waiting for [TryStart::try_started] to succeed before returning its' address.
+    ///
+    /// # Panics
+    ///
+    /// Panics if [TryStart::try_started] panics.
+    async fn start_checked(self) -> Result<Addr<Self>, Self::Error> {
+        let (ret, ctx, msg_rx) = addr_create_impl();
+        start_checked_impl(self, ret, ctx, msg_rx).await
+    }
+    /// Uses the given closure to build the actor, then behaves like [TryStart::start_checked].
+    async fn try_create<F: FnOnce(&mut ActorContext<Self>) -> Self + Send>(
+        f: F,
+    ) -> Result<Addr<Self>, Self::Error> {
+        let (actor, ret, ctx, msg_rx) = actor_create_impl(f);
+        start_checked_impl(actor, ret, ctx, msg_rx).await
+    }
+}
+
+async fn start_checked_impl<A: TryStart>(
+    mut act: A,
+    addr: Addr<A>,
+    mut ctx: ActorContext<A>,
+    msg_rx: Mailbox<A>,
+) -> Result<Addr<A>, A::Error> {
+    let (tx, rx) = oneshot::channel();
+    tokio::spawn(async move {
+        match act.try_started(&mut ctx).await {
+            Ok(()) => {
+                // The creator might have given up on the actor already
+                if tx.send(Ok(())).is_ok() {
+                    actor_runner_loop(act, ctx, msg_rx).await;
+                }
+            }
+            Err(e) => {
+                ctx.set_state(ActorState::Stopped);
+                let _ = tx.send(Err(e));
+            }
+        }
+    });
+    match rx.await {
+        Ok(Ok(())) => Ok(addr),
+        Ok(Err(e)) => Err(e),
+        Err(_) => panic!("The actor panicked during initialization"),
+    }
+}
@@ -3,18 +3,149 @@
 use crate::{
     actor::{actor_create_impl, Actor},
     addr::Addr,
-    context::ActorContext,
+    context::{ActorContext, StreamFailed},
     runner::*,
 };
 use async_trait::async_trait;
+use std::{any::Any, time::Duration};
 
 
+/// Why a [Supervised] actor is being restarted, or why any actor has stopped for good
+/// (see [Addr::terminated])
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum RestartReason {
+    /// The actor has been stopped explicitly,
+    /// via [ActorContext::stop] or [Addr::stop]
+    Stopped,
+    /// A message handler panicked
+    HandlerPanicked {
+        /// Type name of the message being handled
+        message_type: &'static str,
+        /// The panic message, if it was a string
+        message: Option<String>,
+    },
+    /// A message handler got stuck and has been aborted by a [crate::watchdog::Watchdog]
+    Aborted {
+        /// Type name of the message being handled
+        message_type: &'static str,
+    },
+    /// A stream of the actor panicked, and the actor gave up on it via [ActorContext::fail]
+    StreamFailed(StreamFailed),
+    /// A [crate::supervisor::Supervisor] gave up, as one of its' children exhausted the restart budget
+    Escalated {
+        /// Name of the child
+        child: String,
+        /// Why the child stopped the last time
+        reason: Box<RestartReason>,
+    },
+}
+
+impl RestartReason {
+    pub(crate) fn from_panic<M>(payload: &(dyn Any + Send)) -> Self {
+        Self::HandlerPanicked {
+            message_type: std::any::type_name::<M>(),
+            message: panic_message(payload),
+        }
+    }
+}
+
+/// Returns the message of the panic, if it was a string
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> Option<String> {
+    payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+}
+
+/// Delays between consecutive restarts of a [Supervised] actor, preventing crash loops from spinning
+///
+/// The `n`-th consecutive restart is delayed by `base * 2^n`, capped at `max`
+/// and then shortened by a random fraction of at most `jitter`.
+/// Restarts are no longer considered consecutive once the actor keeps running for `reset_after`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Backoff {
+    pub base: Duration,
+    pub max: Duration,
+    /// Between 0.0 (no jitter) and 1.0
+    pub jitter: f64,
+    pub reset_after: Duration,
+}
+
+impl Backoff {
+    /// Starts at 10ms, doubling up to 10s, with 20% jitter
+    pub const DEFAULT: Self = Self {
+        base: Duration::from_millis(10),
+        max: Duration::from_secs(10),
+        jitter: 0.2,
+        reset_after: Duration::from_secs(30),
+    };
+    /// Restarts immediately
+    pub const NONE: Self = Self {
+        base: Duration::ZERO,
+        max: Duration::ZERO,
+        jitter: 0.0,
+        reset_after: Duration::ZERO,
+    };
+    /// Returns the delay before the given consecutive restart, counting from zero
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let delay = self
+            .base
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max);
+        let random = crate::random::fraction();
+        delay.mul_f64(1.0 - self.jitter.clamp(0.0, 1.0) * random)
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Which stops of an actor lead to its' restart, following the restart classes of Erlang/OTP
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Default)]
+pub enum Restart {
+    /// Restarted whenever it stops, like long-running services
+    #[default]
+    Permanent,
+    /// Restarted only after a failure (not after [RestartReason::Stopped]),
+    /// like jobs which need to complete once
+    Transient,
+    /// Never restarted, like jobs which are not worth retrying
+    Temporary,
+}
+
+impl Restart {
+    /// Returns `true` if a stop for the given reason leads to a restart
+    pub fn applies_to(&self, reason: &RestartReason) -> bool {
+        match self {
+            Self::Permanent => true,
+            Self::Transient => *reason != RestartReason::Stopped,
+            Self::Temporary => false,
+        }
+    }
+}
+
 #[async_trait]
 /// Special trait allowing actors to restart after failure,
 /// i.e. to restart after the actor stops but still has valid addresses pointing to it
 pub trait Supervised: Actor {
+    /// Delays between consecutive restarts
+    const RESTART_BACKOFF: Backoff = Backoff::DEFAULT;
+    /// Which stops lead to a restart.
+    ///
+    /// Once the actor stops without being restarted, it's gone for good, like an ordinary actor.
+    const RESTART: Restart = Restart::Permanent;
     /// Called after the actor has stopped and is about to begin its' lifecycle again.
-    async fn restarting(&mut self, _ctx: &mut ActorContext<Self>) {}
+    /// 
+    /// The reason tells why the actor stopped, so that the restart logic can differ between failures.
+    async fn restarting(&mut self, _ctx: &mut ActorContext<Self>, _reason: RestartReason) {}
+    /// Called after the actor has stopped, right before waiting for the given delay before the restart.
+    /// 
+    /// See [Supervised::RESTART_BACKOFF].
+    async fn backing_off(&mut self, _ctx: &mut ActorContext<Self>, _delay: Duration) {}
 
     /// Uses the given closure to start a [Supervised] actor
     fn create_supervised<F: FnOnce(&mut ActorContext<Self>) -> Self + Send>(f: F) -> Addr<Self> {
@@ -23,3 +154,28 @@ pub trait Supervised: Actor {
         ret
     }
 }
+
+#[async_trait]
+/// Supervised actors which get rebuilt from scratch on every restart,
+/// carrying over only a small recovery state (like a routing table) from the previous instance
+pub trait Recoverable: Supervised {
+    /// The state carried over between restarts
+    type Snapshot: Send + 'static;
+    /// Called on the stopped instance, before it gets dropped
+    fn snapshot(&mut self) -> Option<Self::Snapshot>;
+    /// Called on the fresh instance, after [Supervised::restarting]
+    async fn recover(&mut self, ctx: &mut ActorContext<Self>, snapshot: Self::Snapshot);
+
+    /// Uses the given closure to start a [Recoverable] actor.
+    ///
+    /// The closure gets called again to build a fresh instance on every restart.
+    fn create_recoverable<F>(f: F) -> Addr<Self>
+    where
+        F: 'static + FnMut(&mut ActorContext<Self>) -> Self + Send,
+    {
+        let mut f = f;
+        let (actor, ret, ctx, msg_rx) = actor_create_impl(&mut f);
+        tokio::spawn(recoverable_actor_runner_loop(actor, ctx, msg_rx, f));
+        ret
+    }
+}
@@ -0,0 +1,467 @@
+//! Supervision trees
+//!
+//! A [Supervisor] is an actor which starts its' children from [ChildSpec]s
+//! and starts them again once they stop, according to its' [Strategy].
+//! Unlike [crate::supervised::Supervised] actors, which restart on their own,
+//! the children get rebuilt from scratch and receive new addresses, which can be looked up
+//! via [Addr::child].
+//!
+//! Restarts are limited by a [RestartIntensity]. Once a child exhausts it, the supervisor stops
+//! all its' children and then itself, with [RestartReason::Escalated]. As supervisors can be children
+//! of other supervisors, the failure reaches the parent, which applies its' own strategy:
+//! it restarts the whole subtree, or escalates further.
+//!
+//! A [DynamicSupervisor] starts its' children at runtime instead, from a template,
+//! like one actor per connection or session.
+
+use crate::{
+    actor::{Actor, ActorId, Handler, StopMode},
+    addr::{Addr, AnyAddr},
+    context::{ActorContext, ContextEvent},
+    error::ActorError,
+    supervised::{Restart, RestartReason},
+};
+use async_trait::async_trait;
+use std::{
+    collections::VecDeque,
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// Builds a fresh instance of the child
+type Factory = Box<dyn FnMut() -> AnyAddr + Send>;
+
+/// Description of a child of a [Supervisor]
+pub struct ChildSpec {
+    name: String,
+    restart: Restart,
+    factory: Factory,
+}
+
+impl fmt::Debug for ChildSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChildSpec")
+            .field("name", &self.name)
+            .field("restart", &self.restart)
+            .finish()
+    }
+}
+
+impl ChildSpec {
+    /// Describes a [Restart::Permanent] child, started by the factory,
+    /// which gets called again on every restart
+    pub fn new<A, F>(name: impl Into<String>, mut factory: F) -> Self
+    where
+        A: Actor,
+        F: 'static + FnMut() -> Addr<A> + Send,
+    {
+        Self {
+            name: name.into(),
+            restart: Restart::Permanent,
+            factory: Box::new(move || factory().into()),
+        }
+    }
+    /// Sets which stops of the child lead to its' restart.
+    ///
+    /// The children which do not get restarted do not count against the [RestartIntensity].
+    /// [Restart::Temporary] children do not get restarted along with their' siblings either.
+    pub fn restart(mut self, restart: Restart) -> Self {
+        self.restart = restart;
+        self
+    }
+    /// Returns the name of the child
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Which children get restarted when one of them stops
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Default)]
+pub enum Strategy {
+    /// Only the child which stopped
+    #[default]
+    OneForOne,
+    /// All the children, for ones which cannot work without each other
+    OneForAll,
+    /// The child which stopped and the ones declared after it, which presumably depend on it
+    RestForOne,
+}
+
+/// How many restarts a [Supervisor] performs within a period of time before it gives up
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RestartIntensity {
+    pub max_restarts: usize,
+    pub within: Duration,
+}
+
+impl RestartIntensity {
+    /// Three restarts within five seconds
+    pub const DEFAULT: Self = Self {
+        max_restarts: 3,
+        within: Duration::from_secs(5),
+    };
+}
+
+impl Default for RestartIntensity {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Restarts which happened within the [RestartIntensity]
+struct RecentRestarts {
+    intensity: RestartIntensity,
+    restarts: VecDeque<Instant>,
+}
+
+impl RecentRestarts {
+    fn new(intensity: RestartIntensity) -> Self {
+        Self {
+            intensity,
+            restarts: VecDeque::new(),
+        }
+    }
+    /// Records a restart, unless it would exceed the intensity
+    fn allow(&mut self) -> bool {
+        let now = Instant::now();
+        while self
+            .restarts
+            .front()
+            .is_some_and(|restart| now.duration_since(*restart) > self.intensity.within)
+        {
+            self.restarts.pop_front();
+        }
+        if self.restarts.len() >= self.intensity.max_restarts {
+            return false;
+        }
+        self.restarts.push_back(now);
+        true
+    }
+}
+
+struct Child {
+    spec: ChildSpec,
+    /// `None` while the child is not running
+    addr: Option<AnyAddr>,
+}
+
+/// Actor which restarts its' children, see [crate::supervisor]
+pub struct Supervisor {
+    strategy: Strategy,
+    children: Vec<Child>,
+    restarts: RecentRestarts,
+}
+
+impl fmt::Debug for Supervisor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let children: Vec<_> = self.children.iter().map(|child| &child.spec.name).collect();
+        f.debug_struct("Supervisor")
+            .field("strategy", &self.strategy)
+            .field("intensity", &self.restarts.intensity)
+            .field("children", &children)
+            .finish()
+    }
+}
+
+impl Supervisor {
+    /// Creates a supervisor without children, using [RestartIntensity::DEFAULT]
+    pub fn new(strategy: Strategy) -> Self {
+        Self {
+            strategy,
+            children: Vec::new(),
+            restarts: RecentRestarts::new(RestartIntensity::DEFAULT),
+        }
+    }
+    /// Sets how many restarts the supervisor performs before it escalates
+    pub fn with_intensity(mut self, intensity: RestartIntensity) -> Self {
+        self.restarts = RecentRestarts::new(intensity);
+        self
+    }
+    /// Adds a child, started along with the supervisor.
+    ///
+    /// The children start in the order in which they were added and stop in the reverse order.
+    pub fn child(mut self, spec: ChildSpec) -> Self {
+        self.children.push(Child { spec, addr: None });
+        self
+    }
+    fn start_child(&mut self, index: usize, ctx: &ActorContext<Self>) {
+        let addr = (self.children[index].spec.factory)();
+        // Also makes the supervisor rely on the child, so that a coordinated shutdown stops it first
+        ctx.watch_any(&addr);
+        self.children[index].addr = Some(addr);
+    }
+    /// Stops the child and waits until it terminates
+    async fn stop_child(&mut self, index: usize) {
+        // Taken first, so that the supervisor ignores the termination
+        if let Some(addr) = self.children[index].addr.take() {
+            addr.shutdown(StopMode::Abandon);
+            addr.terminated().await;
+        }
+    }
+    async fn stop_children(&mut self) {
+        for index in (0..self.children.len()).rev() {
+            self.stop_child(index).await;
+        }
+    }
+    /// Restarts the terminated child according to the strategy, escalating if it's restarting too often
+    async fn child_terminated(
+        &mut self,
+        actor_id: ActorId,
+        reason: RestartReason,
+        ctx: &mut ActorContext<Self>,
+    ) {
+        // Stopped by the supervisor itself, or already replaced
+        let Some(index) = self
+            .children
+            .iter()
+            .position(|child| child.addr.as_ref().map(AnyAddr::id) == Some(actor_id))
+        else {
+            return;
+        };
+        let child = &mut self.children[index];
+        child.addr = None;
+        if !child.spec.restart.applies_to(&reason) {
+            return;
+        }
+        if !self.restarts.allow() {
+            self.stop_children().await;
+            ctx.shared().failed(RestartReason::Escalated {
+                child: self.children[index].spec.name.clone(),
+                reason: Box::new(reason),
+            });
+            return;
+        }
+        let restarted = match self.strategy {
+            Strategy::OneForOne => index..index + 1,
+            Strategy::OneForAll => 0..self.children.len(),
+            Strategy::RestForOne => index..self.children.len(),
+        };
+        for index in restarted.clone().rev() {
+            self.stop_child(index).await;
+        }
+        for restarted in restarted {
+            if restarted == index || self.children[restarted].spec.restart != Restart::Temporary {
+                self.start_child(restarted, ctx);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Actor for Supervisor {
+    async fn started(&mut self, ctx: &mut ActorContext<Self>) {
+        for index in 0..self.children.len() {
+            self.start_child(index, ctx);
+        }
+    }
+    async fn stopped(&mut self, _ctx: &mut ActorContext<Self>) {
+        self.stop_children().await;
+    }
+    async fn context_event(&mut self, ctx: &mut ActorContext<Self>, event: ContextEvent) {
+        if let ContextEvent::Terminated { actor_id, reason } = event {
+            self.child_terminated(actor_id, reason, ctx).await;
+        }
+    }
+}
+
+/// Message asking a [Supervisor] for the address of a child, see [Addr::child]
+#[doc(hidden)]
+pub struct GetChild(String);
+
+#[async_trait]
+impl Handler<GetChild> for Supervisor {
+    type Response = Option<AnyAddr>;
+    async fn handle(&mut self, msg: GetChild, _ctx: &mut ActorContext<Self>) -> Option<AnyAddr> {
+        self.children
+            .iter()
+            .find(|child| child.spec.name == msg.0)
+            .and_then(|child| child.addr.clone())
+    }
+}
+
+impl Addr<Supervisor> {
+    /// Returns the current address of the child with the given name,
+    /// if it's running and of type `A`.
+    ///
+    /// The address changes whenever the child gets restarted.
+    pub async fn child<A: Actor>(&self, name: &str) -> Result<Option<Addr<A>>, ActorError> {
+        let addr = self.send(GetChild(name.to_string())).await?;
+        Ok(addr.and_then(|addr| addr.downcast()))
+    }
+}
+
+/// Builds a child from its' arguments
+type Template<A, T> = Box<dyn FnMut(T) -> Addr<A> + Send>;
+
+/// Actor which starts children from a template on demand and restarts them, see [crate::supervisor]
+///
+/// The arguments of every child are kept, so that it can be rebuilt from them on restart.
+pub struct DynamicSupervisor<A: Actor, T> {
+    template: Template<A, T>,
+    restart: Restart,
+    max_children: Option<usize>,
+    restarts: RecentRestarts,
+    /// In the order in which the children were started
+    children: Vec<(T, Addr<A>)>,
+}
+
+impl<A: Actor, T> fmt::Debug for DynamicSupervisor<A, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DynamicSupervisor")
+            .field("actor_type", &std::any::type_name::<A>())
+            .field("restart", &self.restart)
+            .field("max_children", &self.max_children)
+            .field("intensity", &self.restarts.intensity)
+            .field("children", &self.children.len())
+            .finish()
+    }
+}
+
+impl<A: Actor, T: 'static + Clone + Send> DynamicSupervisor<A, T> {
+    /// Creates a supervisor without children, which starts [Restart::Permanent] children via the template
+    pub fn new<F>(template: F) -> Self
+    where
+        F: 'static + FnMut(T) -> Addr<A> + Send,
+    {
+        Self {
+            template: Box::new(template),
+            restart: Restart::Permanent,
+            max_children: None,
+            restarts: RecentRestarts::new(RestartIntensity::DEFAULT),
+            children: Vec::new(),
+        }
+    }
+    /// Sets which stops of the children lead to their' restart
+    pub fn with_restart(mut self, restart: Restart) -> Self {
+        self.restart = restart;
+        self
+    }
+    /// Sets how many restarts the supervisor performs before it escalates
+    pub fn with_intensity(mut self, intensity: RestartIntensity) -> Self {
+        self.restarts = RecentRestarts::new(intensity);
+        self
+    }
+    /// Limits the number of children running at once
+    pub fn with_max_children(mut self, max_children: usize) -> Self {
+        self.max_children = Some(max_children);
+        self
+    }
+    fn start_child(&mut self, args: T, ctx: &ActorContext<Self>) -> Addr<A> {
+        let addr = (self.template)(args.clone());
+        ctx.watch(&addr);
+        self.children.push((args, addr.clone()));
+        addr
+    }
+    async fn stop_children(&mut self) {
+        while let Some((_, addr)) = self.children.pop() {
+            addr.shutdown(StopMode::Abandon);
+            addr.terminated().await;
+        }
+    }
+}
+
+#[async_trait]
+impl<A: Actor, T: 'static + Clone + Send> Actor for DynamicSupervisor<A, T> {
+    async fn stopped(&mut self, _ctx: &mut ActorContext<Self>) {
+        self.stop_children().await;
+    }
+    async fn context_event(&mut self, ctx: &mut ActorContext<Self>, event: ContextEvent) {
+        let ContextEvent::Terminated { actor_id, reason } = event else {
+            return;
+        };
+        // Stopped by the supervisor itself
+        let Some(index) = self
+            .children
+            .iter()
+            .position(|(_, addr)| addr.id() == actor_id)
+        else {
+            return;
+        };
+        let (args, _) = self.children.remove(index);
+        if !self.restart.applies_to(&reason) {
+            return;
+        }
+        if !self.restarts.allow() {
+            self.stop_children().await;
+            ctx.shared().failed(RestartReason::Escalated {
+                child: format!("{} {}", std::any::type_name::<A>(), actor_id),
+                reason: Box::new(reason),
+            });
+            return;
+        }
+        self.start_child(args, ctx);
+    }
+}
+
+/// Message asking a [DynamicSupervisor] to start a child, see [Addr::start_child]
+#[doc(hidden)]
+pub struct StartChild<T>(T);
+
+#[async_trait]
+impl<A: Actor, T: 'static + Clone + Send> Handler<StartChild<T>> for DynamicSupervisor<A, T> {
+    type Response = Option<Addr<A>>;
+    async fn handle(&mut self, msg: StartChild<T>, ctx: &mut ActorContext<Self>) -> Self::Response {
+        if self
+            .max_children
+            .is_some_and(|max_children| self.children.len() >= max_children)
+        {
+            return None;
+        }
+        Some(self.start_child(msg.0, ctx))
+    }
+}
+
+/// Message asking a [DynamicSupervisor] for its' children, see [Addr::children]
+#[doc(hidden)]
+pub struct GetChildren;
+
+#[async_trait]
+impl<A: Actor, T: 'static + Clone + Send> Handler<GetChildren> for DynamicSupervisor<A, T> {
+    type Response = Vec<Addr<A>>;
+    async fn handle(&mut self, _msg: GetChildren, _ctx: &mut ActorContext<Self>) -> Vec<Addr<A>> {
+        self.children.iter().map(|(_, addr)| addr.clone()).collect()
+    }
+}
+
+/// Message asking a [DynamicSupervisor] to stop a child for good, see [Addr::terminate_child]
+#[doc(hidden)]
+pub struct TerminateChild(ActorId);
+
+#[async_trait]
+impl<A: Actor, T: 'static + Clone + Send> Handler<TerminateChild> for DynamicSupervisor<A, T> {
+    type Response = bool;
+    async fn handle(&mut self, msg: TerminateChild, _ctx: &mut ActorContext<Self>) -> bool {
+        let Some(index) = self
+            .children
+            .iter()
+            .position(|(_, addr)| addr.id() == msg.0)
+        else {
+            return false;
+        };
+        let (_, addr) = self.children.remove(index);
+        addr.shutdown(StopMode::Abandon);
+        addr.terminated().await;
+        true
+    }
+}
+
+impl<A: Actor, T: 'static + Clone + Send> Addr<DynamicSupervisor<A, T>> {
+    /// Starts a child built from the given arguments, returning its' address.
+    ///
+    /// Returns `None` if the supervisor already runs its' maximum number of children.
+    pub async fn start_child(&self, args: T) -> Result<Option<Addr<A>>, ActorError> {
+        self.send(StartChild(args)).await
+    }
+    /// Returns the addresses of the running children, in the order in which they were started.
+    ///
+    /// The address of a child changes whenever it gets restarted.
+    pub async fn children(&self) -> Result<Vec<Addr<A>>, ActorError> {
+        self.send(GetChildren).await
+    }
+    /// Stops the child for good, waiting until it terminates.
+    ///
+    /// Returns `false` if it's not a child of the supervisor.
+    pub async fn terminate_child(&self, child: ActorId) -> Result<bool, ActorError> {
+        self.send(TerminateChild(child)).await
+    }
+}
@@ -0,0 +1,100 @@
+//! Blue/green replacement of actors behind a stable handle
+//!
+//! A [SwappableAddr] gets handed to the callers instead of the [Addr] of the actor.
+//! Once a new instance of the actor has been started (e.g. restored from the state of the old one),
+//! [SwappableAddr::swap] re-points the handle to it in one step: messages sent from then on reach
+//! the new instance, while the old one stops, either handling the messages it has already received
+//! or abandoning them, as chosen by the [StopMode].
+
+use crate::{
+    actor::{Actor, ActorId, Handler, StopMode},
+    addr::Addr,
+    error::ActorError,
+};
+use std::{
+    fmt,
+    sync::{Arc, RwLock},
+};
+
+/// Handle of an actor which can be replaced in place, see [crate::swap]
+///
+/// Clones share the actor they point to, so swapping re-points all of them.
+pub struct SwappableAddr<T: Actor> {
+    current: Arc<RwLock<Addr<T>>>,
+}
+
+impl<T: Actor> Clone for SwappableAddr<T> {
+    fn clone(&self) -> Self {
+        Self {
+            current: self.current.clone(),
+        }
+    }
+}
+
+impl<T: Actor> fmt::Debug for SwappableAddr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SwappableAddr")
+            .field("current", &self.id())
+            .finish()
+    }
+}
+
+impl<T: Actor> From<Addr<T>> for SwappableAddr<T> {
+    fn from(addr: Addr<T>) -> Self {
+        Self::new(addr)
+    }
+}
+
+impl<T: Actor> SwappableAddr<T> {
+    /// Creates a handle pointing to the given actor
+    pub fn new(addr: Addr<T>) -> Self {
+        Self {
+            current: Arc::new(RwLock::new(addr)),
+        }
+    }
+    /// Returns the address of the actor the handle currently points to
+    pub fn current(&self) -> Addr<T> {
+        self.current.read().unwrap().clone()
+    }
+    /// Returns the identifier of the actor the handle currently points to
+    pub fn id(&self) -> ActorId {
+        self.current.read().unwrap().id()
+    }
+    /// Re-points the handle to the new actor, then stops the old one for good with the given mode, see [Addr::shutdown].
+    ///
+    /// Returns the address of the old actor, e.g. to wait until it has [terminated](Addr::terminated).
+    pub fn swap(&self, new: Addr<T>, mode: StopMode) -> Addr<T> {
+        let old = std::mem::replace(&mut *self.current.write().unwrap(), new);
+        old.shutdown(mode);
+        old
+    }
+    /// Sends the message to the current actor, see [Addr::send].
+    ///
+    /// A message sent right before a swap gets handled by the old actor,
+    /// unless it abandons its' mailbox.
+    pub async fn send<M>(&self, msg: M) -> Result<<T as Handler<M>>::Response, ActorError>
+    where
+        M: 'static + Send,
+        T: Handler<M>,
+    {
+        // Enqueued under the lock, so that the old actor cannot get stopped in between
+        let response = self.current.read().unwrap().request(msg)?;
+        response.await
+    }
+    /// See [Addr::do_send]
+    pub fn do_send<M>(&self, msg: M)
+    where
+        M: 'static + Send,
+        T: Handler<M>,
+    {
+        self.current.read().unwrap().do_send(msg)
+    }
+    /// See [Addr::try_send]
+    pub fn try_send<M>(&self, msg: M) -> Result<(), ActorError>
+    where
+        M: 'static + Send,
+        T: Handler<M>,
+    {
+        self.current.read().unwrap().try_send(msg)
+    }
+}
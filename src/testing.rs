@@ -0,0 +1,54 @@
+//! Utilities for testing actors
+
+use crate::{actor::Actor, addr::Addr, error::ActorError};
+use crate::random::Seeded;
+use std::future::Future;
+use tokio::sync::oneshot;
+
+pub mod fuzz;
+
+/// Runs the future to completion on a fresh single-threaded runtime,
+/// so that the actors it starts get scheduled in a reproducible order.
+///
+/// The seed drives the randomness used by the framework itself, so that a given seed always
+/// leads to the same run:
+/// * the jitter of [crate::supervised::Backoff], i.e. the restart delays
+/// * the interleaving of the messages taken from streams (see [crate::context::ActorContext::add_stream])
+///   with the ones sent directly, as each of them gets forwarded after letting the other
+///   tasks run a seeded number of times
+///
+/// Timers run against the real clock, and work moved to other threads
+/// (like [crate::context::ActorContext::spawn_blocking]) remains nondeterministic, so actors racing those
+/// against their' messages may still interleave differently between runs.
+///
+/// # Panics
+///
+/// Panics if called within another runtime.
+pub fn run_deterministic<F: Future>(seed: u64, f: F) -> F::Output {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+    let _seeded = Seeded::new(seed);
+    rt.block_on(f)
+}
+
+/// Message type used for reporting errors of [inspect]
+#[derive(Clone, Copy, Debug)]
+pub struct Inspect;
+
+/// Runs the closure on the actor in between its' messages, returning the result.
+///
+/// It lets tests check the internal state of the actor without adding dedicated handlers.
+pub async fn inspect<A, F, R>(addr: &Addr<A>, f: F) -> Result<R, ActorError>
+where
+    A: Actor,
+    F: 'static + FnOnce(&A) -> R + Send,
+    R: 'static + Send,
+{
+    let (tx, rx) = oneshot::channel();
+    addr.msg_queue.inspect(Box::new(move |act| {
+        let _ = tx.send(f(act));
+    }))?;
+    rx.await.map_err(|_| addr.msg_queue.lost_error::<Inspect>())
+}
@@ -0,0 +1,158 @@
+//! Driving actors with random sequences of messages
+//!
+//! [Fuzz::run] starts fresh instances of the actor, feeds them messages built by a generator
+//! and checks the given invariant after every step, reporting the first violation along with
+//! the seed and the messages leading up to it. Runs are deterministic for a given seed.
+
+use super::inspect;
+use crate::actor::{Handler, StopMode};
+use std::fmt;
+
+/// Deterministic source of randomness handed to message generators
+#[derive(Clone, Debug)]
+pub struct Gen {
+    state: u64,
+}
+
+impl Gen {
+    /// Creates a generator with the given seed
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+    /// Returns a random number
+    pub fn next_u64(&mut self) -> u64 {
+        crate::random::splitmix64(&mut self.state)
+    }
+    /// Returns a random number lower than `bound`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bound` is zero.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        assert!(bound > 0, "The bound must be positive");
+        self.next_u64() % bound
+    }
+    /// Returns `true` with the probability of one half
+    pub fn flip(&mut self) -> bool {
+        self.next_u64() & 1 == 1
+    }
+    /// Returns a random element of the slice
+    ///
+    /// # Panics
+    ///
+    /// Panics if the slice is empty.
+    pub fn choose<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len() as u64) as usize]
+    }
+}
+
+/// Violation found by [Fuzz::run]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Failure {
+    /// Seed of the failed run, which reproduces it
+    pub seed: u64,
+    /// Messages sent during the failed run, formatted via [fmt::Debug], the last one causing the failure
+    pub history: Vec<String>,
+    /// What went wrong
+    pub reason: String,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Run with seed {} failed after {} messages: {}",
+            self.seed,
+            self.history.len(),
+            self.reason
+        )?;
+        for msg in &self.history {
+            write!(f, "\n  {}", msg)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// Configuration of a fuzzing session
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Fuzz {
+    /// Seed of the first run, each following run uses the next one
+    pub seed: u64,
+    /// Number of fresh actors to drive
+    pub runs: u64,
+    /// Number of messages sent to each actor
+    pub steps: usize,
+}
+
+impl Default for Fuzz {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            runs: 32,
+            steps: 64,
+        }
+    }
+}
+
+impl Fuzz {
+    /// Drives fresh actors built by `factory` with messages built by `generate`,
+    /// checking `invariant` on the actor's state after every message.
+    ///
+    /// Handler panics and violations of the invariant are reported as a [Failure].
+    /// Must be called within a tokio runtime.
+    pub async fn run<A, M, F, G, I>(
+        &self,
+        mut factory: F,
+        mut generate: G,
+        invariant: I,
+    ) -> Result<(), Failure>
+    where
+        A: Handler<M>,
+        M: 'static + Send + fmt::Debug,
+        F: FnMut() -> A,
+        G: FnMut(&mut Gen) -> M,
+        I: 'static + Fn(&A) -> Result<(), String> + Clone + Send,
+    {
+        for run in 0..self.runs {
+            let seed = self.seed.wrapping_add(run);
+            let mut gen = Gen::new(seed);
+            let addr = factory().start();
+            let mut history = Vec::with_capacity(self.steps);
+            for _ in 0..self.steps {
+                let msg = generate(&mut gen);
+                history.push(format!("{:?}", msg));
+                let checked = async {
+                    addr.send(msg).await.map_err(|e| e.to_string())?;
+                    let invariant = invariant.clone();
+                    inspect(&addr, move |act| invariant(act))
+                        .await
+                        .map_err(|e| e.to_string())?
+                };
+                if let Err(reason) = checked.await {
+                    addr.stop(StopMode::Abandon);
+                    return Err(Failure {
+                        seed,
+                        history,
+                        reason,
+                    });
+                }
+            }
+            addr.stop(StopMode::Abandon);
+        }
+        Ok(())
+    }
+}
+
+/// Shorthand for [Fuzz::run] with the default configuration
+pub async fn run<A, M, F, G, I>(factory: F, generate: G, invariant: I) -> Result<(), Failure>
+where
+    A: Handler<M>,
+    M: 'static + Send + fmt::Debug,
+    F: FnMut() -> A,
+    G: FnMut(&mut Gen) -> M,
+    I: 'static + Fn(&A) -> Result<(), String> + Clone + Send,
+{
+    Fuzz::default().run(factory, generate, invariant).await
+}
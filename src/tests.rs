@@ -5,6 +5,9 @@ fn get_runtime() -> tokio::runtime::Runtime {
     tokio::runtime::Runtime::new().unwrap()
 }
 
+/// Held by the tests installing the process-wide logger
+static LOGGER: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
 #[test]
 fn basic_messages() {
     struct Ping;
@@ -155,13 +158,13 @@ fn handling_streams() {
             message_count: 0,
         }
         .start();
-        let stream = futures_util::stream::iter(std::iter::repeat(Ping).take(1000));
+        let stream = futures_util::stream::iter(std::iter::repeat_n(Ping, 1000));
         d.send(As { stream }).await.unwrap();
 
-        let stream2 = futures_util::stream::iter(std::iter::repeat(Ping).take(5000));
+        let stream2 = futures_util::stream::iter(std::iter::repeat_n(Ping, 5000));
         d.send(As { stream: stream2 }).await.unwrap();
 
-        let stream3 = futures_util::stream::iter(std::iter::repeat(Ping).take(10000));
+        let stream3 = futures_util::stream::iter(std::iter::repeat_n(Ping, 10000));
         d.send(As { stream: stream3 }).await.unwrap();
 
         d.send(Ping).await.unwrap();
@@ -218,7 +221,7 @@ fn do_send_gets_delivered() {
             self.hashset.insert(msg)
         }
     }
-    get_runtime().block_on(async {
+    crate::testing::run_deterministic(0, async {
         let memo = Memorizer::default().start();
         let do_send_fingerprints = vec![
             Fingerprint {
@@ -431,10 +434,15 @@ fn actor_stopping_message_correctness() {
         //ensure that actor gets stopped
         stopped_notifier.await.unwrap();
 
-        assert_eq!(actor.try_send(NeverDelivered), Err(ActorError::CannotSend));
+        let context = crate::error::ErrorContext {
+            actor_id: actor.id(),
+            actor_type: std::any::type_name::<DummyHandler>(),
+            message_type: std::any::type_name::<NeverDelivered>(),
+        };
+        assert_eq!(actor.try_send(NeverDelivered), Err(ActorError::CannotSend(context)));
         assert_eq!(
             actor.send(NeverDelivered).await,
-            Err(ActorError::CannotSend)
+            Err(ActorError::CannotSend(context))
         );
     })
 }
@@ -586,7 +594,7 @@ fn supervised_lifecycle() {
 
     #[async_trait]
     impl Supervised for Dummy {
-        async fn restarting(&mut self, _ctx: &mut ActorContext<Dummy>) {
+        async fn restarting(&mut self, _ctx: &mut ActorContext<Dummy>, _reason: RestartReason) {
             self.restart_count += 1;
         }
     }
@@ -606,3 +614,4591 @@ fn supervised_lifecycle() {
         assert_eq!(rx.await.unwrap(), 4);
     })
 }
+
+#[test]
+fn idempotent_addr_suppresses_duplicates() {
+    use std::time::Duration;
+
+    struct Deposit {
+        id: u64,
+        amount: u32,
+    }
+    impl IdempotencyKey for Deposit {
+        type Key = u64;
+        fn idempotency_key(&self) -> u64 {
+            self.id
+        }
+    }
+    struct Balance;
+    struct Hold(oneshot::Receiver<()>);
+
+    struct Account {
+        balance: u32,
+    }
+    impl Actor for Account {}
+    #[async_trait]
+    impl Handler<Hold> for Account {
+        type Response = ();
+        async fn handle(&mut self, msg: Hold, _ctx: &mut ActorContext<Self>) {
+            let _ = msg.0.await;
+        }
+    }
+    #[async_trait]
+    impl Handler<Deposit> for Account {
+        type Response = u32;
+        async fn handle(&mut self, msg: Deposit, _ctx: &mut ActorContext<Self>) -> Self::Response {
+            self.balance += msg.amount;
+            self.balance
+        }
+    }
+    #[async_trait]
+    impl Handler<Balance> for Account {
+        type Response = u32;
+        async fn handle(&mut self, _msg: Balance, _ctx: &mut ActorContext<Self>) -> Self::Response {
+            self.balance
+        }
+    }
+
+    get_runtime().block_on(async {
+        let addr = Account { balance: 0 }.start();
+        let deposits = IdempotentAddr::new(addr.clone(), 2, Duration::from_secs(60));
+        assert_eq!(deposits.send(Deposit { id: 1, amount: 10 }).await, Ok(10));
+        // answered from cache
+        assert_eq!(deposits.send(Deposit { id: 1, amount: 10 }).await, Ok(10));
+        deposits.do_send(Deposit { id: 1, amount: 10 });
+        assert_eq!(deposits.send(Deposit { id: 2, amount: 5 }).await, Ok(15));
+        deposits.do_send(Deposit { id: 3, amount: 1 });
+        assert_eq!(addr.send(Balance).await, Ok(16));
+        // the least recently used key got evicted
+        assert_eq!(deposits.send(Deposit { id: 1, amount: 10 }).await, Ok(26));
+
+        let short_lived = IdempotentAddr::new(addr.clone(), 16, Duration::from_millis(10));
+        assert_eq!(short_lived.send(Deposit { id: 7, amount: 1 }).await, Ok(27));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(short_lived.send(Deposit { id: 7, amount: 1 }).await, Ok(28));
+
+        // Cancelled before the response arrived, so it can be retried
+        let (release, held) = oneshot::channel();
+        addr.do_send(Hold(held));
+        let cancelled = deposits.send(Deposit { id: 9, amount: 0 });
+        assert!(futures_util::FutureExt::now_or_never(cancelled).is_none());
+        release.send(()).unwrap();
+        assert!(deposits.send(Deposit { id: 9, amount: 0 }).await.is_ok());
+    })
+}
+
+#[test]
+fn saga_compensates_completed_steps() {
+    use crate::saga::{SagaError, StepFailure};
+    use std::time::Duration;
+
+    struct Reserve(u32);
+    struct Release(u32);
+    struct Reserved;
+
+    #[derive(Default)]
+    struct Inventory {
+        reserved: u32,
+    }
+    impl Actor for Inventory {}
+    #[async_trait]
+    impl Handler<Reserve> for Inventory {
+        type Response = Result<(), &'static str>;
+        async fn handle(&mut self, msg: Reserve, _ctx: &mut ActorContext<Self>) -> Self::Response {
+            if self.reserved + msg.0 > 10 {
+                return Err("out of stock");
+            }
+            self.reserved += msg.0;
+            Ok(())
+        }
+    }
+    #[async_trait]
+    impl Handler<Release> for Inventory {
+        type Response = ();
+        async fn handle(&mut self, msg: Release, _ctx: &mut ActorContext<Self>) -> Self::Response {
+            self.reserved -= msg.0;
+        }
+    }
+    #[async_trait]
+    impl Handler<Reserved> for Inventory {
+        type Response = u32;
+        async fn handle(&mut self, _msg: Reserved, _ctx: &mut ActorContext<Self>) -> Self::Response {
+            self.reserved
+        }
+    }
+
+    fn reservation(name: &str, inventory: &Addr<Inventory>, amount: u32) -> Step<&'static str> {
+        let (a, b) = (inventory.clone(), inventory.clone());
+        Step::new(name, move || {
+            let a = a.clone();
+            async move { a.send(Reserve(amount)).await.map_err(|_| "actor error")? }
+        })
+        .compensate(move || {
+            let b = b.clone();
+            async move {
+                b.send(Release(amount)).await.unwrap();
+            }
+        })
+    }
+
+    get_runtime().block_on(async {
+        let warehouse = Inventory::default().start();
+        let store = Inventory::default().start();
+
+        let mut saga = Saga::new()
+            .step(reservation("warehouse", &warehouse, 4))
+            .step(reservation("store", &store, 2))
+            .step(reservation("store again", &store, 9));
+        assert_eq!(
+            saga.run().await,
+            Err(SagaError {
+                step: 2,
+                name: "store again".to_owned(),
+                failure: StepFailure::Failed("out of stock"),
+            })
+        );
+        assert_eq!(warehouse.send(Reserved).await, Ok(0));
+        assert_eq!(store.send(Reserved).await, Ok(0));
+
+        let mut slow = Saga::new()
+            .step(reservation("warehouse", &warehouse, 4))
+            .step(
+                Step::new("slow", || async {
+                    tokio::time::sleep(Duration::from_secs(10)).await;
+                    Ok(())
+                })
+                .timeout(Duration::from_millis(20)),
+            );
+        assert_eq!(slow.run().await.unwrap_err().failure, StepFailure::TimedOut);
+        assert_eq!(warehouse.send(Reserved).await, Ok(0));
+
+        let mut fine = Saga::new()
+            .step(reservation("warehouse", &warehouse, 4))
+            .step(reservation("store", &store, 2));
+        assert_eq!(fine.run().await, Ok(()));
+        assert_eq!(warehouse.send(Reserved).await, Ok(4));
+        assert_eq!(store.send(Reserved).await, Ok(2));
+    })
+}
+
+#[test]
+fn two_phase_commit() {
+    use crate::two_phase::*;
+    use std::time::Duration;
+
+    struct Ledger {
+        balance: i32,
+        pending: Option<(TransactionId, i32)>,
+        slow: bool,
+    }
+    impl Ledger {
+        fn new(balance: i32) -> Self {
+            Self {
+                balance,
+                pending: None,
+                slow: false,
+            }
+        }
+    }
+    impl Actor for Ledger {}
+    #[async_trait]
+    impl Handler<Prepare<i32>> for Ledger {
+        type Response = Vote;
+        async fn handle(&mut self, msg: Prepare<i32>, _ctx: &mut ActorContext<Self>) -> Vote {
+            if self.slow {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+            }
+            if self.balance + msg.payload < 0 {
+                return Vote::No;
+            }
+            self.pending = Some((msg.id, msg.payload));
+            Vote::Yes
+        }
+    }
+    #[async_trait]
+    impl Handler<Commit> for Ledger {
+        type Response = ();
+        async fn handle(&mut self, msg: Commit, _ctx: &mut ActorContext<Self>) {
+            let (id, delta) = self.pending.take().unwrap();
+            assert_eq!(id, msg.id);
+            self.balance += delta;
+        }
+    }
+    #[async_trait]
+    impl Handler<Abort> for Ledger {
+        type Response = ();
+        async fn handle(&mut self, _msg: Abort, _ctx: &mut ActorContext<Self>) {
+            self.pending = None;
+        }
+    }
+    struct Balance;
+    #[async_trait]
+    impl Handler<Balance> for Ledger {
+        type Response = i32;
+        async fn handle(&mut self, _msg: Balance, _ctx: &mut ActorContext<Self>) -> i32 {
+            self.balance
+        }
+    }
+
+    get_runtime().block_on(async {
+        let a = Ledger::new(10).start();
+        let b = Ledger::new(3).start();
+        let coordinator = Coordinator::new()
+            .participant(a.clone())
+            .participant(b.clone())
+            .start();
+        assert_eq!(coordinator.send(Transaction(-2)).await, Ok(Ok(Outcome::Committed)));
+        assert_eq!(coordinator.send(Transaction(-5)).await, Ok(Ok(Outcome::Aborted)));
+        assert_eq!(a.send(Balance).await, Ok(8));
+        assert_eq!(b.send(Balance).await, Ok(1));
+
+        let slow = Ledger {
+            slow: true,
+            ..Ledger::new(100)
+        }
+        .start();
+        let mut with_quorum = Coordinator::new()
+            .participant(a.clone())
+            .participant(slow.clone())
+            .quorum(1)
+            .prepare_timeout(Duration::from_millis(50));
+        assert_eq!(with_quorum.run(-8).await, Ok(Outcome::Committed));
+        assert_eq!(a.send(Balance).await, Ok(0));
+        assert_eq!(slow.send(Balance).await, Ok(100));
+    })
+}
+
+#[test]
+fn two_phase_undelivered() {
+    use crate::supervised::Backoff;
+    use crate::two_phase::*;
+    use std::time::{Duration, Instant};
+
+    // Votes yes, but the broken one cannot apply the transaction
+    struct Ledger {
+        broken: bool,
+    }
+    impl Actor for Ledger {}
+    #[async_trait]
+    impl Handler<Prepare<()>> for Ledger {
+        type Response = Vote;
+        async fn handle(&mut self, _msg: Prepare<()>, _ctx: &mut ActorContext<Self>) -> Vote {
+            Vote::Yes
+        }
+    }
+    #[async_trait]
+    impl Handler<Commit> for Ledger {
+        type Response = ();
+        async fn handle(&mut self, _msg: Commit, _ctx: &mut ActorContext<Self>) {
+            if self.broken {
+                panic!("Disk full")
+            }
+        }
+    }
+    #[async_trait]
+    impl Handler<Abort> for Ledger {
+        type Response = ();
+        async fn handle(&mut self, _msg: Abort, _ctx: &mut ActorContext<Self>) {}
+    }
+
+    get_runtime().block_on(async {
+        let mut coordinator = Coordinator::new()
+            .participant(Ledger { broken: false }.start())
+            .participant(Ledger { broken: true }.start())
+            .retries(2)
+            .retry_backoff(Backoff {
+                base: Duration::from_millis(20),
+                jitter: 0.0,
+                ..Backoff::DEFAULT
+            });
+        let started = Instant::now();
+        let undelivered = coordinator.run(()).await.unwrap_err();
+        // Waited before each of the retries
+        assert!(started.elapsed() >= Duration::from_millis(60));
+        assert_eq!(undelivered.outcome, Outcome::Committed);
+        let failed: Vec<_> = undelivered.participants.iter().map(|(index, _)| *index).collect();
+        assert_eq!(failed, vec![1]);
+    })
+}
+
+#[test]
+fn streaming_response() {
+    use crate::response::StreamingResponse;
+
+    struct Tail(usize);
+    struct Evens(u32);
+
+    struct Log {
+        lines: Vec<String>,
+    }
+    impl Actor for Log {}
+    #[async_trait]
+    impl Handler<Tail> for Log {
+        type Response = StreamingResponse<String>;
+        async fn handle(&mut self, msg: Tail, _ctx: &mut ActorContext<Self>) -> Self::Response {
+            let (tx, response) = StreamingResponse::channel(2);
+            let lines = self.lines[self.lines.len() - msg.0..].to_vec();
+            tokio::spawn(async move {
+                for line in lines {
+                    if tx.send(line).await.is_err() {
+                        break;
+                    }
+                }
+            });
+            response
+        }
+    }
+    #[async_trait]
+    impl Handler<Evens> for Log {
+        type Response = StreamingResponse<u32>;
+        async fn handle(&mut self, msg: Evens, _ctx: &mut ActorContext<Self>) -> Self::Response {
+            StreamingResponse::from_stream(futures_util::stream::iter((0..msg.0).map(|i| i * 2)))
+        }
+    }
+
+    get_runtime().block_on(async {
+        let log = Log {
+            lines: (0..10).map(|i| format!("line {}", i)).collect(),
+        }
+        .start();
+        let tail: Vec<String> = log.send(Tail(3)).await.unwrap().collect().await;
+        assert_eq!(tail, vec!["line 7", "line 8", "line 9"]);
+        let evens: Vec<u32> = log.send(Evens(4)).await.unwrap().collect().await;
+        assert_eq!(evens, vec![0, 2, 4, 6]);
+    })
+}
+
+#[test]
+fn dropping_send_cancels_request() {
+    use futures_util::future::{select, Either};
+    use std::time::Duration;
+
+    struct SlowQuery;
+    struct Aborted;
+
+    #[derive(Default)]
+    struct Db {
+        aborted: usize,
+        had_token: Vec<bool>,
+    }
+    impl Actor for Db {}
+    #[async_trait]
+    impl Handler<SlowQuery> for Db {
+        type Response = ();
+        async fn handle(&mut self, _msg: SlowQuery, ctx: &mut ActorContext<Self>) {
+            self.had_token.push(ctx.request_cancellation().is_some());
+            let token = match ctx.request_cancellation() {
+                Some(token) => token,
+                None => return,
+            };
+            let work = tokio::time::sleep(Duration::from_secs(10));
+            let cancelled = token.cancelled();
+            let finished = select(Box::pin(work), Box::pin(cancelled)).await;
+            if let Either::Right(_) = finished {
+                self.aborted += 1;
+            }
+        }
+    }
+    #[async_trait]
+    impl Handler<Aborted> for Db {
+        type Response = (usize, Vec<bool>);
+        async fn handle(&mut self, _msg: Aborted, _ctx: &mut ActorContext<Self>) -> Self::Response {
+            (self.aborted, self.had_token.clone())
+        }
+    }
+
+    get_runtime().block_on(async {
+        let db = Db::default().start();
+        let timed_out = tokio::time::timeout(Duration::from_millis(20), db.send(SlowQuery)).await;
+        assert!(timed_out.is_err());
+        db.do_send(SlowQuery);
+        assert_eq!(db.send(Aborted).await, Ok((1, vec![true, false])));
+    })
+}
+
+#[test]
+fn undeliverable_responses_become_dead_letters() {
+    use crate::dead_letters::{DeadLetterReason, DeadLetters};
+    use std::time::Duration;
+
+    struct Slow;
+    struct Sleepy;
+    impl Actor for Sleepy {}
+    #[async_trait]
+    impl Handler<Slow> for Sleepy {
+        type Response = ();
+        async fn handle(&mut self, _msg: Slow, _ctx: &mut ActorContext<Self>) {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    get_runtime().block_on(async {
+        let mut letters = DeadLetters::subscribe();
+        let count_before = DeadLetters::count();
+        let sleepy = Sleepy.start();
+        let first = tokio::time::timeout(Duration::from_millis(10), sleepy.send(Slow));
+        let second = tokio::time::timeout(Duration::from_millis(10), sleepy.send(Slow));
+        let (first, second) = futures_util::future::join(first, second).await;
+        assert!(first.is_err() && second.is_err());
+        // the actor keeps running
+        sleepy.send(Slow).await.unwrap();
+
+        let mut reasons = vec![];
+        while reasons.len() < 2 {
+            let letter = letters.recv().await.unwrap();
+            if letter.actor_type == std::any::type_name::<Sleepy>() {
+                assert_eq!(letter.message_type, std::any::type_name::<Slow>());
+                reasons.push(letter.reason);
+            }
+        }
+        assert_eq!(
+            reasons,
+            vec![
+                DeadLetterReason::ResponseUndeliverable,
+                DeadLetterReason::Cancelled
+            ]
+        );
+        assert!(DeadLetters::count() >= count_before + 2);
+    })
+}
+
+#[test]
+fn no_panics_after_last_address_is_dropped() {
+    struct Leftover;
+    struct Tidy {
+        stopped_notifier: Option<oneshot::Sender<()>>,
+    }
+    #[async_trait]
+    impl Actor for Tidy {
+        async fn stopped(&mut self, ctx: &mut ActorContext<Self>) {
+            // Both are silently ignored when the actor is gone
+            ctx.notify(Leftover);
+            ctx.add_stream(futures_util::stream::iter(vec![Leftover]));
+            self.stopped_notifier.take().unwrap().send(()).unwrap();
+        }
+    }
+    #[async_trait]
+    impl Handler<Leftover> for Tidy {
+        type Response = ();
+        async fn handle(&mut self, _msg: Leftover, _ctx: &mut ActorContext<Self>) {}
+    }
+
+    get_runtime().block_on(async {
+        let (tx, rx) = oneshot::channel();
+        let tidy = Tidy {
+            stopped_notifier: Some(tx),
+        }
+        .start();
+        let weak = tidy.downgrade();
+        drop(tidy);
+        rx.await.unwrap();
+        assert!(weak.upgrade().is_none());
+    })
+}
+
+#[test]
+fn actor_errors_carry_context() {
+    use std::time::Duration;
+
+    struct Nap(u64);
+    struct Explode;
+
+    struct Fragile;
+    #[async_trait]
+    impl Actor for Fragile {
+        const MAILBOX_CAPACITY: Option<usize> = Some(1);
+    }
+    #[async_trait]
+    impl Handler<Nap> for Fragile {
+        type Response = ();
+        async fn handle(&mut self, msg: Nap, _ctx: &mut ActorContext<Self>) {
+            tokio::time::sleep(Duration::from_millis(msg.0)).await;
+        }
+    }
+    #[async_trait]
+    impl Handler<Explode> for Fragile {
+        type Response = ();
+        async fn handle(&mut self, _msg: Explode, _ctx: &mut ActorContext<Self>) {
+            panic!("boom");
+        }
+    }
+
+    get_runtime().block_on(async {
+        let fragile = Fragile.start();
+        let other = Fragile.start();
+        assert_ne!(fragile.id(), other.id());
+
+        let err = fragile
+            .send_timeout(Nap(1000), Duration::from_millis(10))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ActorError::Timeout(_)));
+        assert_eq!(err.context().actor_id, fragile.id());
+        assert_eq!(err.context().message_type, std::any::type_name::<Nap>());
+        assert_eq!(err.context().actor_type, std::any::type_name::<Fragile>());
+        assert!(err.to_string().contains(&fragile.id().to_string()));
+
+        // the first nap is still being handled, so this one waits in the mailbox
+        fragile.try_send(Nap(0)).unwrap();
+        assert!(matches!(
+            fragile.try_send(Nap(0)),
+            Err(ActorError::MailboxFull(_))
+        ));
+
+        let err = other.send(Explode).await.unwrap_err();
+        assert_eq!(
+            err,
+            ActorError::HandlerPanicked(crate::error::ErrorContext {
+                actor_id: other.id(),
+                actor_type: std::any::type_name::<Fragile>(),
+                message_type: std::any::type_name::<Explode>(),
+            })
+        );
+        // the panic stopped the actor
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(matches!(
+            other.send(Nap(0)).await,
+            Err(ActorError::CannotSend(_))
+        ));
+    })
+}
+
+#[test]
+fn handlers_see_message_ids() {
+    use crate::dead_letters::DeadLetters;
+    use std::time::Duration;
+
+    struct WhoAmI;
+    struct Forget;
+
+    struct Echo {
+        seen: Option<MessageId>,
+    }
+    #[async_trait]
+    impl Actor for Echo {
+        async fn started(&mut self, ctx: &mut ActorContext<Self>) {
+            assert!(ctx.current_message_id().is_none());
+        }
+    }
+    #[async_trait]
+    impl Handler<WhoAmI> for Echo {
+        type Response = (Option<MessageId>, Option<MessageId>);
+        async fn handle(&mut self, _msg: WhoAmI, ctx: &mut ActorContext<Self>) -> Self::Response {
+            let previous = self.seen.replace(ctx.current_message_id().unwrap());
+            (ctx.current_message_id(), previous)
+        }
+    }
+    #[async_trait]
+    impl Handler<Forget> for Echo {
+        type Response = ();
+        async fn handle(&mut self, _msg: Forget, ctx: &mut ActorContext<Self>) {
+            self.seen = ctx.current_message_id();
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    get_runtime().block_on(async {
+        let mut letters = DeadLetters::subscribe();
+        let echo = Echo { seen: None }.start();
+        let (first, _) = echo.send(WhoAmI).await.unwrap();
+        let (second, previous) = echo.send(WhoAmI).await.unwrap();
+        assert_eq!(previous, first);
+        assert_ne!(first, second);
+
+        let forgotten = tokio::time::timeout(Duration::from_millis(10), echo.send(Forget)).await;
+        assert!(forgotten.is_err());
+        let (_, forgotten_id) = echo.send(WhoAmI).await.unwrap();
+        // the caller can learn the identifier up front
+        let (id, response) = echo.send_identified(WhoAmI).unwrap();
+        assert_eq!(response.await.unwrap().0, Some(id));
+        loop {
+            let letter = letters.recv().await.unwrap();
+            if letter.actor_type == std::any::type_name::<Echo>() {
+                assert_eq!(Some(letter.message_id), forgotten_id);
+                break;
+            }
+        }
+    })
+}
+
+#[test]
+fn load_shedding() {
+    use crate::shedding::*;
+    use std::time::Duration;
+
+    struct Work(u64);
+    struct Worker;
+    impl Actor for Worker {}
+    #[async_trait]
+    impl Handler<Work> for Worker {
+        type Response = u64;
+        async fn handle(&mut self, msg: Work, _ctx: &mut ActorContext<Self>) -> u64 {
+            tokio::time::sleep(Duration::from_millis(msg.0)).await;
+            msg.0
+        }
+    }
+
+    get_runtime().block_on(async {
+        let worker = Worker.start();
+        let shedder = LoadShedder::new(worker.recipient::<Work>(), StaticThreshold::new(2));
+        worker.do_send(Work(100));
+        worker.do_send(Work(0));
+        worker.do_send(Work(0));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let err = shedder
+            .send_with_priority(Work(0), Priority::Low)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ActorError::Overloaded(_)));
+        assert_eq!(err.context().actor_id, worker.id());
+        assert_eq!(shedder.send(Work(1)).await, Ok(1));
+        // the queue has been drained in the meantime
+        assert_eq!(shedder.send_with_priority(Work(1), Priority::Low).await, Ok(1));
+
+        let codel = LoadShedder::new(
+            worker.recipient::<Work>(),
+            Codel::new(Duration::from_millis(5), Duration::from_millis(15)),
+        );
+        assert_eq!(codel.send(Work(20)).await, Ok(20));
+        assert_eq!(codel.send(Work(20)).await, Ok(20));
+        assert!(matches!(
+            codel.try_send_with_priority(Work(0), Priority::Low),
+            Err(ActorError::Overloaded(_))
+        ));
+        // Without any other traffic, a probe gets through once per interval
+        tokio::time::sleep(Duration::from_millis(15)).await;
+        assert_eq!(codel.send_with_priority(Work(0), Priority::Low).await, Ok(0));
+        assert_eq!(codel.send(Work(20)).await, Ok(20));
+        assert_eq!(codel.send(Work(20)).await, Ok(20));
+        assert!(matches!(
+            codel.try_send_with_priority(Work(0), Priority::Low),
+            Err(ActorError::Overloaded(_))
+        ));
+        assert_eq!(codel.send(Work(0)).await, Ok(0));
+        assert_eq!(codel.send_with_priority(Work(0), Priority::Low).await, Ok(0));
+    })
+}
+
+#[test]
+fn read_handlers_run_concurrently() {
+    use std::time::{Duration, Instant};
+
+    struct Get;
+    struct Set(u32);
+    struct Register(u32);
+    impl Actor for Register {}
+    #[async_trait]
+    impl ReadHandler<Get> for Register {
+        type Response = u32;
+        async fn handle_read(&self, _msg: Get, _ctx: &ActorContext<Self>) -> u32 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            self.0
+        }
+    }
+    #[async_trait]
+    impl Handler<Set> for Register {
+        type Response = ();
+        async fn handle(&mut self, msg: Set, _ctx: &mut ActorContext<Self>) {
+            self.0 = msg.0;
+        }
+    }
+
+    get_runtime().block_on(async {
+        let register = Register(1).start();
+        let started = Instant::now();
+        let (a, b, _, c) = futures_util::join!(
+            register.send_read(Get),
+            register.send_read(Get),
+            register.send(Set(2)),
+            register.send_read(Get),
+        );
+        assert_eq!((a, b, c), (Ok(1), Ok(1), Ok(2)));
+        // two batches of reads, separated by the write
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(200));
+        assert!(elapsed < Duration::from_millis(300));
+    })
+}
+
+#[test]
+fn messages_behind_panicking_reads() {
+    struct Hold(oneshot::Receiver<()>);
+    struct Boom;
+    struct Set(u32);
+    struct Register(u32);
+    impl Actor for Register {}
+    impl Supervised for Register {}
+    #[async_trait]
+    impl ReadHandler<Boom> for Register {
+        type Response = ();
+        async fn handle_read(&self, _msg: Boom, _ctx: &ActorContext<Self>) {
+            panic!("Boom");
+        }
+    }
+    #[async_trait]
+    impl Handler<Hold> for Register {
+        type Response = ();
+        async fn handle(&mut self, msg: Hold, _ctx: &mut ActorContext<Self>) {
+            let _ = msg.0.await;
+        }
+    }
+    #[async_trait]
+    impl Handler<Set> for Register {
+        type Response = u32;
+        async fn handle(&mut self, msg: Set, _ctx: &mut ActorContext<Self>) -> u32 {
+            std::mem::replace(&mut self.0, msg.0)
+        }
+    }
+
+    get_runtime().block_on(async {
+        let register = Register::create_supervised(|_| Register(1));
+        let (tx, rx) = oneshot::channel();
+        // The write gets taken out of the mailbox along with the read, which brings the actor down
+        let (_, boom, set, ()) = futures_util::join!(
+            register.send(Hold(rx)),
+            register.send_read(Boom),
+            register.send(Set(2)),
+            async { tx.send(()).unwrap() },
+        );
+        assert!(boom.is_err());
+        // Handled by the restarted actor
+        assert_eq!(set, Ok(1));
+    })
+}
+
+#[test]
+fn worker_pool() {
+    use std::time::{Duration, Instant};
+
+    struct Job;
+    #[derive(Clone)]
+    struct Resizer;
+    impl Actor for Resizer {}
+    #[async_trait]
+    impl Handler<Job> for Resizer {
+        type Response = ();
+        async fn handle(&mut self, _msg: Job, _ctx: &mut ActorContext<Self>) {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    get_runtime().block_on(async {
+        let pool = Resizer.start_workers(3);
+        let started = Instant::now();
+        let results = futures_util::future::join_all((0..4).map(|_| pool.send(Job))).await;
+        assert!(results.iter().all(Result::is_ok));
+        // three jobs at once, then the fourth one
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(200));
+        assert!(elapsed < Duration::from_millis(300));
+    })
+}
+
+#[test]
+fn blocking_sections() {
+    use std::time::Duration;
+
+    struct Decode(u64);
+    struct Decoded(u64);
+    struct Total;
+    struct Decoder {
+        total: u64,
+    }
+    impl Actor for Decoder {}
+    #[async_trait]
+    impl Handler<Decode> for Decoder {
+        type Response = ();
+        async fn handle(&mut self, msg: Decode, ctx: &mut ActorContext<Self>) {
+            ctx.spawn_blocking(move || {
+                std::thread::sleep(Duration::from_millis(50));
+                Decoded(msg.0)
+            });
+        }
+    }
+    #[async_trait]
+    impl Handler<Decoded> for Decoder {
+        type Response = ();
+        async fn handle(&mut self, msg: Decoded, _ctx: &mut ActorContext<Self>) {
+            self.total += msg.0;
+        }
+    }
+    #[async_trait]
+    impl Handler<Total> for Decoder {
+        type Response = u64;
+        async fn handle(&mut self, _msg: Total, ctx: &mut ActorContext<Self>) -> u64 {
+            ctx.run_blocking(|| std::thread::sleep(Duration::from_millis(10))).await;
+            self.total
+        }
+    }
+
+    get_runtime().block_on(async {
+        let decoder = Decoder { total: 0 }.start();
+        decoder.send(Decode(1)).await.unwrap();
+        decoder.send(Decode(2)).await.unwrap();
+        // not decoded yet, but the actor is responsive
+        assert_eq!(decoder.send(Total).await, Ok(0));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(decoder.send(Total).await, Ok(3));
+    })
+}
+
+#[test]
+fn stop_token_is_cancelled_when_stopping() {
+    use crate::cancellation::CancellationToken;
+    use std::time::Duration;
+
+    struct Watch;
+    struct Stop;
+    struct Poller {
+        stopped_tx: Option<oneshot::Sender<()>>,
+    }
+    impl Actor for Poller {}
+    #[async_trait]
+    impl Handler<Watch> for Poller {
+        type Response = CancellationToken;
+        async fn handle(&mut self, _msg: Watch, ctx: &mut ActorContext<Self>) -> CancellationToken {
+            let token = ctx.stop_token();
+            let stopped_tx = self.stopped_tx.take().unwrap();
+            tokio::spawn({
+                let token = token.clone();
+                async move {
+                    token.cancelled().await;
+                    stopped_tx.send(()).unwrap();
+                }
+            });
+            token
+        }
+    }
+    #[async_trait]
+    impl Handler<Stop> for Poller {
+        type Response = ();
+        async fn handle(&mut self, _msg: Stop, ctx: &mut ActorContext<Self>) {
+            ctx.stop();
+        }
+    }
+
+    get_runtime().block_on(async {
+        let (stopped_tx, stopped_rx) = oneshot::channel();
+        let poller = Poller {
+            stopped_tx: Some(stopped_tx),
+        }
+        .start();
+        let token = poller.send(Watch).await.unwrap();
+        assert!(!token.is_cancelled());
+        poller.send(Stop).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(1), stopped_rx)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(token.is_cancelled());
+    })
+}
+
+#[test]
+fn stop_modes() {
+    use std::time::Duration;
+
+    struct Work;
+    struct Slow;
+    impl Actor for Slow {}
+    #[async_trait]
+    impl Handler<Work> for Slow {
+        type Response = ();
+        async fn handle(&mut self, _msg: Work, _ctx: &mut ActorContext<Self>) {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    async fn run(mode: StopMode) -> Vec<Result<(), ActorError>> {
+        let slow = Slow.start();
+        let stop = async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            slow.stop(mode);
+        };
+        let (results, _) = futures_util::future::join(
+            futures_util::future::join_all((0..3).map(|_| slow.send(Work))),
+            stop,
+        )
+        .await;
+        assert!(slow.send(Work).await.is_err());
+        results
+    }
+
+    get_runtime().block_on(async {
+        let drained = run(StopMode::Drain { deadline: None }).await;
+        assert!(drained.iter().all(Result::is_ok));
+        let abandoned = run(StopMode::Abandon).await;
+        assert!(abandoned[0].is_ok());
+        assert!(matches!(abandoned[1], Err(ActorError::ActorStopping(_))));
+        assert!(matches!(abandoned[2], Err(ActorError::ActorStopping(_))));
+        // The second message is still being handled at the deadline
+        let deadline = Some(Duration::from_millis(50));
+        let partially_drained = run(StopMode::Drain { deadline }).await;
+        assert!(partially_drained[1].is_ok());
+        assert!(partially_drained[2].is_err());
+    })
+}
+
+#[test]
+fn startup_failures_reach_the_creator() {
+    struct Ping;
+    struct Connection {
+        url: &'static str,
+        started: bool,
+    }
+    impl Actor for Connection {}
+    #[async_trait]
+    impl TryStart for Connection {
+        type Error = String;
+        async fn try_started(&mut self, _ctx: &mut ActorContext<Self>) -> Result<(), String> {
+            if self.url.is_empty() {
+                return Err("no url given".to_string());
+            }
+            self.started = true;
+            Ok(())
+        }
+    }
+    #[async_trait]
+    impl Handler<Ping> for Connection {
+        type Response = bool;
+        async fn handle(&mut self, _msg: Ping, _ctx: &mut ActorContext<Self>) -> bool {
+            self.started
+        }
+    }
+
+    get_runtime().block_on(async {
+        let failed = Connection { url: "", started: false }.start_checked().await;
+        assert_eq!(failed.err().as_deref(), Some("no url given"));
+        let conn = Connection::try_create(|_ctx| Connection {
+            url: "localhost",
+            started: false,
+        })
+        .await
+        .unwrap();
+        assert_eq!(conn.send(Ping).await, Ok(true));
+    })
+}
+
+#[test]
+fn async_factories() {
+    use std::time::Duration;
+
+    struct Get;
+    struct Cache {
+        entries: usize,
+    }
+    impl Actor for Cache {}
+    #[async_trait]
+    impl Handler<Get> for Cache {
+        type Response = usize;
+        async fn handle(&mut self, _msg: Get, _ctx: &mut ActorContext<Self>) -> usize {
+            self.entries
+        }
+    }
+
+    get_runtime().block_on(async {
+        let cache = Cache::create_async(|ctx| async move {
+            assert!(ctx.weak_address().upgrade().is_some());
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            Cache { entries: 42 }
+        });
+        assert_eq!(cache.send(Get).await, Ok(42));
+    })
+}
+
+#[test]
+fn restart_reasons() {
+    struct Crash;
+    struct Halt;
+    struct LastReason;
+    struct Flaky {
+        last_reason: Option<RestartReason>,
+    }
+    impl Actor for Flaky {}
+    #[async_trait]
+    impl Supervised for Flaky {
+        async fn restarting(&mut self, _ctx: &mut ActorContext<Self>, reason: RestartReason) {
+            self.last_reason = Some(reason);
+        }
+    }
+    #[async_trait]
+    impl Handler<Crash> for Flaky {
+        type Response = ();
+        async fn handle(&mut self, _msg: Crash, _ctx: &mut ActorContext<Self>) {
+            panic!("connection reset");
+        }
+    }
+    #[async_trait]
+    impl Handler<Halt> for Flaky {
+        type Response = ();
+        async fn handle(&mut self, _msg: Halt, ctx: &mut ActorContext<Self>) {
+            ctx.stop();
+        }
+    }
+    #[async_trait]
+    impl Handler<LastReason> for Flaky {
+        type Response = Option<RestartReason>;
+        async fn handle(&mut self, _msg: LastReason, _ctx: &mut ActorContext<Self>) -> Self::Response {
+            self.last_reason.clone()
+        }
+    }
+
+    get_runtime().block_on(async {
+        let flaky = Flaky::create_supervised(|_ctx| Flaky { last_reason: None });
+        assert!(flaky.send(Crash).await.is_err());
+        assert_eq!(
+            flaky.send(LastReason).await,
+            Ok(Some(RestartReason::HandlerPanicked {
+                message_type: std::any::type_name::<Crash>(),
+                message: Some("connection reset".to_string()),
+            }))
+        );
+        flaky.send(Halt).await.unwrap();
+        assert_eq!(flaky.send(LastReason).await, Ok(Some(RestartReason::Stopped)));
+    })
+}
+
+#[test]
+fn recovery_state_survives_restarts() {
+    use std::collections::HashMap;
+
+    struct AddRoute(&'static str, u32);
+    struct Lookup(&'static str);
+    struct Crash;
+    struct Router {
+        routes: HashMap<&'static str, u32>,
+        generation: u32,
+    }
+    impl Actor for Router {}
+    impl Supervised for Router {}
+    #[async_trait]
+    impl Recoverable for Router {
+        type Snapshot = HashMap<&'static str, u32>;
+        fn snapshot(&mut self) -> Option<Self::Snapshot> {
+            Some(std::mem::take(&mut self.routes))
+        }
+        async fn recover(&mut self, _ctx: &mut ActorContext<Self>, snapshot: Self::Snapshot) {
+            self.routes = snapshot;
+        }
+    }
+    #[async_trait]
+    impl Handler<AddRoute> for Router {
+        type Response = ();
+        async fn handle(&mut self, msg: AddRoute, _ctx: &mut ActorContext<Self>) {
+            self.routes.insert(msg.0, msg.1);
+        }
+    }
+    #[async_trait]
+    impl Handler<Lookup> for Router {
+        type Response = (Option<u32>, u32);
+        async fn handle(&mut self, msg: Lookup, _ctx: &mut ActorContext<Self>) -> Self::Response {
+            (self.routes.get(msg.0).copied(), self.generation)
+        }
+    }
+    #[async_trait]
+    impl Handler<Crash> for Router {
+        type Response = ();
+        async fn handle(&mut self, _msg: Crash, ctx: &mut ActorContext<Self>) {
+            ctx.stop();
+        }
+    }
+
+    get_runtime().block_on(async {
+        let mut generation = 0;
+        let router = Router::create_recoverable(move |_ctx| {
+            generation += 1;
+            Router {
+                routes: HashMap::new(),
+                generation,
+            }
+        });
+        router.send(AddRoute("users", 7)).await.unwrap();
+        router.send(Crash).await.unwrap();
+        assert_eq!(router.send(Lookup("users")).await, Ok((Some(7), 2)));
+    })
+}
+
+#[test]
+fn restart_backoff() {
+    use std::time::Duration;
+
+    struct Kill;
+    struct Delays;
+    struct Crashy {
+        delays: Vec<Duration>,
+    }
+    impl Actor for Crashy {}
+    #[async_trait]
+    impl Supervised for Crashy {
+        const RESTART_BACKOFF: Backoff = Backoff {
+            base: Duration::from_millis(20),
+            max: Duration::from_millis(50),
+            jitter: 0.0,
+            reset_after: Duration::from_secs(60),
+        };
+        async fn backing_off(&mut self, _ctx: &mut ActorContext<Self>, delay: Duration) {
+            self.delays.push(delay);
+        }
+    }
+    #[async_trait]
+    impl Handler<Kill> for Crashy {
+        type Response = ();
+        async fn handle(&mut self, _msg: Kill, ctx: &mut ActorContext<Self>) {
+            ctx.stop();
+        }
+    }
+    #[async_trait]
+    impl Handler<Delays> for Crashy {
+        type Response = Vec<Duration>;
+        async fn handle(&mut self, _msg: Delays, _ctx: &mut ActorContext<Self>) -> Self::Response {
+            self.delays.clone()
+        }
+    }
+
+    let jittered = Backoff::DEFAULT.delay(3);
+    assert!(jittered <= Duration::from_millis(80));
+    assert!(jittered >= Duration::from_millis(64));
+
+    get_runtime().block_on(async {
+        let crashy = Crashy::create_supervised(|_ctx| Crashy { delays: Vec::new() });
+        for _ in 0..3 {
+            crashy.send(Kill).await.unwrap();
+        }
+        assert_eq!(
+            crashy.send(Delays).await,
+            Ok(vec![
+                Duration::from_millis(20),
+                Duration::from_millis(40),
+                Duration::from_millis(50)
+            ])
+        );
+    })
+}
+
+#[test]
+fn health_checks() {
+    use crate::health::Ping;
+    use std::time::Duration;
+
+    struct Hang(oneshot::Sender<()>);
+    struct Sleeper;
+    impl Actor for Sleeper {}
+    #[async_trait]
+    impl Handler<Hang> for Sleeper {
+        type Response = ();
+        async fn handle(&mut self, msg: Hang, _ctx: &mut ActorContext<Self>) {
+            let _ = msg.0.send(());
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+    }
+
+    get_runtime().block_on(async {
+        let sleeper = Sleeper.start();
+        let health = sleeper.ping(Duration::from_millis(50)).await.unwrap();
+        assert_eq!(health.state, ActorState::Running);
+        assert_eq!(health.queue_depth, 0);
+
+        // Pings jump the queue, so the actor has to be busy already
+        let (busy_tx, busy) = oneshot::channel();
+        sleeper.do_send(Hang(busy_tx));
+        busy.await.unwrap();
+        let err = sleeper.ping(Duration::from_millis(50)).await.unwrap_err();
+        assert!(matches!(err, ActorError::Timeout(_)));
+        assert_eq!(err.context().message_type, std::any::type_name::<Ping>());
+        let health = sleeper.ping(Duration::from_millis(500)).await.unwrap();
+        assert!(health.last_activity.elapsed() < Duration::from_millis(50));
+    })
+}
+
+#[test]
+fn watchdog_aborts_stuck_actors() {
+    use crate::watchdog::*;
+    use std::time::Duration;
+
+    struct Deadlock;
+    struct Restarts;
+    struct Stuck {
+        restarts: Vec<RestartReason>,
+    }
+    impl Actor for Stuck {}
+    #[async_trait]
+    impl Supervised for Stuck {
+        async fn restarting(&mut self, _ctx: &mut ActorContext<Self>, reason: RestartReason) {
+            self.restarts.push(reason);
+        }
+    }
+    #[async_trait]
+    impl Handler<Deadlock> for Stuck {
+        type Response = ();
+        async fn handle(&mut self, _msg: Deadlock, _ctx: &mut ActorContext<Self>) {
+            futures_util::future::pending::<()>().await;
+        }
+    }
+    #[async_trait]
+    impl Handler<Restarts> for Stuck {
+        type Response = Vec<RestartReason>;
+        async fn handle(&mut self, _msg: Restarts, _ctx: &mut ActorContext<Self>) -> Self::Response {
+            self.restarts.clone()
+        }
+    }
+
+    get_runtime().block_on(async {
+        let stuck = Stuck::create_supervised(|_ctx| Stuck { restarts: Vec::new() });
+        let watchdog = Watchdog::new(Duration::from_millis(20), Duration::from_millis(20), 2);
+        let mut events = watchdog.subscribe();
+        let watchdog = watchdog.start();
+        watchdog.send(Watch(stuck.clone())).await.unwrap();
+
+        let err = stuck.send(Deadlock).await.unwrap_err();
+        assert!(matches!(err, ActorError::Aborted(_)));
+        assert!(matches!(events.recv().await, Ok(WatchdogEvent::Missed { failures: 1, .. })));
+        assert!(matches!(events.recv().await, Ok(WatchdogEvent::Missed { failures: 2, .. })));
+        assert_eq!(
+            events.recv().await,
+            Ok(WatchdogEvent::Aborted {
+                actor_id: stuck.id(),
+                actor_type: std::any::type_name::<Stuck>(),
+            })
+        );
+        assert_eq!(
+            stuck.send(Restarts).await,
+            Ok(vec![RestartReason::Aborted {
+                message_type: std::any::type_name::<Deadlock>(),
+            }])
+        );
+        // aborting an idle actor does not abort the next handler
+        stuck.any().abort();
+        assert_eq!(stuck.send(Restarts).await.unwrap().len(), 1);
+    })
+}
+
+#[test]
+fn cyclic_sends_are_detected() {
+    struct Ask;
+    struct Peer {
+        other: Option<Addr<Peer>>,
+    }
+    impl Actor for Peer {}
+    struct Introduce(Addr<Peer>);
+    #[async_trait]
+    impl Handler<Introduce> for Peer {
+        type Response = ();
+        async fn handle(&mut self, msg: Introduce, _ctx: &mut ActorContext<Self>) {
+            self.other = Some(msg.0);
+        }
+    }
+    #[async_trait]
+    impl Handler<Ask> for Peer {
+        type Response = bool;
+        async fn handle(&mut self, _msg: Ask, _ctx: &mut ActorContext<Self>) -> bool {
+            match &self.other {
+                Some(other) => other.send(Ask).await.unwrap_or(false),
+                None => true,
+            }
+        }
+    }
+
+    crate::deadlock::set_detection(true);
+    get_runtime().block_on(async {
+        let a = Peer { other: None }.start();
+        let b = Peer { other: None }.start();
+        a.send(Introduce(b.clone())).await.unwrap();
+        b.send(Introduce(a.clone())).await.unwrap();
+        // b panics instead of waiting for a
+        assert_eq!(a.send(Ask).await, Ok(false));
+        assert!(b.send(Ask).await.is_err());
+    })
+}
+
+#[test]
+fn self_sends_are_rejected() {
+    struct Outer;
+    struct Inner;
+    struct Loner;
+    impl Actor for Loner {}
+    #[async_trait]
+    impl Handler<Outer> for Loner {
+        type Response = Result<(), ActorError>;
+        async fn handle(&mut self, _msg: Outer, ctx: &mut ActorContext<Self>) -> Self::Response {
+            ctx.address().send(Inner).await
+        }
+    }
+    #[async_trait]
+    impl Handler<Inner> for Loner {
+        type Response = ();
+        async fn handle(&mut self, _msg: Inner, _ctx: &mut ActorContext<Self>) {}
+    }
+
+    get_runtime().block_on(async {
+        let loner = Loner.start();
+        let resp = loner.send(Outer).await.unwrap();
+        assert!(matches!(resp, Err(ActorError::Reentrant(_))));
+        assert_eq!(loner.send(Inner).await, Ok(()));
+    })
+}
+
+#[test]
+fn lifecycle_queries() {
+    struct Quit;
+    struct Mortal;
+    impl Actor for Mortal {}
+    #[async_trait]
+    impl Handler<Quit> for Mortal {
+        type Response = ();
+        async fn handle(&mut self, _msg: Quit, ctx: &mut ActorContext<Self>) {
+            ctx.stop();
+        }
+    }
+
+    get_runtime().block_on(async {
+        let mortal = Mortal.start();
+        let weak = mortal.downgrade();
+        mortal.ping(std::time::Duration::from_secs(1)).await.unwrap();
+        assert!(mortal.connected());
+        assert_eq!(mortal.state(), Some(ActorState::Running));
+        mortal.send(Quit).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        assert!(!mortal.connected());
+        assert_eq!(mortal.state(), None);
+        drop(mortal);
+        assert!(!weak.connected());
+        assert_eq!(weak.state(), None);
+    })
+}
+
+#[test]
+fn weak_addr_sends() {
+    struct Hello;
+    struct Subscriber;
+    impl Actor for Subscriber {}
+    #[async_trait]
+    impl Handler<Hello> for Subscriber {
+        type Response = &'static str;
+        async fn handle(&mut self, _msg: Hello, _ctx: &mut ActorContext<Self>) -> &'static str {
+            "hi"
+        }
+    }
+
+    get_runtime().block_on(async {
+        let subscriber = Subscriber.start();
+        let weak = subscriber.downgrade();
+        assert_eq!(weak.send(Hello).await, Ok("hi"));
+        assert_eq!(weak.try_send(Hello), Ok(()));
+        let id = subscriber.id();
+        drop(subscriber);
+        let context = crate::error::ErrorContext {
+            actor_id: id,
+            actor_type: std::any::type_name::<Subscriber>(),
+            message_type: std::any::type_name::<Hello>(),
+        };
+        assert_eq!(weak.send(Hello).await, Err(ActorError::CannotSend(context)));
+        assert_eq!(weak.try_send(Hello), Err(ActorError::CannotSend(context)));
+        weak.do_send(Hello);
+    })
+}
+
+#[test]
+fn context_diagnostics() {
+    use crate::context::Diagnostics;
+
+    struct Inspect;
+    struct Tick;
+    struct Inspected;
+    impl Actor for Inspected {
+        const MAILBOX_CAPACITY: Option<usize> = Some(8);
+    }
+    #[async_trait]
+    impl Handler<Tick> for Inspected {
+        type Response = ();
+        async fn handle(&mut self, _msg: Tick, _ctx: &mut ActorContext<Self>) {}
+    }
+    #[async_trait]
+    impl Handler<Inspect> for Inspected {
+        type Response = (Diagnostics, String);
+        async fn handle(&mut self, _msg: Inspect, ctx: &mut ActorContext<Self>) -> Self::Response {
+            ctx.add_stream(futures_util::stream::pending::<Tick>());
+            (ctx.diagnostics(), format!("{:?}", ctx))
+        }
+    }
+
+    get_runtime().block_on(async {
+        let inspected = Inspected.start();
+        let (diagnostics, debug) = inspected.send(Inspect).await.unwrap();
+        assert_eq!(diagnostics.actor_id, inspected.id());
+        assert_eq!(diagnostics.state, ActorState::Running);
+        assert!(diagnostics.current_message_id.is_some());
+        assert_eq!(diagnostics.mailbox_capacity, Some(8));
+        assert_eq!(diagnostics.streams, 1);
+        assert_eq!(diagnostics.blocking_tasks, 0);
+        assert!(debug.starts_with("ActorContext"));
+    })
+}
+
+#[test]
+fn router_members() {
+    use std::time::Duration;
+
+    struct WhoAreYou;
+    #[derive(Clone)]
+    struct Flush;
+    struct Member {
+        flushed: bool,
+    }
+    impl Actor for Member {}
+    #[async_trait]
+    impl Handler<WhoAreYou> for Member {
+        type Response = (ActorId, bool);
+        async fn handle(&mut self, _msg: WhoAreYou, ctx: &mut ActorContext<Self>) -> Self::Response {
+            (ctx.id(), self.flushed)
+        }
+    }
+    #[async_trait]
+    impl Handler<Flush> for Member {
+        type Response = ();
+        async fn handle(&mut self, _msg: Flush, _ctx: &mut ActorContext<Self>) {
+            self.flushed = true;
+        }
+    }
+
+    get_runtime().block_on(async {
+        let router = Router::spawn(3, || Member { flushed: false });
+        assert_eq!(router.len(), 3);
+        let members: Vec<ActorId> = router.members().map(|member| member.id()).collect();
+        let mut seen = Vec::new();
+        for _ in 0..3 {
+            seen.push(router.send(WhoAreYou).await.unwrap().0);
+        }
+        seen.sort();
+        assert_eq!(seen, members);
+
+        router.broadcast(Flush);
+        for member in router.members() {
+            assert_eq!(member.send(WhoAreYou).await, Ok((member.id(), true)));
+        }
+        let health = router.health(Duration::from_secs(1)).await;
+        assert!(health.iter().all(|(_, health)| health.is_ok()));
+    })
+}
+
+#[test]
+fn router_resizing() {
+    use crate::router::*;
+    use std::time::Duration;
+
+    struct Work;
+    struct Member;
+    impl Actor for Member {}
+    #[async_trait]
+    impl Handler<Work> for Member {
+        type Response = ();
+        async fn handle(&mut self, _msg: Work, _ctx: &mut ActorContext<Self>) {
+            tokio::time::sleep(Duration::from_millis(30)).await;
+        }
+    }
+
+    get_runtime().block_on(async {
+        let router = Router::spawn(2, || Member);
+        assert_eq!(router.resize(4), 4);
+        let removed: Vec<_> = router.members().skip(1).collect();
+        router.do_send(Work);
+        router.do_send(Work);
+        assert_eq!(router.resize(1), 1);
+        // removed members drain their' mailboxes before stopping
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(removed.iter().all(|member| !member.connected()));
+        assert_eq!(router.resize(0), 1);
+
+        let fixed = Router::new(router.members());
+        assert_eq!(fixed.resize(3), 1);
+
+        router.autoscale(
+            QueueDepthScaling {
+                min: 1,
+                max: 3,
+                grow_above: 2,
+                shrink_below: 1,
+            },
+            Duration::from_millis(10),
+        );
+        for _ in 0..20 {
+            router.do_send(Work);
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(router.len() > 1);
+    })
+}
+
+#[test]
+fn router_shared_dispatch() {
+    use crate::router::Dispatch;
+    use std::time::{Duration, Instant};
+
+    struct Job(u64);
+    struct Member;
+    impl Actor for Member {}
+    #[async_trait]
+    impl Handler<Job> for Member {
+        type Response = ();
+        async fn handle(&mut self, msg: Job, _ctx: &mut ActorContext<Self>) {
+            tokio::time::sleep(Duration::from_millis(msg.0)).await;
+        }
+    }
+
+    get_runtime().block_on(async {
+        let router = Router::spawn(2, || Member).with_dispatch(Dispatch::Shared);
+        let started = Instant::now();
+        let fast = async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            for _ in 0..4 {
+                router.send(Job(10)).await.unwrap();
+            }
+            started.elapsed()
+        };
+        let (slow, fast) = futures_util::join!(router.send(Job(300)), fast);
+        slow.unwrap();
+        // the fast jobs did not wait for the slow one
+        assert!(fast < Duration::from_millis(200));
+        assert!(router.try_send(Job(300)).is_ok());
+        assert!(router.try_send(Job(300)).is_ok());
+        assert!(matches!(router.try_send(Job(0)), Err(ActorError::MailboxFull(_))));
+    })
+}
+
+#[test]
+fn router_shared_dispatch_without_members() {
+    use crate::router::Dispatch;
+
+    struct Hold(oneshot::Receiver<()>);
+    struct Member;
+    impl Actor for Member {}
+    #[async_trait]
+    impl Handler<Hold> for Member {
+        type Response = ();
+        async fn handle(&mut self, msg: Hold, _ctx: &mut ActorContext<Self>) {
+            let _ = msg.0.await;
+        }
+    }
+
+    get_runtime().block_on(async {
+        let member = Member.start();
+        let router = Router::new([member.clone()]).with_dispatch(Dispatch::Shared);
+        let (_release, hold) = oneshot::channel();
+        let busy = tokio::spawn({
+            let router = router.clone();
+            async move { router.send(Hold(hold)).await }
+        });
+        tokio::task::yield_now().await;
+        // Waits for the busy member, which stops before becoming idle
+        let waiting = tokio::spawn({
+            let router = router.clone();
+            async move { router.send(Hold(oneshot::channel().1)).await }
+        });
+        tokio::task::yield_now().await;
+        member.stop(StopMode::Abandon);
+        assert!(busy.await.unwrap().is_err());
+        assert!(matches!(waiting.await.unwrap(), Err(ActorError::CannotSend(_))));
+        let sent = router.send(Hold(oneshot::channel().1)).await;
+        assert!(matches!(sent, Err(ActorError::CannotSend(_))));
+        let sent = router.try_send(Hold(oneshot::channel().1));
+        assert!(matches!(sent, Err(ActorError::CannotSend(_))));
+    })
+}
+
+#[test]
+fn router_sticky_sessions() {
+    use crate::actor::ActorId;
+    use std::time::Duration;
+
+    struct WhoAmI;
+    struct Member;
+    impl Actor for Member {}
+    #[async_trait]
+    impl Handler<WhoAmI> for Member {
+        type Response = ActorId;
+        async fn handle(&mut self, _msg: WhoAmI, ctx: &mut ActorContext<Self>) -> ActorId {
+            ctx.id()
+        }
+    }
+
+    get_runtime().block_on(async {
+        let router = Router::spawn(3, || Member).with_session_timeout(Duration::from_millis(100));
+        let first = router.send_sticky(&"alice", WhoAmI).await.unwrap();
+        let other = router.send_sticky(&"bob", WhoAmI).await.unwrap();
+        assert_ne!(first, other);
+        for _ in 0..5 {
+            assert_eq!(router.send_sticky(&"alice", WhoAmI).await.unwrap(), first);
+            // unrelated traffic does not move the pin
+            router.send(WhoAmI).await.unwrap();
+        }
+        // the pin expires when idle
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let repinned = router.send_sticky(&"alice", WhoAmI).await.unwrap();
+        // the pinned member fails
+        let member = router.members().find(|m| m.id() == repinned).unwrap();
+        member.stop(StopMode::Abandon);
+        while member.connected() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        let moved = router.send_sticky(&"alice", WhoAmI).await.unwrap();
+        assert_ne!(moved, repinned);
+        assert_eq!(router.send_sticky(&"alice", WhoAmI).await.unwrap(), moved);
+    })
+}
+
+#[test]
+fn control_messages_bypass_the_queue() {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use std::time::{Duration, Instant};
+
+    struct Work;
+    struct Busy {
+        handled: Arc<AtomicUsize>,
+    }
+    impl Actor for Busy {}
+    #[async_trait]
+    impl Handler<Work> for Busy {
+        type Response = ();
+        async fn handle(&mut self, _msg: Work, _ctx: &mut ActorContext<Self>) {
+            tokio::time::sleep(Duration::from_millis(2)).await;
+            self.handled.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    get_runtime().block_on(async {
+        let handled = Arc::new(AtomicUsize::new(0));
+        let busy = Busy {
+            handled: handled.clone(),
+        }
+        .start();
+        for _ in 0..1000 {
+            busy.do_send(Work);
+        }
+        // answered long before the queue gets through
+        let health = busy.ping(Duration::from_millis(500)).await.unwrap();
+        assert!(health.queue_depth > 500);
+
+        let started = Instant::now();
+        busy.stop(StopMode::Abandon);
+        while busy.connected() {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        assert!(started.elapsed() < Duration::from_millis(500));
+        assert!(handled.load(Ordering::Relaxed) < 500);
+    })
+}
+
+#[test]
+fn batched_sends() {
+    struct Add(u64);
+    struct Total;
+    struct Summer(u64);
+    impl Actor for Summer {
+        const MAILBOX_CAPACITY: Option<usize> = Some(1000);
+    }
+    #[async_trait]
+    impl Handler<Add> for Summer {
+        type Response = ();
+        async fn handle(&mut self, msg: Add, _ctx: &mut ActorContext<Self>) {
+            self.0 += msg.0;
+        }
+    }
+    #[async_trait]
+    impl Handler<Total> for Summer {
+        type Response = u64;
+        async fn handle(&mut self, _msg: Total, _ctx: &mut ActorContext<Self>) -> u64 {
+            self.0
+        }
+    }
+
+    get_runtime().block_on(async {
+        let summer = Summer(0).start();
+        summer.do_send_many((1..=100).map(Add));
+        assert_eq!(summer.send(Total).await.unwrap(), 5050);
+        // only the messages which fit in the mailbox get enqueued
+        let summer = Summer(0).start();
+        summer.do_send_many((0..5000).map(|_| Add(1)));
+        let total = loop {
+            match summer.send(Total).await {
+                Err(ActorError::MailboxFull(_)) => tokio::task::yield_now().await,
+                total => break total.unwrap(),
+            }
+        };
+        assert_eq!(total, 1000);
+    })
+}
+
+#[test]
+fn mailbox_byte_budget() {
+    struct Hold(Option<oneshot::Sender<()>>, oneshot::Receiver<()>);
+    struct Blob(Vec<u8>);
+    struct Storage;
+    impl Actor for Storage {
+        const MAILBOX_BYTES: Option<usize> = Some(1024);
+    }
+    #[async_trait]
+    impl Handler<Hold> for Storage {
+        type Response = ();
+        async fn handle(&mut self, msg: Hold, _ctx: &mut ActorContext<Self>) {
+            if let Some(busy) = msg.0 {
+                let _ = busy.send(());
+            }
+            let _ = msg.1.await;
+        }
+    }
+    #[async_trait]
+    impl Handler<Blob> for Storage {
+        type Response = usize;
+        async fn handle(&mut self, msg: Blob, _ctx: &mut ActorContext<Self>) -> usize {
+            msg.0.len()
+        }
+        fn message_size(msg: &Blob) -> usize {
+            msg.0.len()
+        }
+    }
+
+    get_runtime().block_on(async {
+        let storage = Storage.start();
+        let (busy_tx, busy) = oneshot::channel();
+        let (tx, rx) = oneshot::channel();
+        let held = storage.send(Hold(Some(busy_tx), rx));
+        let blobs = async {
+            // The actor has taken Hold out of the mailbox
+            busy.await.unwrap();
+            // a single oversized message still gets through
+            let oversized = storage.send(Blob(vec![0; 4096]));
+            let (oversized, ()) = futures_util::join!(oversized, async {
+                assert!(matches!(
+                    storage.try_send(Blob(vec![0; 16])),
+                    Err(ActorError::MailboxFull(_))
+                ));
+                tx.send(()).unwrap();
+            });
+            // Handled, so no longer taking up the budget
+            assert_eq!(oversized.unwrap(), 4096);
+            assert_eq!(storage.send(Blob(vec![0; 600])).await.unwrap(), 600);
+
+            let (tx, rx) = oneshot::channel();
+            storage.do_send(Hold(None, rx));
+            assert!(storage.try_send(Blob(vec![0; 600])).is_ok());
+            assert!(matches!(
+                storage.try_send(Blob(vec![0; 600])),
+                Err(ActorError::MailboxFull(_))
+            ));
+            assert!(storage.try_send(Blob(vec![0; 200])).is_ok());
+            tx.send(()).unwrap();
+            assert_eq!(storage.send(Blob(vec![0; 16])).await.unwrap(), 16);
+        };
+        let (held, ()) = futures_util::join!(held, blobs);
+        held.unwrap();
+    })
+}
+
+#[test]
+fn memory_footprints() {
+    use crate::footprint::{self, MemoryFootprint};
+    use std::time::Duration;
+
+    struct Cache(Vec<u64>);
+    impl Actor for Cache {}
+    impl MemoryFootprint for Cache {
+        fn heap_size(&self) -> usize {
+            self.0.capacity() * std::mem::size_of::<u64>()
+        }
+    }
+
+    get_runtime().block_on(async {
+        let small = Cache(Vec::with_capacity(10)).start();
+        let large = Cache(Vec::with_capacity(1000)).start();
+        footprint::track(&small);
+        footprint::track(&large);
+        let report = footprint::report(Duration::from_millis(500)).await;
+        let bytes_of = |addr: &Addr<Cache>| {
+            report
+                .iter()
+                .find(|footprint| footprint.actor_id == addr.id())
+                .map(|footprint| footprint.bytes)
+        };
+        let base = std::mem::size_of::<Cache>();
+        assert_eq!(bytes_of(&small), Some(base + 80));
+        assert_eq!(bytes_of(&large), Some(base + 8000));
+        assert!(footprint::total(Duration::from_millis(500)).await >= 2 * base + 8080);
+
+        // stopped actors are no longer reported
+        let id = large.id();
+        drop(large);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let report = footprint::report(Duration::from_millis(500)).await;
+        assert!(report.iter().all(|footprint| footprint.actor_id != id));
+    })
+}
+
+#[test]
+fn record_and_replay() {
+    use crate::record::{self, Recordable, RecordingSender};
+
+    #[derive(Debug, PartialEq)]
+    enum Door {
+        Open,
+        Close,
+        Label(String),
+    }
+    impl Recordable for Door {
+        fn encode(&self) -> String {
+            match self {
+                Door::Open => "open".into(),
+                Door::Close => "close".into(),
+                Door::Label(label) => format!("label {}", label),
+            }
+        }
+        fn decode(text: &str) -> Option<Self> {
+            match text {
+                "open" => Some(Door::Open),
+                "close" => Some(Door::Close),
+                _ => Some(Door::Label(text.strip_prefix("label ")?.to_string())),
+            }
+        }
+    }
+    #[derive(Default)]
+    struct Machine {
+        open: bool,
+        label: String,
+    }
+    impl Actor for Machine {}
+    #[async_trait]
+    impl Handler<Door> for Machine {
+        type Response = (bool, String);
+        async fn handle(&mut self, msg: Door, _ctx: &mut ActorContext<Self>) -> (bool, String) {
+            match msg {
+                Door::Open => self.open = true,
+                Door::Close => self.open = false,
+                Door::Label(label) => self.label = label,
+            }
+            (self.open, self.label.clone())
+        }
+    }
+
+    let path = std::env::temp_dir().join(format!("aspartam-record-{}.txt", std::process::id()));
+    get_runtime().block_on(async {
+        let recording = RecordingSender::create(Machine::default().start(), &path).unwrap();
+        recording.send(Door::Open).await.unwrap();
+        recording.do_send(Door::Label("front\ndoor \\ 1".into()));
+        let live = recording.send(Door::Close).await.unwrap();
+        // Bypasses the recording
+        recording.addr().send(Door::Open).await.unwrap();
+        recording.flush().await.unwrap();
+
+        let recorded = record::load::<Door>(&path).unwrap();
+        assert_eq!(recorded.len(), 3);
+        assert_eq!(recorded[1].1, Door::Label("front\ndoor \\ 1".into()));
+        assert!(recorded.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+
+        let fresh = Machine::default().start();
+        let replayed = record::replay::<_, Door>(&path, &fresh).await.unwrap();
+        assert_eq!(replayed.len(), 3);
+        assert_eq!(*replayed[2].as_ref().unwrap(), live);
+    });
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn fuzzing_finds_invariant_violations() {
+    use crate::testing::fuzz::{self, Fuzz, Gen};
+
+    #[derive(Debug)]
+    enum Op {
+        Inc,
+        Dec,
+    }
+    struct Counter {
+        value: u8,
+        saturating: bool,
+    }
+    impl Actor for Counter {}
+    #[async_trait]
+    impl Handler<Op> for Counter {
+        type Response = ();
+        async fn handle(&mut self, msg: Op, _ctx: &mut ActorContext<Self>) {
+            self.value = match (msg, self.saturating) {
+                (Op::Inc, _) => self.value.saturating_add(1),
+                (Op::Dec, true) => self.value.saturating_sub(1),
+                (Op::Dec, false) => self.value.wrapping_sub(1),
+            }
+        }
+    }
+    let generate = |gen: &mut Gen| if gen.flip() { Op::Inc } else { Op::Dec };
+    let invariant = |counter: &Counter| match counter.value {
+        0..=100 => Ok(()),
+        value => Err(format!("value {} out of range", value)),
+    };
+
+    get_runtime().block_on(async {
+        let correct = || Counter {
+            value: 0,
+            saturating: true,
+        };
+        fuzz::run(correct, generate, invariant).await.unwrap();
+
+        let buggy = || Counter {
+            value: 0,
+            saturating: false,
+        };
+        let failure = fuzz::run(buggy, generate, invariant).await.unwrap_err();
+        assert_eq!(failure.history.last().unwrap(), "Dec");
+        assert_eq!(failure.reason, "value 255 out of range");
+        // the failing run can be reproduced from its' seed
+        let config = Fuzz {
+            seed: failure.seed,
+            runs: 1,
+            ..Default::default()
+        };
+        let again = config.run(buggy, generate, invariant).await.unwrap_err();
+        assert_eq!(again, failure);
+    });
+
+    // no seed leaves the generator stuck on a single value
+    for seed in [0, 0x9E37_79B9_7F4A_7C15, u64::MAX] {
+        let mut gen = Gen::new(seed);
+        let values = (0..8).map(|_| gen.next_u64()).collect::<std::collections::HashSet<_>>();
+        assert_eq!(values.len(), 8);
+    }
+}
+
+#[test]
+fn deterministic_runs() {
+    use crate::testing::run_deterministic;
+    use std::time::Duration;
+
+    struct Tick(u32);
+    struct Log(Vec<u32>);
+    #[async_trait]
+    impl Actor for Log {
+        async fn started(&mut self, ctx: &mut ActorContext<Self>) {
+            ctx.add_stream(futures_util::stream::iter(100..120).map(Tick));
+        }
+    }
+    #[async_trait]
+    impl Handler<Tick> for Log {
+        type Response = ();
+        async fn handle(&mut self, msg: Tick, _ctx: &mut ActorContext<Self>) {
+            self.0.push(msg.0);
+        }
+    }
+    struct Dump;
+    #[async_trait]
+    impl Handler<Dump> for Log {
+        type Response = Vec<u32>;
+        async fn handle(&mut self, _msg: Dump, _ctx: &mut ActorContext<Self>) -> Vec<u32> {
+            self.0.clone()
+        }
+    }
+
+    let session = |seed| {
+        run_deterministic(seed, async {
+            // a stream racing with direct sends
+            let log = Log(Vec::new()).start();
+            for i in 0..20 {
+                log.do_send(Tick(i));
+                tokio::task::yield_now().await;
+            }
+            while log.send(Dump).await.unwrap().len() < 40 {
+                tokio::task::yield_now().await;
+            }
+            let jitter = Backoff::DEFAULT.delay(5);
+            (log.send(Dump).await.unwrap(), jitter)
+        })
+    };
+    let (order, jitter) = session(7);
+    assert_eq!(order.len(), 40);
+    assert_eq!(session(7), (order.clone(), jitter));
+    // other seeds interleave the stream differently
+    assert!((0..8).any(|seed| session(seed).0 != order));
+    assert!(jitter <= Duration::from_millis(320));
+
+    // a panicking run does not leave its' seed behind
+    let panicked = std::panic::catch_unwind(|| run_deterministic(7, async { panic!("boom") }));
+    assert!(panicked.is_err());
+    assert_ne!(Backoff::DEFAULT.delay(5), jitter);
+}
+
+#[test]
+fn inspecting_generic_handlers() {
+    use crate::testing::inspect;
+
+    // Handles every message, which leaves no room for handlers added by the framework
+    struct Counter(usize);
+    impl Actor for Counter {}
+    #[async_trait]
+    impl<M: 'static + Send> Handler<M> for Counter {
+        type Response = ();
+        async fn handle(&mut self, _msg: M, _ctx: &mut ActorContext<Self>) {
+            self.0 += 1;
+        }
+    }
+
+    get_runtime().block_on(async {
+        let counter = Counter(0).start();
+        counter.send(()).await.unwrap();
+        counter.send("hello").await.unwrap();
+        assert_eq!(inspect(&counter, |counter: &Counter| counter.0).await.unwrap(), 2);
+        // Inspecting is not a message of its' own
+        assert_eq!(inspect(&counter, |counter: &Counter| counter.0).await.unwrap(), 2);
+        counter.stop(StopMode::Abandon);
+        counter.terminated().await;
+        assert!(inspect(&counter, |counter: &Counter| counter.0).await.is_err());
+    })
+}
+
+#[test]
+fn heterogeneous_addresses() {
+    use std::time::Duration;
+
+    struct First;
+    impl Actor for First {}
+    struct Second;
+    impl Actor for Second {}
+
+    get_runtime().block_on(async {
+        let first = First.start();
+        let actors: Vec<AnyAddr> = vec![first.any(), Second.start().into()];
+        assert_eq!(actors[0].id(), first.id());
+        assert!(actors[1].actor_type().ends_with("Second"));
+        assert!(actors[0].downcast::<First>().is_some());
+        assert!(actors[1].downcast::<First>().is_none());
+        for actor in &actors {
+            let health = actor.ping(Duration::from_millis(500)).await.unwrap();
+            assert_eq!(health.state, ActorState::Running);
+            actor.stop(StopMode::Abandon);
+        }
+        for actor in &actors {
+            while actor.connected() {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+            assert_eq!(actor.state(), None);
+        }
+    })
+}
+
+#[test]
+fn actor_sets() {
+    use std::time::Duration;
+
+    struct Crash;
+    struct Worker;
+    impl Actor for Worker {}
+    #[async_trait]
+    impl Handler<Crash> for Worker {
+        type Response = ();
+        async fn handle(&mut self, _msg: Crash, _ctx: &mut ActorContext<Self>) {
+            panic!("crashed")
+        }
+    }
+    struct Other;
+    impl Actor for Other {}
+    // Restarts whenever it stops
+    impl Supervised for Other {}
+
+    get_runtime().block_on(async {
+        let mut set = ActorSet::new();
+        let crashing = set.start(Worker);
+        set.start(Worker);
+        set.insert(Other::create_supervised(|_| Other));
+        assert_eq!(set.len(), 3);
+
+        let _ = crashing.send(Crash).await;
+        let first = set.join_next().await.unwrap();
+        assert_eq!(first.actor_id, crashing.id());
+        assert!(matches!(
+            first.reason,
+            RestartReason::HandlerPanicked { message: Some(ref message), .. } if message == "crashed"
+        ));
+        assert_eq!(set.len(), 2);
+
+        let rest = tokio::time::timeout(Duration::from_secs(1), set.shutdown(StopMode::Abandon))
+            .await
+            .unwrap();
+        assert_eq!(rest.len(), 2);
+        assert!(rest.iter().all(|t| t.reason == RestartReason::Stopped));
+        assert!(set.is_empty());
+        assert!(set.join_next().await.is_none());
+    })
+}
+
+#[test]
+fn bootstrap_ordering() {
+    use crate::bootstrap::{Bootstrap, BootstrapError};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    type Log = Arc<Mutex<Vec<&'static str>>>;
+    struct Service {
+        name: &'static str,
+        log: Log,
+    }
+    #[async_trait]
+    impl Actor for Service {
+        async fn started(&mut self, _ctx: &mut ActorContext<Self>) {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            self.log.lock().unwrap().push(self.name);
+        }
+    }
+    let service = |name, log: &Log| {
+        let log = log.clone();
+        move |_: &_| async move { Service { name, log }.start() }
+    };
+
+    get_runtime().block_on(async {
+        let log = Log::default();
+        let actors = Bootstrap::new()
+            .actor("api", &["db", "cache"], service("api", &log))
+            .actor("cache", &["db"], service("cache", &log))
+            .actor("db", &[], service("db", &log))
+            .actor("metrics", &[], service("metrics", &log))
+            .start()
+            .await
+            .unwrap();
+        assert_eq!(actors.len(), 4);
+        assert!(actors.get::<Service>("api").is_some());
+        let log = log.lock().unwrap().clone();
+        let position = |name| log.iter().position(|n| *n == name).unwrap();
+        assert!(position("db") < position("cache"));
+        assert!(position("cache") < position("api"));
+        // independent actors start concurrently
+        assert!(position("metrics") < position("cache"));
+        assert_eq!(actors.names().last(), Some("api"));
+
+        let log = Log::default();
+        let err = Bootstrap::new()
+            .actor("a", &["b"], service("a", &log))
+            .actor("b", &["a"], service("b", &log))
+            .start()
+            .await
+            .unwrap_err();
+        assert!(matches!(err, BootstrapError::Cycle(_)));
+        let err = Bootstrap::new()
+            .actor("a", &["missing"], service("a", &log))
+            .start()
+            .await
+            .unwrap_err();
+        assert!(matches!(err, BootstrapError::UnknownDependency { .. }));
+
+        // a failure stops the actors started so far
+        let db = Arc::new(Mutex::new(None));
+        let seen = db.clone();
+        let err = Bootstrap::new()
+            .actor("db", &[], service("db", &log))
+            .try_actor("broken", &["db"], move |actors| {
+                *seen.lock().unwrap() = actors.get::<Service>("db");
+                async { Err::<Addr<Service>, _>("no connection") }
+            })
+            .start()
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err,
+            BootstrapError::Failed {
+                actor: "broken".into(),
+                reason: "no connection".into()
+            }
+        );
+        let db = db.lock().unwrap().take().unwrap();
+        tokio::time::timeout(Duration::from_secs(1), db.terminated())
+            .await
+            .unwrap();
+    })
+}
+
+#[test]
+fn coordinated_shutdown() {
+    use crate::shutdown::*;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    type Log = Arc<Mutex<Vec<String>>>;
+    struct Job;
+    struct Hang;
+    struct Service {
+        name: &'static str,
+        log: Log,
+    }
+    #[async_trait]
+    impl Actor for Service {
+        async fn stopped(&mut self, _ctx: &mut ActorContext<Self>) {
+            self.log.lock().unwrap().push(format!("{} stopped", self.name));
+        }
+    }
+    #[async_trait]
+    impl Handler<Job> for Service {
+        type Response = ();
+        async fn handle(&mut self, _msg: Job, _ctx: &mut ActorContext<Self>) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            self.log.lock().unwrap().push(format!("{} worked", self.name));
+        }
+    }
+    #[async_trait]
+    impl Handler<Hang> for Service {
+        type Response = ();
+        async fn handle(&mut self, _msg: Hang, _ctx: &mut ActorContext<Self>) {
+            std::future::pending::<()>().await
+        }
+    }
+
+    get_runtime().block_on(async {
+        let log = Log::default();
+        let service = |name| {
+            Service {
+                name,
+                log: log.clone(),
+            }
+            .start()
+        };
+        let timeout = Duration::from_millis(200);
+        let coordinator = ShutdownCoordinator::with_phases(&[
+            (STOP_INGESTION, timeout),
+            (DRAIN_WORKERS, timeout),
+            (FLUSH_SINKS, timeout),
+            (STOP_INFRASTRUCTURE, timeout),
+        ]);
+        let worker = service("worker");
+        worker.do_send(Job);
+        worker.do_send(Job);
+        let pool = service("pool");
+        pool.do_send(Hang);
+        coordinator.register(STOP_INFRASTRUCTURE, pool.clone(), StopMode::Abandon);
+        coordinator.register(DRAIN_WORKERS, worker, StopMode::Drain { deadline: None });
+        coordinator.register(STOP_INGESTION, service("listener"), StopMode::Abandon);
+        let hook_log = log.clone();
+        coordinator.on_phase(FLUSH_SINKS, move || async move {
+            hook_log.lock().unwrap().push("flushed".into());
+        });
+        let triggered = coordinator.triggered();
+        assert!(!triggered.is_cancelled());
+
+        let reports = coordinator.run().await;
+        assert!(triggered.is_cancelled());
+        let names: Vec<_> = reports.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, [STOP_INGESTION, DRAIN_WORKERS, FLUSH_SINKS, STOP_INFRASTRUCTURE]);
+        assert!(reports[..3].iter().all(|r| r.aborted.is_empty()));
+        assert_eq!(reports[3].aborted, [pool.id()]);
+        tokio::time::timeout(Duration::from_secs(1), pool.terminated())
+            .await
+            .unwrap();
+        assert_eq!(
+            *log.lock().unwrap(),
+            [
+                "listener stopped",
+                "worker worked",
+                "worker worked",
+                "worker stopped",
+                "flushed",
+                "pool stopped"
+            ]
+        );
+    })
+}
+
+#[test]
+fn supervision_escalation() {
+    use crate::supervisor::{ChildSpec, RestartIntensity, Strategy, Supervisor};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use std::time::Duration;
+
+    struct Crash;
+    struct Worker(Arc<AtomicUsize>);
+    #[async_trait]
+    impl Actor for Worker {
+        async fn started(&mut self, _ctx: &mut ActorContext<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+    #[async_trait]
+    impl Handler<Crash> for Worker {
+        type Response = ();
+        async fn handle(&mut self, _msg: Crash, _ctx: &mut ActorContext<Self>) {
+            panic!("crashed")
+        }
+    }
+
+    async fn worker(parent: &Addr<Supervisor>) -> (Addr<Supervisor>, Addr<Worker>) {
+        loop {
+            let sub = parent.child::<Supervisor>("sub").await.unwrap();
+            if let Some(sub) = sub {
+                if let Some(worker) = sub.child::<Worker>("worker").await.unwrap() {
+                    return (sub, worker);
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    }
+    async fn crash(worker: Addr<Worker>) {
+        assert!(matches!(
+            worker.send(Crash).await,
+            Err(ActorError::HandlerPanicked(_))
+        ));
+        worker.terminated().await;
+        // Let the supervisors react
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    get_runtime().block_on(async {
+        let starts = Arc::new(AtomicUsize::new(0));
+        let sub_starts = starts.clone();
+        let parent = Supervisor::new(Strategy::OneForOne)
+            .child(ChildSpec::new("sub", move || {
+                let starts = sub_starts.clone();
+                Supervisor::new(Strategy::OneForOne)
+                    .with_intensity(RestartIntensity {
+                        max_restarts: 1,
+                        within: Duration::from_secs(5),
+                    })
+                    .child(ChildSpec::new("worker", move || Worker(starts.clone()).start()))
+                    .start()
+            }))
+            .start();
+
+        let (sub, first) = worker(&parent).await;
+        crash(first.clone()).await;
+        // Restarted by the inner supervisor
+        let (same_sub, second) = worker(&parent).await;
+        assert_eq!(same_sub.id(), sub.id());
+        assert_ne!(second.id(), first.id());
+        assert_eq!(starts.load(Ordering::SeqCst), 2);
+
+        // The inner supervisor gives up, so the parent restarts the whole subtree
+        crash(second).await;
+        let reason = sub.terminated().await;
+        assert!(matches!(
+            reason,
+            RestartReason::Escalated { ref child, ref reason }
+                if child == "worker" && matches!(**reason, RestartReason::HandlerPanicked { .. })
+        ));
+        let (new_sub, _third) = worker(&parent).await;
+        assert_ne!(new_sub.id(), sub.id());
+        assert_eq!(starts.load(Ordering::SeqCst), 3);
+
+        parent.stop(StopMode::Abandon);
+        parent.terminated().await;
+        assert!(!new_sub.connected());
+    })
+}
+
+#[test]
+fn restart_classes() {
+    use crate::supervisor::{ChildSpec, Strategy, Supervisor};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use std::time::Duration;
+
+    struct Finish;
+    struct Crash;
+    struct Job(Arc<AtomicUsize>);
+    #[async_trait]
+    impl Actor for Job {
+        async fn started(&mut self, _ctx: &mut ActorContext<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+    #[async_trait]
+    impl Supervised for Job {
+        const RESTART_BACKOFF: Backoff = Backoff::NONE;
+        const RESTART: Restart = Restart::Transient;
+    }
+    #[async_trait]
+    impl Handler<Finish> for Job {
+        type Response = ();
+        async fn handle(&mut self, _msg: Finish, ctx: &mut ActorContext<Self>) {
+            ctx.stop();
+        }
+    }
+    #[async_trait]
+    impl Handler<Crash> for Job {
+        type Response = ();
+        async fn handle(&mut self, _msg: Crash, _ctx: &mut ActorContext<Self>) {
+            panic!("crashed")
+        }
+    }
+
+    get_runtime().block_on(async {
+        // Supervised actors
+        let starts = Arc::new(AtomicUsize::new(0));
+        let job = Job::create_supervised(|_| Job(starts.clone()));
+        let _ = job.send(Crash).await;
+        job.send(Finish).await.unwrap();
+        assert_eq!(job.terminated().await, RestartReason::Stopped);
+        assert!(!job.connected());
+        assert_eq!(starts.load(Ordering::SeqCst), 2);
+
+        // Children of a supervisor
+        let counters: Vec<_> = (0..3).map(|_| Arc::new(AtomicUsize::new(0))).collect();
+        let spec = |name: &'static str, counter: &Arc<AtomicUsize>, restart| {
+            let counter = counter.clone();
+            ChildSpec::new(name, move || Job(counter.clone()).start()).restart(restart)
+        };
+        let supervisor = Supervisor::new(Strategy::OneForOne)
+            .child(spec("permanent", &counters[0], Restart::Permanent))
+            .child(spec("transient", &counters[1], Restart::Transient))
+            .child(spec("temporary", &counters[2], Restart::Temporary))
+            .start();
+        for name in ["permanent", "transient", "temporary"] {
+            let child = loop {
+                match supervisor.child::<Job>(name).await.unwrap() {
+                    Some(child) => break child,
+                    None => tokio::task::yield_now().await,
+                }
+            };
+            let _ = child.send(Crash).await;
+            child.terminated().await;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        for name in ["permanent", "transient"] {
+            let child = supervisor.child::<Job>(name).await.unwrap().unwrap();
+            child.send(Finish).await.unwrap();
+            child.terminated().await;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let starts: Vec<_> = counters.iter().map(|c| c.load(Ordering::SeqCst)).collect();
+        assert_eq!(starts, [3, 2, 1]);
+        assert!(supervisor.child::<Job>("permanent").await.unwrap().is_some());
+        assert!(supervisor.child::<Job>("transient").await.unwrap().is_none());
+        assert!(supervisor.child::<Job>("temporary").await.unwrap().is_none());
+    })
+}
+
+#[test]
+fn dynamic_supervisor() {
+    use crate::supervisor::DynamicSupervisor;
+    use std::time::Duration;
+
+    struct Crash;
+    struct Session(String);
+    impl Actor for Session {}
+    #[async_trait]
+    impl Handler<Crash> for Session {
+        type Response = ();
+        async fn handle(&mut self, _msg: Crash, _ctx: &mut ActorContext<Self>) {
+            panic!("crashed")
+        }
+    }
+
+    get_runtime().block_on(async {
+        let supervisor = DynamicSupervisor::new(|user: String| Session(user).start())
+            .with_max_children(2)
+            .start();
+        let alice = supervisor.start_child("alice".into()).await.unwrap().unwrap();
+        let bob = supervisor.start_child("bob".into()).await.unwrap().unwrap();
+        assert!(supervisor.start_child("eve".into()).await.unwrap().is_none());
+
+        let _ = alice.send(Crash).await;
+        alice.terminated().await;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let children = supervisor.children().await.unwrap();
+        assert_eq!(children.len(), 2);
+        assert_eq!(children[0].id(), bob.id());
+        let user = crate::testing::inspect(&children[1], |s: &Session| s.0.clone()).await;
+        assert_eq!(user.unwrap(), "alice");
+
+        assert!(supervisor.terminate_child(bob.id()).await.unwrap());
+        assert!(!supervisor.terminate_child(bob.id()).await.unwrap());
+        assert!(!bob.connected());
+        assert_eq!(supervisor.children().await.unwrap().len(), 1);
+
+        supervisor.stop(StopMode::Abandon);
+        supervisor.terminated().await;
+        assert!(!children[1].connected());
+    })
+}
+
+#[test]
+fn demand_driven_streams() {
+    use crate::demand::Demand;
+    use std::sync::{Arc, Mutex};
+
+    struct Tick;
+    #[derive(Default)]
+    struct Numbers {
+        next: u32,
+        demanded: Arc<Mutex<Vec<usize>>>,
+        pending: Option<Demand<u32>>,
+    }
+    impl Actor for Numbers {}
+    impl Numbers {
+        fn produce(&mut self) {
+            let Some(mut demand) = self.pending.take() else {
+                return;
+            };
+            if self.next == 50 {
+                return demand.complete();
+            }
+            demand.emit(self.next).unwrap();
+            self.next += 1;
+            if demand.remaining() > 0 {
+                self.pending = Some(demand);
+            }
+        }
+    }
+    #[async_trait]
+    impl Handler<Demand<u32>> for Numbers {
+        type Response = ();
+        async fn handle(&mut self, msg: Demand<u32>, _ctx: &mut ActorContext<Self>) {
+            self.demanded.lock().unwrap().push(msg.remaining());
+            self.pending = Some(msg);
+        }
+    }
+    #[async_trait]
+    impl Handler<Tick> for Numbers {
+        type Response = ();
+        async fn handle(&mut self, _msg: Tick, _ctx: &mut ActorContext<Self>) {
+            self.produce()
+        }
+    }
+    /// Gives every demand back right away
+    struct Empty(Arc<Mutex<Vec<usize>>>);
+    impl Actor for Empty {}
+    #[async_trait]
+    impl Handler<Demand<u32>> for Empty {
+        type Response = ();
+        async fn handle(&mut self, msg: Demand<u32>, _ctx: &mut ActorContext<Self>) {
+            self.0.lock().unwrap().push(msg.remaining());
+        }
+    }
+
+    get_runtime().block_on(async {
+        let demanded = Arc::new(Mutex::new(Vec::new()));
+        let numbers = Numbers {
+            demanded: demanded.clone(),
+            ..Default::default()
+        }
+        .start();
+        let mut stream = numbers.clone().into_stream::<u32>(16);
+        let ticker = numbers.clone();
+        let ticks = tokio::spawn(async move {
+            // Produces only as much as the consumer asked for, however many ticks arrive
+            while ticker.send(Tick).await.is_ok() {
+                tokio::task::yield_now().await;
+            }
+        });
+        let mut received = Vec::new();
+        while let Some(n) = stream.next().await {
+            received.push(n);
+            assert!(received.len() <= demanded.lock().unwrap().len() * 16);
+        }
+        assert_eq!(received, (0..50).collect::<Vec<_>>());
+        assert_eq!(*demanded.lock().unwrap(), [16, 16, 16, 16]);
+        numbers.stop(StopMode::Abandon);
+        ticks.await.unwrap();
+
+        // demands given back without items are asked for again after a delay instead of right away
+        let demanded = Arc::new(Mutex::new(Vec::new()));
+        let empty = Empty(demanded.clone()).start();
+        let mut stream = empty.into_stream::<u32>(16);
+        let next = tokio::time::timeout(std::time::Duration::from_millis(100), stream.next());
+        next.await.unwrap_err();
+        assert!(demanded.lock().unwrap().len() < 20);
+    })
+}
+
+#[test]
+fn batched_acknowledgements() {
+    use crate::ack::{AckBatcher, Watermark};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    get_runtime().block_on(async {
+        let commits = Arc::new(Mutex::new(Vec::new()));
+        let committed = commits.clone();
+        let nacks = Arc::new(Mutex::new(Vec::new()));
+        let nacked = nacks.clone();
+        let batcher = AckBatcher::new(move |mut watermarks: Vec<Watermark>| {
+            watermarks.sort_by_key(|w| w.partition);
+            committed.lock().unwrap().push(watermarks);
+            async {}
+        })
+        .with_nack(move |watermark| {
+            nacked.lock().unwrap().push(watermark);
+            async {}
+        })
+        .with_batch(3)
+        .with_interval(Duration::from_millis(50))
+        .start();
+        let mut tokens: Vec<_> = (0..4).map(|offset| batcher.track(0, offset)).collect();
+        let other = batcher.track(1, 10);
+        // Out of order, so that offset 0 holds the watermark back
+        tokens.remove(3).ack();
+        tokens.remove(2).ack();
+        tokens.remove(1).ack();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(commits.lock().unwrap().is_empty());
+
+        tokens.remove(0).ack();
+        other.ack();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(
+            *commits.lock().unwrap(),
+            [vec![
+                Watermark {
+                    partition: 0,
+                    offset: 3
+                },
+                Watermark {
+                    partition: 1,
+                    offset: 10
+                }
+            ]]
+        );
+        // Dropped tokens get nacked, so that they do not hold the watermark back
+        drop(batcher.track(0, 4));
+        batcher.track(0, 5).ack();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let watermark = Watermark {
+            partition: 0,
+            offset: 5,
+        };
+        assert_eq!(commits.lock().unwrap()[1..], [vec![watermark]]);
+        assert_eq!(
+            *nacks.lock().unwrap(),
+            [Watermark {
+                partition: 0,
+                offset: 4
+            }]
+        );
+    })
+}
+
+#[test]
+fn outbox_delivery() {
+    use crate::outbox::{MemoryStore, Outbox};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    struct Deposit(u32);
+    struct Account {
+        balance: u32,
+        outbox: Outbox<MemoryStore<String>>,
+    }
+    impl Actor for Account {}
+    #[async_trait]
+    impl Handler<Deposit> for Account {
+        type Response = ();
+        async fn handle(&mut self, msg: Deposit, _ctx: &mut ActorContext<Self>) {
+            self.balance += msg.0;
+            let effect = format!("deposited {}, balance {}", msg.0, self.balance);
+            self.outbox.push(vec![effect]).await.unwrap();
+        }
+    }
+
+    get_runtime().block_on(async {
+        let outbox = Outbox::new(MemoryStore::new());
+        let delivered = Arc::new(Mutex::new(Vec::new()));
+        let failures = Arc::new(Mutex::new(2));
+        let (sink, remaining) = (delivered.clone(), failures.clone());
+        let _forwarder = outbox
+            .forwarder(move |effect: String| {
+                let mut remaining = remaining.lock().unwrap();
+                let ret = if *remaining > 0 {
+                    *remaining -= 1;
+                    Err(())
+                } else {
+                    sink.lock().unwrap().push(effect);
+                    Ok(())
+                };
+                async move { ret }
+            })
+            .with_backoff(Backoff {
+                base: Duration::from_millis(5),
+                max: Duration::from_millis(20),
+                jitter: 0.0,
+                reset_after: Duration::ZERO,
+            })
+            .start();
+        let account = Account {
+            balance: 0,
+            outbox: outbox.clone(),
+        }
+        .start();
+        account.send(Deposit(10)).await.unwrap();
+        account.send(Deposit(5)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(*failures.lock().unwrap(), 0);
+        assert_eq!(
+            *delivered.lock().unwrap(),
+            ["deposited 10, balance 10", "deposited 5, balance 15"]
+        );
+        assert!(outbox.store().is_empty());
+    })
+}
+
+#[test]
+fn durable_workflows() {
+    use crate::workflow::{MemoryProgress, Progress, ProgressStore, Workflow, WorkflowError};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    type Log = Arc<Mutex<Vec<String>>>;
+    fn workflow(
+        id: &str,
+        store: &Arc<MemoryProgress>,
+        log: &Log,
+        hang: Option<&'static str>,
+        fail: bool,
+    ) -> Workflow<&'static str, MemoryProgress> {
+        let step = |name: &'static str| {
+            let log = log.clone();
+            Step::new(name, move || {
+                log.lock().unwrap().push(name.to_string());
+                let hanging = hang == Some(name);
+                async move {
+                    if hanging {
+                        std::future::pending::<()>().await;
+                    }
+                    if fail && name == "ship" {
+                        return Err("out of stock");
+                    }
+                    Ok(())
+                }
+            })
+        };
+        let undo_log = log.clone();
+        Workflow::new(id, store.clone())
+            .step(step("charge").compensate(move || {
+                undo_log.lock().unwrap().push("refund".into());
+                let hanging = hang == Some("refund");
+                async move {
+                    if hanging {
+                        std::future::pending::<()>().await;
+                    }
+                }
+            }))
+            .step(step("ship"))
+            .step(step("notify"))
+    }
+
+    get_runtime().block_on(async {
+        let store = Arc::new(MemoryProgress::new());
+        let log = Log::default();
+        // The process "stops" in the middle of the second step
+        let mut interrupted = workflow("order-1", &store, &log, Some("ship"), false);
+        assert!(tokio::time::timeout(Duration::from_millis(20), interrupted.run())
+            .await
+            .is_err());
+        assert_eq!(
+            store.load("order-1").await.unwrap(),
+            Some(Progress::Running { next_step: 1 })
+        );
+        let mut resumed = workflow("order-1", &store, &log, None, false);
+        resumed.run().await.unwrap();
+        resumed.run().await.unwrap();
+        assert_eq!(*log.lock().unwrap(), ["charge", "ship", "ship", "notify"]);
+        assert_eq!(store.load("order-1").await.unwrap(), Some(Progress::Completed));
+
+        log.lock().unwrap().clear();
+        let mut failing = workflow("order-2", &store, &log, None, true);
+        let err = failing.run().await.unwrap_err();
+        assert!(matches!(err, WorkflowError::Failed(ref e) if e.step == 1));
+        assert_eq!(
+            failing.run().await.unwrap_err(),
+            WorkflowError::AlreadyCompensated { failed_step: 1 }
+        );
+        assert_eq!(*log.lock().unwrap(), ["charge", "ship", "refund"]);
+
+        // The process "stops" in the middle of the compensations
+        log.lock().unwrap().clear();
+        let mut interrupted = workflow("order-3", &store, &log, Some("refund"), true);
+        assert!(tokio::time::timeout(Duration::from_millis(20), interrupted.run())
+            .await
+            .is_err());
+        let compensating = Progress::Compensating {
+            failed_step: 1,
+            remaining: 1,
+        };
+        assert_eq!(store.load("order-3").await.unwrap(), Some(compensating));
+        let mut resumed = workflow("order-3", &store, &log, None, true);
+        assert_eq!(
+            resumed.run().await.unwrap_err(),
+            WorkflowError::AlreadyCompensated { failed_step: 1 }
+        );
+        assert_eq!(*log.lock().unwrap(), ["charge", "ship", "refund", "refund"]);
+        let compensated = Progress::Compensated { failed_step: 1 };
+        assert_eq!(store.load("order-3").await.unwrap(), Some(compensated));
+    })
+}
+
+#[test]
+fn request_pipelines() {
+    use crate::response::RequestExt;
+
+    struct Get;
+    struct Put(u32);
+    struct Cell(u32);
+    impl Actor for Cell {}
+    #[async_trait]
+    impl Handler<Get> for Cell {
+        type Response = u32;
+        async fn handle(&mut self, _msg: Get, _ctx: &mut ActorContext<Self>) -> u32 {
+            self.0
+        }
+    }
+    #[async_trait]
+    impl Handler<Put> for Cell {
+        type Response = ();
+        async fn handle(&mut self, msg: Put, _ctx: &mut ActorContext<Self>) {
+            self.0 = msg.0
+        }
+    }
+
+    get_runtime().block_on(async {
+        let source = Cell(20).start();
+        let target = Cell(0).start();
+        let copied = source
+            .send(Get)
+            .map_response(|v| v * 2)
+            .and_then_response(|v| target.send(Put(v)))
+            .and_then_response(|_| target.send(Get))
+            .await;
+        assert_eq!(copied.unwrap(), 40);
+
+        let stopped = Cell(0).start();
+        stopped.stop(StopMode::Abandon);
+        stopped.terminated().await;
+        let failed = stopped
+            .send(Get)
+            .and_then_response(|v| target.send(Put(v)))
+            .await;
+        assert!(failed.is_err());
+        assert_eq!(target.send(Get).await.unwrap(), 40);
+    })
+}
+
+#[test]
+fn racing_replicas() {
+    use crate::race::race;
+    use futures_util::future::{select, Either};
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+    use std::time::Duration;
+
+    #[derive(Clone)]
+    struct Query;
+    struct Replica {
+        delay: Duration,
+        cancelled: Arc<AtomicBool>,
+    }
+    impl Actor for Replica {}
+    #[async_trait]
+    impl Handler<Query> for Replica {
+        type Response = Duration;
+        async fn handle(&mut self, _msg: Query, ctx: &mut ActorContext<Self>) -> Duration {
+            let cancellation = ctx.request_cancellation().unwrap();
+            let sleep = std::pin::pin!(tokio::time::sleep(self.delay));
+            let cancelled = std::pin::pin!(cancellation.cancelled());
+            if let Either::Right(_) = select(sleep, cancelled).await {
+                self.cancelled.store(true, Ordering::SeqCst);
+            }
+            self.delay
+        }
+    }
+
+    get_runtime().block_on(async {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let replica = |millis| {
+            Replica {
+                delay: Duration::from_millis(millis),
+                cancelled: cancelled.clone(),
+            }
+            .start()
+        };
+        let down = replica(0);
+        down.stop(StopMode::Abandon);
+        down.terminated().await;
+        let replicas = [down, replica(500), replica(10)];
+        let fastest = race(&replicas, Query).await.unwrap();
+        assert_eq!(fastest, Duration::from_millis(10));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(cancelled.load(Ordering::SeqCst));
+        assert!(race(&replicas[..1], Query).await.is_err());
+    })
+}
+
+#[test]
+fn hedged_requests() {
+    use crate::race::{Hedge, HedgeStats};
+    use std::time::Duration;
+
+    #[derive(Clone)]
+    struct Query;
+    struct Replica(Duration);
+    impl Actor for Replica {}
+    #[async_trait]
+    impl Handler<Query> for Replica {
+        type Response = Duration;
+        async fn handle(&mut self, _msg: Query, _ctx: &mut ActorContext<Self>) -> Duration {
+            tokio::time::sleep(self.0).await;
+            self.0
+        }
+    }
+
+    get_runtime().block_on(async {
+        let replica = |millis| Replica(Duration::from_millis(millis)).start();
+        let hedge = Hedge::new(Duration::from_millis(30));
+        // The primary answers in time
+        let fast = [replica(5), replica(1)];
+        assert_eq!(hedge.send(&fast, Query).await.unwrap(), Duration::from_millis(5));
+        // The primary is too slow, so the secondary gets asked as well
+        let slow = [replica(500), replica(5)];
+        assert_eq!(hedge.send(&slow, Query).await.unwrap(), Duration::from_millis(5));
+        // The primary is down, so the secondary gets asked right away
+        let down = replica(0);
+        down.stop(StopMode::Abandon);
+        down.terminated().await;
+        let started = std::time::Instant::now();
+        let failover = [down, replica(1)];
+        assert!(hedge.send(&failover, Query).await.is_ok());
+        assert!(started.elapsed() < Duration::from_millis(30));
+
+        let stats = hedge.stats();
+        assert_eq!(
+            stats,
+            HedgeStats {
+                requests: 3,
+                hedged: 2,
+                hedge_wins: 2
+            }
+        );
+        assert!((stats.hedge_rate() - 2.0 / 3.0).abs() < 1e-9);
+    })
+}
+
+#[test]
+fn cached_queries() {
+    use crate::cache::{CacheKey, CachedRecipient, Invalidations};
+    use std::time::Duration;
+
+    struct Get(&'static str);
+    impl CacheKey for Get {
+        type Key = &'static str;
+        fn cache_key(&self) -> &'static str {
+            self.0
+        }
+    }
+    struct Set(&'static str, u32);
+    struct Store {
+        queries: u32,
+        value: u32,
+        invalidations: Invalidations<&'static str>,
+    }
+    impl Actor for Store {}
+    #[async_trait]
+    impl Handler<Get> for Store {
+        type Response = (u32, u32);
+        async fn handle(&mut self, _msg: Get, _ctx: &mut ActorContext<Self>) -> (u32, u32) {
+            self.queries += 1;
+            (self.value, self.queries)
+        }
+    }
+    #[async_trait]
+    impl Handler<Set> for Store {
+        type Response = ();
+        async fn handle(&mut self, msg: Set, _ctx: &mut ActorContext<Self>) {
+            self.value = msg.1;
+            self.invalidations.invalidate(msg.0);
+        }
+    }
+
+    get_runtime().block_on(async {
+        let invalidations = Invalidations::new();
+        let store = Store {
+            queries: 0,
+            value: 1,
+            invalidations: invalidations.clone(),
+        }
+        .start();
+        let cached = CachedRecipient::new(store.recipient(), 2, Duration::from_millis(50))
+            .with_invalidations(&invalidations);
+        assert_eq!(cached.send(Get("a")).await.unwrap(), (1, 1));
+        assert_eq!(cached.send(Get("a")).await.unwrap(), (1, 1));
+        assert_eq!(cached.send(Get("b")).await.unwrap(), (1, 2));
+
+        store.send(Set("a", 7)).await.unwrap();
+        assert_eq!(cached.send(Get("a")).await.unwrap(), (7, 3));
+        assert_eq!(cached.send(Get("b")).await.unwrap(), (1, 2));
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(cached.send(Get("b")).await.unwrap(), (7, 4));
+        // Evicts the least recently used key
+        cached.send(Get("c")).await.unwrap();
+        cached.send(Get("d")).await.unwrap();
+        assert_eq!(cached.send(Get("b")).await.unwrap(), (7, 7));
+    })
+}
+
+#[test]
+fn coalesced_requests() {
+    use crate::cache::{CacheKey, CoalescingRecipient};
+    use std::time::Duration;
+
+    struct Get(u32);
+    impl CacheKey for Get {
+        type Key = u32;
+        fn cache_key(&self) -> u32 {
+            self.0
+        }
+    }
+    struct Store {
+        queries: u32,
+    }
+    impl Actor for Store {}
+    #[async_trait]
+    impl Handler<Get> for Store {
+        type Response = (u32, u32);
+        async fn handle(&mut self, msg: Get, _ctx: &mut ActorContext<Self>) -> (u32, u32) {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            self.queries += 1;
+            (msg.0, self.queries)
+        }
+    }
+
+    get_runtime().block_on(async {
+        let store = Store { queries: 0 }.start();
+        let coalescing = CoalescingRecipient::new(store.recipient());
+        let (a, b, c) = futures_util::join!(
+            coalescing.send(Get(1)),
+            coalescing.send(Get(1)),
+            coalescing.send(Get(2))
+        );
+        assert_eq!(a.unwrap(), (1, 1));
+        assert_eq!(b.unwrap(), (1, 1));
+        assert_eq!(c.unwrap(), (2, 2));
+        // Finished requests are not reused
+        assert_eq!(coalescing.send(Get(1)).await.unwrap(), (1, 3));
+        // Nor the ones all the callers gave up on
+        assert!(futures_util::FutureExt::now_or_never(coalescing.send(Get(1))).is_none());
+        assert!(format!("{coalescing:?}").contains("in_flight: 0"));
+        assert_eq!(coalescing.send(Get(1)).await.unwrap().0, 1);
+    })
+}
+
+#[test]
+fn batched_lookups() {
+    use crate::batching::{GetMany, MultiGet};
+    use std::{collections::HashMap, time::Duration};
+
+    struct Store {
+        batches: Vec<Vec<u32>>,
+    }
+    impl Actor for Store {}
+    #[async_trait]
+    impl Handler<GetMany<u32>> for Store {
+        type Response = HashMap<u32, u32>;
+        async fn handle(
+            &mut self,
+            msg: GetMany<u32>,
+            _ctx: &mut ActorContext<Self>,
+        ) -> HashMap<u32, u32> {
+            self.batches.push(msg.keys.clone());
+            msg.keys.into_iter().filter(|k| *k < 10).map(|k| (k, k * 2)).collect()
+        }
+    }
+    struct Batches;
+    #[async_trait]
+    impl Handler<Batches> for Store {
+        type Response = Vec<Vec<u32>>;
+        async fn handle(&mut self, _msg: Batches, _ctx: &mut ActorContext<Self>) -> Vec<Vec<u32>> {
+            std::mem::take(&mut self.batches)
+        }
+    }
+
+    get_runtime().block_on(async {
+        let store = Store { batches: vec![] }.start();
+        let multi_get = MultiGet::new(store.recipient())
+            .with_linger(Duration::from_millis(20))
+            .with_max_batch(3);
+        let (a, b, c) = futures_util::join!(multi_get.get(1), multi_get.get(1), multi_get.get(11));
+        assert_eq!((a.unwrap(), b.unwrap(), c.unwrap()), (Some(2), Some(2), None));
+        // A batch which is not full gets sent after the linger time
+        let (d, e) = futures_util::join!(multi_get.get(2), multi_get.get(3));
+        assert_eq!((d.unwrap(), e.unwrap()), (Some(4), Some(6)));
+        assert_eq!(store.send(Batches).await.unwrap(), vec![vec![1, 11], vec![2, 3]]);
+    })
+}
+
+#[cfg(feature = "kv")]
+#[test]
+fn sharded_kv_store() {
+    use crate::kv::{HashRing, Kv, KvBackend};
+    use std::{
+        collections::HashMap,
+        convert::Infallible,
+        sync::{Arc, Mutex},
+    };
+
+    #[derive(Clone, Default)]
+    struct Backend(Arc<Mutex<HashMap<(usize, u32), String>>>);
+    #[async_trait]
+    impl KvBackend<u32, String> for Backend {
+        type Error = Infallible;
+        async fn load(&self, shard: usize) -> Result<Vec<(u32, String)>, Infallible> {
+            let stored = self.0.lock().unwrap();
+            let entries = stored.iter().filter(|((s, _), _)| *s == shard);
+            Ok(entries.map(|((_, k), v)| (*k, v.clone())).collect())
+        }
+        async fn put(&self, shard: usize, key: &u32, value: &String) -> Result<(), Infallible> {
+            self.0.lock().unwrap().insert((shard, *key), value.clone());
+            Ok(())
+        }
+        async fn delete(&self, shard: usize, key: &u32) -> Result<(), Infallible> {
+            self.0.lock().unwrap().remove(&(shard, *key));
+            Ok(())
+        }
+    }
+
+    let ring = HashRing::new(4);
+    let mut used = (0..100).map(|key: u32| ring.shard(&key)).collect::<Vec<_>>();
+    used.sort();
+    used.dedup();
+    assert_eq!(used, vec![0, 1, 2, 3]);
+    // the hashes are fixed, so the keys stay in their' shards between runs and builds
+    let owners = (0..8).map(|key: u32| ring.shard(&key)).collect::<Vec<_>>();
+    assert_eq!(owners, [2, 3, 2, 3, 3, 2, 1, 0]);
+
+    get_runtime().block_on(async {
+        let backend = Backend::default();
+        let kv = Kv::start_with(4, backend.clone());
+        for key in 0..10 {
+            assert_eq!(kv.put(key, key.to_string()).await.unwrap(), None);
+        }
+        assert_eq!(kv.put(3, "three".into()).await.unwrap(), Some("3".into()));
+        assert_eq!(kv.delete(4).await.unwrap(), Some("4".into()));
+        assert_eq!(kv.get(4).await.unwrap(), None);
+        assert_eq!(kv.len().await.unwrap(), 9);
+        drop(kv);
+
+        // A new store picks the entries up from the backend
+        let kv = Kv::start_with(4, backend);
+        assert_eq!(kv.get(3).await.unwrap(), Some("three".into()));
+        assert_eq!(kv.len().await.unwrap(), 9);
+        let memory_only = Kv::<u32, u32>::start(2);
+        assert!(memory_only.is_empty().await.unwrap());
+    })
+}
+
+#[test]
+fn coordination_actors() {
+    use crate::coordination::{Barrier, Semaphore, TokenBucket};
+    use std::time::{Duration, Instant};
+
+    get_runtime().block_on(async {
+        let semaphore = Semaphore::new(3).start();
+        let two = semaphore.acquire(2).await.unwrap();
+        assert_eq!(two.permits(), 2);
+        // The big request waits, and the small one cannot overtake it
+        let big = tokio::spawn({
+            let semaphore = semaphore.clone();
+            async move { semaphore.acquire(3).await.unwrap() }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(semaphore.try_acquire(1).await.unwrap().is_none());
+        drop(two);
+        let three = big.await.unwrap();
+        assert_eq!(three.permits(), 3);
+        drop(three);
+        assert!(semaphore.try_acquire(3).await.unwrap().is_some());
+
+        let bucket = TokenBucket::new(2, Duration::from_millis(20)).start();
+        assert!(bucket.try_take(2).await.unwrap());
+        assert!(!bucket.try_take(1).await.unwrap());
+        let started = Instant::now();
+        bucket.take(2).await.unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(30));
+
+        let barrier = Barrier::new(3).start();
+        let waits = (0..3).map(|_| barrier.wait());
+        let released = futures_util::future::join_all(waits).await;
+        let leaders = released.into_iter().filter(|leader| *leader.as_ref().unwrap());
+        assert_eq!(leaders.count(), 1);
+    })
+}
+
+#[test]
+fn leader_election() {
+    use crate::election::{Demoted, Elected, Election};
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    struct Worker {
+        name: &'static str,
+        events: mpsc::UnboundedSender<(&'static str, bool)>,
+    }
+    impl Actor for Worker {}
+    #[async_trait]
+    impl Handler<Elected> for Worker {
+        type Response = ();
+        async fn handle(&mut self, _msg: Elected, _ctx: &mut ActorContext<Self>) {
+            self.events.send((self.name, true)).unwrap();
+        }
+    }
+    #[async_trait]
+    impl Handler<Demoted> for Worker {
+        type Response = ();
+        async fn handle(&mut self, _msg: Demoted, _ctx: &mut ActorContext<Self>) {
+            self.events.send((self.name, false)).unwrap();
+        }
+    }
+    struct Hang;
+    #[async_trait]
+    impl Handler<Hang> for Worker {
+        type Response = ();
+        async fn handle(&mut self, _msg: Hang, _ctx: &mut ActorContext<Self>) {
+            tokio::time::sleep(Duration::from_millis(150)).await;
+        }
+    }
+
+    get_runtime().block_on(async {
+        let (tx, mut events) = mpsc::unbounded_channel();
+        let worker = |name| {
+            let events = tx.clone();
+            Worker { name, events }.start()
+        };
+        let (a, b, c) = (worker("a"), worker("b"), worker("c"));
+        let election = Election::new()
+            .with_lease(Duration::from_millis(30))
+            .start();
+        for candidate in [&a, &b, &c] {
+            election.join(candidate).await.unwrap();
+        }
+        assert_eq!(events.recv().await, Some(("a", true)));
+        assert_eq!(election.leader().await.unwrap(), Some(a.id()));
+
+        assert!(election.leave(a.id()).await.unwrap());
+        // Delivered to different actors, so in any order
+        let mut handover = [events.recv().await, events.recv().await];
+        handover.sort();
+        assert_eq!(handover, [Some(("a", false)), Some(("b", true))]);
+
+        b.stop(StopMode::Abandon);
+        assert_eq!(events.recv().await, Some(("c", true)));
+
+        // A stuck leader loses its' lease
+        c.do_send(Hang);
+        election.join(&a).await.unwrap();
+        let mut handover = [events.recv().await, events.recv().await];
+        handover.sort();
+        assert_eq!(handover, [Some(("a", true)), Some(("c", false))]);
+        assert_eq!(election.leader().await.unwrap(), Some(a.id()));
+    })
+}
+
+#[test]
+fn fair_streams() {
+    use std::time::Duration;
+
+    #[derive(Clone, Copy)]
+    enum Source {
+        Firehose,
+        Other,
+        Weighted,
+    }
+    struct Start;
+    struct Consumer {
+        handled: Vec<u8>,
+    }
+    impl Actor for Consumer {}
+    #[async_trait]
+    impl Handler<Start> for Consumer {
+        type Response = ();
+        async fn handle(&mut self, _msg: Start, ctx: &mut ActorContext<Self>) {
+            let items = |source| futures_util::stream::iter(std::iter::repeat_n(source, 100));
+            ctx.add_stream(items(Source::Firehose));
+            ctx.add_stream(items(Source::Other));
+            ctx.add_stream_with_share(items(Source::Weighted), 2);
+        }
+    }
+    #[async_trait]
+    impl Handler<Source> for Consumer {
+        type Response = ();
+        async fn handle(&mut self, msg: Source, _ctx: &mut ActorContext<Self>) {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+            self.handled.push(msg as u8);
+        }
+    }
+    struct Handled;
+    #[async_trait]
+    impl Handler<Handled> for Consumer {
+        type Response = Vec<u8>;
+        async fn handle(&mut self, _msg: Handled, _ctx: &mut ActorContext<Self>) -> Vec<u8> {
+            self.handled.clone()
+        }
+    }
+
+    get_runtime().block_on(async {
+        let consumer = Consumer { handled: vec![] }.start();
+        consumer.send(Start).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let handled = consumer.send(Handled).await.unwrap();
+        let mut counts = [0; 3];
+        for source in &handled[..40] {
+            counts[*source as usize] += 1;
+        }
+        // Turns of 1:1:2, give or take the start
+        assert!((8..=12).contains(&counts[0]), "{counts:?}");
+        assert!((8..=12).contains(&counts[1]), "{counts:?}");
+        assert!((17..=23).contains(&counts[2]), "{counts:?}");
+    })
+}
+
+#[test]
+fn paused_streams() {
+    use crate::context::StreamHandle;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    struct Item(u32);
+    struct Congested(bool);
+    struct Reader {
+        items: Option<mpsc::UnboundedReceiver<u32>>,
+        stream: Option<StreamHandle>,
+        handled: Vec<u32>,
+    }
+    #[async_trait]
+    impl Actor for Reader {
+        async fn started(&mut self, ctx: &mut ActorContext<Self>) {
+            let items = self.items.take().unwrap();
+            let stream = futures_util::stream::unfold(items, |mut items| async move {
+                Some((Item(items.recv().await?), items))
+            });
+            self.stream = Some(ctx.add_stream(Box::pin(stream)));
+        }
+    }
+    #[async_trait]
+    impl Handler<Item> for Reader {
+        type Response = ();
+        async fn handle(&mut self, msg: Item, _ctx: &mut ActorContext<Self>) {
+            self.handled.push(msg.0);
+        }
+    }
+    #[async_trait]
+    impl Handler<Congested> for Reader {
+        type Response = Vec<u32>;
+        async fn handle(&mut self, msg: Congested, ctx: &mut ActorContext<Self>) -> Vec<u32> {
+            let stream = self.stream.as_ref().unwrap();
+            match msg.0 {
+                true => ctx.pause_stream(stream),
+                false => ctx.resume_stream(stream),
+            }
+            assert_eq!(stream.is_paused(), msg.0);
+            self.handled.clone()
+        }
+    }
+
+    get_runtime().block_on(async {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let reader = Reader {
+            items: Some(rx),
+            stream: None,
+            handled: vec![],
+        }
+        .start();
+        tx.send(1).unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(reader.send(Congested(true)).await.unwrap(), vec![1]);
+        tx.send(2).unwrap();
+        tx.send(3).unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(reader.send(Congested(true)).await.unwrap(), vec![1]);
+        assert_eq!(reader.send(Congested(false)).await.unwrap(), vec![1]);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(reader.send(Congested(true)).await.unwrap(), vec![1, 2, 3]);
+    })
+}
+
+#[test]
+fn failing_streams() {
+    use crate::context::{ContextEvent, StreamFailed};
+    use tokio::sync::oneshot;
+
+    struct Item(u32);
+    struct Watcher {
+        handled: Vec<u32>,
+        failed: Option<oneshot::Sender<(Vec<u32>, StreamFailed, usize)>>,
+    }
+    #[async_trait]
+    impl Actor for Watcher {
+        async fn started(&mut self, ctx: &mut ActorContext<Self>) {
+            let items = futures_util::stream::iter(0..).map(|i| match i {
+                3 => panic!("Connection reset"),
+                i => Item(i),
+            });
+            ctx.add_stream(items);
+        }
+        async fn context_event(&mut self, ctx: &mut ActorContext<Self>, event: ContextEvent) {
+            let ContextEvent::StreamFailed(failure) = event else {
+                panic!("Unexpected event: {event:?}");
+            };
+            let failed_streams = ctx.diagnostics().failed_streams;
+            let handled = std::mem::take(&mut self.handled);
+            self.failed.take().unwrap().send((handled, failure, failed_streams)).unwrap();
+        }
+    }
+    #[async_trait]
+    impl Handler<Item> for Watcher {
+        type Response = ();
+        async fn handle(&mut self, msg: Item, _ctx: &mut ActorContext<Self>) {
+            self.handled.push(msg.0);
+        }
+    }
+    struct Fragile {
+        restarted: Option<oneshot::Sender<RestartReason>>,
+    }
+    #[async_trait]
+    impl Actor for Fragile {
+        async fn started(&mut self, ctx: &mut ActorContext<Self>) {
+            if self.restarted.is_some() {
+                ctx.add_stream(futures_util::stream::iter(0..).map(|i| match i {
+                    3 => panic!("Connection reset"),
+                    i => Item(i),
+                }));
+            }
+        }
+        async fn context_event(&mut self, ctx: &mut ActorContext<Self>, event: ContextEvent) {
+            if let ContextEvent::StreamFailed(failure) = event {
+                ctx.fail(RestartReason::StreamFailed(failure));
+            }
+        }
+    }
+    #[async_trait]
+    impl Supervised for Fragile {
+        async fn restarting(&mut self, _ctx: &mut ActorContext<Self>, reason: RestartReason) {
+            let _ = self.restarted.take().unwrap().send(reason);
+        }
+    }
+    #[async_trait]
+    impl Handler<Item> for Fragile {
+        type Response = ();
+        async fn handle(&mut self, _msg: Item, _ctx: &mut ActorContext<Self>) {}
+    }
+
+    get_runtime().block_on(async {
+        let (tx, rx) = oneshot::channel();
+        let watcher = Watcher {
+            handled: vec![],
+            failed: Some(tx),
+        }
+        .start();
+        let (handled, failure, failed_streams) = rx.await.unwrap();
+        assert_eq!(handled, vec![0, 1, 2]);
+        assert_eq!(failure.message_type, std::any::type_name::<Item>());
+        assert_eq!(failure.message.as_deref(), Some("Connection reset"));
+        assert_eq!(failed_streams, 1);
+        // The actor keeps running
+        watcher.send(Item(7)).await.unwrap();
+
+        // Unless it gives up, which a supervised actor gets told about when restarting
+        let (tx, rx) = oneshot::channel();
+        let mut restarted = Some(tx);
+        let _fragile = Fragile::create_supervised(move |_ctx| Fragile {
+            restarted: restarted.take(),
+        });
+        let RestartReason::StreamFailed(failure) = rx.await.unwrap() else {
+            panic!("Restarted for the wrong reason");
+        };
+        assert_eq!(failure.message.as_deref(), Some("Connection reset"));
+    })
+}
+
+#[test]
+fn streams_outlive_restarts() {
+    use crate::supervised::Backoff;
+
+    struct Item(u32);
+    struct Flaky {
+        streamed: bool,
+        handled: Vec<u32>,
+        done: Option<oneshot::Sender<Vec<u32>>>,
+    }
+    #[async_trait]
+    impl Actor for Flaky {
+        async fn started(&mut self, ctx: &mut ActorContext<Self>) {
+            // The stream gets added only once, so it has to survive the restart
+            if !std::mem::replace(&mut self.streamed, true) {
+                ctx.add_stream(futures_util::stream::iter(0..10).map(Item));
+            }
+        }
+    }
+    #[async_trait]
+    impl Supervised for Flaky {
+        const RESTART_BACKOFF: Backoff = Backoff::NONE;
+    }
+    #[async_trait]
+    impl Handler<Item> for Flaky {
+        type Response = ();
+        async fn handle(&mut self, msg: Item, _ctx: &mut ActorContext<Self>) {
+            if msg.0 == 3 {
+                panic!("Malformed item");
+            }
+            self.handled.push(msg.0);
+            if msg.0 == 9 {
+                self.done.take().unwrap().send(self.handled.clone()).unwrap();
+            }
+        }
+    }
+
+    get_runtime().block_on(async {
+        let (tx, rx) = oneshot::channel();
+        let _flaky = Flaky::create_supervised(|_ctx| Flaky {
+            streamed: false,
+            handled: vec![],
+            done: Some(tx),
+        });
+        assert_eq!(rx.await.unwrap(), vec![0, 1, 2, 4, 5, 6, 7, 8, 9]);
+    })
+}
+
+#[test]
+fn context_events() {
+    use crate::context::{ContextEvent, StreamId, TimerId};
+
+    struct Child;
+    impl Actor for Child {}
+
+    struct Item;
+    struct Parent {
+        child: Addr<Child>,
+        stream: Option<StreamId>,
+        timer: Option<TimerId>,
+        events: Vec<ContextEvent>,
+        done: Option<oneshot::Sender<(Vec<ContextEvent>, StreamId, TimerId)>>,
+    }
+    #[async_trait]
+    impl Actor for Parent {
+        async fn started(&mut self, ctx: &mut ActorContext<Self>) {
+            let stream = ctx.add_stream(futures_util::stream::iter([Item]));
+            self.stream = Some(stream.id());
+            self.timer = Some(ctx.set_timer(std::time::Duration::from_millis(20)));
+            ctx.watch(&self.child);
+        }
+        async fn context_event(&mut self, _ctx: &mut ActorContext<Self>, event: ContextEvent) {
+            self.events.push(event);
+            if self.events.len() == 3 {
+                let events = std::mem::take(&mut self.events);
+                let ids = (self.stream.unwrap(), self.timer.unwrap());
+                self.done.take().unwrap().send((events, ids.0, ids.1)).unwrap();
+            }
+        }
+    }
+    #[async_trait]
+    impl Handler<Item> for Parent {
+        type Response = ();
+        async fn handle(&mut self, _msg: Item, _ctx: &mut ActorContext<Self>) {}
+    }
+
+    get_runtime().block_on(async {
+        let child = Child.start();
+        let child_id = child.id();
+        let (tx, rx) = oneshot::channel();
+        let _parent = Parent {
+            child: child.clone(),
+            stream: None,
+            timer: None,
+            events: vec![],
+            done: Some(tx),
+        }
+        .start();
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        child.stop(StopMode::Abandon);
+        drop(child);
+        let (events, stream, timer) = rx.await.unwrap();
+        assert_eq!(events[0], ContextEvent::StreamFinished(stream));
+        assert!(matches!(
+            events[1],
+            ContextEvent::Terminated { actor_id, .. } if actor_id == child_id
+        ));
+        assert_eq!(events[2], ContextEvent::TimerFired(timer));
+    })
+}
+
+#[test]
+fn actor_futures() {
+    struct Fetch;
+    struct GetLog;
+    struct Hang(oneshot::Sender<()>);
+    #[derive(Default)]
+    struct Fetcher {
+        log: Vec<String>,
+    }
+    impl Actor for Fetcher {}
+    #[async_trait]
+    impl Handler<Fetch> for Fetcher {
+        type Response = ();
+        async fn handle(&mut self, _msg: Fetch, ctx: &mut ActorContext<Self>) {
+            let fetch = async {
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+                1
+            };
+            let pages = futures_util::stream::iter([2, 3]).into_actor(self);
+            let fetching = fetch
+                .into_actor(self)
+                .map(|page, act, _ctx| act.log.push(format!("fetched {page}")))
+                .then(|(), act, _ctx| {
+                    act.log.push("paging".to_string());
+                    tokio::time::sleep(std::time::Duration::from_millis(5))
+                })
+                .and_then(move |(), _act, _ctx| {
+                    pages.fold(0, |sum, page, act, _ctx| {
+                        act.log.push(format!("page {page}"));
+                        sum + page
+                    })
+                })
+                .map(|sum, act, _ctx| act.log.push(format!("sum {sum}")));
+            ctx.spawn(fetching);
+            self.log.push("spawned".to_string());
+        }
+    }
+    #[async_trait]
+    impl Handler<GetLog> for Fetcher {
+        type Response = Vec<String>;
+        async fn handle(&mut self, _msg: GetLog, _ctx: &mut ActorContext<Self>) -> Vec<String> {
+            self.log.clone()
+        }
+    }
+    #[async_trait]
+    impl Handler<Hang> for Fetcher {
+        type Response = ();
+        async fn handle(&mut self, msg: Hang, ctx: &mut ActorContext<Self>) {
+            let hanging = async move {
+                let _held = msg.0;
+                std::future::pending::<()>().await
+            };
+            ctx.spawn(hanging.into_actor(self));
+        }
+    }
+
+    get_runtime().block_on(async {
+        let fetcher = Fetcher::default().start();
+        fetcher.send(Fetch).await.unwrap();
+        // The actor keeps handling messages while the future is pending
+        assert_eq!(fetcher.send(GetLog).await.unwrap(), vec!["spawned"]);
+        tokio::time::sleep(std::time::Duration::from_millis(60)).await;
+        let log = fetcher.send(GetLog).await.unwrap();
+        let expected = ["spawned", "fetched 1", "paging", "page 2", "page 3", "sum 5"];
+        assert_eq!(log, expected);
+
+        // Pending futures get dropped along with the actor
+        let (held, dropped) = oneshot::channel();
+        fetcher.send(Hang(held)).await.unwrap();
+        fetcher.stop(StopMode::Abandon);
+        fetcher.terminated().await;
+        let dropped = tokio::time::timeout(std::time::Duration::from_secs(1), dropped).await;
+        assert!(dropped.unwrap().is_err());
+    })
+}
+
+#[test]
+fn into_actor_adapter() {
+    struct Load(oneshot::Sender<()>);
+    struct GetValue;
+    #[derive(Default)]
+    struct Loader {
+        value: Option<u32>,
+    }
+    impl Actor for Loader {}
+    #[async_trait]
+    impl Handler<Load> for Loader {
+        type Response = ();
+        async fn handle(&mut self, msg: Load, ctx: &mut ActorContext<Self>) {
+            let load = async { 7 };
+            ctx.spawn(load.then_actor(ctx, move |value, act, _ctx| {
+                act.value = Some(value);
+                let _ = msg.0.send(());
+            }));
+        }
+    }
+    #[async_trait]
+    impl Handler<GetValue> for Loader {
+        type Response = Option<u32>;
+        async fn handle(&mut self, _msg: GetValue, _ctx: &mut ActorContext<Self>) -> Option<u32> {
+            self.value
+        }
+    }
+
+    get_runtime().block_on(async {
+        let loader = Loader::default().start();
+        let (done, continued) = oneshot::channel();
+        loader.send(Load(done)).await.unwrap();
+        continued.await.unwrap();
+        assert_eq!(loader.send(GetValue).await.unwrap(), Some(7));
+    })
+}
+
+#[test]
+fn atomic_actor_futures() {
+    struct Transfer(u32);
+    struct Audit;
+    struct Account {
+        balance: u32,
+        reserved: u32,
+    }
+    impl Actor for Account {}
+    #[async_trait]
+    impl Handler<Transfer> for Account {
+        type Response = ();
+        async fn handle(&mut self, msg: Transfer, ctx: &mut ActorContext<Self>) {
+            let amount = msg.0;
+            let settle = tokio::time::sleep(std::time::Duration::from_millis(20));
+            let transfer = async {}
+                .then_actor(self, move |(), act, _ctx| {
+                    act.balance -= amount;
+                    act.reserved += amount;
+                })
+                .then(|(), _act, _ctx| settle)
+                .map(move |(), act, _ctx| act.reserved -= amount);
+            transfer.run_atomic(self, ctx).await
+        }
+    }
+    #[async_trait]
+    impl Handler<Audit> for Account {
+        type Response = (u32, u32);
+        async fn handle(&mut self, _msg: Audit, _ctx: &mut ActorContext<Self>) -> (u32, u32) {
+            (self.balance, self.reserved)
+        }
+    }
+
+    get_runtime().block_on(async {
+        let account = Account {
+            balance: 100,
+            reserved: 0,
+        }
+        .start();
+        let transfer = account.send(Transfer(30));
+        let audit = async {
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+            account.send(Audit).await
+        };
+        let (transferred, audited) = futures_util::join!(transfer, audit);
+        transferred.unwrap();
+        // The audit never sees the reservation half-way
+        assert_eq!(audited.unwrap(), (70, 0));
+    })
+}
+
+#[test]
+fn runtime_mailbox_capacity() {
+    struct Nap;
+    struct Limit(Option<usize>);
+    struct Sleeper;
+    impl Actor for Sleeper {}
+    #[async_trait]
+    impl Handler<Nap> for Sleeper {
+        type Response = ();
+        async fn handle(&mut self, _msg: Nap, _ctx: &mut ActorContext<Self>) {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+    }
+    #[async_trait]
+    impl Handler<Limit> for Sleeper {
+        type Response = ();
+        async fn handle(&mut self, msg: Limit, ctx: &mut ActorContext<Self>) {
+            ctx.set_mailbox_capacity(msg.0);
+        }
+    }
+
+    get_runtime().block_on(async {
+        let sleeper = Sleeper.start();
+        sleeper.send(Limit(Some(1))).await.unwrap();
+        assert_eq!(sleeper.mailbox_capacity(), Some(1));
+        sleeper.do_send(Nap);
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        sleeper.try_send(Nap).unwrap();
+        assert!(matches!(sleeper.try_send(Nap), Err(ActorError::MailboxFull(_))));
+        // Loosened from the outside, e.g. by an operator holding the type-erased address
+        let any = sleeper.any();
+        any.set_mailbox_capacity(None);
+        assert_eq!(any.mailbox_capacity(), None);
+        sleeper.try_send(Nap).unwrap();
+    })
+}
+
+#[test]
+fn muted_messages() {
+    use crate::dead_letters::{DeadLetterReason, DeadLetters};
+
+    struct Expensive;
+    struct Cheap;
+    struct Shed(bool);
+    struct Service;
+    impl Actor for Service {}
+    #[async_trait]
+    impl Handler<Expensive> for Service {
+        type Response = ();
+        async fn handle(&mut self, _msg: Expensive, _ctx: &mut ActorContext<Self>) {}
+    }
+    #[async_trait]
+    impl Handler<Cheap> for Service {
+        type Response = ();
+        async fn handle(&mut self, _msg: Cheap, _ctx: &mut ActorContext<Self>) {}
+    }
+    #[async_trait]
+    impl Handler<Shed> for Service {
+        type Response = bool;
+        async fn handle(&mut self, msg: Shed, ctx: &mut ActorContext<Self>) -> bool {
+            match msg.0 {
+                true => ctx.mute::<Expensive>(),
+                false => ctx.unmute::<Expensive>(),
+            }
+        }
+    }
+
+    get_runtime().block_on(async {
+        let mut dead_letters = DeadLetters::subscribe();
+        let service = Service.start();
+        assert!(service.send(Shed(true)).await.unwrap());
+        assert!(!service.send(Shed(true)).await.unwrap());
+        assert!(matches!(service.send(Expensive).await, Err(ActorError::Muted(_))));
+        assert!(matches!(service.try_send(Expensive), Err(ActorError::Muted(_))));
+        service.send(Cheap).await.unwrap();
+        service.do_send(Expensive);
+        let letter = loop {
+            let letter = dead_letters.recv().await.unwrap();
+            if letter.message_type == std::any::type_name::<Expensive>() {
+                break letter;
+            }
+        };
+        assert_eq!(letter.reason, DeadLetterReason::Muted);
+        let any = service.any();
+        assert_eq!(any.muted(), vec![std::any::type_name::<Expensive>()]);
+        assert_eq!(any.unmute_all(), 1);
+        service.send(Expensive).await.unwrap();
+        assert!(service.mute::<Expensive>());
+        assert!(service.send(Shed(false)).await.unwrap());
+        service.send(Expensive).await.unwrap();
+    })
+}
+
+#[test]
+fn mirrored_traffic() {
+    use crate::mirror::{MirrorStats, MirroredRecipient};
+
+    #[derive(Clone)]
+    struct Quote(u32);
+    struct Seen;
+    #[derive(Default)]
+    struct Pricer {
+        seen: Vec<u32>,
+    }
+    impl Actor for Pricer {}
+    #[async_trait]
+    impl Handler<Quote> for Pricer {
+        type Response = u32;
+        async fn handle(&mut self, msg: Quote, _ctx: &mut ActorContext<Self>) -> u32 {
+            self.seen.push(msg.0);
+            msg.0 * 2
+        }
+    }
+    #[async_trait]
+    impl Handler<Seen> for Pricer {
+        type Response = Vec<u32>;
+        async fn handle(&mut self, _msg: Seen, _ctx: &mut ActorContext<Self>) -> Vec<u32> {
+            self.seen.clone()
+        }
+    }
+    #[derive(Default)]
+    struct Rewrite {
+        seen: Vec<u32>,
+    }
+    impl Actor for Rewrite {}
+    #[async_trait]
+    impl Handler<Quote> for Rewrite {
+        type Response = Result<u32, String>;
+        async fn handle(&mut self, msg: Quote, _ctx: &mut ActorContext<Self>) -> Self::Response {
+            self.seen.push(msg.0);
+            Err("Not implemented yet".to_string())
+        }
+    }
+    #[async_trait]
+    impl Handler<Seen> for Rewrite {
+        type Response = Vec<u32>;
+        async fn handle(&mut self, _msg: Seen, _ctx: &mut ActorContext<Self>) -> Vec<u32> {
+            self.seen.clone()
+        }
+    }
+
+    get_runtime().block_on(async {
+        let primary = Pricer::default().start();
+        let shadow = Rewrite::default().start();
+        let mirrored = MirroredRecipient::new(primary.recipient(), shadow.recipient());
+        assert_eq!(mirrored.send(Quote(1)).await.unwrap(), 2);
+        mirrored.do_send(Quote(2));
+        assert_eq!(primary.send(Seen).await.unwrap(), vec![1, 2]);
+        assert_eq!(shadow.send(Seen).await.unwrap(), vec![1, 2]);
+        // The shadow going away does not affect the callers
+        shadow.stop(StopMode::Abandon);
+        shadow.terminated().await;
+        assert_eq!(mirrored.send(Quote(3)).await.unwrap(), 6);
+        let expected = MirrorStats {
+            mirrored: 2,
+            dropped: 1,
+        };
+        assert_eq!(mirrored.stats(), expected);
+    })
+}
+
+#[test]
+fn swappable_actors() {
+    use crate::swap::SwappableAddr;
+
+    struct Version;
+    struct Slow;
+    struct Service(&'static str);
+    impl Actor for Service {}
+    #[async_trait]
+    impl Handler<Version> for Service {
+        type Response = &'static str;
+        async fn handle(&mut self, _msg: Version, _ctx: &mut ActorContext<Self>) -> &'static str {
+            self.0
+        }
+    }
+    #[async_trait]
+    impl Handler<Slow> for Service {
+        type Response = &'static str;
+        async fn handle(&mut self, _msg: Slow, _ctx: &mut ActorContext<Self>) -> &'static str {
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            self.0
+        }
+    }
+
+    get_runtime().block_on(async {
+        let service = SwappableAddr::new(Service("blue").start());
+        let callers = service.clone();
+        assert_eq!(callers.send(Version).await.unwrap(), "blue");
+        // Sent to the old instance right before the swap, so it still gets handled there
+        let in_flight = callers.send(Slow);
+        let swap = async {
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+            let deadline = None;
+            let old = service.swap(Service("green").start(), StopMode::Drain { deadline });
+            old.terminated().await;
+        };
+        let (answered, ()) = futures_util::join!(in_flight, swap);
+        assert_eq!(answered.unwrap(), "blue");
+        assert_eq!(callers.send(Version).await.unwrap(), "green");
+    })
+}
+
+#[test]
+fn readiness_gates() {
+    use crate::bootstrap::Bootstrap;
+
+    struct Lookup;
+    struct User;
+    impl Actor for User {}
+    #[derive(Default)]
+    struct Cache<const READINESS: u8> {
+        warm: bool,
+    }
+    #[async_trait]
+    impl<const READINESS: u8> Actor for Cache<READINESS> {
+        const READINESS: Readiness = match READINESS {
+            0 => Readiness::Buffer,
+            _ => Readiness::Reject,
+        };
+        async fn started(&mut self, ctx: &mut ActorContext<Self>) {
+            let warm_up = tokio::time::sleep(std::time::Duration::from_millis(20));
+            ctx.spawn(warm_up.then_actor(ctx, |(), act, ctx| {
+                act.warm = true;
+                ctx.set_ready();
+            }));
+        }
+    }
+    #[async_trait]
+    impl<const READINESS: u8> Handler<Lookup> for Cache<READINESS> {
+        type Response = bool;
+        async fn handle(&mut self, _msg: Lookup, _ctx: &mut ActorContext<Self>) -> bool {
+            self.warm
+        }
+    }
+
+    get_runtime().block_on(async {
+        // Messages wait aside until the cache is warm
+        let buffering = Cache::<0>::default().start();
+        assert!(!buffering.is_ready());
+        assert!(buffering.send(Lookup).await.unwrap());
+        assert!(buffering.is_ready());
+
+        let rejecting = Cache::<1>::default().start();
+        assert!(matches!(rejecting.send(Lookup).await, Err(ActorError::NotReady(_))));
+        assert!(rejecting.ready().await);
+        assert!(rejecting.send(Lookup).await.unwrap());
+
+        // Dependents start only once the cache is warm
+        let actors = Bootstrap::new()
+            .actor("cache", &[], |_| async { Cache::<1>::default().start() })
+            .actor("user", &["cache"], |actors| {
+                let cache: Addr<Cache<1>> = actors.get("cache").unwrap();
+                assert!(cache.is_ready());
+                async { User.start() }
+            })
+            .start()
+            .await
+            .unwrap();
+        assert_eq!(actors.len(), 2);
+    })
+}
+
+#[test]
+fn readiness_gates_of_stopped_actors() {
+    struct Lookup;
+    struct Quitter(Option<oneshot::Receiver<()>>);
+    #[async_trait]
+    impl Actor for Quitter {
+        const READINESS: Readiness = Readiness::Buffer;
+        async fn started(&mut self, ctx: &mut ActorContext<Self>) {
+            let _ = self.0.take().unwrap().await;
+            ctx.stop();
+        }
+    }
+    #[async_trait]
+    impl Handler<Lookup> for Quitter {
+        type Response = ();
+        async fn handle(&mut self, _msg: Lookup, _ctx: &mut ActorContext<Self>) {}
+    }
+
+    get_runtime().block_on(async {
+        let (quit, quitting) = oneshot::channel();
+        let quitter = Quitter(Some(quitting)).start();
+        // Held until the actor becomes ready, which it never does
+        let (held, _) = futures_util::join!(quitter.send(Lookup), async {
+            quit.send(()).unwrap();
+            quitter.terminated().await
+        });
+        assert!(matches!(held, Err(ActorError::ActorStopping(_))));
+        assert!(matches!(quitter.send(Lookup).await, Err(ActorError::CannotSend(_))));
+    })
+}
+
+#[test]
+fn dependency_ordered_shutdown() {
+    use crate::shutdown::*;
+    use crate::supervisor::{ChildSpec, Strategy, Supervisor};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    type Log = Arc<Mutex<Vec<&'static str>>>;
+    struct Watch(Addr<Node>);
+    struct Node {
+        name: &'static str,
+        log: Log,
+    }
+    #[async_trait]
+    impl Actor for Node {
+        async fn stopped(&mut self, _ctx: &mut ActorContext<Self>) {
+            // Slow enough for the actors relying on it to notice if it went first
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            self.log.lock().unwrap().push(self.name);
+        }
+    }
+    #[async_trait]
+    impl Handler<Watch> for Node {
+        type Response = ();
+        async fn handle(&mut self, msg: Watch, ctx: &mut ActorContext<Self>) {
+            ctx.watch(&msg.0);
+        }
+    }
+
+    get_runtime().block_on(async {
+        let log = Log::default();
+        let node = |name| {
+            Node {
+                name,
+                log: log.clone(),
+            }
+            .start()
+        };
+        let (source, stage, sink) = (node("source"), node("stage"), node("sink"));
+        stage.send(Watch(source.clone())).await.unwrap();
+        let sink = sink.any();
+        sink.depend_on(stage.id());
+        assert_eq!(stage.any().dependencies(), [source.id()]);
+
+        let timeout = Duration::from_secs(1);
+        let coordinator = ShutdownCoordinator::with_phases(&[(DRAIN_WORKERS, timeout)]);
+        coordinator.register(DRAIN_WORKERS, source.any(), StopMode::Abandon);
+        coordinator.register(DRAIN_WORKERS, stage.any(), StopMode::Abandon);
+        coordinator.register(DRAIN_WORKERS, sink, StopMode::Abandon);
+        let reports = coordinator.run().await;
+        assert!(reports[0].aborted.is_empty());
+        let terminated: Vec<_> = reports[0].terminated.iter().map(|t| t.actor_id).collect();
+        assert_eq!(terminated[2], source.id());
+        assert_eq!(*log.lock().unwrap(), ["sink", "stage", "source"]);
+
+        // Supervisors rely on their' children, which they stop on their' own instead of restarting them
+        log.lock().unwrap().clear();
+        let child_log = log.clone();
+        let supervisor = Supervisor::new(Strategy::OneForOne)
+            .child(ChildSpec::new("child", move || {
+                Node {
+                    name: "child",
+                    log: child_log.clone(),
+                }
+                .start()
+            }))
+            .start();
+        let child = supervisor.child::<Node>("child").await.unwrap().unwrap();
+        assert_eq!(supervisor.any().dependencies(), [child.id()]);
+        let coordinator = ShutdownCoordinator::with_phases(&[(DRAIN_WORKERS, timeout)]);
+        coordinator.register(DRAIN_WORKERS, child.any(), StopMode::Abandon);
+        coordinator.register(DRAIN_WORKERS, supervisor.any(), StopMode::Abandon);
+        let reports = coordinator.run().await;
+        assert!(reports[0].aborted.is_empty());
+        assert_eq!(*log.lock().unwrap(), ["child"]);
+    })
+}
+
+#[test]
+fn actor_logging() {
+    use crate::logging::{self, Level, LogRecord};
+    use std::sync::{Arc, Mutex};
+
+    struct Greet(&'static str);
+    struct Greeter;
+    #[async_trait]
+    impl Actor for Greeter {
+        async fn started(&mut self, ctx: &mut ActorContext<Self>) {
+            ctx.set_name("greeter-1");
+        }
+    }
+    #[async_trait]
+    impl Handler<Greet> for Greeter {
+        type Response = ();
+        async fn handle(&mut self, msg: Greet, ctx: &mut ActorContext<Self>) {
+            let log = ctx.log();
+            assert!(!log.enabled(Level::Debug));
+            log.debug("skipped");
+            log.info(format_args!("hello, {}", msg.0));
+        }
+    }
+
+    let _logger = LOGGER.lock().unwrap();
+    get_runtime().block_on(async {
+        let records: Arc<Mutex<Vec<LogRecord>>> = Arc::default();
+        let sink = records.clone();
+        logging::set_logger(Level::Info, move |record| {
+            sink.lock().unwrap().push(record.clone())
+        });
+        let greeter = Greeter.start();
+        greeter.send(Greet("world")).await.unwrap();
+        logging::remove_logger();
+        greeter.send(Greet("nobody")).await.unwrap();
+
+        let records = records.lock().unwrap();
+        let records: Vec<_> = records
+            .iter()
+            .filter(|record| record.actor_id == greeter.id())
+            .collect();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].level, Level::Info);
+        assert_eq!(records[0].actor_type, std::any::type_name::<Greeter>());
+        assert_eq!(records[0].actor_name.as_deref(), Some("greeter-1"));
+        assert!(records[0].message_id.is_some());
+        assert_eq!(records[0].message, "hello, world");
+    })
+}
+
+#[test]
+fn sampled_message_traces() {
+    use crate::logging::{self, Level, LogRecord, Sampling};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    struct Fast;
+    struct Slow;
+    struct Audited;
+    struct Crash;
+    struct Busy;
+    impl Actor for Busy {
+        const TRACE_SAMPLING: Sampling = Sampling::NONE.with_slow(Duration::from_millis(20));
+    }
+    #[async_trait]
+    impl Handler<Fast> for Busy {
+        type Response = ();
+        async fn handle(&mut self, _msg: Fast, _ctx: &mut ActorContext<Self>) {}
+    }
+    #[async_trait]
+    impl Handler<Slow> for Busy {
+        type Response = ();
+        async fn handle(&mut self, _msg: Slow, _ctx: &mut ActorContext<Self>) {
+            tokio::time::sleep(Duration::from_millis(30)).await;
+        }
+    }
+    #[async_trait]
+    impl Handler<Audited> for Busy {
+        type Response = ();
+        const MESSAGE_SAMPLING: Option<Sampling> = Some(Sampling::ALL);
+        async fn handle(&mut self, _msg: Audited, _ctx: &mut ActorContext<Self>) {}
+    }
+    #[async_trait]
+    impl Handler<Crash> for Busy {
+        type Response = ();
+        async fn handle(&mut self, _msg: Crash, _ctx: &mut ActorContext<Self>) {
+            panic!("crashed")
+        }
+    }
+
+    let _logger = LOGGER.lock().unwrap();
+    get_runtime().block_on(async {
+        let records: Arc<Mutex<Vec<LogRecord>>> = Arc::default();
+        let sink = records.clone();
+        logging::set_logger(Level::Trace, move |record| {
+            sink.lock().unwrap().push(record.clone())
+        });
+        let busy = Busy.start();
+        for _ in 0..100 {
+            busy.send(Fast).await.unwrap();
+        }
+        busy.send(Slow).await.unwrap();
+        busy.send(Audited).await.unwrap();
+        assert!(busy.send(Crash).await.is_err());
+        logging::remove_logger();
+
+        let records = records.lock().unwrap();
+        let traces: Vec<_> = records
+            .iter()
+            .filter(|record| record.actor_id == busy.id())
+            .map(|record| record.message.as_str())
+            .collect();
+        assert_eq!(traces.len(), 3, "{traces:?}");
+        assert!(traces[0].starts_with(&format!("handled {}", std::any::type_name::<Slow>())));
+        assert!(traces[1].starts_with(&format!("handled {}", std::any::type_name::<Audited>())));
+        assert!(traces[2].starts_with(&format!("failed handling {}", std::any::type_name::<Crash>())));
+    })
+}
+
+#[test]
+fn latency_histograms() {
+    use crate::histogram::Histogram;
+    use std::time::Duration;
+
+    let histogram = Histogram::new();
+    for micros in 1..=1000 {
+        histogram.record(Duration::from_micros(micros));
+    }
+    let snapshot = histogram.snapshot();
+    assert_eq!(snapshot.count(), 1000);
+    assert_eq!(snapshot.max(), Duration::from_millis(1));
+    let within = |value: Duration, expected: Duration| {
+        value >= expected && value <= expected + expected / 16
+    };
+    assert!(within(snapshot.p50(), Duration::from_micros(500)), "{snapshot:?}");
+    assert!(within(snapshot.p95(), Duration::from_micros(950)), "{snapshot:?}");
+    assert!(within(snapshot.p999(), Duration::from_micros(999)), "{snapshot:?}");
+    assert_eq!(Histogram::new().snapshot().p50(), Duration::ZERO);
+
+    struct Work;
+    struct Worker;
+    impl Actor for Worker {}
+    #[async_trait]
+    impl Handler<Work> for Worker {
+        type Response = ();
+        async fn handle(&mut self, _msg: Work, _ctx: &mut ActorContext<Self>) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    get_runtime().block_on(async {
+        let worker = Worker.start();
+        assert_eq!(worker.latency().handling.count(), 0);
+        for _ in 0..3 {
+            worker.do_send(Work);
+        }
+        worker.send(Work).await.unwrap();
+        let latency = worker.any().latency();
+        assert_eq!(latency.handling.count(), 4);
+        assert!(latency.handling.p50() >= Duration::from_millis(10));
+        // The last message waited for the three before it
+        assert!(latency.dwell.max() >= Duration::from_millis(30));
+        assert!(latency.dwell.p50() < latency.dwell.max());
+    })
+}
+
+#[test]
+fn mailbox_lag_gauge() {
+    use std::time::Duration;
+
+    struct Work;
+    struct Lag;
+    struct Hold(oneshot::Receiver<()>);
+    struct Worker;
+    impl Actor for Worker {}
+    #[async_trait]
+    impl Handler<Work> for Worker {
+        type Response = ();
+        async fn handle(&mut self, _msg: Work, _ctx: &mut ActorContext<Self>) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+    #[async_trait]
+    impl Handler<Hold> for Worker {
+        type Response = ();
+        async fn handle(&mut self, msg: Hold, _ctx: &mut ActorContext<Self>) {
+            let _ = msg.0.await;
+        }
+    }
+    #[async_trait]
+    impl Handler<Lag> for Worker {
+        type Response = Duration;
+        async fn handle(&mut self, _msg: Lag, ctx: &mut ActorContext<Self>) -> Duration {
+            ctx.diagnostics().mailbox_lag
+        }
+    }
+
+    get_runtime().block_on(async {
+        let worker = Worker.start();
+        assert_eq!(worker.mailbox_lag(), Duration::ZERO);
+        for _ in 0..3 {
+            worker.do_send(Work);
+        }
+        // Visible to the handler itself, as the gauge gets set before handling
+        let lag = worker.send(Lag).await.unwrap();
+        assert!(lag >= Duration::from_millis(30), "{lag:?}");
+        // Caught up
+        assert_eq!(worker.any().mailbox_lag(), Duration::ZERO);
+        // Keeps growing while the actor is stuck in a handler
+        let (release, hold) = oneshot::channel();
+        worker.do_send(Hold(hold));
+        worker.do_send(Work);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let stuck = worker.mailbox_lag();
+        assert!(stuck >= Duration::from_millis(20), "{stuck:?}");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(worker.mailbox_lag() > stuck);
+        release.send(()).unwrap();
+        worker.send(Lag).await.unwrap();
+        assert_eq!(worker.mailbox_lag(), Duration::ZERO);
+    })
+}
+
+#[test]
+fn dead_letter_replay() {
+    use crate::dead_letters::{DeadLetterReason, DeadLetters};
+    use std::time::Duration;
+
+    struct Block(oneshot::Receiver<()>);
+    struct Work(u32);
+    struct Done;
+    #[derive(Default)]
+    struct Worker(Vec<u32>);
+    impl Actor for Worker {
+        const MAILBOX_CAPACITY: Option<usize> = Some(2);
+    }
+    #[async_trait]
+    impl Handler<Block> for Worker {
+        type Response = ();
+        async fn handle(&mut self, msg: Block, _ctx: &mut ActorContext<Self>) {
+            let _ = msg.0.await;
+        }
+    }
+    #[async_trait]
+    impl Handler<Work> for Worker {
+        type Response = ();
+        async fn handle(&mut self, msg: Work, _ctx: &mut ActorContext<Self>) {
+            self.0.push(msg.0);
+        }
+    }
+    #[async_trait]
+    impl Handler<Done> for Worker {
+        type Response = Vec<u32>;
+        async fn handle(&mut self, _msg: Done, _ctx: &mut ActorContext<Self>) -> Vec<u32> {
+            self.0.clone()
+        }
+    }
+
+    get_runtime().block_on(async {
+        let captured = || {
+            DeadLetters::captured()
+                .into_iter()
+                .filter(|letter| letter.actor_type == std::any::type_name::<Worker>())
+                .collect::<Vec<_>>()
+        };
+        DeadLetters::capture(1024);
+        let worker = Worker::default().start();
+        let (release, blocked) = oneshot::channel();
+        worker.do_send(Block(blocked));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        for i in 1..=4 {
+            worker.do_send(Work(i));
+        }
+        let lost = captured();
+        assert_eq!(lost.len(), 2);
+        assert!(lost.iter().all(|letter| letter.reason == DeadLetterReason::MailboxFull));
+
+        release.send(()).unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(worker.send(Done).await.unwrap(), [1, 2]);
+        // Rejected again while muted, so they stay captured
+        worker.mute::<Work>();
+        assert_eq!(DeadLetters::replay_into(&worker), 0);
+        assert_eq!(captured().len(), 2);
+        worker.unmute::<Work>();
+        assert_eq!(DeadLetters::replay_into(&worker), 2);
+        assert!(captured().is_empty());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(worker.send(Done).await.unwrap(), [1, 2, 3, 4]);
+
+        // Left in the mailbox on stop, then sent to the stopped actor
+        let stopped = Worker::default().start();
+        let (release, blocked) = oneshot::channel();
+        stopped.do_send(Block(blocked));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        stopped.do_send(Work(5));
+        stopped.stop(StopMode::Abandon);
+        release.send(()).unwrap();
+        stopped.terminated().await;
+        stopped.do_send(Work(6));
+        let reasons: Vec<_> = captured().into_iter().map(|letter| letter.reason).collect();
+        use DeadLetterReason::{Abandoned, Stopped};
+        assert_eq!(reasons, [Abandoned, Stopped]);
+        // Keyed by the actor they were addressed to
+        assert_eq!(DeadLetters::replay_into(&worker), 0);
+        assert_eq!(DeadLetters::replay_type_into(&worker), 2);
+        assert!(captured().is_empty());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(worker.send(Done).await.unwrap(), [1, 2, 3, 4, 5, 6]);
+        DeadLetters::capture(0);
+    })
+}
+
+#[test]
+fn stashing_mailbox_filter() {
+    use crate::context::MessageType;
+    use std::time::Duration;
+
+    struct HandshakeHello;
+    struct HandshakeDone;
+    struct Data(u32);
+    struct Stashed;
+    #[derive(Default)]
+    struct Session {
+        log: Vec<String>,
+    }
+    #[async_trait]
+    impl Actor for Session {
+        async fn started(&mut self, ctx: &mut ActorContext<Self>) {
+            ctx.set_mailbox_filter(|message_type: MessageType| {
+                message_type.name().contains("::Handshake") || message_type.is::<Stashed>()
+            });
+        }
+    }
+    #[async_trait]
+    impl Handler<HandshakeHello> for Session {
+        type Response = ();
+        async fn handle(&mut self, _msg: HandshakeHello, _ctx: &mut ActorContext<Self>) {
+            self.log.push("hello".into());
+        }
+    }
+    #[async_trait]
+    impl Handler<HandshakeDone> for Session {
+        type Response = usize;
+        async fn handle(&mut self, _msg: HandshakeDone, ctx: &mut ActorContext<Self>) -> usize {
+            self.log.push("done".into());
+            ctx.clear_mailbox_filter();
+            ctx.unstash_all()
+        }
+    }
+    #[async_trait]
+    impl Handler<Data> for Session {
+        type Response = Vec<String>;
+        async fn handle(&mut self, msg: Data, _ctx: &mut ActorContext<Self>) -> Vec<String> {
+            self.log.push(format!("data {}", msg.0));
+            self.log.clone()
+        }
+    }
+    #[async_trait]
+    impl Handler<Stashed> for Session {
+        type Response = usize;
+        async fn handle(&mut self, _msg: Stashed, ctx: &mut ActorContext<Self>) -> usize {
+            ctx.diagnostics().stashed
+        }
+    }
+
+    get_runtime().block_on(async {
+        let session = Session::default().start();
+        let send = |i| {
+            let session = session.clone();
+            tokio::spawn(async move { session.send(Data(i)).await })
+        };
+        let first = send(1);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let second = send(2);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        session.send(HandshakeHello).await.unwrap();
+        // Messages of the framework itself are not filtered
+        session.ping(Duration::from_secs(1)).await.unwrap();
+        assert_eq!(session.send(Stashed).await.unwrap(), 2);
+        assert_eq!(session.send(HandshakeDone).await.unwrap(), 2);
+        assert_eq!(first.await.unwrap().unwrap(), ["hello", "done", "data 1"]);
+        let log = second.await.unwrap().unwrap();
+        assert_eq!(log, ["hello", "done", "data 1", "data 2"]);
+        assert_eq!(session.send(Stashed).await.unwrap(), 0);
+    })
+}
+
+#[test]
+fn stash_on_stop_and_restart() {
+    struct Lock;
+    struct Release;
+    struct Bounce;
+    struct Data(u32);
+    struct Gate;
+    impl Actor for Gate {}
+    impl Supervised for Gate {}
+    #[async_trait]
+    impl Handler<Lock> for Gate {
+        type Response = ();
+        async fn handle(&mut self, _msg: Lock, ctx: &mut ActorContext<Self>) {
+            ctx.set_mailbox_filter(|message_type| !message_type.is::<Data>());
+        }
+    }
+    #[async_trait]
+    impl Handler<Release> for Gate {
+        type Response = usize;
+        async fn handle(&mut self, _msg: Release, ctx: &mut ActorContext<Self>) -> usize {
+            ctx.clear_mailbox_filter();
+            ctx.stop_with(StopMode::Drain { deadline: None });
+            ctx.unstash_all()
+        }
+    }
+    #[async_trait]
+    impl Handler<Bounce> for Gate {
+        type Response = ();
+        async fn handle(&mut self, _msg: Bounce, ctx: &mut ActorContext<Self>) {
+            ctx.stop();
+        }
+    }
+    #[async_trait]
+    impl Handler<Data> for Gate {
+        type Response = u32;
+        async fn handle(&mut self, msg: Data, _ctx: &mut ActorContext<Self>) -> u32 {
+            msg.0
+        }
+    }
+
+    get_runtime().block_on(async {
+        // Released messages get drained along with the mailbox
+        let gate = Gate.start();
+        gate.send(Lock).await.unwrap();
+        let (data, released) = futures_util::join!(gate.send(Data(1)), gate.send(Release));
+        assert_eq!(released.unwrap(), 1);
+        assert_eq!(data.unwrap(), 1);
+
+        // The filter does not outlive the instance which installed it
+        let gate = Gate::create_supervised(|_| Gate);
+        gate.send(Lock).await.unwrap();
+        gate.send(Bounce).await.unwrap();
+        assert_eq!(gate.send(Data(2)).await.unwrap(), 2);
+    })
+}
@@ -0,0 +1,241 @@
+//! Two-phase commit among actors
+//!
+//! Participants implement [Handler]s for [Prepare], [Commit] and [Abort].
+//! The [Coordinator] asks all of them to prepare the transaction,
+//! and then, depending on the votes, tells them to commit or to abort it.
+//! Participants which cannot be told about the outcome get reported via [Undelivered].
+
+use crate::{
+    actor::{Actor, Handler},
+    addr::Addr,
+    context::ActorContext,
+    error::ActorError,
+    supervised::Backoff,
+};
+use async_trait::async_trait;
+use futures_util::future::join_all;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Identifier of a transaction, unique within its' [Coordinator]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct TransactionId(pub u64);
+
+/// First phase: the participant should get ready to apply the payload and vote
+#[derive(Clone, Debug)]
+pub struct Prepare<T> {
+    pub id: TransactionId,
+    pub payload: T,
+}
+
+/// Second phase: the participant should apply the prepared transaction
+#[derive(Clone, Copy, Debug)]
+pub struct Commit {
+    pub id: TransactionId,
+}
+
+/// Second phase: the participant should discard the prepared transaction
+#[derive(Clone, Copy, Debug)]
+pub struct Abort {
+    pub id: TransactionId,
+}
+
+/// Answer of a participant to [Prepare]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Vote {
+    Yes,
+    No,
+}
+
+/// The final result of a transaction
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Outcome {
+    Committed,
+    Aborted,
+}
+
+/// Error returned when some participants did not get told about the outcome of a transaction,
+/// even after retrying
+#[derive(Error, Clone, Debug, PartialEq, Eq)]
+#[error("Transaction {outcome:?}, but {} participant(s) did not get told.", .participants.len())]
+pub struct Undelivered {
+    /// The outcome decided by the votes, which the other participants got told about
+    pub outcome: Outcome,
+    /// Indices of the participants, in the order of adding them,
+    /// along with the error of the latest attempt to tell them
+    pub participants: Vec<(usize, ActorError)>,
+}
+
+/// Type-erased transaction participant, implemented for addresses of suitable actors
+#[async_trait]
+pub trait Participant<T>: Send + Sync {
+    async fn prepare(&self, msg: Prepare<T>) -> Result<Vote, ActorError>;
+    async fn commit(&self, msg: Commit) -> Result<(), ActorError>;
+    async fn abort(&self, msg: Abort) -> Result<(), ActorError>;
+}
+
+#[async_trait]
+impl<A, T> Participant<T> for Addr<A>
+where
+    A: Handler<Prepare<T>, Response = Vote>,
+    A: Handler<Commit, Response = ()>,
+    A: Handler<Abort, Response = ()>,
+    T: Send + 'static,
+{
+    async fn prepare(&self, msg: Prepare<T>) -> Result<Vote, ActorError> {
+        self.send(msg).await
+    }
+    async fn commit(&self, msg: Commit) -> Result<(), ActorError> {
+        self.send(msg).await
+    }
+    async fn abort(&self, msg: Abort) -> Result<(), ActorError> {
+        self.send(msg).await
+    }
+}
+
+/// Message asking the [Coordinator] actor to carry out a transaction
+pub struct Transaction<T>(pub T);
+
+/// Two-phase commit coordinator
+///
+/// It can be used directly, via [Coordinator::run], or started as an actor
+/// accepting [Transaction] messages, in which case transactions are carried out one at a time.
+pub struct Coordinator<T> {
+    participants: Vec<Box<dyn Participant<T>>>,
+    quorum: Option<usize>,
+    prepare_timeout: Option<Duration>,
+    retries: usize,
+    backoff: Backoff,
+    next_id: u64,
+}
+
+impl<T: Clone + Send + 'static> Coordinator<T> {
+    /// Creates a coordinator with no participants.
+    ///
+    /// By default, every participant has to vote [Vote::Yes],
+    /// there is no timeout for the prepare phase
+    /// and second-phase messages are not retried.
+    pub fn new() -> Self {
+        Self {
+            participants: Vec::new(),
+            quorum: None,
+            prepare_timeout: None,
+            retries: 0,
+            backoff: Backoff::DEFAULT,
+            next_id: 0,
+        }
+    }
+    /// Adds a participant
+    pub fn participant(mut self, participant: impl Participant<T> + 'static) -> Self {
+        self.participants.push(Box::new(participant));
+        self
+    }
+    /// Sets how many [Vote::Yes] votes are required to commit.
+    ///
+    /// Participants which did not vote yes are told to abort.
+    pub fn quorum(mut self, quorum: usize) -> Self {
+        self.quorum = Some(quorum);
+        self
+    }
+    /// Participants which do not vote within the given time count as having voted [Vote::No].
+    pub fn prepare_timeout(mut self, timeout: Duration) -> Self {
+        self.prepare_timeout = Some(timeout);
+        self
+    }
+    /// Sets how many more times [Commit] and [Abort] get sent if delivering them fails.
+    pub fn retries(mut self, retries: usize) -> Self {
+        self.retries = retries;
+        self
+    }
+    /// Sets the delays between retries of [Commit] and [Abort], [Backoff::DEFAULT] by default
+    pub fn retry_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+    /// Carries out a transaction with the given payload.
+    ///
+    /// Fails if [Commit] or [Abort] could not be delivered to some participants.
+    pub async fn run(&mut self, payload: T) -> Result<Outcome, Undelivered> {
+        let id = TransactionId(self.next_id);
+        self.next_id += 1;
+        let prepare_timeout = self.prepare_timeout;
+        let votes = join_all(self.participants.iter().map(|p| {
+            let vote = p.prepare(Prepare {
+                id,
+                payload: payload.clone(),
+            });
+            async move {
+                let vote = match prepare_timeout {
+                    Some(timeout) => tokio::time::timeout(timeout, vote)
+                        .await
+                        .unwrap_or(Ok(Vote::No)),
+                    None => vote.await,
+                };
+                vote.unwrap_or(Vote::No)
+            }
+        }))
+        .await;
+        let yes_count = votes.iter().filter(|v| **v == Vote::Yes).count();
+        let quorum = self.quorum.unwrap_or(self.participants.len());
+        let outcome = if yes_count >= quorum {
+            Outcome::Committed
+        } else {
+            Outcome::Aborted
+        };
+        let retries = self.retries;
+        let backoff = self.backoff;
+        let undelivered = join_all(
+            self.participants
+                .iter()
+                .zip(votes)
+                .enumerate()
+                .map(|(index, (p, vote))| async move {
+                    let mut attempt = 0;
+                    loop {
+                        let delivered = if outcome == Outcome::Committed && vote == Vote::Yes {
+                            p.commit(Commit { id }).await
+                        } else {
+                            p.abort(Abort { id }).await
+                        };
+                        match delivered {
+                            Ok(()) => return None,
+                            Err(e) if attempt == retries => return Some((index, e)),
+                            Err(_) => {
+                                tokio::time::sleep(backoff.delay(attempt as u32)).await;
+                                attempt += 1;
+                            }
+                        }
+                    }
+                }),
+        )
+        .await;
+        let participants: Vec<_> = undelivered.into_iter().flatten().collect();
+        match participants.is_empty() {
+            true => Ok(outcome),
+            false => Err(Undelivered {
+                outcome,
+                participants,
+            }),
+        }
+    }
+}
+
+impl<T: Clone + Send + 'static> Default for Coordinator<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Clone + Send + 'static> Actor for Coordinator<T> {}
+
+#[async_trait]
+impl<T: Clone + Send + 'static> Handler<Transaction<T>> for Coordinator<T> {
+    type Response = Result<Outcome, Undelivered>;
+    async fn handle(
+        &mut self,
+        msg: Transaction<T>,
+        _ctx: &mut ActorContext<Self>,
+    ) -> Result<Outcome, Undelivered> {
+        self.run(msg.0).await
+    }
+}
@@ -0,0 +1,194 @@
+//! Detection of stuck actors
+//!
+//! The [Watchdog] periodically [pings](crate::addr::Addr::ping) the actors it watches.
+//! Once an actor misses `max_failures` pings in a row (most likely being stuck in a handler),
+//! its' running handlers get aborted at their' next await point.
+//! The actor then stops, getting restarted if it's [crate::supervised::Supervised]
+//! with [crate::supervised::RestartReason::Aborted].
+//!
+//! Aborting only affects the handlers running at that moment. If the actor got unstuck in the meantime,
+//! or is stuck outside of its' handlers, nothing gets aborted and the next handler runs normally.
+
+use crate::{
+    actor::{Actor, ActorId, Handler},
+    addr::{Addr, WeakAddr},
+    context::ActorContext,
+    error::ActorError,
+    health::Health,
+};
+use async_trait::async_trait;
+use futures_util::future::{join_all, BoxFuture};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+/// How many events are buffered for each subscriber before the oldest ones get dropped
+const CHANNEL_CAPACITY: usize = 256;
+
+/// Something the [Watchdog] noticed
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum WatchdogEvent {
+    /// The actor did not respond to a ping in time
+    Missed {
+        actor_id: ActorId,
+        actor_type: &'static str,
+        /// Number of pings missed in a row
+        failures: u32,
+    },
+    /// The handlers of the actor have been aborted
+    Aborted {
+        actor_id: ActorId,
+        actor_type: &'static str,
+    },
+}
+
+/// Type-erased actor watched by the [Watchdog]
+trait Probe: Send + Sync {
+    /// Resolves to `None` if the actor is gone
+    fn ping(&self, deadline: Duration) -> BoxFuture<'static, Option<Result<Health, ActorError>>>;
+    fn abort(&self);
+    fn id(&self) -> ActorId;
+    fn actor_type(&self) -> &'static str;
+}
+
+struct Target<A: Actor> {
+    addr: WeakAddr<A>,
+    id: ActorId,
+}
+
+impl<A: Actor> Probe for Target<A> {
+    fn ping(&self, deadline: Duration) -> BoxFuture<'static, Option<Result<Health, ActorError>>> {
+        let addr = self.addr.upgrade();
+        Box::pin(async move { Some(addr?.ping(deadline).await) })
+    }
+    fn abort(&self) {
+        if let Some(addr) = self.addr.upgrade() {
+            addr.msg_queue.shared().abort_handlers();
+        }
+    }
+    fn id(&self) -> ActorId {
+        self.id
+    }
+    fn actor_type(&self) -> &'static str {
+        std::any::type_name::<A>()
+    }
+}
+
+struct Watched {
+    probe: Box<dyn Probe>,
+    failures: u32,
+}
+
+/// Actor watching other actors, see [crate::watchdog]
+///
+/// The watched actors are not kept alive by the watchdog.
+pub struct Watchdog {
+    interval: Duration,
+    deadline: Duration,
+    max_failures: u32,
+    watched: Vec<Watched>,
+    events: broadcast::Sender<WatchdogEvent>,
+}
+
+impl Watchdog {
+    /// Creates a watchdog pinging every `interval`, waiting `deadline` for each response
+    /// and aborting actors after `max_failures` missed pings in a row.
+    pub fn new(interval: Duration, deadline: Duration, max_failures: u32) -> Self {
+        Self {
+            interval,
+            deadline,
+            max_failures: max_failures.max(1),
+            watched: Vec::new(),
+            events: broadcast::channel(CHANNEL_CAPACITY).0,
+        }
+    }
+    /// Returns a receiver of all events from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<WatchdogEvent> {
+        self.events.subscribe()
+    }
+    fn emit(&self, event: WatchdogEvent) {
+        // Nobody might be listening
+        let _ = self.events.send(event);
+    }
+}
+
+/// Makes the [Watchdog] watch the actor
+pub struct Watch<A: Actor>(pub Addr<A>);
+
+/// Message making the [Watchdog] ping the actors
+#[doc(hidden)]
+pub struct Tick;
+
+#[async_trait]
+impl Actor for Watchdog {
+    async fn started(&mut self, ctx: &mut ActorContext<Self>) {
+        let addr = ctx.weak_address();
+        let interval = self.interval;
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                let Some(addr) = addr.upgrade() else {
+                    break;
+                };
+                if addr.send(Tick).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+#[async_trait]
+impl<A: Actor> Handler<Watch<A>> for Watchdog {
+    type Response = ();
+    async fn handle(&mut self, msg: Watch<A>, _ctx: &mut ActorContext<Self>) {
+        let probe = Target {
+            addr: msg.0.downgrade(),
+            id: msg.0.id(),
+        };
+        self.watched.push(Watched {
+            probe: Box::new(probe),
+            failures: 0,
+        });
+    }
+}
+
+#[async_trait]
+impl Handler<Tick> for Watchdog {
+    type Response = ();
+    async fn handle(&mut self, _msg: Tick, _ctx: &mut ActorContext<Self>) {
+        let pings = join_all(self.watched.iter().map(|w| w.probe.ping(self.deadline))).await;
+        let mut events = Vec::new();
+        let mut still_watched = Vec::with_capacity(self.watched.len());
+        for (mut watched, ping) in self.watched.drain(..).zip(pings) {
+            let (actor_id, actor_type) = (watched.probe.id(), watched.probe.actor_type());
+            match ping {
+                // The actor is gone
+                None => continue,
+                Some(Ok(_)) => watched.failures = 0,
+                Some(Err(ActorError::Timeout(_))) => {
+                    watched.failures += 1;
+                    events.push(WatchdogEvent::Missed {
+                        actor_id,
+                        actor_type,
+                        failures: watched.failures,
+                    });
+                    if watched.failures >= self.max_failures {
+                        watched.probe.abort();
+                        watched.failures = 0;
+                        events.push(WatchdogEvent::Aborted {
+                            actor_id,
+                            actor_type,
+                        });
+                    }
+                }
+                // The actor is stopping
+                Some(Err(_)) => {}
+            }
+            still_watched.push(watched);
+        }
+        self.watched = still_watched;
+        for event in events {
+            self.emit(event);
+        }
+    }
+}
@@ -0,0 +1,193 @@
+//! Durable workflows
+//!
+//! A [Workflow] is a [Saga] whose progress gets recorded in a [ProgressStore] after every step.
+//! Running a workflow again (e.g. after the process has been restarted) continues from the first step
+//! which has not completed yet, instead of running the whole saga again,
+//! and a workflow which has already finished is not run again at all.
+//! Likewise, the compensations of a failed workflow which got interrupted continue
+//! from the first step which has not been compensated yet.
+//! Steps and compensations can get executed more than once if the process stops in the middle of them,
+//! so they should be idempotent, e.g. via [crate::idempotency].
+
+use crate::saga::{Saga, SagaError, Step};
+use async_trait::async_trait;
+use std::{collections::HashMap, convert::Infallible, fmt, sync::Arc, sync::Mutex};
+use thiserror::Error;
+
+/// How far a workflow has got
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Progress {
+    /// Steps before the given one have completed
+    Running { next_step: usize },
+    /// All the steps have completed
+    Completed,
+    /// The given step failed, and the completed steps before `remaining` have yet to be compensated
+    Compensating { failed_step: usize, remaining: usize },
+    /// The given step failed and the completed ones have been compensated
+    Compensated { failed_step: usize },
+}
+
+/// Storage of the progress of workflows, keyed by their' identifiers
+#[async_trait]
+pub trait ProgressStore: Send + Sync + 'static {
+    type Error: std::error::Error + Send + 'static;
+    /// Returns the recorded progress of the workflow, `None` if it has never run
+    async fn load(&self, workflow: &str) -> Result<Option<Progress>, Self::Error>;
+    /// Records the progress of the workflow
+    async fn save(&self, workflow: &str, progress: Progress) -> Result<(), Self::Error>;
+}
+
+/// [ProgressStore] keeping the progress in memory
+#[derive(Debug, Default)]
+pub struct MemoryProgress {
+    workflows: Mutex<HashMap<String, Progress>>,
+}
+
+impl MemoryProgress {
+    /// Creates an empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl ProgressStore for MemoryProgress {
+    type Error = Infallible;
+    async fn load(&self, workflow: &str) -> Result<Option<Progress>, Infallible> {
+        Ok(self.workflows.lock().unwrap().get(workflow).copied())
+    }
+    async fn save(&self, workflow: &str, progress: Progress) -> Result<(), Infallible> {
+        self.workflows
+            .lock()
+            .unwrap()
+            .insert(workflow.to_string(), progress);
+        Ok(())
+    }
+}
+
+/// Error returned when a [Workflow] does not complete
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum WorkflowError<E, S> {
+    #[error(transparent)]
+    /// A step failed and the completed ones have been compensated.
+    Failed(SagaError<E>),
+    #[error("Workflow step #{failed_step} failed in a previous run.")]
+    /// The workflow failed in a previous run.
+    ///
+    /// Compensations interrupted in the previous run have been completed.
+    AlreadyCompensated { failed_step: usize },
+    #[error("Could not access the progress of the workflow: {0}")]
+    /// The progress could not be loaded or saved, so the workflow has been interrupted.
+    ///
+    /// Running it again continues from the last recorded step.
+    Store(S),
+}
+
+/// [Saga] whose progress survives restarts, see [crate::workflow]
+pub struct Workflow<E, S> {
+    id: String,
+    saga: Saga<E>,
+    store: Arc<S>,
+}
+
+impl<E: Send + 'static, S> fmt::Debug for Workflow<E, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Workflow")
+            .field("id", &self.id)
+            .field("steps", &self.saga.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<E: Send + 'static, S: ProgressStore> Workflow<E, S> {
+    /// Creates a workflow without steps.
+    ///
+    /// The identifier has to be unique within the store, e.g. derived from the order being processed.
+    pub fn new(id: impl Into<String>, store: Arc<S>) -> Self {
+        Self {
+            id: id.into(),
+            saga: Saga::new(),
+            store,
+        }
+    }
+    /// Appends a step to the workflow.
+    ///
+    /// The steps need to be the same on every run, as they are identified by their' indices.
+    pub fn step(mut self, step: Step<E>) -> Self {
+        self.saga = self.saga.step(step);
+        self
+    }
+    /// Returns the identifier of the workflow
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+    /// Runs the steps which have not completed yet.
+    ///
+    /// Returns `Ok` right away if the workflow has already completed.
+    pub async fn run(&mut self) -> Result<(), WorkflowError<E, S::Error>> {
+        let progress = self.store.load(&self.id).await.map_err(WorkflowError::Store)?;
+        let first_step = match progress {
+            None => 0,
+            Some(Progress::Running { next_step }) => next_step,
+            Some(Progress::Completed) => return Ok(()),
+            Some(Progress::Compensating {
+                failed_step,
+                remaining,
+            }) => {
+                self.compensate(failed_step, remaining).await?;
+                return Err(WorkflowError::AlreadyCompensated { failed_step });
+            }
+            Some(Progress::Compensated { failed_step }) => {
+                return Err(WorkflowError::AlreadyCompensated { failed_step })
+            }
+        };
+        let (id, store) = (&self.id, &self.store);
+        let completed = |index: usize| async move {
+            let progress = Progress::Running {
+                next_step: index + 1,
+            };
+            store.save(id, progress).await
+        };
+        let result = self
+            .saga
+            .resume_with(first_step, completed)
+            .await
+            .map_err(WorkflowError::Store)?;
+        match result {
+            Ok(()) => {
+                self.store
+                    .save(&self.id, Progress::Completed)
+                    .await
+                    .map_err(WorkflowError::Store)?;
+                Ok(())
+            }
+            Err(e) => {
+                self.compensate(e.step, e.step).await?;
+                Err(WorkflowError::Failed(e))
+            }
+        }
+    }
+    /// Compensates the completed steps before `remaining`, recording the progress after every one of them
+    async fn compensate(
+        &mut self,
+        failed_step: usize,
+        remaining: usize,
+    ) -> Result<(), WorkflowError<E, S::Error>> {
+        let (id, store) = (&self.id, &self.store);
+        let compensating = |remaining: usize| async move {
+            let progress = Progress::Compensating {
+                failed_step,
+                remaining,
+            };
+            store.save(id, progress).await
+        };
+        // Recorded first, so that the compensations get resumed if the process stops in the middle of them
+        compensating(remaining).await.map_err(WorkflowError::Store)?;
+        self.saga
+            .compensate_with(remaining, compensating)
+            .await
+            .map_err(WorkflowError::Store)?;
+        let progress = Progress::Compensated { failed_step };
+        store.save(id, progress).await.map_err(WorkflowError::Store)
+    }
+}